@@ -0,0 +1,118 @@
+use super::clip::AnimationClip;
+use super::skeleton::{Pose, Skeleton};
+
+#[derive(Clone, Copy, Debug)]
+struct Playback {
+    clip: usize,
+    time: f32,
+    looping: bool,
+}
+
+impl Playback {
+    fn advance(&mut self, dt: f32, clips: &[AnimationClip]) {
+        let duration = clips.get(self.clip).map(|c| c.duration).unwrap_or(0.0);
+        self.time += dt;
+        if self.looping && duration > 0.0 {
+            self.time %= duration;
+        } else {
+            self.time = self.time.min(duration);
+        }
+    }
+
+    fn sample(&self, clips: &[AnimationClip], pose: &mut Pose) {
+        if let Some(clip) = clips.get(self.clip) {
+            clip.sample(self.time, pose);
+        }
+    }
+}
+
+/// Plays the clips of one skeleton, e.g. of one unit.
+///
+/// Switching clips cross-fades from the pose of the old clip, which keeps
+/// playing during the fade, to the new one. Clips are referred to by their
+/// index in the slice passed to `update` and `pose`, which must not change.
+#[derive(Clone, Debug)]
+pub struct Animator {
+    current: Playback,
+    previous: Option<Playback>,
+    /// Seconds into the current fade.
+    fade_time: f32,
+    fade_duration: f32,
+    /// Scales the time of `update`.
+    pub speed: f32,
+}
+
+impl Animator {
+    /// Starts looping `clip`.
+    pub fn new(clip: usize) -> Animator {
+        Animator {
+            current: Playback {
+                clip: clip,
+                time: 0.0,
+                looping: true,
+            },
+            previous: None,
+            fade_time: 0.0,
+            fade_duration: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    /// Starts `clip` from its beginning, blending it in over `fade`
+    /// seconds. Clips that do not loop hold their last pose once done.
+    pub fn play(&mut self, clip: usize, looping: bool, fade: f32) {
+        self.previous = if fade > 0.0 { Some(self.current) } else { None };
+        self.current = Playback {
+            clip: clip,
+            time: 0.0,
+            looping: looping,
+        };
+        self.fade_time = 0.0;
+        self.fade_duration = fade;
+    }
+
+    /// Index of the clip playing, or fading in.
+    pub fn clip(&self) -> usize {
+        self.current.clip
+    }
+
+    /// Seconds into the current clip.
+    pub fn time(&self) -> f32 {
+        self.current.time
+    }
+
+    /// Whether a clip that does not loop reached its end.
+    pub fn is_finished(&self, clips: &[AnimationClip]) -> bool {
+        !self.current.looping &&
+            clips.get(self.current.clip).map(|c| self.current.time >= c.duration).unwrap_or(true)
+    }
+
+    pub fn update(&mut self, dt: f32, clips: &[AnimationClip]) {
+        let dt = dt * self.speed;
+        self.current.advance(dt, clips);
+
+        if let Some(mut previous) = self.previous.take() {
+            self.fade_time += dt;
+            if self.fade_time < self.fade_duration {
+                previous.advance(dt, clips);
+                self.previous = Some(previous);
+            }
+        }
+    }
+
+    /// Pose of the skeleton at the current time. Joints no clip animates
+    /// stay at rest.
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Pose {
+        let mut pose = skeleton.rest_pose();
+        self.current.sample(clips, &mut pose);
+
+        if let Some(ref previous) = self.previous {
+            let mut faded = skeleton.rest_pose();
+            previous.sample(clips, &mut faded);
+            faded.blend(&pose, self.fade_time / self.fade_duration);
+            return faded;
+        }
+
+        pose
+    }
+}
@@ -0,0 +1,209 @@
+use cgmath::{InnerSpace, Quaternion, Vector3};
+
+use std::cmp::Ordering;
+use std::ops::{Add, Mul};
+
+use super::skeleton::Pose;
+use super::slerp;
+
+/// How values between two keys are computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The earlier key holds until the next one.
+    Step,
+    /// Vectors are interpolated linearly and rotations spherically.
+    Linear,
+    /// Hermite spline through the keys, with tangents stored next to them.
+    CubicSpline,
+}
+
+/// Keys of one property of a joint.
+///
+/// With `Interpolation::CubicSpline` each key is stored as its in-tangent,
+/// its value and its out-tangent, as in glTF.
+#[derive(Clone, Debug)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+/// Animation of one property of one joint.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub joint: usize,
+    /// Key times in seconds, increasing.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+/// Named animation of a skeleton, e.g. a walk cycle.
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Time of the last key of any channel.
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> AnimationClip {
+        let duration = channels.iter()
+            .filter_map(|c| c.times.last().cloned())
+            .fold(0.0, f32::max);
+
+        AnimationClip {
+            name: name.to_owned(),
+            duration: duration,
+            channels: channels,
+        }
+    }
+
+    /// Writes the transforms of the animated joints at `time` into `pose`.
+    /// Times outside the clip hold its first or last keys, and joints the
+    /// clip does not animate keep their transforms.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            let joint = match pose.local.get_mut(channel.joint) {
+                Some(joint) => joint,
+                None => continue,
+            };
+            match channel.keyframes {
+                Keyframes::Translation(ref values) => {
+                    if let Some(value) = channel.sample(values, time, Vector3::lerp) {
+                        joint.translation = value;
+                    }
+                },
+                Keyframes::Rotation(ref values) => {
+                    if let Some(value) = channel.sample(values, time, slerp) {
+                        joint.rotation = value.normalize();
+                    }
+                },
+                Keyframes::Scale(ref values) => {
+                    if let Some(value) = channel.sample(values, time, Vector3::lerp) {
+                        joint.scale = value;
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn sample<T, F>(&self, values: &[T], time: f32, interpolate: F) -> Option<T>
+        where T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+              F: Fn(T, T, f32) -> T
+    {
+        self.interpolation.sample(&self.times, values, time, interpolate)
+    }
+}
+
+impl Interpolation {
+    /// Value at `time` of the keys at `times`, or `None` if there are fewer
+    /// values than keys. `interpolate` blends two values linearly.
+    pub fn sample<T, F>(self, times: &[f32], values: &[T], time: f32, interpolate: F) -> Option<T>
+        where T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+              F: Fn(T, T, f32) -> T
+    {
+        let cubic = self == Interpolation::CubicSpline;
+        let stride = if cubic { 3 } else { 1 };
+        let keys = times.len();
+        if keys == 0 || values.len() < keys * stride {
+            return None;
+        }
+        let value = |key: usize| if cubic { values[key * 3 + 1] } else { values[key] };
+
+        // Index of the first key after `time`
+        let next = match times.binary_search_by(|t| t.partial_cmp(&time).unwrap_or(Ordering::Less)) {
+            Ok(key) => return Some(value(key)),
+            Err(key) => key,
+        };
+        if next == 0 {
+            return Some(value(0));
+        }
+        if next == keys {
+            return Some(value(keys - 1));
+        }
+
+        let previous = next - 1;
+        let span = times[next] - times[previous];
+        let t = (time - times[previous]) / span;
+
+        Some(match self {
+            Interpolation::Step => value(previous),
+            Interpolation::Linear => interpolate(value(previous), value(next), t),
+            Interpolation::CubicSpline => {
+                let out_tangent = values[previous * 3 + 2] * span;
+                let in_tangent = values[next * 3] * span;
+                let t2 = t * t;
+                let t3 = t2 * t;
+                value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0) +
+                    out_tangent * (t3 - 2.0 * t2 + t) +
+                    value(next) * (-2.0 * t3 + 3.0 * t2) +
+                    in_tangent * (t3 - t2)
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lerp(a: f32, b: f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+
+    const TIMES: [f32; 3] = [0.0, 1.0, 2.0];
+
+    #[test]
+    fn step_holds_the_earlier_key() {
+        let values = [0.0, 10.0, 20.0];
+        let sample = |time| Interpolation::Step.sample(&TIMES, &values, time, lerp).unwrap();
+        assert_eq!(sample(0.5), 0.0);
+        assert_eq!(sample(1.0), 10.0);
+        assert_eq!(sample(1.99), 10.0);
+    }
+
+    #[test]
+    fn linear_blends_neighbouring_keys() {
+        let values = [0.0, 10.0, 30.0];
+        let sample = |time| Interpolation::Linear.sample(&TIMES, &values, time, lerp).unwrap();
+        assert_eq!(sample(0.25), 2.5);
+        assert_eq!(sample(1.5), 20.0);
+    }
+
+    #[test]
+    fn times_outside_the_keys_hold_the_ends() {
+        let values = [0.0, 10.0, 20.0];
+        for &interpolation in &[Interpolation::Step, Interpolation::Linear] {
+            assert_eq!(interpolation.sample(&TIMES, &values, -1.0, lerp), Some(0.0));
+            assert_eq!(interpolation.sample(&TIMES, &values, 3.0, lerp), Some(20.0));
+        }
+    }
+
+    #[test]
+    fn cubic_spline_uses_tangents() {
+        // In-tangent, value and out-tangent of each key
+        let flat = [0.0, 0.0, 0.0, 0.0, 10.0, 0.0];
+        let sample = |values: &[f32], time| {
+            Interpolation::CubicSpline.sample(&TIMES[..2], values, time, lerp).unwrap()
+        };
+        assert_eq!(sample(&flat, 0.0), 0.0);
+        assert_eq!(sample(&flat, 0.5), 5.0);
+        assert_eq!(sample(&flat, 0.25), 1.5625);
+        assert_eq!(sample(&flat, 1.0), 10.0);
+
+        // Tangents matching the slope give a straight line
+        let sloped = [10.0, 0.0, 10.0, 10.0, 10.0, 10.0];
+        assert_eq!(sample(&sloped, 0.25), 2.5);
+    }
+
+    #[test]
+    fn missing_values_sample_nothing() {
+        assert_eq!(Interpolation::Linear.sample(&[], &[1.0], 0.0, lerp), None);
+        assert_eq!(Interpolation::Linear.sample(&TIMES, &[1.0, 2.0], 0.0, lerp), None);
+        assert_eq!(Interpolation::CubicSpline.sample(&TIMES, &[1.0; 6], 0.0, lerp), None);
+    }
+}
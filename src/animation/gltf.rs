@@ -0,0 +1,611 @@
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3};
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::clip::{AnimationClip, Channel, Interpolation, Keyframes};
+use super::json::{self, Value};
+use super::skeleton::{Joint, Skeleton};
+use super::super::framework::gfx::VertexStreams;
+use super::super::framework::parse::invalid_data;
+use super::super::scene::Transform;
+
+/// Skinned mesh with its skeleton and animations.
+#[derive(Clone, Debug)]
+pub struct SkinnedModel {
+    /// Positions, normals, texture coordinates, joints and weights, with
+    /// joints indexing `skeleton`.
+    pub streams: VertexStreams,
+    /// Triangle list.
+    pub indices: Vec<u32>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+
+const GLB_MAGIC: &'static [u8] = b"glTF";
+const GLB_JSON: u32 = 0x4e4f534a;
+const GLB_BIN: u32 = 0x004e4942;
+
+/// Reads the first skinned mesh of a glTF 2.0 file, `.gltf` with external
+/// or embedded buffers or binary `.glb`, and every animation of its skin.
+///
+/// All triangle primitives of the mesh are merged. Joints must have
+/// translation, rotation and scale rather than a matrix, and transforms of
+/// nodes above the skeleton are ignored. Sparse accessors and morph targets
+/// are not supported.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<SkinnedModel> {
+    let path = path.as_ref();
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let (document, binary) = if data.starts_with(GLB_MAGIC) {
+        split_glb(&data)?
+    } else {
+        let text = String::from_utf8(data)
+            .map_err(|_| invalid_data("glTF", "the file is not UTF-8"))?;
+        (json::parse(&text)?, None)
+    };
+
+    let mut buffers = Vec::new();
+    for buffer in document.get("buffers").members() {
+        buffers.push(match buffer.get("uri").as_str() {
+            Some(uri) if uri.starts_with("data:") => {
+                let start = uri.find(";base64,")
+                    .ok_or_else(|| invalid_data("glTF", "only base64 data URIs are supported"))?;
+                decode_base64(&uri[start + 8..])?
+            },
+            Some(uri) => {
+                let mut bytes = Vec::new();
+                File::open(path.with_file_name(uri))?.read_to_end(&mut bytes)?;
+                bytes
+            },
+            None => binary.clone().ok_or_else(|| invalid_data("glTF", "missing binary chunk"))?,
+        });
+    }
+
+    let gltf = Gltf {
+        document: &document,
+        buffers: buffers,
+    };
+    gltf.skinned_model()
+}
+
+struct Gltf<'a> {
+    document: &'a Value,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl<'a> Gltf<'a> {
+    fn skinned_model(&self) -> io::Result<SkinnedModel> {
+        let nodes = self.document.get("nodes").members();
+        let node = nodes.iter()
+            .find(|n| !n.get("skin").is_null() && !n.get("mesh").is_null())
+            .ok_or_else(|| invalid_data("glTF", "no node has a skinned mesh"))?;
+        let skin = self.index("skins", node.get("skin"))?;
+        let mesh = self.index("meshes", node.get("mesh"))?;
+
+        let (skeleton, skin_joints, remap) = self.skeleton(skin)?;
+        let (mut streams, indices) = self.mesh(mesh)?;
+        for joints in &mut streams.joints {
+            for joint in joints.iter_mut() {
+                *joint = *remap.get(*joint as usize)
+                    .ok_or_else(|| invalid_data("glTF", "vertex joint out of range"))? as u16;
+            }
+        }
+
+        let mut clips = Vec::new();
+        for (idx, animation) in self.document.get("animations").members().iter().enumerate() {
+            let name = animation.get("name").as_str()
+                .map(|n| n.to_owned())
+                .unwrap_or_else(|| format!("animation {}", idx));
+            let channels = self.channels(animation, &skin_joints, &remap)?;
+            if !channels.is_empty() {
+                clips.push(AnimationClip::new(&name, channels));
+            }
+        }
+
+        Ok(SkinnedModel {
+            streams: streams,
+            indices: indices,
+            skeleton: skeleton,
+            clips: clips,
+        })
+    }
+
+    /// Skeleton of a skin, with the node of each skin joint and the new
+    /// index of each skin joint after sorting parents first.
+    fn skeleton(&self, skin: &Value) -> io::Result<(Skeleton, Vec<usize>, Vec<usize>)> {
+        let nodes = self.document.get("nodes").members();
+        let joints = skin.get("joints").members().iter()
+            .map(|j| bounded(j, nodes.len()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid_data("glTF", "bad skin joints"))?;
+
+        let mut node_parents = vec![None; nodes.len()];
+        for (idx, node) in nodes.iter().enumerate() {
+            for child in node.get("children").members() {
+                let child = bounded(child, nodes.len())
+                    .ok_or_else(|| invalid_data("glTF", "bad node child"))?;
+                node_parents[child] = Some(idx);
+            }
+        }
+
+        // Nearest ancestor that is a joint of the skin
+        let parents = joints.iter().map(|&node| {
+            let mut ancestor = node_parents[node];
+            while let Some(idx) = ancestor {
+                if let Some(joint) = joints.iter().position(|&j| j == idx) {
+                    return Some(joint);
+                }
+                ancestor = node_parents[idx];
+            }
+            None
+        }).collect::<Vec<_>>();
+
+        let inverse_binds = match skin.get("inverseBindMatrices").as_usize() {
+            Some(accessor) => {
+                let (values, components) = self.accessor(accessor)?;
+                if components != 16 || values.len() != joints.len() * 16 {
+                    return Err(invalid_data("glTF", "bad inverse bind matrices"));
+                }
+                values.chunks(16).map(|m| matrix(m)).collect()
+            },
+            None => vec![Matrix4::identity(); joints.len()],
+        };
+
+        // Parents first, keeping the order of the file where possible
+        let mut order = Vec::with_capacity(joints.len());
+        let mut remap = vec![usize::max_value(); joints.len()];
+        while order.len() < joints.len() {
+            let before = order.len();
+            for joint in 0..joints.len() {
+                let ready = match parents[joint] {
+                    Some(parent) => remap[parent] != usize::max_value(),
+                    None => true,
+                };
+                if remap[joint] == usize::max_value() && ready {
+                    remap[joint] = order.len();
+                    order.push(joint);
+                }
+            }
+            if order.len() == before {
+                return Err(invalid_data("glTF", "the joints form a cycle"));
+            }
+        }
+
+        let mut skeleton_joints = Vec::with_capacity(joints.len());
+        for &joint in &order {
+            let node = &nodes[joints[joint]];
+            if !node.get("matrix").is_null() {
+                return Err(invalid_data("glTF", "joints with matrices are not supported"));
+            }
+            skeleton_joints.push(Joint {
+                name: node.get("name").as_str()
+                    .map(|n| n.to_owned())
+                    .unwrap_or_else(|| format!("joint {}", joint)),
+                parent: parents[joint].map(|p| remap[p]),
+                rest: transform(node)?,
+                inverse_bind: inverse_binds[joint],
+            });
+        }
+
+        let skeleton = Skeleton::new(skeleton_joints)
+            .map_err(|_| invalid_data("glTF", "bad joint hierarchy"))?;
+        Ok((skeleton, joints, remap))
+    }
+
+    fn mesh(&self, mesh: &Value) -> io::Result<(VertexStreams, Vec<u32>)> {
+        let mut streams = VertexStreams::default();
+        let mut indices = Vec::new();
+
+        for primitive in mesh.get("primitives").members() {
+            // Only triangle lists
+            if primitive.get("mode").as_usize().unwrap_or(4) != 4 {
+                continue;
+            }
+            let attributes = primitive.get("attributes");
+            let base = streams.positions.len();
+
+            let positions = self.attribute(attributes, "POSITION", 3)?
+                .ok_or_else(|| invalid_data("glTF", "primitive without positions"))?;
+            let count = positions.len() / 3;
+            streams.positions.extend(positions.chunks(3).map(|p| [p[0] as f32, p[1] as f32, p[2] as f32]));
+
+            if let Some(normals) = self.attribute(attributes, "NORMAL", 3)? {
+                streams.normals.extend(normals.chunks(3).map(|n| [n[0] as f32, n[1] as f32, n[2] as f32]));
+            }
+            if let Some(uvs) = self.attribute(attributes, "TEXCOORD_0", 2)? {
+                streams.uv0.extend(uvs.chunks(2).map(|t| [t[0] as f32, t[1] as f32]));
+            }
+            let joints = self.attribute(attributes, "JOINTS_0", 4)?
+                .ok_or_else(|| invalid_data("glTF", "primitive without joints"))?;
+            streams.joints.extend(joints.chunks(4).map(|j| [j[0] as u16, j[1] as u16, j[2] as u16, j[3] as u16]));
+            let weights = self.attribute(attributes, "WEIGHTS_0", 4)?
+                .ok_or_else(|| invalid_data("glTF", "primitive without weights"))?;
+            streams.weights.extend(weights.chunks(4).map(|w| {
+                // Exporters do not always normalize the weights
+                let sum = (w[0] + w[1] + w[2] + w[3]).max(0.0001);
+                [(w[0] / sum) as f32, (w[1] / sum) as f32, (w[2] / sum) as f32, (w[3] / sum) as f32]
+            }));
+
+            if streams.joints.len() != streams.positions.len() ||
+                streams.weights.len() != streams.positions.len() {
+                return Err(invalid_data("glTF", "vertex attributes have different counts"));
+            }
+
+            match primitive.get("indices").as_usize() {
+                Some(accessor) => {
+                    let (values, _) = self.accessor(accessor)?;
+                    for index in values {
+                        if index as usize >= count {
+                            return Err(invalid_data("glTF", "index out of range"));
+                        }
+                        indices.push((base + index as usize) as u32);
+                    }
+                },
+                None => indices.extend((base..base + count).map(|i| i as u32)),
+            }
+        }
+
+        if streams.is_empty() {
+            return Err(invalid_data("glTF", "the skinned mesh has no triangles"));
+        }
+        // Streams some primitives lack cannot be matched to the vertices
+        if streams.normals.len() != streams.positions.len() {
+            streams.normals.clear();
+        }
+        if streams.uv0.len() != streams.positions.len() {
+            streams.uv0.clear();
+        }
+
+        Ok((streams, indices))
+    }
+
+    fn channels(&self,
+                animation: &Value,
+                skin_joints: &[usize],
+                remap: &[usize]) -> io::Result<Vec<Channel>> {
+        let samplers = animation.get("samplers").members();
+        let mut channels = Vec::new();
+
+        for channel in animation.get("channels").members() {
+            let target = channel.get("target");
+            let joint = match target.get("node").as_usize()
+                .and_then(|node| skin_joints.iter().position(|&j| j == node)) {
+                Some(joint) => remap[joint],
+                None => continue,
+            };
+            let sampler = channel.get("sampler").as_usize()
+                .and_then(|s| samplers.get(s))
+                .ok_or_else(|| invalid_data("glTF", "bad animation sampler"))?;
+
+            let interpolation = match sampler.get("interpolation").as_str().unwrap_or("LINEAR") {
+                "STEP" => Interpolation::Step,
+                "LINEAR" => Interpolation::Linear,
+                "CUBICSPLINE" => Interpolation::CubicSpline,
+                _ => return Err(invalid_data("glTF", "unknown interpolation")),
+            };
+
+            let input = sampler.get("input").as_usize()
+                .ok_or_else(|| invalid_data("glTF", "animation sampler without input"))?;
+            let output = sampler.get("output").as_usize()
+                .ok_or_else(|| invalid_data("glTF", "animation sampler without output"))?;
+            let (times, _) = self.accessor(input)?;
+            let (values, components) = self.accessor(output)?;
+
+            let keyframes = match (target.get("path").as_str(), components) {
+                (Some("translation"), 3) => Keyframes::Translation(
+                    values.chunks(3).map(|v| Vector3::new(v[0] as f32, v[1] as f32, v[2] as f32)).collect()),
+                (Some("scale"), 3) => Keyframes::Scale(
+                    values.chunks(3).map(|v| Vector3::new(v[0] as f32, v[1] as f32, v[2] as f32)).collect()),
+                (Some("rotation"), 4) => Keyframes::Rotation(
+                    values.chunks(4).map(|v| Quaternion::new(v[3] as f32, v[0] as f32, v[1] as f32, v[2] as f32)).collect()),
+                // Morph target weights
+                (Some("weights"), _) => continue,
+                _ => return Err(invalid_data("glTF", "bad animation channel")),
+            };
+
+            channels.push(Channel {
+                joint: joint,
+                times: times.iter().map(|&t| t as f32).collect(),
+                keyframes: keyframes,
+                interpolation: interpolation,
+            });
+        }
+
+        Ok(channels)
+    }
+
+    /// Values of a vertex attribute with the given number of components.
+    fn attribute(&self, attributes: &Value, name: &str, components: usize) -> io::Result<Option<Vec<f64>>> {
+        let accessor = match attributes.get(name).as_usize() {
+            Some(accessor) => accessor,
+            None => return Ok(None),
+        };
+        let (values, found) = self.accessor(accessor)?;
+        if found != components {
+            return Err(invalid_data("glTF", &format!("{} has {} components, not {}",
+                                                     name, found, components)));
+        }
+        Ok(Some(values))
+    }
+
+    /// Values of an accessor with the number of components of each element.
+    /// Normalized integers are mapped to 0 to 1, or -1 to 1.
+    fn accessor(&self, index: usize) -> io::Result<(Vec<f64>, usize)> {
+        let accessor = self.document.get("accessors").members().get(index)
+            .ok_or_else(|| invalid_data("glTF", "accessor out of range"))?;
+        if !accessor.get("sparse").is_null() {
+            return Err(invalid_data("glTF", "sparse accessors are not supported"));
+        }
+
+        let components = match accessor.get("type").as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(invalid_data("glTF", "bad accessor type")),
+        };
+        let count = accessor.get("count").as_usize()
+            .ok_or_else(|| invalid_data("glTF", "accessor without count"))?;
+        let component_type = accessor.get("componentType").as_usize().unwrap_or(0);
+        let (size, max) = match component_type {
+            5120 => (1, 127.0),
+            5121 => (1, 255.0),
+            5122 => (2, 32767.0),
+            5123 => (2, 65535.0),
+            5125 => (4, 4294967295.0),
+            5126 => (4, 1.0),
+            _ => return Err(invalid_data("glTF", "bad accessor component type")),
+        };
+        let normalized = accessor.get("normalized") == &Value::Bool(true);
+
+        let view = match accessor.get("bufferView").as_usize() {
+            Some(view) => self.document.get("bufferViews").members().get(view)
+                .ok_or_else(|| invalid_data("glTF", "buffer view out of range"))?,
+            // Accessors without a view are all zeros
+            None => return Ok((vec![0.0; count * components], components)),
+        };
+        let buffer = view.get("buffer").as_usize()
+            .and_then(|b| self.buffers.get(b))
+            .ok_or_else(|| invalid_data("glTF", "buffer out of range"))?;
+        let offset = view.get("byteOffset").as_usize().unwrap_or(0) +
+            accessor.get("byteOffset").as_usize().unwrap_or(0);
+        let stride = view.get("byteStride").as_usize().unwrap_or(size * components);
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let start = offset + element * stride + component * size;
+                let bytes = buffer.get(start..start + size)
+                    .ok_or_else(|| invalid_data("glTF", "accessor past the end of its buffer"))?;
+                let value = match component_type {
+                    5120 => bytes[0] as i8 as f64,
+                    5121 => bytes[0] as f64,
+                    5122 => read_u16(bytes) as i16 as f64,
+                    5123 => read_u16(bytes) as f64,
+                    5125 => read_u32(bytes) as f64,
+                    _ => f32::from_bits(read_u32(bytes)) as f64,
+                };
+                values.push(if normalized { (value / max).max(-1.0) } else { value });
+            }
+        }
+
+        Ok((values, components))
+    }
+
+    fn index(&self, list: &str, index: &Value) -> io::Result<&'a Value> {
+        let document: &'a Value = self.document;
+        index.as_usize()
+            .and_then(|i| document.get(list).members().get(i))
+            .ok_or_else(|| invalid_data("glTF", &format!("bad index into {}", list)))
+    }
+}
+
+/// Index below `len`.
+fn bounded(index: &Value, len: usize) -> Option<usize> {
+    index.as_usize().and_then(|i| if i < len { Some(i) } else { None })
+}
+
+fn split_glb(data: &[u8]) -> io::Result<(Value, Option<Vec<u8>>)> {
+    if data.len() < 12 || read_u32(&data[4..8]) != 2 {
+        return Err(invalid_data("glTF", "only glTF 2.0 binaries are supported"));
+    }
+
+    let mut document = None;
+    let mut binary = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let length = read_u32(&data[pos..pos + 4]) as usize;
+        let kind = read_u32(&data[pos + 4..pos + 8]);
+        let chunk = data.get(pos + 8..pos + 8 + length)
+            .ok_or_else(|| invalid_data("glTF", "truncated chunk"))?;
+        match kind {
+            GLB_JSON => {
+                let text = ::std::str::from_utf8(chunk)
+                    .map_err(|_| invalid_data("glTF", "the JSON chunk is not UTF-8"))?;
+                document = Some(json::parse(text)?);
+            },
+            GLB_BIN if binary.is_none() => binary = Some(chunk.to_vec()),
+            _ => (),
+        }
+        pos += 8 + length;
+    }
+
+    let document = document.ok_or_else(|| invalid_data("glTF", "missing JSON chunk"))?;
+    Ok((document, binary))
+}
+
+fn transform(node: &Value) -> io::Result<Transform> {
+    let mut transform = Transform::identity();
+    if let Some(t) = node.get("translation").as_floats() {
+        if t.len() != 3 {
+            return Err(invalid_data("glTF", "bad node translation"));
+        }
+        transform.translation = Vector3::new(t[0], t[1], t[2]);
+    }
+    if let Some(r) = node.get("rotation").as_floats() {
+        if r.len() != 4 {
+            return Err(invalid_data("glTF", "bad node rotation"));
+        }
+        transform.rotation = Quaternion::new(r[3], r[0], r[1], r[2]);
+    }
+    if let Some(s) = node.get("scale").as_floats() {
+        if s.len() != 3 {
+            return Err(invalid_data("glTF", "bad node scale"));
+        }
+        transform.scale = Vector3::new(s[0], s[1], s[2]);
+    }
+    Ok(transform)
+}
+
+/// Column major matrix.
+fn matrix(m: &[f64]) -> Matrix4<f32> {
+    Matrix4::new(m[0] as f32, m[1] as f32, m[2] as f32, m[3] as f32,
+                 m[4] as f32, m[5] as f32, m[6] as f32, m[7] as f32,
+                 m[8] as f32, m[9] as f32, m[10] as f32, m[11] as f32,
+                 m[12] as f32, m[13] as f32, m[14] as f32, m[15] as f32)
+}
+
+fn decode_base64(text: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for c in text.bytes().take_while(|&c| c != b'=') {
+        let value = match c {
+            b'A'...b'Z' => c - b'A',
+            b'a'...b'z' => c - b'a' + 26,
+            b'0'...b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(invalid_data("glTF", "bad base64 data")),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gltf(document: &Value, buffer: Vec<u8>) -> Gltf {
+        Gltf {
+            document: document,
+            buffers: vec![buffer],
+        }
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGk").unwrap(), b"hi");
+        assert_eq!(decode_base64("AAEC/w==").unwrap(), [0, 1, 2, 255]);
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert!(decode_base64("aG k=").is_err());
+    }
+
+    #[test]
+    fn reads_interleaved_accessors() {
+        // Two VEC2 of u16 after a 2 byte header, 6 bytes apart
+        let buffer = vec![0xff, 0xff,
+                          1, 0, 2, 0, 0xff, 0xff,
+                          3, 0, 4, 1];
+        let document = json::parse(r#"{
+            "bufferViews": [{"buffer": 0, "byteOffset": 2, "byteStride": 6}],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5123, "count": 2, "type": "VEC2"},
+                {"bufferView": 0, "byteOffset": 2, "componentType": 5121, "count": 2,
+                 "type": "SCALAR"}
+            ]
+        }"#).unwrap();
+        let gltf = gltf(&document, buffer);
+
+        assert_eq!(gltf.accessor(0).unwrap(), (vec![1.0, 2.0, 3.0, 260.0], 2));
+        assert_eq!(gltf.accessor(1).unwrap(), (vec![2.0, 4.0], 1));
+    }
+
+    #[test]
+    fn normalizes_integer_accessors() {
+        let buffer = vec![0, 255, 0x80, 0x7f, 0, 0x80];
+        let document = json::parse(r#"{
+            "bufferViews": [{"buffer": 0}],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5121, "count": 2, "type": "SCALAR",
+                 "normalized": true},
+                {"bufferView": 0, "byteOffset": 2, "componentType": 5120, "count": 2,
+                 "type": "SCALAR", "normalized": true},
+                {"bufferView": 0, "byteOffset": 4, "componentType": 5122, "count": 1,
+                 "type": "SCALAR", "normalized": true}
+            ]
+        }"#).unwrap();
+        let gltf = gltf(&document, buffer);
+
+        assert_eq!(gltf.accessor(0).unwrap().0, vec![0.0, 1.0]);
+        // -128 is clamped to -1
+        assert_eq!(gltf.accessor(1).unwrap().0, vec![-1.0, 1.0]);
+        assert_eq!(gltf.accessor(2).unwrap().0, vec![-1.0]);
+    }
+
+    #[test]
+    fn rejects_accessors_past_their_buffer() {
+        let document = json::parse(r#"{
+            "bufferViews": [{"buffer": 0, "byteStride": 8}],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR"}]
+        }"#).unwrap();
+        assert!(gltf(&document, vec![0; 8]).accessor(0).is_err());
+        assert!(gltf(&document, vec![0; 12]).accessor(0).is_ok());
+    }
+
+    #[test]
+    fn sorts_joints_parents_first() {
+        // Node 2 is the root, node 0 its child and node 1 a grandchild, but
+        // the skin lists them child first
+        let document = json::parse(r#"{
+            "nodes": [
+                {"name": "arm", "children": [1]},
+                {"name": "hand"},
+                {"name": "root", "children": [0]}
+            ],
+            "skins": [{"joints": [1, 0, 2]}]
+        }"#).unwrap();
+        let gltf = gltf(&document, Vec::new());
+        let skin = &document.get("skins").members()[0];
+
+        let (skeleton, joints, remap) = gltf.skeleton(skin).unwrap();
+        let names = skeleton.joints().iter().map(|j| j.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["root", "arm", "hand"]);
+        let parents = skeleton.joints().iter().map(|j| j.parent).collect::<Vec<_>>();
+        assert_eq!(parents, [None, Some(0), Some(1)]);
+        assert_eq!(joints, [1, 0, 2]);
+        assert_eq!(remap, [2, 1, 0]);
+    }
+
+    #[test]
+    fn rejects_joint_cycles() {
+        let document = json::parse(r#"{
+            "nodes": [{"children": [1]}, {"children": [0]}],
+            "skins": [{"joints": [0, 1]}]
+        }"#).unwrap();
+        let gltf = gltf(&document, Vec::new());
+        assert!(gltf.skeleton(&document.get("skins").members()[0]).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::char;
+use std::io;
+
+use super::super::framework::parse::invalid_data;
+
+/// JSON value, just enough to read glTF documents.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in document order.
+    Object(Vec<(String, Value)>),
+}
+
+static NULL: Value = Value::Null;
+
+impl Value {
+    /// Member `key` of an object, `Null` if there is none.
+    pub fn get(&self, key: &str) -> &Value {
+        match *self {
+            Value::Object(ref members) => members.iter()
+                .find(|&&(ref k, _)| k == key)
+                .map(|&(_, ref v)| v)
+                .unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64().and_then(|n| if n >= 0.0 && n.fract() == 0.0 { Some(n as usize) } else { None })
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Elements of an array, none for anything else.
+    pub fn members(&self) -> &[Value] {
+        match *self {
+            Value::Array(ref values) => &values[..],
+            _ => &[],
+        }
+    }
+
+    /// Numbers of an array, or `None` if it is not an array of numbers.
+    pub fn as_floats(&self) -> Option<Vec<f32>> {
+        match *self {
+            Value::Array(ref values) =>
+                values.iter().map(|v| v.as_f64().map(|n| n as f32)).collect(),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(text: &str) -> io::Result<Value> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self) -> io::Result<Value> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self) -> io::Result<Value> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+
+        loop {
+            self.whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+
+            self.whitespace();
+            match self.next() {
+                Some(b',') => continue,
+                Some(b'}') => return Ok(Value::Object(members)),
+                _ => return Err(self.error("expected , or }")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> io::Result<Value> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+            match self.next() {
+                Some(b',') => continue,
+                Some(b']') => return Ok(Value::Array(values)),
+                _ => return Err(self.error("expected , or ]")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.next() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.escaped_char()?,
+                        _ => return Err(self.error("bad escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                Some(byte) => bytes.push(byte),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    /// Character of a `\u` escape, which may be followed by a second one
+    /// for characters outside the basic plane.
+    fn escaped_char(&mut self) -> io::Result<char> {
+        let high = self.hex4()?;
+        if high < 0xd800 || high > 0xdbff {
+            return char::from_u32(high).ok_or_else(|| self.error("bad escape"));
+        }
+
+        if self.next() != Some(b'\\') || self.next() != Some(b'u') {
+            return Err(self.error("unpaired surrogate"));
+        }
+        let low = self.hex4()?;
+        if low < 0xdc00 || low > 0xdfff {
+            return Err(self.error("unpaired surrogate"));
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+            .ok_or_else(|| self.error("bad escape"))
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self.next()
+                .and_then(|b| (b as char).to_digit(16))
+                .ok_or_else(|| self.error("bad escape"))?;
+            value = value * 16 + digit;
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> io::Result<Value> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            match b {
+                b'-' | b'+' | b'.' | b'e' | b'E' | b'0'...b'9' => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        // The characters are ASCII, so this cannot fail
+        let text = ::std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        text.parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| self.error("bad number"))
+    }
+
+    fn literal(&mut self, text: &str, value: Value) -> io::Result<Value> {
+        if self.bytes[self.pos..].starts_with(text.as_bytes()) {
+            self.pos += text.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> io::Result<()> {
+        if self.next() == Some(byte) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {}", byte as char)))
+        }
+    }
+
+    fn whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') => self.pos += 1,
+                _ => return,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek();
+        if byte.is_some() {
+            self.pos += 1;
+        }
+        byte
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        invalid_data(&format!("JSON at byte {}", self.pos), message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_values_in_order() {
+        let value = parse(" {\"b\": [1, -2.5e1, true, null], \"a\": {\"c\": \"d\"}} ").unwrap();
+        match value {
+            Value::Object(ref members) => {
+                assert_eq!(members[0].0, "b");
+                assert_eq!(members[1].0, "a");
+            },
+            _ => panic!("not an object"),
+        }
+        assert_eq!(value.get("b").members(), &[Value::Number(1.0),
+                                               Value::Number(-25.0),
+                                               Value::Bool(true),
+                                               Value::Null]);
+        assert_eq!(value.get("a").get("c").as_str(), Some("d"));
+        assert!(value.get("missing").is_null());
+        assert!(value.get("b").get("a").is_null());
+    }
+
+    #[test]
+    fn parses_empty_containers() {
+        assert_eq!(parse("{}").unwrap(), Value::Object(Vec::new()));
+        assert_eq!(parse("[ ]").unwrap(), Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        let value = parse(r#""a\"\\\/\b\f\n\r\t\u00e9\ud83d\ude00""#).unwrap();
+        assert_eq!(value.as_str(), Some("a\"\\/\u{8}\u{c}\n\r\t\u{e9}\u{1f600}"));
+    }
+
+    #[test]
+    fn rejects_bad_escapes() {
+        assert!(parse(r#""\x""#).is_err());
+        assert!(parse(r#""\u12""#).is_err());
+        assert!(parse(r#""\ud83d""#).is_err());
+        assert!(parse(r#""\ud83dA""#).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(parse("").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("[1 2]").is_err());
+        assert!(parse("{\"a\" 1}").is_err());
+        assert!(parse("{1: 2}").is_err());
+        assert!(parse("\"open").is_err());
+        assert!(parse("tru").is_err());
+        assert!(parse("1.2.3").is_err());
+        assert!(parse("{} {}").is_err());
+    }
+
+    #[test]
+    fn converts_numbers() {
+        assert_eq!(parse("3").unwrap().as_usize(), Some(3));
+        assert_eq!(parse("3.5").unwrap().as_usize(), None);
+        assert_eq!(parse("-1").unwrap().as_usize(), None);
+        assert_eq!(parse("[1, 2.5]").unwrap().as_floats(), Some(vec![1.0, 2.5]));
+        assert_eq!(parse("[1, \"2\"]").unwrap().as_floats(), None);
+        assert_eq!(parse("null").unwrap().as_floats(), None);
+    }
+}
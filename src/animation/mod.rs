@@ -0,0 +1,32 @@
+//! Skeletal and scene node animation.
+//!
+//! Skeletons and their clips are imported from glTF files. An `Animator`
+//! samples the clips of one skinned instance into a `Pose`, whose skinning
+//! matrices are submitted with the instance and applied by skinned
+//! materials on the GPU.
+//!
+//! Scene nodes and material parameters are animated by the tracks of a
+//! `Timeline`, played on the game loop clock by a `TimelinePlayer`.
+
+use cgmath::{InnerSpace, Quaternion};
+
+pub mod animator;
+pub mod clip;
+pub mod gltf;
+mod json;
+pub mod skeleton;
+pub mod timeline;
+
+pub use self::animator::Animator;
+pub use self::clip::{AnimationClip, Channel, Interpolation, Keyframes};
+pub use self::gltf::SkinnedModel;
+pub use self::skeleton::{Joint, Pose, Skeleton};
+pub use self::timeline::{Event, Property, Timeline, TimelinePlayer, Track, Wrap};
+
+/// Spherical interpolation along the shortest arc between two rotations.
+pub fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, amount: f32) -> Quaternion<f32> {
+    // q and -q are the same rotation, the one closer to `a` takes the
+    // shorter way
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, amount).normalize()
+}
@@ -0,0 +1,105 @@
+use cgmath::{InnerSpace, Matrix4};
+
+use super::super::scene::Transform;
+use super::slerp;
+
+/// Joint of a skeleton.
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint, which comes before this one.
+    pub parent: Option<usize>,
+    /// Local transform when no animation moves the joint.
+    pub rest: Transform,
+    /// Transforms the mesh from its bind pose into the space of the joint.
+    pub inverse_bind: Matrix4<f32>,
+}
+
+/// Joint hierarchy of a skinned mesh.
+///
+/// Like scene nodes, joints are ordered so parents come before their
+/// children, and the world matrices of a pose are computed in one pass.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Result<Skeleton, ()> {
+        for (idx, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent {
+                if parent >= idx {
+                    println!("Joint {} comes before its parent", joint.name);
+                    return Err(());
+                }
+            }
+        }
+
+        Ok(Skeleton {
+            joints: joints,
+        })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    /// Every joint at its rest transform.
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            local: self.joints.iter().map(|j| j.rest).collect(),
+        }
+    }
+}
+
+/// Local transforms of the joints of a skeleton, in the same order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub local: Vec<Transform>,
+}
+
+impl Pose {
+    /// Moves every joint `weight` of the way towards `other`, interpolating
+    /// rotations along the shortest arc.
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (joint, target) in self.local.iter_mut().zip(&other.local) {
+            joint.translation = joint.translation.lerp(target.translation, weight);
+            joint.rotation = slerp(joint.rotation, target.rotation, weight);
+            joint.scale = joint.scale.lerp(target.scale, weight);
+        }
+    }
+
+    /// Transforms of the joints relative to the root of the skeleton.
+    pub fn model_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        let mut matrices: Vec<Matrix4<f32>> = Vec::with_capacity(self.local.len());
+        for (joint, local) in skeleton.joints.iter().zip(&self.local) {
+            let matrix = match joint.parent {
+                Some(parent) => matrices[parent] * local.matrix(),
+                None => local.matrix(),
+            };
+            matrices.push(matrix);
+        }
+        matrices
+    }
+
+    /// Matrices moving vertices from the bind pose to this pose, as read by
+    /// skinned materials.
+    pub fn skinning_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        self.model_matrices(skeleton).iter()
+            .zip(&skeleton.joints)
+            .map(|(model, joint)| *model * joint.inverse_bind)
+            .collect()
+    }
+}
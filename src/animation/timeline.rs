@@ -0,0 +1,259 @@
+use cgmath::{InnerSpace, Quaternion, Vector3, Vector4};
+
+use std::cmp::Ordering;
+
+use super::super::renderer::{MaterialId, Renderer};
+use super::super::scene::{NodeId, Scene};
+use super::clip::Interpolation;
+use super::slerp;
+
+/// Property animated by a track, with its keys.
+///
+/// With `Interpolation::CubicSpline` each key is stored as its in-tangent,
+/// its value and its out-tangent, like the keys of skeletal clips.
+#[derive(Clone, Debug)]
+pub enum Property {
+    Translation(NodeId, Vec<Vector3<f32>>),
+    Rotation(NodeId, Vec<Quaternion<f32>>),
+    Scale(NodeId, Vec<Vector3<f32>>),
+    /// Tint of the renderable of the node.
+    Tint(NodeId, Vec<Vector4<f32>>),
+    Specular(MaterialId, Vec<f32>),
+    Shininess(MaterialId, Vec<f32>),
+    Emissive(MaterialId, Vec<f32>),
+}
+
+/// Animation of one property of a scene node or material.
+#[derive(Clone, Debug)]
+pub struct Track {
+    /// Key times in seconds, increasing.
+    pub times: Vec<f32>,
+    pub property: Property,
+    pub interpolation: Interpolation,
+}
+
+/// Named point in time of a timeline, reported when playback passes it.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub time: f32,
+    pub name: String,
+}
+
+/// Tracks animating scene nodes and materials together, e.g. a door
+/// swinging open.
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    pub name: String,
+    /// Time of the last key of any track.
+    pub duration: f32,
+    pub tracks: Vec<Track>,
+    pub events: Vec<Event>,
+}
+
+impl Timeline {
+    pub fn new(name: &str, tracks: Vec<Track>) -> Timeline {
+        let duration = tracks.iter()
+            .filter_map(|t| t.times.last().cloned())
+            .fold(0.0, f32::max);
+
+        Timeline {
+            name: name.to_owned(),
+            duration: duration,
+            tracks: tracks,
+            events: Vec::new(),
+        }
+    }
+
+    /// Reports `name` whenever playback passes `time`, usually that of a
+    /// key.
+    pub fn add_event(&mut self, time: f32, name: &str) {
+        self.events.push(Event {
+            time: time,
+            name: name.to_owned(),
+        });
+    }
+
+    /// Writes the animated properties at `time` into the nodes of `scene`
+    /// and the materials of `renderer`. Times outside the timeline hold its
+    /// first or last keys. Material tracks are skipped without a renderer,
+    /// e.g. in headless replays.
+    pub fn sample(&self, time: f32, scene: &mut Scene, mut renderer: Option<&mut Renderer>) {
+        for track in &self.tracks {
+            let times = &track.times;
+            let interpolation = track.interpolation;
+            match track.property {
+                Property::Translation(node, ref values) => {
+                    if let Some(value) = interpolation.sample(times, values, time, Vector3::lerp) {
+                        scene.node_mut(node).transform.translation = value;
+                    }
+                },
+                Property::Rotation(node, ref values) => {
+                    if let Some(value) = interpolation.sample(times, values, time, slerp) {
+                        scene.node_mut(node).transform.rotation = value.normalize();
+                    }
+                },
+                Property::Scale(node, ref values) => {
+                    if let Some(value) = interpolation.sample(times, values, time, Vector3::lerp) {
+                        scene.node_mut(node).transform.scale = value;
+                    }
+                },
+                Property::Tint(node, ref values) => {
+                    if let Some(value) = interpolation.sample(times, values, time, Vector4::lerp) {
+                        if let Some(ref mut renderable) = scene.node_mut(node).renderable {
+                            renderable.tint = value.into();
+                        }
+                    }
+                },
+                Property::Specular(material, ref values) => {
+                    if let Some(ref mut renderer) = renderer {
+                        if let Some(value) = interpolation.sample(times, values, time, lerp) {
+                            renderer.material_params_mut(material).specular = value;
+                        }
+                    }
+                },
+                Property::Shininess(material, ref values) => {
+                    if let Some(ref mut renderer) = renderer {
+                        if let Some(value) = interpolation.sample(times, values, time, lerp) {
+                            renderer.material_params_mut(material).shininess = value;
+                        }
+                    }
+                },
+                Property::Emissive(material, ref values) => {
+                    if let Some(ref mut renderer) = renderer {
+                        if let Some(value) = interpolation.sample(times, values, time, lerp) {
+                            renderer.material_params_mut(material).emissive = value;
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// What playback does at the end of a timeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    /// Holds the last keys.
+    Once,
+    /// Starts over.
+    Loop,
+    /// Plays backwards to the start, then forwards again.
+    PingPong,
+}
+
+/// Plays a timeline on the game loop clock.
+///
+/// `update` advances it by fixed steps, and `apply` poses the scene in
+/// between them, so the animation is the same at any frame rate.
+#[derive(Clone, Debug)]
+pub struct TimelinePlayer {
+    timeline: Timeline,
+    wrap: Wrap,
+    /// Seconds into the current loop, or pair of passes when ping-ponging.
+    elapsed: f32,
+    /// Scales the time of `update`, must not be negative.
+    pub speed: f32,
+}
+
+impl TimelinePlayer {
+    pub fn new(timeline: Timeline, wrap: Wrap) -> TimelinePlayer {
+        TimelinePlayer {
+            timeline: timeline,
+            wrap: wrap,
+            elapsed: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Seconds into the timeline, decreasing while ping-ponging back.
+    pub fn time(&self) -> f32 {
+        self.local_time(self.elapsed)
+    }
+
+    /// Whether a timeline played once reached its end.
+    pub fn is_finished(&self) -> bool {
+        self.wrap == Wrap::Once && self.elapsed >= self.timeline.duration
+    }
+
+    /// Advances playback by `dt` seconds, appending the names of the events
+    /// passed to `fired` in the order they happened. Events at the very
+    /// start are only reported when looping back to it.
+    pub fn update(&mut self, dt: f32, fired: &mut Vec<String>) {
+        let duration = self.timeline.duration;
+        let start = self.elapsed;
+        let end = start + (dt * self.speed).max(0.0);
+
+        let mut passed = Vec::new();
+        if self.wrap == Wrap::Once || duration <= 0.0 {
+            let end = end.min(duration);
+            for event in &self.timeline.events {
+                if start < event.time && event.time <= end {
+                    passed.push((event.time, &event.name));
+                }
+            }
+            self.elapsed = end;
+        } else {
+            let period = self.period();
+            let mut cycle = 0.0;
+            while cycle <= end {
+                for event in &self.timeline.events {
+                    let forward = cycle + event.time;
+                    if start < forward && forward <= end {
+                        passed.push((forward, &event.name));
+                    }
+                    // The way back passes the event again, unless it is
+                    // at either end
+                    let back = cycle + period - event.time;
+                    if self.wrap == Wrap::PingPong &&
+                        event.time > 0.0 && event.time < duration &&
+                        start < back && back <= end {
+                        passed.push((back, &event.name));
+                    }
+                }
+                cycle += period;
+            }
+            self.elapsed = end % period;
+        }
+
+        passed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        fired.extend(passed.into_iter().map(|(_, name)| name.clone()));
+    }
+
+    /// Poses `scene` and the materials of `renderer` at `ahead` seconds
+    /// past the last `update`, e.g. the fraction of a step the frame is
+    /// drawn at.
+    pub fn apply(&self, scene: &mut Scene, renderer: Option<&mut Renderer>, ahead: f32) {
+        let elapsed = self.elapsed + ahead * self.speed;
+        let elapsed = if self.wrap == Wrap::Once || self.timeline.duration <= 0.0 {
+            elapsed.min(self.timeline.duration)
+        } else {
+            elapsed % self.period()
+        };
+        self.timeline.sample(self.local_time(elapsed), scene, renderer);
+    }
+
+    /// Length of one cycle of playback.
+    fn period(&self) -> f32 {
+        match self.wrap {
+            Wrap::PingPong => 2.0 * self.timeline.duration,
+            _ => self.timeline.duration,
+        }
+    }
+
+    fn local_time(&self, elapsed: f32) -> f32 {
+        let duration = self.timeline.duration;
+        if self.wrap == Wrap::PingPong && elapsed > duration {
+            2.0 * duration - elapsed
+        } else {
+            elapsed.min(duration)
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, amount: f32) -> f32 {
+    a + (b - a) * amount
+}
@@ -0,0 +1,111 @@
+//! Sixteen-segment font for debug labels, drawn with lines so labels need
+//! no textures.
+//!
+//! Glyphs are 1 unit wide and 2 units tall, with the origin at the lower
+//! left. Their segments are named by letters:
+//!
+//! ```text
+//!  a b
+//! hklmc
+//!  i j
+//! gnopd
+//!  f e
+//! ```
+//!
+//! `a`, `b`, `e`, `f`, `i` and `j` are half-width horizontal lines,
+//! `c`, `d`, `g`, `h`, `l` and `o` half-height vertical lines and `k`, `m`,
+//! `n` and `p` diagonals from the corners to the center.
+
+/// Horizontal distance between the origins of two glyphs.
+pub const ADVANCE: f32 = 1.5;
+
+/// Height of a glyph.
+pub const HEIGHT: f32 = 2.0;
+
+/// Ends of the segments `a` to `p`.
+const SEGMENTS: [([f32; 2], [f32; 2]); 16] = [
+    ([0.0, 2.0], [0.5, 2.0]),
+    ([0.5, 2.0], [1.0, 2.0]),
+    ([1.0, 2.0], [1.0, 1.0]),
+    ([1.0, 1.0], [1.0, 0.0]),
+    ([1.0, 0.0], [0.5, 0.0]),
+    ([0.5, 0.0], [0.0, 0.0]),
+    ([0.0, 0.0], [0.0, 1.0]),
+    ([0.0, 1.0], [0.0, 2.0]),
+    ([0.0, 1.0], [0.5, 1.0]),
+    ([0.5, 1.0], [1.0, 1.0]),
+    ([0.0, 2.0], [0.5, 1.0]),
+    ([0.5, 2.0], [0.5, 1.0]),
+    ([1.0, 2.0], [0.5, 1.0]),
+    ([0.0, 0.0], [0.5, 1.0]),
+    ([0.5, 0.0], [0.5, 1.0]),
+    ([1.0, 0.0], [0.5, 1.0]),
+];
+
+/// Segments lit for `c`. Lowercase letters are drawn as uppercase and
+/// characters without a glyph are left blank.
+fn glyph(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        '0' => "abcdefghmn",
+        '1' => "cdm",
+        '2' => "abcefgij",
+        '3' => "abcdefj",
+        '4' => "cdhij",
+        '5' => "abdefhij",
+        '6' => "abdefghij",
+        '7' => "abcd",
+        '8' => "abcdefghij",
+        '9' => "abcdefhij",
+        'A' => "abcdghij",
+        'B' => "abcdefjlo",
+        'C' => "abefgh",
+        'D' => "abcdeflo",
+        'E' => "abefghi",
+        'F' => "abghi",
+        'G' => "abdefghj",
+        'H' => "cdghij",
+        'I' => "abeflo",
+        'J' => "cdefg",
+        'K' => "ghimp",
+        'L' => "efgh",
+        'M' => "cdghkm",
+        'N' => "cdghkp",
+        'O' => "abcdefgh",
+        'P' => "abcghij",
+        'Q' => "abcdefghp",
+        'R' => "abcghijp",
+        'S' => "abdefhij",
+        'T' => "ablo",
+        'U' => "cdefgh",
+        'V' => "ghmn",
+        'W' => "cdghnp",
+        'X' => "kmnp",
+        'Y' => "kmo",
+        'Z' => "abefmn",
+        '-' => "ij",
+        '+' => "ijlo",
+        '*' => "ijklmnop",
+        '=' => "efij",
+        '_' => "ef",
+        '.' | ',' => "o",
+        ':' => "lo",
+        '\'' => "l",
+        '/' => "mn",
+        '\\' => "kp",
+        '(' | '<' => "mp",
+        ')' | '>' => "kn",
+        '[' => "abefgh",
+        ']' => "abcdef",
+        '%' => "ahjdmn",
+        '?' => "abcjo",
+        '!' => "l",
+        _ => "",
+    }
+}
+
+/// Segments of `c` as line ends in glyph units.
+pub fn strokes(c: char) -> Vec<([f32; 2], [f32; 2])> {
+    glyph(c).bytes()
+        .map(|segment| SEGMENTS[(segment - b'a') as usize])
+        .collect()
+}
@@ -0,0 +1,192 @@
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::framebuffer as vkfb;
+use vulkano::pipeline as vkp;
+use vulkano::pipeline::depth_stencil as vkdst;
+
+use std::mem;
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+use super::super::framework::gfx::PipelineBuilderExt;
+use super::super::renderer::Camera;
+
+/// Pipeline drawing lines read from a storage buffer. Its concrete type is
+/// kept since vulkano only draws bufferless pipelines it can see the vertex
+/// definition of.
+type LinePipeline = vkp::GraphicsPipeline<vkp::vertex::BufferlessDefinition,
+                                          Box<PipelineLayoutAbstract + Send + Sync>,
+                                          Arc<vkfb::RenderPassAbstract + Send + Sync>>;
+
+/// Line end, laid out as the `Vertex` struct of the line shader.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LineVertex {
+    pub position: [f32; 4],
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ViewProj {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// Line lists of one frame, drawn in the transparent subpass.
+pub struct Lines {
+    gfx: Arc<gfx::Core>,
+    tested_pipeline: Arc<LinePipeline>,
+    on_top_pipeline: Arc<LinePipeline>,
+    vertex_pool: vkb::CpuBufferPool<LineVertex>,
+    /// Pairs of vertices hidden behind the scene.
+    pub tested: Vec<LineVertex>,
+    /// Pairs of vertices drawn over everything.
+    pub on_top: Vec<LineVertex>,
+}
+
+impl Lines {
+    pub fn new(gfx: Arc<gfx::Core>) -> Result<Lines, ()> {
+        let usage = vkb::BufferUsage {
+            storage_buffer: true,
+            .. vkb::BufferUsage::none()
+        };
+
+        // Lines lie on the surfaces they outline, so they pass at equal
+        // depth, and they do not hide each other
+        let tested = vkdst::DepthStencil {
+            depth_compare: vkdst::Compare::LessOrEqual,
+            depth_write: false,
+            .. vkdst::DepthStencil::simple_depth_test()
+        };
+
+        Ok(Lines {
+            tested_pipeline: Lines::pipeline(&gfx, tested)?,
+            on_top_pipeline: Lines::pipeline(&gfx, vkdst::DepthStencil::disabled())?,
+            vertex_pool: vkb::CpuBufferPool::new(gfx.device.clone(), usage),
+            tested: Vec::new(),
+            on_top: Vec::new(),
+            gfx: gfx,
+        })
+    }
+
+    /// Records the lines into a secondary command buffer for the transparent
+    /// subpass and clears them, or returns `None` if there are none.
+    pub fn render(&mut self,
+                  camera: &Camera,
+                  dynamic: &vkcb::DynamicState)
+                  -> Result<Option<vkcb::AutoCommandBuffer>, ()> {
+        if self.tested.is_empty() && self.on_top.is_empty() {
+            return Ok(None);
+        }
+
+        let subpass = self.gfx.scene_graph.subpass(self.gfx.transparent_pass);
+        let mut builder = vkcb::AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+            self.gfx.device.clone(),
+            self.gfx.queue.family(),
+            subpass
+        ).map_err(|e| println!("Failed to create debug draw command buffer ({:?})", e))?;
+
+        let view_proj = ViewProj {
+            view_proj: (camera.proj * camera.view).into(),
+        };
+        // Lines on top last, so they cover the depth tested ones
+        let lists = [(mem::replace(&mut self.tested, Vec::new()), self.tested_pipeline.clone()),
+                     (mem::replace(&mut self.on_top, Vec::new()), self.on_top_pipeline.clone())];
+        for &(ref vertices, ref pipeline) in &lists {
+            if vertices.is_empty() {
+                continue;
+            }
+
+            let buffer = self.vertex_pool.chunk(vertices.iter().cloned())
+                .map_err(|e| println!("Failed to allocate debug lines ({:?})", e))?;
+            let set = vkds::PersistentDescriptorSet::start(pipeline.clone(), 0)
+                .add_buffer(buffer)
+                .map_err(|e| println!("Failed to bind debug lines ({:?})", e))?
+                .build()
+                .map_err(|e| println!("Failed to create debug line set ({:?})", e))?;
+
+            builder = builder.draw(pipeline.clone(),
+                                   dynamic.clone(),
+                                   vkp::vertex::BufferlessVertices {
+                                       vertices: vertices.len(),
+                                       instances: 1,
+                                   },
+                                   set,
+                                   view_proj)
+                .map_err(|e| println!("Failed to draw debug lines ({:?})", e))?;
+        }
+
+        builder.build()
+            .map(Some)
+            .map_err(|e| println!("Failed to build debug draw command buffer ({:?})", e))
+    }
+
+    fn pipeline(core: &gfx::Core, depth: vkdst::DepthStencil) -> Result<Arc<LinePipeline>, ()> {
+        let vs = line_vs::Shader::load(core.device.clone())
+            .map_err(|e| println!("Failed to load debug line vertex shader ({:?})", e))?;
+        let fs = line_fs::Shader::load(core.device.clone())
+            .map_err(|e| println!("Failed to load debug line fragment shader ({:?})", e))?;
+
+        let pipeline = vkp::GraphicsPipeline::start()
+            .vertex_input(vkp::vertex::BufferlessDefinition)
+            .vertex_shader(vs.main_entry_point(), ())
+            .topology(gfx::Topology::LineList)
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .depth_stencil(depth)
+            .blend(gfx::BlendMode::Alpha)
+            .render_pass(core.scene_graph.subpass(core.transparent_pass))
+            .build(core.device.clone())
+            .map_err(|e| println!("Failed to create debug line pipeline ({:?})", e))?;
+
+        Ok(Arc::new(pipeline))
+    }
+}
+
+mod line_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) out vec4 v_color;
+
+struct Vertex {
+    vec4 position;
+    vec4 color;
+};
+
+layout(set = 0, binding = 0) readonly buffer Vertices {
+    Vertex vertices[];
+};
+
+layout(push_constant) uniform ViewProj {
+    mat4 view_proj;
+} camera;
+
+void main() {
+    Vertex v = vertices[gl_VertexIndex];
+    v_color = v.color;
+    gl_Position = camera.view_proj * vec4(v.position.xyz, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod line_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = v_color;
+}
+"]
+    struct Dummy;
+}
@@ -0,0 +1,267 @@
+//! Immediate mode debug drawing.
+//!
+//! Lines, shapes and labels are queued in world space while updating a
+//! frame, drawn over the scene by the next `render` and then forgotten.
+//! Without the `debug-draw` feature every call does nothing and no
+//! pipelines are created, so the calls can stay in release builds.
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+use vulkano::command_buffer as vkcb;
+
+#[cfg(feature = "debug-draw")]
+use std::mem;
+use std::sync::Arc;
+
+use super::framework::bounds::{Aabb, Sphere};
+use super::framework::gfx;
+use super::renderer::Camera;
+
+#[cfg(feature = "debug-draw")]
+mod font;
+#[cfg(feature = "debug-draw")]
+mod lines;
+
+#[cfg(feature = "debug-draw")]
+use self::lines::{LineVertex, Lines};
+
+/// Segments of circles and of the circles of spheres.
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Whether a primitive is hidden by the scene in front of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Depth {
+    Tested,
+    /// Drawn over everything, e.g. to see through walls.
+    OnTop,
+}
+
+#[cfg(feature = "debug-draw")]
+struct Label {
+    position: Point3<f32>,
+    text: String,
+    size: f32,
+    color: [f32; 4],
+    depth: Depth,
+}
+
+/// Debug primitives of the current frame.
+///
+/// Every shape is drawn with lines. Colors are blended by their alpha.
+pub struct DebugDraw {
+    #[cfg(feature = "debug-draw")]
+    lines: Lines,
+    #[cfg(feature = "debug-draw")]
+    labels: Vec<Label>,
+}
+
+impl DebugDraw {
+    #[cfg(feature = "debug-draw")]
+    pub fn new(gfx: Arc<gfx::Core>) -> Result<DebugDraw, ()> {
+        Ok(DebugDraw {
+            lines: Lines::new(gfx)?,
+            labels: Vec::new(),
+        })
+    }
+
+    #[cfg(not(feature = "debug-draw"))]
+    pub fn new(_gfx: Arc<gfx::Core>) -> Result<DebugDraw, ()> {
+        Ok(DebugDraw {})
+    }
+
+    #[cfg(feature = "debug-draw")]
+    pub fn line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4], depth: Depth) {
+        let list = match depth {
+            Depth::Tested => &mut self.lines.tested,
+            Depth::OnTop => &mut self.lines.on_top,
+        };
+        list.push(LineVertex {
+            position: [from.x, from.y, from.z, 1.0],
+            color: color,
+        });
+        list.push(LineVertex {
+            position: [to.x, to.y, to.z, 1.0],
+            color: color,
+        });
+    }
+
+    #[cfg(not(feature = "debug-draw"))]
+    pub fn line(&mut self, _from: Point3<f32>, _to: Point3<f32>, _color: [f32; 4], _depth: Depth) {
+    }
+
+    /// Line from `from` to `to` with an arrowhead at `to`.
+    pub fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4], depth: Depth) {
+        self.line(from, to, color, depth);
+
+        let direction = to - from;
+        let length = direction.magnitude();
+        if length <= 0.0 {
+            return;
+        }
+        let direction = direction / length;
+        // Any axis not parallel to the arrow gives two perpendicular ones
+        let other = if direction.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let side = direction.cross(other).normalize();
+        let up = direction.cross(side);
+
+        let head = length.min(1.0) * 0.2;
+        let back = to + direction * -head;
+        for &spoke in &[side, -side, up, -up] {
+            self.line(to, back + spoke * (head * 0.4), color, depth);
+        }
+    }
+
+    /// Edges of `aabb`.
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4], depth: Depth) {
+        let half = aabb.half_extents();
+        let m = Matrix4::from_translation(aabb.center().to_vec()) *
+            Matrix4::from_nonuniform_scale(half.x, half.y, half.z);
+        self.oriented_box(&m, color, depth);
+    }
+
+    /// Edges of the cube from -1 to 1 transformed by `m`.
+    pub fn oriented_box(&mut self, m: &Matrix4<f32>, color: [f32; 4], depth: Depth) {
+        // Bits 0 to 2 of a corner pick its side along x, y and z
+        let corner = |idx: usize| {
+            let side = |bit: usize| if idx & bit != 0 { 1.0 } else { -1.0 };
+            m.transform_point(Point3::new(side(1), side(2), side(4)))
+        };
+        for idx in 0..8 {
+            for &bit in &[1, 2, 4] {
+                if idx & bit == 0 {
+                    self.line(corner(idx), corner(idx | bit), color, depth);
+                }
+            }
+        }
+    }
+
+    /// Circle around `center` through `center + u` and `center + v`, which
+    /// must be perpendicular and of the same length.
+    pub fn circle(&mut self,
+                  center: Point3<f32>,
+                  u: Vector3<f32>,
+                  v: Vector3<f32>,
+                  color: [f32; 4],
+                  depth: Depth) {
+        let point = |idx: usize| {
+            let angle = idx as f32 * 2.0 * ::std::f32::consts::PI / CIRCLE_SEGMENTS as f32;
+            center + u * angle.cos() + v * angle.sin()
+        };
+        for idx in 0..CIRCLE_SEGMENTS {
+            self.line(point(idx), point(idx + 1), color, depth);
+        }
+    }
+
+    /// Circles of `sphere` around the x, y and z axes.
+    pub fn sphere(&mut self, sphere: &Sphere, color: [f32; 4], depth: Depth) {
+        let x = Vector3::unit_x() * sphere.radius;
+        let y = Vector3::unit_y() * sphere.radius;
+        let z = Vector3::unit_z() * sphere.radius;
+        self.circle(sphere.center, y, z, color, depth);
+        self.circle(sphere.center, z, x, color, depth);
+        self.circle(sphere.center, x, y, color, depth);
+    }
+
+    /// Square grid on the XZ plane around `center`, `cells` squares of
+    /// `spacing` across.
+    pub fn grid(&mut self,
+                center: Point3<f32>,
+                cells: u32,
+                spacing: f32,
+                color: [f32; 4],
+                depth: Depth) {
+        let half = cells as f32 * spacing * 0.5;
+        for idx in 0..cells + 1 {
+            let offset = idx as f32 * spacing - half;
+            self.line(center + Vector3::new(offset, 0.0, -half),
+                      center + Vector3::new(offset, 0.0, half),
+                      color, depth);
+            self.line(center + Vector3::new(-half, 0.0, offset),
+                      center + Vector3::new(half, 0.0, offset),
+                      color, depth);
+        }
+    }
+
+    /// The x, y and z axes of `m` in red, green and blue, `length` long
+    /// whatever the scale of `m`.
+    pub fn axes(&mut self, m: &Matrix4<f32>, length: f32, depth: Depth) {
+        let origin = m.transform_point(Point3::origin());
+        let axes = [(Vector3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+                    (Vector3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+                    (Vector3::unit_z(), [0.0, 0.0, 1.0, 1.0])];
+        for &(axis, color) in &axes {
+            let axis = m.transform_vector(axis).normalize() * length;
+            self.arrow(origin, origin + axis, color, depth);
+        }
+    }
+
+    /// Text facing the camera, centered on `position` with its letters
+    /// `size` tall. Only digits, letters, which are drawn in uppercase, and
+    /// common punctuation have glyphs.
+    #[cfg(feature = "debug-draw")]
+    pub fn label(&mut self,
+                 position: Point3<f32>,
+                 text: &str,
+                 size: f32,
+                 color: [f32; 4],
+                 depth: Depth) {
+        self.labels.push(Label {
+            position: position,
+            text: text.to_owned(),
+            size: size,
+            color: color,
+            depth: depth,
+        });
+    }
+
+    #[cfg(not(feature = "debug-draw"))]
+    pub fn label(&mut self,
+                 _position: Point3<f32>,
+                 _text: &str,
+                 _size: f32,
+                 _color: [f32; 4],
+                 _depth: Depth) {
+    }
+
+    /// Records the primitives queued since the last call into a secondary
+    /// command buffer for the transparent subpass, to be executed after
+    /// everything else. Returns `None` if there were none.
+    #[cfg(feature = "debug-draw")]
+    pub fn render(&mut self,
+                  camera: &Camera,
+                  dynamic: &vkcb::DynamicState)
+                  -> Result<Option<vkcb::AutoCommandBuffer>, ()> {
+        // The rows of the view rotation are the camera axes in world space.
+        // Projections flipping y put view space up at the bottom of the
+        // screen, so glyphs go the other way
+        let view = camera.view;
+        let flip = if camera.proj.y.y > 0.0 { -1.0 } else { 1.0 };
+        let right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        let up = Vector3::new(view.x.y, view.y.y, view.z.y) * flip;
+
+        for label in mem::replace(&mut self.labels, Vec::new()) {
+            let scale = label.size / font::HEIGHT;
+            let count = label.text.chars().count() as f32;
+            let width = (count * font::ADVANCE - (font::ADVANCE - 1.0)) * scale;
+            let origin = label.position + right * (-0.5 * width) + up * (-0.5 * label.size);
+
+            for (idx, c) in label.text.chars().enumerate() {
+                let x = idx as f32 * font::ADVANCE;
+                let point = |p: [f32; 2]| origin + right * ((x + p[0]) * scale) + up * (p[1] * scale);
+                for (from, to) in font::strokes(c) {
+                    self.line(point(from), point(to), label.color, label.depth);
+                }
+            }
+        }
+
+        self.lines.render(camera, dynamic)
+    }
+
+    #[cfg(not(feature = "debug-draw"))]
+    pub fn render(&mut self,
+                  _camera: &Camera,
+                  _dynamic: &vkcb::DynamicState)
+                  -> Result<Option<vkcb::AutoCommandBuffer>, ()> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,160 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3, Vector4};
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// Smallest box containing all `points`, or `None` if there are none.
+    pub fn from_points<I>(points: I) -> Option<Aabb>
+        where I: IntoIterator<Item = [f32; 3]>
+    {
+        let mut points = points.into_iter();
+        let first = Point3::from(points.next()?);
+
+        Some(points.fold(Aabb { min: first, max: first }, |aabb, p| {
+            Aabb {
+                min: Point3::new(aabb.min.x.min(p[0]), aabb.min.y.min(p[1]), aabb.min.z.min(p[2])),
+                max: Point3::new(aabb.max.x.max(p[0]), aabb.max.y.max(p[1]), aabb.max.z.max(p[2])),
+            }
+        }))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box containing this box after transforming it by `m`.
+    pub fn transform(&self, m: &Matrix4<f32>) -> Aabb {
+        let center = m.transform_point(self.center());
+        let half = self.half_extents();
+        let extent = Vector3::new(
+            m.x.x.abs() * half.x + m.y.x.abs() * half.y + m.z.x.abs() * half.z,
+            m.x.y.abs() * half.x + m.y.y.abs() * half.y + m.z.y.abs() * half.z,
+            m.x.z.abs() * half.x + m.y.z.abs() * half.y + m.z.z.abs() * half.z,
+        );
+
+        Aabb {
+            min: center + -extent,
+            max: center + extent,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    /// Sphere centered on the box of `points` that contains all of them.
+    pub fn from_points<I>(points: I) -> Option<Sphere>
+        where I: IntoIterator<Item = [f32; 3]> + Clone
+    {
+        let center = Aabb::from_points(points.clone())?.center();
+        let radius = points.into_iter()
+            .map(|p| (Point3::from(p) - center).magnitude2())
+            .fold(0.0f32, f32::max)
+            .sqrt();
+
+        Some(Sphere {
+            center: center,
+            radius: radius,
+        })
+    }
+
+    /// Sphere containing this sphere after transforming it by `m`.
+    pub fn transform(&self, m: &Matrix4<f32>) -> Sphere {
+        let scale = m.x.truncate().magnitude2()
+            .max(m.y.truncate().magnitude2())
+            .max(m.z.truncate().magnitude2())
+            .sqrt();
+
+        Sphere {
+            center: m.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Bounding volumes of a mesh, computed once when it is loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+}
+
+impl Bounds {
+    pub fn from_points(points: &[[f32; 3]]) -> Option<Bounds> {
+        Some(Bounds {
+            aabb: Aabb::from_points(points.iter().cloned())?,
+            sphere: Sphere::from_points(points.iter().cloned())?,
+        })
+    }
+
+    pub fn transform(&self, m: &Matrix4<f32>) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transform(m),
+            sphere: self.sphere.transform(m),
+        }
+    }
+}
+
+/// Planes of a view frustum, pointing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Frustum of a view-projection matrix that maps depth to `[0, 1]`.
+    pub fn from_matrix(m: &Matrix4<f32>) -> Frustum {
+        let row = |i: usize| Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let normalize = |p: Vector4<f32>| p / p.truncate().magnitude();
+
+        Frustum {
+            planes: [
+                normalize(w + x),
+                normalize(w - x),
+                normalize(w + y),
+                normalize(w - y),
+                normalize(z),
+                normalize(w - z),
+            ],
+        }
+    }
+
+    fn distance(plane: &Vector4<f32>, p: Point3<f32>) -> f32 {
+        plane.truncate().dot(p.to_vec()) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes.iter().all(|plane| Frustum::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let half = aabb.half_extents();
+
+        self.planes.iter().all(|plane| {
+            let reach = plane.x.abs() * half.x + plane.y.abs() * half.y + plane.z.abs() * half.z;
+            Frustum::distance(plane, center) >= -reach
+        })
+    }
+
+    /// Sphere test first, since it is cheaper and rejects most of what is
+    /// outside.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
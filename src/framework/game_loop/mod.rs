@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+/// Timing of one rendered frame.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTiming {
+    /// Wall time since the previous frame.
+    pub frame_time: Duration,
+    /// Number of simulation ticks to run this frame.
+    pub ticks: u32,
+    /// How far between the last two ticks the frame should be rendered,
+    /// in `[0, 1)`.
+    pub alpha: f32,
+    /// Whether time was thrown away because the simulation fell behind
+    /// more than the maximum number of catch-up steps.
+    pub fell_behind: bool,
+}
+
+/// Fixed timestep loop driver.
+///
+/// Frame times are accumulated and spent in ticks of a fixed duration, so the
+/// simulation advances identically regardless of the frame rate. The time
+/// source is supplied by the caller, which lets replays drive the loop with
+/// recorded timestamps. Frames are drawn `alpha` of a tick past the last
+/// one, e.g. by sampling timelines that far ahead.
+pub struct GameLoop {
+    pub tick_duration: Duration,
+    pub max_steps: u32,
+    accumulator: Duration,
+    last_time: Option<Duration>,
+    tick: u64,
+}
+
+impl GameLoop {
+    /// Panics unless `ticks_per_second` is between 1 and a billion, the
+    /// ticks being counted in whole nanoseconds.
+    pub fn new(ticks_per_second: u32) -> GameLoop {
+        assert!(ticks_per_second > 0 && ticks_per_second <= 1_000_000_000,
+                "GameLoop::new: {} ticks per second is out of range", ticks_per_second);
+        GameLoop {
+            tick_duration: Duration::new(0, 1_000_000_000 / ticks_per_second),
+            max_steps: 8,
+            accumulator: Duration::new(0, 0),
+            last_time: None,
+            tick: 0,
+        }
+    }
+
+    /// Length of one tick in seconds.
+    pub fn dt(&self) -> f32 {
+        duration_to_secs(self.tick_duration)
+    }
+
+    /// Total number of ticks simulated so far.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Simulated time, which only advances in whole ticks.
+    pub fn simulation_time(&self) -> Duration {
+        let nanos = self.tick_duration.subsec_nanos() as u64 * self.tick;
+        Duration::new(self.tick_duration.as_secs() * self.tick + nanos / 1_000_000_000,
+                      (nanos % 1_000_000_000) as u32)
+    }
+
+    /// Advances the loop to `now`, measured from any fixed origin, and
+    /// returns how many ticks to simulate before rendering this frame.
+    pub fn advance(&mut self, now: Duration) -> FrameTiming {
+        let frame_time = match self.last_time {
+            Some(last) if now > last => now - last,
+            _ => Duration::new(0, 0),
+        };
+        self.last_time = Some(now);
+        self.accumulator += frame_time;
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick_duration && ticks < self.max_steps {
+            self.accumulator -= self.tick_duration;
+            ticks += 1;
+        }
+
+        let fell_behind = self.accumulator >= self.tick_duration;
+        if fell_behind {
+            self.accumulator = Duration::new(0, 0);
+        }
+
+        let timing = FrameTiming {
+            frame_time: frame_time,
+            ticks: ticks,
+            alpha: duration_to_secs(self.accumulator) / self.dt(),
+            fell_behind: fell_behind,
+        };
+
+        self.tick += ticks as u64;
+
+        timing
+    }
+}
+
+pub fn duration_to_secs(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn spends_frame_time_in_whole_ticks() {
+        // 10 ms ticks
+        let mut game_loop = GameLoop::new(100);
+        let timing = game_loop.advance(ms(1000));
+        assert_eq!(timing.ticks, 0);
+        assert_eq!(timing.frame_time, ms(0));
+
+        let timing = game_loop.advance(ms(1025));
+        assert_eq!(timing.ticks, 2);
+        assert!((timing.alpha - 0.5).abs() < 1e-6);
+        assert!(!timing.fell_behind);
+
+        // The leftover 5 ms carry over into the next frame
+        let timing = game_loop.advance(ms(1030));
+        assert_eq!(timing.ticks, 1);
+        assert_eq!(timing.alpha, 0.0);
+        assert_eq!(game_loop.tick(), 3);
+        assert_eq!(game_loop.simulation_time(), ms(30));
+    }
+
+    #[test]
+    fn ignores_time_going_backwards() {
+        let mut game_loop = GameLoop::new(100);
+        game_loop.advance(ms(50));
+        let timing = game_loop.advance(ms(40));
+        assert_eq!(timing.ticks, 0);
+        assert_eq!(timing.frame_time, ms(0));
+        assert_eq!(game_loop.advance(ms(50)).ticks, 1);
+    }
+
+    #[test]
+    fn caps_catch_up_steps() {
+        let mut game_loop = GameLoop::new(100);
+        game_loop.max_steps = 3;
+        game_loop.advance(ms(0));
+
+        let timing = game_loop.advance(ms(55));
+        assert_eq!(timing.ticks, 3);
+        assert!(timing.fell_behind);
+        // The time beyond the cap is dropped rather than caught up later
+        assert_eq!(timing.alpha, 0.0);
+        let timing = game_loop.advance(ms(60));
+        assert_eq!(timing.ticks, 0);
+        assert!(!timing.fell_behind);
+        assert!((timing.alpha - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn counts_simulation_time_past_u32_ticks() {
+        let mut game_loop = GameLoop::new(1_000_000_000);
+        game_loop.tick = 5_000_000_000;
+        assert_eq!(game_loop.simulation_time(), Duration::new(5, 0));
+
+        let mut game_loop = GameLoop::new(60);
+        game_loop.tick = 1 << 33;
+        assert_eq!(game_loop.simulation_time(),
+                   Duration::new(0, 16_666_666) * 1024 * (1 << 23));
+    }
+}
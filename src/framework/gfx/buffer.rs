@@ -0,0 +1,425 @@
+use vulkano::buffer as vkb;
+use vulkano::buffer::TypedBufferAccess;
+use vulkano::command_buffer as vkcb;
+use vulkano::device as vkd;
+use vulkano::instance as vki;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use std::cmp;
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::ownership;
+
+/// Keeps count of the bytes allocated through this module.
+///
+/// Vulkan does not tell which heap a buffer ended up in, so device local
+/// buffers are counted against the first device local heap and staging
+/// buffers against the first host heap.
+pub struct MemoryTracker {
+    device_local: AtomicUsize,
+    host_visible: AtomicUsize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapUsage {
+    pub heap: u32,
+    pub size: usize,
+    pub device_local: bool,
+    pub used: usize,
+}
+
+impl MemoryTracker {
+    pub fn new() -> MemoryTracker {
+        MemoryTracker {
+            device_local: AtomicUsize::new(0),
+            host_visible: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn device_local_bytes(&self) -> usize {
+        self.device_local.load(Ordering::Relaxed)
+    }
+
+    pub fn host_visible_bytes(&self) -> usize {
+        self.host_visible.load(Ordering::Relaxed)
+    }
+
+    pub fn report(&self, physical: vki::PhysicalDevice) -> Vec<HeapUsage> {
+        let mut heaps = physical.memory_heaps().map(|heap| {
+            HeapUsage {
+                heap: heap.id(),
+                size: heap.size(),
+                device_local: heap.is_device_local(),
+                used: 0,
+            }
+        }).collect::<Vec<_>>();
+
+        let device_heap = heaps.iter().position(|h| h.device_local).unwrap_or(0);
+        let host_heap = heaps.iter().position(|h| !h.device_local).unwrap_or(device_heap);
+
+        if !heaps.is_empty() {
+            heaps[device_heap].used += self.device_local_bytes();
+            heaps[host_heap].used += self.host_visible_bytes();
+        }
+
+        heaps
+    }
+}
+
+/// Range of elements handed out by a `BufferArena`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Allocation {
+    pub chunk: usize,
+    pub range: Range<usize>,
+}
+
+impl Allocation {
+    pub fn len(&self) -> usize {
+        self.range.end - self.range.start
+    }
+}
+
+struct Chunk<T> {
+    buffer: Arc<vkb::DeviceLocalBuffer<[T]>>,
+    /// Free ranges, sorted by start and never adjacent to each other.
+    free: Vec<Range<usize>>,
+}
+
+/// Device local buffers that many meshes are suballocated from.
+///
+/// Memory is taken in chunks of `chunk_len` elements. A new chunk is added
+/// whenever an allocation does not fit in the existing ones. Chunks are
+/// owned by `queue_family`, the family of the queue drawing from them, and
+/// `Uploader` transfers the ranges it writes on other queues back to it.
+pub struct BufferArena<T> {
+    device: Arc<vkd::Device>,
+    queue_family: u32,
+    usage: vkb::BufferUsage,
+    chunk_len: usize,
+    chunks: Vec<Chunk<T>>,
+    tracker: Arc<MemoryTracker>,
+}
+
+pub type ArenaSlice<T> = vkb::BufferSlice<[T], Arc<vkb::DeviceLocalBuffer<[T]>>>;
+
+impl<T: Send + Sync + 'static> BufferArena<T> {
+    pub fn new(device: Arc<vkd::Device>,
+               queue_family: u32,
+               usage: vkb::BufferUsage,
+               chunk_len: usize,
+               tracker: Arc<MemoryTracker>) -> BufferArena<T> {
+        BufferArena {
+            device: device,
+            queue_family: queue_family,
+            usage: vkb::BufferUsage {
+                transfer_destination: true,
+                .. usage
+            },
+            chunk_len: chunk_len,
+            chunks: Vec::new(),
+            tracker: tracker,
+        }
+    }
+
+    pub fn vertices(device: Arc<vkd::Device>,
+                    queue_family: u32,
+                    tracker: Arc<MemoryTracker>) -> BufferArena<T> {
+        BufferArena::new(device,
+                         queue_family,
+                         vkb::BufferUsage::vertex_buffer(),
+                         1 << 16,
+                         tracker)
+    }
+
+    pub fn indices(device: Arc<vkd::Device>,
+                   queue_family: u32,
+                   tracker: Arc<MemoryTracker>) -> BufferArena<T> {
+        BufferArena::new(device,
+                         queue_family,
+                         vkb::BufferUsage::index_buffer(),
+                         1 << 18,
+                         tracker)
+    }
+
+    /// Reserves `len` elements, which must not be zero.
+    pub fn allocate(&mut self, len: usize) -> Result<Allocation, ()> {
+        if len == 0 {
+            println!("Failed to allocate an empty buffer range");
+            return Err(());
+        }
+
+        for (idx, chunk) in self.chunks.iter_mut().enumerate() {
+            if let Some(range) = take_range(&mut chunk.free, len) {
+                return Ok(Allocation {
+                    chunk: idx,
+                    range: range,
+                });
+            }
+        }
+
+        let chunk_len = cmp::max(self.chunk_len, len);
+        let physical = self.device.physical_device();
+        let family = physical.queue_family_by_id(self.queue_family).unwrap();
+        let buffer = vkb::DeviceLocalBuffer::array(self.device.clone(),
+                                                   chunk_len,
+                                                   self.usage,
+                                                   iter::once(family))
+            .map_err(|e| println!("Failed to allocate buffer chunk ({:?})", e))?;
+
+        self.tracker.device_local.fetch_add(chunk_len * mem::size_of::<T>(), Ordering::Relaxed);
+
+        let mut free = vec![0 .. chunk_len];
+        let range = take_range(&mut free, len).unwrap();
+        self.chunks.push(Chunk {
+            buffer: buffer,
+            free: free,
+        });
+
+        Ok(Allocation {
+            chunk: self.chunks.len() - 1,
+            range: range,
+        })
+    }
+
+    pub fn free(&mut self, allocation: Allocation) {
+        free_range(&mut self.chunks[allocation.chunk].free, allocation.range);
+    }
+
+    pub fn slice(&self, allocation: &Allocation) -> ArenaSlice<T> {
+        let buffer = self.chunks[allocation.chunk].buffer.clone();
+        vkb::BufferSlice::from_typed_buffer_access(buffer)
+            .slice(allocation.range.clone())
+            .unwrap()
+    }
+
+    pub fn capacity_bytes(&self) -> usize {
+        self.chunks.iter().map(|c| c.buffer.len()).sum::<usize>() * mem::size_of::<T>()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        let free = self.chunks.iter()
+            .flat_map(|c| c.free.iter())
+            .map(|r| r.end - r.start)
+            .sum::<usize>();
+
+        self.capacity_bytes() - free * mem::size_of::<T>()
+    }
+}
+
+/// First fit allocation out of a free list.
+fn take_range(free: &mut Vec<Range<usize>>, len: usize) -> Option<Range<usize>> {
+    let idx = free.iter().position(|r| r.end - r.start >= len)?;
+    let start = free[idx].start;
+
+    free[idx].start += len;
+    if free[idx].start == free[idx].end {
+        free.remove(idx);
+    }
+
+    Some(start .. start + len)
+}
+
+/// Returns a range to a sorted free list, merging it with its neighbours.
+fn free_range(free: &mut Vec<Range<usize>>, range: Range<usize>) {
+    let idx = free.iter().position(|r| r.start > range.start).unwrap_or(free.len());
+    free.insert(idx, range);
+
+    // Merge with the following and the preceding range
+    if idx + 1 < free.len() && free[idx].end == free[idx + 1].start {
+        free[idx].end = free.remove(idx + 1).end;
+    }
+    if idx > 0 && free[idx - 1].end == free[idx].start {
+        free[idx - 1].end = free.remove(idx).end;
+    }
+}
+
+/// Copy of a staging buffer into an arena, waiting for `Uploader::flush`.
+trait PendingCopy {
+    fn record(&self, builder: vkcb::AutoCommandBufferBuilder)
+              -> Result<vkcb::AutoCommandBufferBuilder, vkcb::CopyBufferError>;
+}
+
+struct StagedCopy<T> {
+    staging: Arc<vkb::CpuAccessibleBuffer<[T]>>,
+    slice: ArenaSlice<T>,
+}
+
+impl<T: Send + Sync + 'static> PendingCopy for StagedCopy<T> {
+    fn record(&self, builder: vkcb::AutoCommandBufferBuilder)
+              -> Result<vkcb::AutoCommandBufferBuilder, vkcb::CopyBufferError> {
+        builder.copy_buffer(self.staging.clone(), self.slice.clone())
+    }
+}
+
+/// Copies data into arenas through host visible staging buffers.
+///
+/// Copies are checked when staged and recorded into one command buffer
+/// when `flush` is called, so a failed upload leaves the others pending.
+/// They run on whichever queue the uploader is given, usually the transfer queue,
+/// and the written ranges are then transferred to the family of `owner`,
+/// the queue the arenas belong to. Ranges being overwritten need no acquire
+/// on the transfer queue, their old contents are discarded.
+pub struct Uploader {
+    device: Arc<vkd::Device>,
+    queue: Arc<vkd::Queue>,
+    owner: Arc<vkd::Queue>,
+    pending: Vec<Box<PendingCopy>>,
+    written: Vec<Arc<vkb::BufferAccess + Send + Sync>>,
+    staged_bytes: usize,
+    tracker: Arc<MemoryTracker>,
+}
+
+impl Uploader {
+    pub fn new(device: Arc<vkd::Device>,
+               queue: Arc<vkd::Queue>,
+               owner: Arc<vkd::Queue>,
+               tracker: Arc<MemoryTracker>) -> Uploader {
+        Uploader {
+            device: device,
+            queue: queue,
+            owner: owner,
+            pending: Vec::new(),
+            written: Vec::new(),
+            staged_bytes: 0,
+            tracker: tracker,
+        }
+    }
+
+    pub fn upload<T>(&mut self, arena: &mut BufferArena<T>, data: &[T]) -> Result<Allocation, ()>
+        where T: Copy + Send + Sync + 'static
+    {
+        let allocation = arena.allocate(data.len())?;
+        let slice = arena.slice(&allocation);
+
+        let staging = vkb::CpuAccessibleBuffer::from_iter(self.device.clone(),
+                                                          vkb::BufferUsage::transfer_source(),
+                                                          data.iter().cloned());
+        let staging = match staging {
+            Ok(staging) => staging,
+            Err(e) => {
+                println!("Failed to allocate staging buffer ({:?})", e);
+                arena.free(allocation);
+                return Err(());
+            },
+        };
+        if let Err(e) = vkcb::validity::check_copy_buffer(&self.device, &staging, &slice) {
+            println!("Failed to record buffer upload ({:?})", e);
+            arena.free(allocation);
+            return Err(());
+        }
+
+        let bytes = data.len() * mem::size_of::<T>();
+        self.staged_bytes += bytes;
+        self.tracker.host_visible.fetch_add(bytes, Ordering::Relaxed);
+        self.written.push(Arc::new(slice.clone()));
+        self.pending.push(Box::new(StagedCopy {
+            staging: staging,
+            slice: slice,
+        }));
+
+        Ok(allocation)
+    }
+
+    /// Submits all pending uploads and waits for them to complete, which
+    /// frees the staging buffers and hands the written ranges to the owner.
+    ///
+    /// The pending uploads are dropped either way, along with their staging
+    /// buffers.
+    pub fn flush(&mut self) -> Result<(), ()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = mem::replace(&mut self.pending, Vec::new());
+        let written = mem::replace(&mut self.written, Vec::new());
+
+        let result = self.submit(pending, written);
+        self.tracker.host_visible.fetch_sub(self.staged_bytes, Ordering::Relaxed);
+        self.staged_bytes = 0;
+        result
+    }
+
+    fn submit(&self,
+              pending: Vec<Box<PendingCopy>>,
+              written: Vec<Arc<vkb::BufferAccess + Send + Sync>>) -> Result<(), ()> {
+        let mut builder = vkcb::AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.queue.family()
+        ).map_err(|e| println!("Failed to create upload command buffer ({:?})", e))?;
+        for copy in &pending {
+            builder = copy.record(builder)
+                .map_err(|e| println!("Failed to record buffer upload ({:?})", e))?;
+        }
+
+        let command_buffer = builder.build()
+            .map_err(|e| println!("Failed to build upload command buffer ({:?})", e))?;
+
+        let uploads = sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)
+            .map_err(|e| println!("Failed to submit uploads ({:?})", e))?;
+        ownership::transfer(uploads, &self.queue, &self.owner, written)?
+            .then_signal_fence_and_flush()
+            .map_err(|e| println!("Failed to submit uploads ({:?})", e))?
+            .wait(None)
+            .map_err(|e| println!("Failed to wait for uploads ({:?})", e))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_first_range_that_fits() {
+        let mut free = vec![0 .. 2, 4 .. 10, 12 .. 20];
+        assert_eq!(take_range(&mut free, 3), Some(4 .. 7));
+        assert_eq!(free, vec![0 .. 2, 7 .. 10, 12 .. 20]);
+        assert_eq!(take_range(&mut free, 9), None);
+    }
+
+    #[test]
+    fn removes_exactly_fitting_ranges() {
+        let mut free = vec![0 .. 2, 4 .. 10];
+        assert_eq!(take_range(&mut free, 2), Some(0 .. 2));
+        assert_eq!(free, vec![4 .. 10]);
+        assert_eq!(take_range(&mut free, 6), Some(4 .. 10));
+        assert!(free.is_empty());
+    }
+
+    #[test]
+    fn keeps_separate_ranges_apart() {
+        let mut free = vec![0 .. 2, 10 .. 12];
+        free_range(&mut free, 5 .. 7);
+        assert_eq!(free, vec![0 .. 2, 5 .. 7, 10 .. 12]);
+        free_range(&mut free, 20 .. 22);
+        assert_eq!(free, vec![0 .. 2, 5 .. 7, 10 .. 12, 20 .. 22]);
+    }
+
+    #[test]
+    fn merges_with_neighbours() {
+        let mut free = vec![0 .. 2, 10 .. 12];
+        free_range(&mut free, 2 .. 4);
+        assert_eq!(free, vec![0 .. 4, 10 .. 12]);
+        free_range(&mut free, 8 .. 10);
+        assert_eq!(free, vec![0 .. 4, 8 .. 12]);
+        free_range(&mut free, 4 .. 8);
+        assert_eq!(free, vec![0 .. 12]);
+    }
+
+    #[test]
+    fn freed_ranges_are_taken_again() {
+        let mut free = vec![0 .. 16];
+        let a = take_range(&mut free, 4).unwrap();
+        let b = take_range(&mut free, 4).unwrap();
+        free_range(&mut free, a);
+        assert_eq!(take_range(&mut free, 2), Some(0 .. 2));
+        free_range(&mut free, b);
+        assert_eq!(free, vec![2 .. 16]);
+    }
+}
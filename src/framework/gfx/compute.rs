@@ -0,0 +1,187 @@
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set::DescriptorSetsCollection;
+use vulkano::descriptor::pipeline_layout::PipelineLayout;
+use vulkano::device as vkd;
+use vulkano::format as vkfmt;
+use vulkano::image as vkim;
+use vulkano::pipeline as vkp;
+use vulkano::pipeline::shader::EntryPointAbstract;
+use vulkano::sync::GpuFuture;
+
+use std::iter;
+use std::sync::Arc;
+
+use super::ownership;
+
+/// Compute pipeline together with the workgroup size of its shader, so
+/// dispatches can be sized by the number of elements to process.
+#[derive(Clone)]
+pub struct ComputePipeline {
+    pub id: Arc<vkp::ComputePipelineAbstract + Send + Sync>,
+    /// `local_size_x/y/z` of the shader.
+    pub local_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    pub fn new(id: Arc<vkp::ComputePipelineAbstract + Send + Sync>,
+               local_size: [u32; 3]) -> ComputePipeline {
+        ComputePipeline {
+            id: id,
+            local_size: local_size,
+        }
+    }
+
+    /// Builds the pipeline of a compute shader entry point.
+    pub fn build<Cs>(device: Arc<vkd::Device>,
+                     shader: &Cs,
+                     local_size: [u32; 3]) -> Result<ComputePipeline, ()>
+        where Cs: EntryPointAbstract<SpecializationConstants = ()>,
+              Cs::PipelineLayout: Clone,
+              vkp::ComputePipeline<PipelineLayout<Cs::PipelineLayout>>:
+                  vkp::ComputePipelineAbstract + Send + Sync + 'static
+    {
+        let pipeline = vkp::ComputePipeline::new(device, shader, &())
+            .map_err(|e| println!("Failed to create compute pipeline ({:?})", e))?;
+        Ok(ComputePipeline::new(Arc::new(pipeline), local_size))
+    }
+
+    /// Number of workgroups covering `elements` invocations.
+    pub fn groups(&self, elements: [u32; 3]) -> [u32; 3] {
+        let mut groups = [1; 3];
+        for axis in 0..3 {
+            groups[axis] = (elements[axis] + self.local_size[axis] - 1) / self.local_size[axis];
+        }
+        groups
+    }
+}
+
+/// Device local buffer compute shaders can read and write.
+///
+/// The buffer is owned by `queue_family`. Batches on a queue of another
+/// family transfer its ownership, see `ComputeBatch::share`.
+pub fn storage_buffer<T>(device: Arc<vkd::Device>,
+                         queue_family: u32,
+                         len: usize,
+                         usage: vkb::BufferUsage)
+                         -> Result<Arc<vkb::DeviceLocalBuffer<[T]>>, ()>
+    where T: Send + Sync + 'static
+{
+    let usage = vkb::BufferUsage {
+        storage_buffer: true,
+        transfer_destination: true,
+        .. usage
+    };
+
+    let family = device.physical_device().queue_family_by_id(queue_family).unwrap();
+
+    vkb::DeviceLocalBuffer::array(device.clone(), len, usage, iter::once(family))
+        .map_err(|e| println!("Failed to create storage buffer ({:?})", e))
+}
+
+/// 2D image compute shaders can write and later passes can sample, used
+/// only by queues of `queue_family`.
+pub fn storage_image(device: Arc<vkd::Device>,
+                     queue_family: u32,
+                     format: vkfmt::Format,
+                     width: u32,
+                     height: u32)
+                     -> Result<Arc<vkim::StorageImage<vkfmt::Format>>, ()> {
+    let family = device.physical_device().queue_family_by_id(queue_family).unwrap();
+
+    vkim::StorageImage::new(device.clone(),
+                            vkim::Dimensions::Dim2d {
+                                width: width,
+                                height: height,
+                            },
+                            format,
+                            iter::once(family))
+        .map_err(|e| println!("Failed to create storage image ({:?})", e))
+}
+
+/// Compute work recorded into its own command buffer.
+///
+/// Work submitted to the async compute queue runs alongside graphics work.
+/// The future returned by `submit` signals a semaphore, so graphics
+/// submissions that join it wait on the GPU rather than on the host.
+pub struct ComputeBatch {
+    queue: Arc<vkd::Queue>,
+    graphics: Arc<vkd::Queue>,
+    builder: vkcb::AutoCommandBufferBuilder,
+    dispatches: usize,
+    shared: Vec<Arc<vkb::BufferAccess + Send + Sync>>,
+}
+
+impl ComputeBatch {
+    /// Starts a batch for `queue` of buffers otherwise used by `graphics`.
+    pub fn new(device: Arc<vkd::Device>,
+               queue: Arc<vkd::Queue>,
+               graphics: Arc<vkd::Queue>) -> Result<ComputeBatch, ()> {
+        let builder = vkcb::AutoCommandBufferBuilder::primary_one_time_submit(device,
+                                                                              queue.family())
+            .map_err(|e| println!("Failed to create compute command buffer ({:?})", e))?;
+
+        Ok(ComputeBatch {
+            queue: queue,
+            graphics: graphics,
+            builder: builder,
+            dispatches: 0,
+            shared: Vec::new(),
+        })
+    }
+
+    /// Marks `buffer` as owned by the graphics queue family. If the batch
+    /// runs on another family, the buffer is acquired from graphics before
+    /// the batch and given back after it.
+    pub fn share(mut self, buffer: Arc<vkb::BufferAccess + Send + Sync>) -> ComputeBatch {
+        self.shared.push(buffer);
+        self
+    }
+
+    /// Dispatches enough workgroups of `pipeline` to cover `elements`.
+    pub fn dispatch<S, Pc>(mut self,
+                           pipeline: &ComputePipeline,
+                           elements: [u32; 3],
+                           sets: S,
+                           constants: Pc) -> Result<ComputeBatch, ()>
+        where S: DescriptorSetsCollection
+    {
+        self.builder = self.builder
+            .dispatch(pipeline.groups(elements), pipeline.id.clone(), sets, constants)
+            .map_err(|e| println!("Failed to record dispatch ({:?})", e))?;
+        self.dispatches += 1;
+        Ok(self)
+    }
+
+    /// Records other commands, e.g. buffer copies, between dispatches.
+    pub fn record<F>(mut self, f: F) -> Result<ComputeBatch, ()>
+        where F: FnOnce(vkcb::AutoCommandBufferBuilder) -> Result<vkcb::AutoCommandBufferBuilder, ()>
+    {
+        self.builder = f(self.builder)?;
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dispatches == 0
+    }
+
+    /// Submits the work once `after`, which must end on the graphics queue,
+    /// is done. Join the returned future into graphics work that reads the
+    /// results, shared buffers are owned by graphics again by then.
+    pub fn submit<F>(self, after: F) -> Result<Box<GpuFuture>, ()>
+        where F: GpuFuture + 'static
+    {
+        let command_buffer = self.builder.build()
+            .map_err(|e| println!("Failed to build compute command buffer ({:?})", e))?;
+
+        let acquired = ownership::transfer(after, &self.graphics, &self.queue,
+                                           self.shared.clone())?;
+        let executed = acquired.then_execute(self.queue.clone(), command_buffer)
+            .map_err(|e| println!("Failed to submit compute work ({:?})", e))?;
+        let future = ownership::transfer(executed, &self.queue, &self.graphics, self.shared)?
+            .then_signal_semaphore_and_flush()
+            .map_err(|e| println!("Failed to submit compute work ({:?})", e))?;
+
+        Ok(Box::new(future))
+    }
+}
@@ -1,46 +1,98 @@
-use winit;
 //use vulkano;
 use vulkano_win;
-use vulkano_win::VkSurfaceBuild;
 
 use std::sync::{Arc,RwLock};
 
 use vulkano::instance as vki;
 use vulkano::device as vkd;
 use vulkano::swapchain as vks;
-use vulkano::framebuffer as vkfb;
 //use vulkano::image as vkim;
 use vulkano::format as vkfmt;
 
 use super::swapchain;
-use super::swapchain::Dimensions;
+use super::swapchain::{AcquiredFrame, Dimensions, RefreshError};
+use super::scene_target::{self, SceneTarget};
+use super::graph::{AttachmentSize, GraphBuilder, GraphTargets, PassDesc, PassId, RenderGraph};
+use super::buffer::{HeapUsage, MemoryTracker};
+use super::queues::{QueueFamilies, Queues};
 
-use vulkano::swapchain::{AcquireError, SwapchainAcquireFuture};
+use vulkano::swapchain::AcquireError;
 
 pub struct Core {
     pub swapchain: Arc<RwLock<swapchain::Swapchain>>,
-    pub render_pass: Arc<vkfb::RenderPassAbstract + Send + Sync>,
-    pub surface_capabilities: Arc<vks::Capabilities>,
+    /// Passes drawing the 3D scene into the scene target.
+    pub scene_graph: RenderGraph,
+    pub render_path: RenderPath,
+    /// Pass of `scene_graph` opaque geometry is drawn in. With the deferred
+    /// path, it writes the G-buffer instead of colors.
+    pub scene_pass: PassId,
+    /// Pass shading the G-buffer, with the deferred path.
+    pub lighting_pass: Option<PassId>,
+    /// Pass blended geometry is drawn in, after opaque geometry is shaded.
+    /// The same as `scene_pass` with the forward path.
+    pub transparent_pass: PassId,
+    /// Pass drawing the UI on top of the upscaled scene, directly into the
+    /// swapchain image at native resolution.
+    pub ui_graph: RenderGraph,
+    pub ui_pass: PassId,
+    /// Framebuffers of `ui_graph` for the swapchain images.
+    pub ui_targets: RwLock<GraphTargets>,
+    pub scene_target: RwLock<SceneTarget>,
+    /// Fraction of the window size the scene is rendered at.
+    pub render_scale: RwLock<f32>,
+    /// Ratio of physical pixels to logical points of the window, by which
+    /// UI sizes given in points are scaled to the native resolution.
+    pub hidpi_factor: RwLock<f32>,
+    /// Format of the swapchain images.
+    pub color_format: vkfmt::Format,
+    /// Format of the scene color target, which holds linear HDR values.
+    pub scene_format: vkfmt::Format,
+    pub surface_capabilities: RwLock<Arc<vks::Capabilities>>,
+    /// Graphics and present queue, the same as `queues.graphics`.
     pub queue: Arc<vkd::Queue>,
+    pub queues: Queues,
     pub device: Arc<vkd::Device>,
     pub dimensions: RwLock<Dimensions>,
-    pub window: Arc<vulkano_win::Window>,
+    pub window: RwLock<Arc<vulkano_win::Window>>,
+    pub memory: Arc<MemoryTracker>,
 }
 
-impl Core {
-    pub fn new(events_loop: &winit::EventsLoop) -> Result<Arc<Core>, ()> {
-        // Create Instance
-        
-        let extensions = vulkano_win::required_extensions();
-        let instance = vki::Instance::new(None, &extensions, None)
-            .expect("failed to create instance");
+/// Half floats are enough for HDR lighting and can be written by compute
+/// shaders on every device.
+const SCENE_FORMAT: vkfmt::Format = vkfmt::Format::R16G16B16A16Sfloat;
 
-        // Create window
-        
-        let window = Arc::new(winit::WindowBuilder::new().build_vk_surface(
-            events_loop,
-            instance.clone()
-        ).unwrap());
+/// How opaque geometry is shaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    /// Every fragment is lit as it is drawn, by every light.
+    Forward,
+    /// Geometry writes albedo, normals and material parameters to a
+    /// G-buffer, which a lighting subpass shades once per pixel.
+    Deferred,
+}
+
+impl Default for RenderPath {
+    fn default() -> RenderPath {
+        RenderPath::Forward
+    }
+}
+
+/// Passes of the scene graph, see the fields of `Core`.
+struct ScenePasses {
+    scene: PassId,
+    lighting: Option<PassId>,
+    transparent: PassId,
+}
+
+pub fn create_instance() -> Arc<vki::Instance> {
+    let extensions = vulkano_win::required_extensions();
+    vki::Instance::new(None, &extensions, None)
+        .expect("failed to create instance")
+}
+
+impl Core {
+    pub fn new(window: Arc<vulkano_win::Window>, render_path: RenderPath) -> Result<Arc<Core>, ()> {
+        let instance = window.surface().instance().clone();
 
         let (width, height) = window.window().get_inner_size_pixels().unwrap();
 
@@ -58,10 +110,8 @@ impl Core {
         let surface_capabilities = Arc::new(window.surface().capabilities(physical)
             .expect("failed to get surface capabilities"));
 
-        let queue = physical.queue_families().find(|&q| {
-            q.supports_graphics() &&
-                window.surface().is_supported(q).unwrap_or(false)
-        }).expect("Could not find a graphical queue family");
+        let families = QueueFamilies::find(physical, window.surface())
+            .expect("Could not find a graphical queue family");
 
         // Create Logical Device
 
@@ -70,89 +120,293 @@ impl Core {
             .. vkd::DeviceExtensions::none()
         };
 
-        let (device, mut queues) = vkd::Device::new(
+        let (device, queues) = vkd::Device::new(
             physical,
             &required_features,
             &device_extensions,
-            [(queue, 0.5)].iter().cloned()
+            families.requests()
         ).expect("failed to create device");
         
         // Create Queues
 
-        let queue = queues.next().unwrap();
-
-        // Create Render Pass
-
-        let render_pass = Arc::new(
-            single_pass_renderpass!(
-                device.clone(),
-                attachments: {
-                    color: {
-                        load: Clear,
-                        store: Store,
-                        format: surface_capabilities.supported_formats[0].0,
-                        samples: 1,
-                    },
-                    depth: {
-                        load: Clear,
-                        store: DontCare,
-                        format: vkfmt::Format::D16Unorm,
-                        samples: 1,
-                    }
-                },
-                pass: {
-                    color: [color],
-                    depth_stencil: {depth}
-                }
-            ).unwrap()
-        );
+        let queues = Queues::new(&families, queues);
+        let queue = queues.graphics.clone();
+
+        println!("Transfer queue: {}, compute queue: {}",
+                 if queues.has_dedicated_transfer() { "dedicated" } else { "shared" },
+                 if queues.has_async_compute() { "async" } else { "shared" });
+
+        // Create Render Graphs
+
+        let color_format = surface_capabilities.supported_formats[0].0;
+        let scene_format = SCENE_FORMAT;
+
+        let (scene_graph, passes) = match render_path {
+            RenderPath::Forward => forward_graph(device.clone(), scene_format)?,
+            RenderPath::Deferred => deferred_graph(device.clone(), scene_format)?,
+        };
+        let (ui_graph, ui_pass) = ui_graph(device.clone(), color_format)?;
 
         let swapchain = swapchain::Swapchain::new(device.clone(),
                                                   queue.clone(),
                                                   window.clone(),
-                                                  render_pass.clone(),
                                                   surface_capabilities.clone(),
                                                   width,
-                                                  height).unwrap();
+                                                  height)
+            .map_err(|e| println!("Failed to create swapchain ({:?})", e))?;
+        let swapchain = Arc::new(RwLock::new(swapchain));
+
+        // Create Scene Target
+
+        let render_scale = 1.0;
+        let (scene_width, scene_height) = scene_target::scaled_dimensions(width,
+                                                                          height,
+                                                                          render_scale);
+        let scene_target = SceneTarget::new(device.clone(),
+                                            &scene_graph,
+                                            scene_width,
+                                            scene_height)?;
+        let ui_targets = ui_graph.targets(device.clone(), Dimensions {width: width,
+                                                                     height: height})?;
+
+        let hidpi_factor = window.window().hidpi_factor();
 
         // Return Core part of GFX
         
         Ok(Arc::new(Core {
             swapchain: swapchain,
-            render_pass: render_pass,
-            surface_capabilities: surface_capabilities,
+            scene_graph: scene_graph,
+            render_path: render_path,
+            scene_pass: passes.scene,
+            lighting_pass: passes.lighting,
+            transparent_pass: passes.transparent,
+            ui_graph: ui_graph,
+            ui_pass: ui_pass,
+            ui_targets: RwLock::new(ui_targets),
+            scene_target: RwLock::new(scene_target),
+            render_scale: RwLock::new(render_scale),
+            hidpi_factor: RwLock::new(hidpi_factor),
+            color_format: color_format,
+            scene_format: scene_format,
+            surface_capabilities: RwLock::new(surface_capabilities),
             queue: queue,
+            queues: queues,
             device: device,
             dimensions: RwLock::new(Dimensions {width: width,
                                                 height: height}),
-            window: window,
+            window: RwLock::new(window),
+            memory: Arc::new(MemoryTracker::new()),
         }))
     }
 
-    pub fn acquire_next_framebuffer(&self) -> Result<(usize, Arc<vkfb::FramebufferAbstract + Send + Sync>, SwapchainAcquireFuture), AcquireError> {
-        self.swapchain.read().unwrap().acquire_next_framebuffer()
+    pub fn acquire_next_image(&self) -> Result<AcquiredFrame, AcquireError> {
+        let mut acquired = self.swapchain.read().unwrap().acquire_next_image()?;
+
+        // vulkano does not report suboptimal acquires, so compare against
+        // the window size ourselves. Querying the surface is costlier and
+        // left to `recreate_swapchain`.
+        let window = self.window.read().unwrap();
+        if let Some((width, height)) = window.window().get_inner_size_pixels() {
+            let dimensions = self.dimensions.read().unwrap();
+            acquired.suboptimal = width != dimensions.width || height != dimensions.height;
+        }
+
+        Ok(acquired)
     }
 
-    pub fn recreate_swapchain(&self) {
-        let mut done = false;
-        
-        while !done {
-            let (new_width, new_height) = self.window.window()
-                .get_inner_size_pixels().unwrap();
-
-            done = self.swapchain.write().unwrap()
-                .refresh(self.device.clone(),
-                         self.render_pass.clone(),
-                         new_width,
-                         new_height);
-
-            let mut dimensions_ref = self.dimensions.write().unwrap();
-            dimensions_ref.width = new_width;
-            dimensions_ref.height = new_height;
+    /// Memory allocated through `framework::gfx::buffer`, per heap.
+    pub fn memory_report(&self) -> Vec<HeapUsage> {
+        self.memory.report(self.device.physical_device())
+    }
+
+    /// Moves rendering to a new window, e.g. after switching to fullscreen.
+    ///
+    /// The GPU must be done with the old window's swapchain images.
+    pub fn set_window(&self, window: Arc<vulkano_win::Window>) -> Result<(), ()> {
+        let physical = self.device.physical_device();
+
+        if !window.surface().is_supported(self.queue.family()).unwrap_or(false) {
+            println!("The new window cannot be presented to by the graphics queue");
+            return Err(());
         }
+
+        let surface_capabilities = Arc::new(window.surface().capabilities(physical)
+            .map_err(|e| println!("Failed to get surface capabilities ({:?})", e))?);
+
+        let (width, height) = window.window().get_inner_size_pixels().ok_or(())?;
+
+        let swapchain = swapchain::Swapchain::new(self.device.clone(),
+                                                  self.queue.clone(),
+                                                  window.clone(),
+                                                  surface_capabilities.clone(),
+                                                  width,
+                                                  height)
+            .map_err(|e| println!("Failed to create swapchain for the new window ({:?})", e))?;
+        let ui_targets = self.ui_graph.targets(self.device.clone(),
+                                               Dimensions {width: width, height: height})?;
+        let scene_target = self.scene_target_for(width, height)?;
+
+        // Nothing can fail past this point, so a failed switch keeps the
+        // old window fully usable
+        *self.hidpi_factor.write().unwrap() = window.window().hidpi_factor();
+        *self.swapchain.write().unwrap() = swapchain;
+        *self.ui_targets.write().unwrap() = ui_targets;
+        *self.surface_capabilities.write().unwrap() = surface_capabilities;
+        *self.window.write().unwrap() = window;
+        *self.scene_target.write().unwrap() = scene_target;
+
+        let mut dimensions_ref = self.dimensions.write().unwrap();
+        dimensions_ref.width = width;
+        dimensions_ref.height = height;
+
+        Ok(())
+    }
+
+    /// Renders the scene at `scale` times the window size, clamped to
+    /// `[0.1, 1.0]`.
+    pub fn set_render_scale(&self, scale: f32) -> Result<(), ()> {
+        *self.render_scale.write().unwrap() = scale.max(0.1).min(1.0);
+        self.recreate_scene_target()
+    }
+
+    pub fn scene_dimensions(&self) -> Dimensions {
+        self.scene_target.read().unwrap().dimensions
+    }
+
+    fn recreate_scene_target(&self) -> Result<(), ()> {
+        let dimensions = *self.dimensions.read().unwrap();
+        let scene_target = self.scene_target_for(dimensions.width, dimensions.height)?;
+        *self.scene_target.write().unwrap() = scene_target;
+
+        Ok(())
+    }
+
+    /// Scene target for a window of `width` by `height` at the render scale.
+    fn scene_target_for(&self, width: u32, height: u32) -> Result<SceneTarget, ()> {
+        let (width, height) = scene_target::scaled_dimensions(width,
+                                                              height,
+                                                              *self.render_scale.read().unwrap());
+        SceneTarget::new(self.device.clone(), &self.scene_graph, width, height)
+    }
+
+    /// Recreates the swapchain for the current window size.
+    ///
+    /// Makes a single attempt and never blocks, so it can be retried every
+    /// frame until it succeeds. The framebuffers and the scene target are
+    /// built first and the swapchain is recreated last, so on failure
+    /// everything is left as it was.
+    pub fn recreate_swapchain(&self) -> Result<(), RefreshError> {
+        let (new_width, new_height) = self.window.read().unwrap().window()
+            .get_inner_size_pixels()
+            .unwrap_or((0, 0));
+
+        let capabilities = self.window.read().unwrap().surface()
+            .capabilities(self.device.physical_device());
+        let capabilities = match capabilities {
+            Ok(capabilities) => capabilities,
+            Err(vks::CapabilitiesError::SurfaceLost) => return Err(RefreshError::SurfaceLost),
+            Err(vks::CapabilitiesError::OomError(e)) => {
+                println!("Failed to get surface capabilities ({:?})", e);
+                return Err(RefreshError::Other);
+            },
+        };
+        if new_width == 0 || new_height == 0 {
+            return Err(RefreshError::Minimized);
+        }
+
+        let dimensions = Dimensions {width: new_width, height: new_height};
+        let ui_targets = self.ui_graph.targets(self.device.clone(), dimensions)
+            .map_err(|_| RefreshError::Other)?;
+        let scene_target = self.scene_target_for(new_width, new_height)
+            .map_err(|_| RefreshError::Other)?;
+        self.swapchain.write().unwrap()
+            .refresh(new_width, new_height)?;
+
+        // Drops the framebuffers of the old swapchain images
+        *self.ui_targets.write().unwrap() = ui_targets;
+        *self.scene_target.write().unwrap() = scene_target;
+        *self.surface_capabilities.write().unwrap() = Arc::new(capabilities);
+        *self.dimensions.write().unwrap() = dimensions;
+        *self.hidpi_factor.write().unwrap() = self.window.read().unwrap().window().hidpi_factor();
+
+        Ok(())
     }
 }
 
+/// The scene is drawn into an HDR color attachment kept for the
+/// post-processing chain, with a depth buffer that never leaves the pass.
+fn forward_graph(device: Arc<vkd::Device>,
+                 format: vkfmt::Format) -> Result<(RenderGraph, ScenePasses), ()> {
+    let mut graph = GraphBuilder::new();
+
+    let color = graph.attachment("color", format, AttachmentSize::Target);
+    let depth = graph.attachment("depth", vkfmt::Format::D16Unorm, AttachmentSize::Target);
+    graph.clear(color);
+    graph.clear(depth);
+    graph.keep(color);
+
+    let pass = graph.pass(PassDesc::new("scene").color(color).depth(depth).secondary());
+
+    Ok((graph.build(device)?, ScenePasses {
+        scene: pass,
+        lighting: None,
+        transparent: pass,
+    }))
+}
+
+/// G-buffer, lighting and transparent passes share one render pass, so the
+/// G-buffer is read through input attachments and never leaves tile memory
+/// on tiled GPUs.
+fn deferred_graph(device: Arc<vkd::Device>,
+                  format: vkfmt::Format) -> Result<(RenderGraph, ScenePasses), ()> {
+    let mut graph = GraphBuilder::new();
+
+    let albedo = graph.attachment("albedo", vkfmt::Format::R8G8B8A8Unorm, AttachmentSize::Target);
+    let normal = graph.attachment("normal", vkfmt::Format::R16G16B16A16Sfloat,
+                                  AttachmentSize::Target);
+    let material = graph.attachment("material", vkfmt::Format::R8G8B8A8Unorm,
+                                    AttachmentSize::Target);
+    let depth = graph.attachment("depth", vkfmt::Format::D16Unorm, AttachmentSize::Target);
+    let color = graph.attachment("color", format, AttachmentSize::Target);
+    graph.clear(depth);
+    graph.clear(color);
+    graph.keep(color);
+
+    let scene = graph.pass(PassDesc::new("gbuffer")
+                           .color(albedo)
+                           .color(normal)
+                           .color(material)
+                           .depth(depth)
+                           .secondary());
+    let lighting = graph.pass(PassDesc::new("lighting")
+                              .input(albedo)
+                              .input(normal)
+                              .input(material)
+                              .input(depth)
+                              .color(color));
+    let transparent = graph.pass(PassDesc::new("transparent")
+                                 .color(color)
+                                 .depth(depth)
+                                 .secondary());
+
+    Ok((graph.build(device)?, ScenePasses {
+        scene: scene,
+        lighting: Some(lighting),
+        transparent: transparent,
+    }))
+}
+
+/// The UI is drawn over the swapchain image the scene was blitted to.
+fn ui_graph(device: Arc<vkd::Device>,
+            format: vkfmt::Format) -> Result<(RenderGraph, PassId), ()> {
+    let mut graph = GraphBuilder::new();
+
+    let swapchain = graph.import("swapchain", format);
+    let pass = graph.pass(PassDesc::new("ui").color(swapchain));
+
+    Ok((graph.build(device)?, pass))
+}
+
 fn get_required_features() -> vki::Features {
     vki::Features {
         tessellation_shader: true,
@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+
+use super::super::bounds::Aabb;
+use super::vertex::VertexStreams;
+
+/// Simplifies a triangle list by vertex clustering, for generating LODs at
+/// import time.
+///
+/// The bounds of the mesh are split into a grid with `resolution` cells
+/// along their longest side. Vertices sharing a cell are merged at their
+/// average position and triangles that collapse are dropped. Attributes
+/// other than the position are taken from the first vertex of each cell.
+///
+/// Fails on indices past the vertices and on attribute streams shorter than
+/// the positions.
+pub fn decimate(streams: &VertexStreams,
+                indices: &[u32],
+                resolution: u32) -> Result<(VertexStreams, Vec<u32>), ()> {
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= streams.len()) {
+        println!("Failed to decimate mesh (index {} of {} vertices)", index, streams.len());
+        return Err(());
+    }
+    let lens = [streams.normals.len(), streams.tangents.len(), streams.uv0.len(),
+                streams.uv1.len(), streams.colors.len(), streams.joints.len(),
+                streams.weights.len()];
+    if lens.iter().any(|&len| len != 0 && len < streams.len()) {
+        println!("Failed to decimate mesh (attributes missing for some vertices)");
+        return Err(());
+    }
+
+    let aabb = match Aabb::from_points(streams.positions.iter().cloned()) {
+        Some(aabb) => aabb,
+        None => return Ok((streams.clone(), indices.to_vec())),
+    };
+
+    let extent = aabb.max - aabb.min;
+    let longest = extent.x.max(extent.y).max(extent.z);
+    if longest <= 0.0 {
+        return Ok((streams.clone(), indices.to_vec()));
+    }
+    let cell_size = longest / resolution.max(1) as f32;
+
+    let mut cells = HashMap::new();
+    let mut firsts = Vec::new();
+    let mut sums: Vec<([f32; 3], f32)> = Vec::new();
+    let mut remap = Vec::with_capacity(streams.len());
+
+    for (idx, p) in streams.positions.iter().enumerate() {
+        let key = (((p[0] - aabb.min.x) / cell_size) as i32,
+                   ((p[1] - aabb.min.y) / cell_size) as i32,
+                   ((p[2] - aabb.min.z) / cell_size) as i32);
+
+        let cell = *cells.entry(key).or_insert_with(|| {
+            firsts.push(idx);
+            sums.push(([0.0; 3], 0.0));
+            (firsts.len() - 1) as u32
+        });
+
+        let sum = &mut sums[cell as usize];
+        for axis in 0..3 {
+            sum.0[axis] += p[axis];
+        }
+        sum.1 += 1.0;
+        remap.push(cell);
+    }
+
+    let simplified = VertexStreams {
+        positions: sums.iter().map(|&(p, n)| [p[0] / n, p[1] / n, p[2] / n]).collect(),
+        normals: pick(&streams.normals, &firsts),
+        tangents: pick(&streams.tangents, &firsts),
+        uv0: pick(&streams.uv0, &firsts),
+        uv1: pick(&streams.uv1, &firsts),
+        colors: pick(&streams.colors, &firsts),
+        joints: pick(&streams.joints, &firsts),
+        weights: pick(&streams.weights, &firsts),
+    };
+
+    let mut seen = HashSet::new();
+    let mut simplified_indices = Vec::new();
+
+    for triangle in indices.chunks(3).filter(|t| t.len() == 3) {
+        let (a, b, c) = (remap[triangle[0] as usize],
+                         remap[triangle[1] as usize],
+                         remap[triangle[2] as usize]);
+        if a == b || b == c || a == c {
+            continue;
+        }
+
+        // Rotate the smallest index first so duplicates are found regardless
+        // of where they start, without changing the winding
+        let key = if a < b && a < c {
+            (a, b, c)
+        } else if b < c {
+            (b, c, a)
+        } else {
+            (c, a, b)
+        };
+        if seen.insert(key) {
+            simplified_indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    Ok((simplified, simplified_indices))
+}
+
+fn pick<T: Copy>(stream: &[T], vertices: &[usize]) -> Vec<T> {
+    if stream.is_empty() {
+        Vec::new()
+    } else {
+        vertices.iter().map(|&v| stream[v]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flat grid of `n` by `n` quads of two triangles each.
+    fn grid(n: u32) -> (VertexStreams, Vec<u32>) {
+        let mut streams = VertexStreams::default();
+        for z in 0..n + 1 {
+            for x in 0..n + 1 {
+                streams.positions.push([x as f32, 0.0, z as f32]);
+                streams.uv0.push([x as f32 / n as f32, z as f32 / n as f32]);
+            }
+        }
+
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let corner = z * (n + 1) + x;
+                indices.extend_from_slice(&[corner, corner + n + 1, corner + 1,
+                                            corner + 1, corner + n + 1, corner + n + 2]);
+            }
+        }
+
+        (streams, indices)
+    }
+
+    #[test]
+    fn reduces_grids_without_degenerate_triangles() {
+        let (streams, indices) = grid(16);
+        let (simplified, simplified_indices) = decimate(&streams, &indices, 4).unwrap();
+
+        assert!(simplified.len() < streams.len());
+        assert_eq!(simplified.uv0.len(), simplified.len());
+        assert!(simplified_indices.len() % 3 == 0);
+        assert!(simplified_indices.len() > 0);
+        assert!(simplified_indices.len() < indices.len() / 4);
+
+        for triangle in simplified_indices.chunks(3) {
+            assert!(triangle.iter().all(|&i| (i as usize) < simplified.len()));
+            assert!(triangle[0] != triangle[1]);
+            assert!(triangle[1] != triangle[2]);
+            assert!(triangle[0] != triangle[2]);
+        }
+    }
+
+    #[test]
+    fn drops_triangles_within_a_cell() {
+        let (streams, _) = grid(4);
+        // At resolution 1 all vertices share a cell, apart from the ones on
+        // the far sides which start the next cells
+        let (_, indices) = decimate(&streams, &[0, 5, 1, 0, 24, 4], 1).unwrap();
+        assert_eq!(indices.len(), 3);
+    }
+
+    #[test]
+    fn keeps_meshes_at_full_resolution() {
+        let (streams, indices) = grid(4);
+        let (simplified, simplified_indices) = decimate(&streams, &indices, 4).unwrap();
+        assert_eq!(simplified.len(), streams.len());
+        assert_eq!(simplified_indices.len(), indices.len());
+    }
+
+    #[test]
+    fn rejects_indices_past_the_vertices() {
+        let (streams, mut indices) = grid(2);
+        indices.push(9);
+        assert!(decimate(&streams, &indices, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_short_attribute_streams() {
+        let (mut streams, indices) = grid(2);
+        streams.normals = vec![[0.0, 1.0, 0.0]; 4];
+        assert!(decimate(&streams, &indices, 1).is_err());
+    }
+}
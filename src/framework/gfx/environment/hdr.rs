@@ -0,0 +1,232 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use super::super::super::parse::invalid_data;
+
+/// Linear HDR image in equirectangular layout: columns cover longitude
+/// from -X around +Z, rows cover latitude from +Y (up) at the top to -Y at
+/// the bottom.
+#[derive(Clone, Debug)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from the top, each from left to right.
+    pub pixels: Vec<[f32; 3]>,
+}
+
+/// Faces of a cubemap, in the Vulkan order.
+pub const CUBE_FACES: [&'static str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+impl HdrImage {
+    /// Equirectangular image of the colors `f` returns for directions.
+    pub fn from_fn<F>(width: u32, height: u32, f: F) -> HdrImage
+        where F: Fn([f32; 3]) -> [f32; 3]
+    {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                pixels.push(f(direction(u, v)));
+            }
+        }
+
+        HdrImage {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    /// Resamples the six square faces of a cubemap, in the order of
+    /// `CUBE_FACES`, to an equirectangular image of the given size.
+    pub fn from_cube_faces(faces: &[HdrImage], width: u32, height: u32) -> Result<HdrImage, ()> {
+        if faces.len() != 6 {
+            println!("Cubemaps have 6 faces, not {}", faces.len());
+            return Err(());
+        }
+        let size = faces[0].width;
+        for (face, name) in faces.iter().zip(CUBE_FACES.iter()) {
+            if face.width != size || face.height != size {
+                println!("Cubemap face {} is not {}x{}", name, size, size);
+                return Err(());
+            }
+        }
+
+        Ok(HdrImage::from_fn(width, height, |d| {
+            let (face, u, v) = cube_coordinates(d);
+            faces[face].sample(u, v)
+        }))
+    }
+
+    /// Reads a Radiance `.hdr` image, flat or run length encoded.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<HdrImage> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid_data("HDR image", "not a Radiance image"));
+        }
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("HDR image", "missing resolution"));
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid_data("HDR image", "only RGBE pixels are supported"));
+            }
+        }
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+            return Err(invalid_data("HDR image",
+                                    "only top to bottom, left to right images are supported"));
+        }
+        let height = fields[1].parse::<u32>().map_err(|_| invalid_data("HDR image", "bad height"))?;
+        let width = fields[3].parse::<u32>().map_err(|_| invalid_data("HDR image", "bad width"))?;
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        let mut pos = 0;
+        for _ in 0..height {
+            pos = read_scanline(&data, pos, &mut scanline)?;
+            pixels.extend(scanline.iter().map(rgbe_to_float));
+        }
+
+        Ok(HdrImage {
+            width: width,
+            height: height,
+            pixels: pixels,
+        })
+    }
+
+    /// Bilinearly filtered color at `u`, `v` in `[0, 1]`, clamped to the
+    /// edges.
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 3] {
+        let x = (u * self.width as f32 - 0.5).max(0.0);
+        let y = (v * self.height as f32 - 0.5).max(0.0);
+        let x0 = (x as u32).min(self.width - 1);
+        let y0 = (y as u32).min(self.height - 1);
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+        let fx = x - x0 as f32;
+        let fy = y - y0 as f32;
+
+        let texel = |x: u32, y: u32| self.pixels[(y * self.width + x) as usize];
+        let mut color = [0.0; 3];
+        for c in 0..3 {
+            let top = texel(x0, y0)[c] * (1.0 - fx) + texel(x1, y0)[c] * fx;
+            let bottom = texel(x0, y1)[c] * (1.0 - fx) + texel(x1, y1)[c] * fx;
+            color[c] = top * (1.0 - fy) + bottom * fy;
+        }
+        color
+    }
+}
+
+/// Direction of the equirectangular coordinates `u`, `v`. The inverse of
+/// the mapping in the environment shaders.
+pub fn direction(u: f32, v: f32) -> [f32; 3] {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    [theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()]
+}
+
+/// Face and coordinates within it of a direction, as Vulkan samples
+/// cubemaps.
+fn cube_coordinates(d: [f32; 3]) -> (usize, f32, f32) {
+    let (x, y, z) = (d[0], d[1], d[2]);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+    } else if ay >= az {
+        if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+    } else {
+        if z > 0.0 { (4, x, -y, az) } else { (5, -x, -y, az) }
+    };
+
+    (face, (sc / ma + 1.0) * 0.5, (tc / ma + 1.0) * 0.5)
+}
+
+fn read_scanline(data: &[u8], pos: usize, scanline: &mut [[u8; 4]]) -> io::Result<usize> {
+    let width = scanline.len();
+    let header = data.get(pos..pos + 4)
+        .ok_or_else(|| invalid_data("HDR image", "truncated pixels"))?;
+
+    // New run length encoding stores each component separately
+    let encoded = width >= 8 && width < 0x8000 && header[0] == 2 && header[1] == 2 &&
+        header[2] & 0x80 == 0;
+    if !encoded {
+        for (idx, pixel) in scanline.iter_mut().enumerate() {
+            let bytes = data.get(pos + idx * 4..pos + idx * 4 + 4)
+                .ok_or_else(|| invalid_data("HDR image", "truncated pixels"))?;
+            if bytes[0] == 1 && bytes[1] == 1 && bytes[2] == 1 {
+                return Err(invalid_data("HDR image", "old run length encoding is not supported"));
+            }
+            pixel.copy_from_slice(bytes);
+        }
+        return Ok(pos + width * 4);
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err(invalid_data("HDR image", "scanline width mismatch"));
+    }
+
+    let mut pos = pos + 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = next_byte(data, &mut pos)? as usize;
+            if count > 128 {
+                let run = count - 128;
+                let value = next_byte(data, &mut pos)?;
+                if x + run > width {
+                    return Err(invalid_data("HDR image", "run past the end of a scanline"));
+                }
+                for pixel in &mut scanline[x..x + run] {
+                    pixel[component] = value;
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("HDR image", "bad run length"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[component] = next_byte(data, &mut pos)?;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(pos)
+}
+
+fn next_byte(data: &[u8], pos: &mut usize) -> io::Result<u8> {
+    let byte = *data.get(*pos).ok_or_else(|| invalid_data("HDR image", "truncated pixels"))?;
+    *pos += 1;
+    Ok(byte)
+}
+
+fn rgbe_to_float(rgbe: &[u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    [(rgbe[0] as f32 + 0.5) * scale,
+     (rgbe[1] as f32 + 0.5) * scale,
+     (rgbe[2] as f32 + 0.5) * scale]
+}
@@ -0,0 +1,202 @@
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::format as vkfmt;
+use vulkano::image as vkim;
+use vulkano::sampler as vksm;
+use vulkano::sync::GpuFuture;
+
+use std::sync::Arc;
+
+use super::compute::{self, ComputePipeline};
+use super::core::Core;
+
+pub mod hdr;
+mod shaders;
+
+pub use self::hdr::HdrImage;
+
+/// Size of the irradiance map.
+pub const IRRADIANCE_SIZE: [u32; 2] = [32, 16];
+/// Size of each roughness level of the prefiltered map.
+pub const PREFILTERED_SIZE: [u32; 2] = [128, 64];
+/// Roughness levels of the prefiltered map, from 0 to 1. Lit shaders
+/// assume 5.
+pub const PREFILTERED_LEVELS: u32 = 5;
+/// Size of the BRDF lookup table.
+pub const BRDF_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Level {
+    roughness: f32,
+    offset: i32,
+    height: i32,
+}
+
+type EnvironmentImage = vkim::StorageImage<vkfmt::Format>;
+
+/// Light coming from far away in every direction, shown by the skybox and
+/// used for image-based lighting.
+///
+/// Every map is equirectangular, laid out like `HdrImage`, and filtered
+/// when it is created:
+///
+/// - `irradiance` is the cosine weighted average around each direction,
+///   the diffuse light reaching a surface facing it.
+/// - `prefiltered` is the source blurred for GGX specular reflections, one
+///   band of `PREFILTERED_SIZE` per roughness level, stacked from smooth at
+///   the top to rough at the bottom. Shaders blend the two nearest bands.
+/// - `brdf` holds the scale and bias of the Fresnel reflectance, indexed by
+///   the cosine of the view angle and roughness.
+pub struct Environment {
+    pub source: Arc<EnvironmentImage>,
+    pub irradiance: Arc<EnvironmentImage>,
+    pub prefiltered: Arc<EnvironmentImage>,
+    pub brdf: Arc<EnvironmentImage>,
+    /// Linear sampler wrapping around horizontally, for the maps.
+    pub sampler: Arc<vksm::Sampler>,
+    /// Linear sampler clamped to the edges, for the BRDF table.
+    pub lut_sampler: Arc<vksm::Sampler>,
+}
+
+impl Environment {
+    /// Uploads `image` and filters it, waiting for the GPU to finish.
+    pub fn new(core: &Core, image: &HdrImage) -> Result<Environment, ()> {
+        let device = core.device.clone();
+        let family = core.queue.family().id();
+
+        let equirect = shaders::equirect_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load environment conversion shader ({:?})", e))?;
+        let irradiance_cs = shaders::irradiance_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load irradiance shader ({:?})", e))?;
+        let prefilter = shaders::prefilter_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load prefilter shader ({:?})", e))?;
+        let brdf_cs = shaders::brdf_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load BRDF shader ({:?})", e))?;
+
+        let equirect = ComputePipeline::build(device.clone(), &equirect.main_entry_point(), [8, 8, 1])?;
+        let irradiance_pipeline = ComputePipeline::build(device.clone(),
+                                                         &irradiance_cs.main_entry_point(),
+                                                         [8, 8, 1])?;
+        let prefilter = ComputePipeline::build(device.clone(), &prefilter.main_entry_point(),
+                                               [8, 8, 1])?;
+        let brdf_pipeline = ComputePipeline::build(device.clone(), &brdf_cs.main_entry_point(),
+                                                   [8, 8, 1])?;
+
+        let sampler = |address: vksm::SamplerAddressMode, filter: vksm::Filter| {
+            vksm::Sampler::new(device.clone(),
+                               filter,
+                               filter,
+                               vksm::MipmapMode::Nearest,
+                               address,
+                               vksm::SamplerAddressMode::ClampToEdge,
+                               vksm::SamplerAddressMode::ClampToEdge,
+                               0.0, 1.0, 0.0, 0.0)
+                .map_err(|e| println!("Failed to create environment sampler ({:?})", e))
+        };
+        let nearest = sampler(vksm::SamplerAddressMode::ClampToEdge, vksm::Filter::Nearest)?;
+        let linear = sampler(vksm::SamplerAddressMode::Repeat, vksm::Filter::Linear)?;
+        let lut_sampler = sampler(vksm::SamplerAddressMode::ClampToEdge, vksm::Filter::Linear)?;
+
+        let texels = image.pixels.iter().map(|c| [c[0], c[1], c[2], 1.0]).collect::<Vec<_>>();
+        let (uploaded, upload) = vkim::ImmutableImage::from_iter(
+            texels.into_iter(),
+            vkim::Dimensions::Dim2d {
+                width: image.width,
+                height: image.height,
+            },
+            vkfmt::R32G32B32A32Sfloat,
+            core.queue.clone()
+        ).map_err(|e| println!("Failed to create environment image ({:?})", e))?;
+
+        let storage = |width: u32, height: u32| {
+            compute::storage_image(device.clone(), family,
+                                   vkfmt::Format::R16G16B16A16Sfloat, width, height)
+        };
+        let source = storage(image.width, image.height)?;
+        let irradiance = storage(IRRADIANCE_SIZE[0], IRRADIANCE_SIZE[1])?;
+        let prefiltered = storage(PREFILTERED_SIZE[0], PREFILTERED_SIZE[1] * PREFILTERED_LEVELS)?;
+        let brdf = storage(BRDF_SIZE, BRDF_SIZE)?;
+
+        let equirect_set = vkds::PersistentDescriptorSet::start(equirect.id.clone(), 0)
+            .add_sampled_image(uploaded.clone(), nearest.clone())
+            .and_then(|s| s.add_image(source.clone()))
+            .map_err(|e| println!("Failed to bind environment images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create environment set ({:?})", e))?;
+
+        let irradiance_set = vkds::PersistentDescriptorSet::start(irradiance_pipeline.id.clone(), 0)
+            .add_sampled_image(source.clone(), linear.clone())
+            .and_then(|s| s.add_image(irradiance.clone()))
+            .map_err(|e| println!("Failed to bind irradiance images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create irradiance set ({:?})", e))?;
+
+        let prefilter_set = Arc::new(vkds::PersistentDescriptorSet::start(prefilter.id.clone(), 0)
+            .add_sampled_image(source.clone(), linear.clone())
+            .and_then(|s| s.add_image(prefiltered.clone()))
+            .map_err(|e| println!("Failed to bind prefilter images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create prefilter set ({:?})", e))?);
+
+        let brdf_set = vkds::PersistentDescriptorSet::start(brdf_pipeline.id.clone(), 0)
+            .add_image(brdf.clone())
+            .map_err(|e| println!("Failed to bind BRDF table ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create BRDF set ({:?})", e))?;
+
+        let mut builder = vkcb::AutoCommandBufferBuilder::primary_one_time_submit(device.clone(),
+                                                                                  core.queue.family())
+            .map_err(|e| println!("Failed to create environment command buffer ({:?})", e))?;
+
+        builder = dispatch(builder, &equirect, [image.width, image.height, 1], equirect_set, ())?;
+        builder = dispatch(builder, &irradiance_pipeline,
+                           [IRRADIANCE_SIZE[0], IRRADIANCE_SIZE[1], 1], irradiance_set, ())?;
+        for level in 0..PREFILTERED_LEVELS {
+            builder = dispatch(builder, &prefilter,
+                               [PREFILTERED_SIZE[0], PREFILTERED_SIZE[1], 1],
+                               prefilter_set.clone(),
+                               Level {
+                                   roughness: level as f32 / (PREFILTERED_LEVELS - 1) as f32,
+                                   offset: (level * PREFILTERED_SIZE[1]) as i32,
+                                   height: PREFILTERED_SIZE[1] as i32,
+                               })?;
+        }
+        builder = dispatch(builder, &brdf_pipeline, [BRDF_SIZE, BRDF_SIZE, 1], brdf_set, ())?;
+
+        let command_buffer = builder.build()
+            .map_err(|e| println!("Failed to build environment command buffer ({:?})", e))?;
+
+        upload.then_execute(core.queue.clone(), command_buffer)
+            .map_err(|e| println!("Failed to filter environment ({:?})", e))?
+            .then_signal_fence_and_flush()
+            .map_err(|e| println!("Failed to filter environment ({:?})", e))?
+            .wait(None)
+            .map_err(|e| println!("Failed to filter environment ({:?})", e))?;
+
+        Ok(Environment {
+            source: source,
+            irradiance: irradiance,
+            prefiltered: prefiltered,
+            brdf: brdf,
+            sampler: linear,
+            lut_sampler: lut_sampler,
+        })
+    }
+
+    /// Environment of the same color in every direction.
+    pub fn uniform(core: &Core, color: [f32; 3]) -> Result<Environment, ()> {
+        Environment::new(core, &HdrImage::from_fn(16, 8, |_| color))
+    }
+}
+
+fn dispatch<S, Pc>(builder: vkcb::AutoCommandBufferBuilder,
+                   pipeline: &ComputePipeline,
+                   size: [u32; 3],
+                   sets: S,
+                   constants: Pc) -> Result<vkcb::AutoCommandBufferBuilder, ()>
+    where S: vkds::DescriptorSetsCollection
+{
+    builder.dispatch(pipeline.groups(size), pipeline.id.clone(), sets, constants)
+        .map_err(|e| println!("Failed to record environment filtering ({:?})", e))
+}
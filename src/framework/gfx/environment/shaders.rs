@@ -0,0 +1,230 @@
+//! Compute shaders precomputing the environment maps. They all run in 8x8
+//! workgroups, one invocation per output pixel, and map directions to
+//! equirectangular coordinates like `hdr::direction`.
+
+pub mod equirect_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D target;
+
+// Largest half float
+const float MAX_HALF = 65504.0;
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(target);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    // 32 bit floats cannot be filtered everywhere, so the source is only
+    // fetched here and the half float copy is sampled from then on
+    vec3 color = texelFetch(source, pixel, 0).rgb;
+    imageStore(target, pixel, vec4(min(color, vec3(MAX_HALF)), 1.0));
+}
+"]
+    struct Dummy;
+}
+
+pub mod irradiance_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D irradiance;
+
+const float PI = 3.14159265359;
+const int AZIMUTH_STEPS = 48;
+const int ZENITH_STEPS = 16;
+
+vec2 equirect(vec3 d) {
+    return vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+vec3 direction(vec2 uv) {
+    float phi = (uv.x - 0.5) * 2.0 * PI;
+    float theta = uv.y * PI;
+    return vec3(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(irradiance);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    vec3 n = direction((vec2(pixel) + 0.5) / vec2(size));
+    vec3 up = abs(n.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, n));
+    vec3 bitangent = cross(n, tangent);
+
+    // Cosine weighted average of the hemisphere around the normal, which
+    // times the albedo is the diffuse light reflected towards any viewer
+    vec3 sum = vec3(0.0);
+    float weight = 0.0;
+    for (int i = 0; i < AZIMUTH_STEPS; i++) {
+        float phi = 2.0 * PI * (float(i) + 0.5) / float(AZIMUTH_STEPS);
+        for (int j = 0; j < ZENITH_STEPS; j++) {
+            float theta = 0.5 * PI * (float(j) + 0.5) / float(ZENITH_STEPS);
+            vec3 local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 d = local.x * tangent + local.y * bitangent + local.z * n;
+            float w = cos(theta) * sin(theta);
+            sum += textureLod(source, equirect(d), 0.0).rgb * w;
+            weight += w;
+        }
+    }
+
+    imageStore(irradiance, pixel, vec4(sum / weight, 1.0));
+}
+"]
+    struct Dummy;
+}
+
+pub mod prefilter_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D prefiltered;
+
+layout(push_constant) uniform Level {
+    float roughness;
+    int offset;
+    int height;
+} level;
+
+const float PI = 3.14159265359;
+const uint SAMPLES = 128;
+
+vec2 equirect(vec3 d) {
+    return vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+vec3 direction(vec2 uv) {
+    float phi = (uv.x - 0.5) * 2.0 * PI;
+    float theta = uv.y * PI;
+    return vec3(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), float(bitfieldReverse(i)) * 2.3283064365386963e-10);
+}
+
+vec3 sample_ggx(vec2 xi, vec3 n, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+    vec3 up = abs(n.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, n));
+    vec3 bitangent = cross(n, tangent);
+    return normalize(tangent * sin_theta * cos(phi) + bitangent * sin_theta * sin(phi) +
+                     n * cos_theta);
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = ivec2(imageSize(prefiltered).x, level.height);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    // The reflected direction stands in for the normal and the view
+    // direction, which loses the stretched highlights at grazing angles
+    vec3 n = direction((vec2(pixel) + 0.5) / vec2(size));
+    vec3 sum = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0; i < SAMPLES; i++) {
+        vec3 h = sample_ggx(hammersley(i, SAMPLES), n, level.roughness);
+        vec3 l = reflect(-n, h);
+        float n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            sum += textureLod(source, equirect(l), 0.0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    imageStore(prefiltered, pixel + ivec2(0, level.offset), vec4(sum / max(weight, 0.0001), 1.0));
+}
+"]
+    struct Dummy;
+}
+
+pub mod brdf_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0, rgba16f) uniform writeonly image2D brdf;
+
+const float PI = 3.14159265359;
+const uint SAMPLES = 256;
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), float(bitfieldReverse(i)) * 2.3283064365386963e-10);
+}
+
+float geometry(float n_dot, float roughness) {
+    float k = roughness * roughness / 2.0;
+    return n_dot / (n_dot * (1.0 - k) + k);
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(brdf);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    // x: cosine of the view angle, y: roughness
+    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+    float n_dot_v = uv.x;
+    float roughness = uv.y;
+    float a = roughness * roughness;
+    vec3 v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    // Scale and bias of the Fresnel reflectance at normal incidence
+    vec2 sum = vec2(0.0);
+    for (uint i = 0; i < SAMPLES; i++) {
+        vec2 xi = hammersley(i, SAMPLES);
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+        float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        vec3 h = vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+        vec3 l = reflect(-v, h);
+
+        float n_dot_l = max(l.z, 0.0);
+        float n_dot_h = max(h.z, 0.0);
+        float v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            float g = geometry(n_dot_v, roughness) * geometry(n_dot_l, roughness);
+            float visibility = g * v_dot_h / (n_dot_h * n_dot_v);
+            float fresnel = pow(1.0 - v_dot_h, 5.0);
+            sum += vec2((1.0 - fresnel) * visibility, fresnel * visibility);
+        }
+    }
+
+    imageStore(brdf, pixel, vec4(sum / float(SAMPLES), 0.0, 1.0));
+}
+"]
+    struct Dummy;
+}
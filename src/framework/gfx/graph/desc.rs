@@ -0,0 +1,46 @@
+use vulkano::format as vkfmt;
+use vulkano::framebuffer as vkfb;
+
+/// Render pass description built at runtime by the render graph, for
+/// passes that `single_pass_renderpass!` and `ordered_passes_renderpass!`
+/// cannot describe ahead of time.
+#[derive(Clone, Debug)]
+pub struct GraphPassDesc {
+    pub attachments: Vec<vkfb::LayoutAttachmentDescription>,
+    pub subpasses: Vec<vkfb::LayoutPassDescription>,
+    pub dependencies: Vec<vkfb::LayoutPassDependencyDescription>,
+}
+
+unsafe impl vkfb::RenderPassDesc for GraphPassDesc {
+    fn num_attachments(&self) -> usize {
+        self.attachments.len()
+    }
+
+    fn attachment_desc(&self, num: usize) -> Option<vkfb::LayoutAttachmentDescription> {
+        self.attachments.get(num).cloned()
+    }
+
+    fn num_subpasses(&self) -> usize {
+        self.subpasses.len()
+    }
+
+    fn subpass_desc(&self, num: usize) -> Option<vkfb::LayoutPassDescription> {
+        self.subpasses.get(num).cloned()
+    }
+
+    fn num_dependencies(&self) -> usize {
+        self.dependencies.len()
+    }
+
+    fn dependency_desc(&self, num: usize) -> Option<vkfb::LayoutPassDependencyDescription> {
+        self.dependencies.get(num).cloned()
+    }
+}
+
+unsafe impl vkfb::RenderPassDescClearValues<Vec<vkfmt::ClearValue>> for GraphPassDesc {
+    fn convert_clear_values(&self, values: Vec<vkfmt::ClearValue>)
+                            -> Box<Iterator<Item = vkfmt::ClearValue>> {
+        // The graph passes one value per attachment, `None` unless cleared
+        Box::new(values.into_iter())
+    }
+}
@@ -0,0 +1,547 @@
+use vulkano::command_buffer as vkcb;
+use vulkano::device as vkd;
+use vulkano::format as vkfmt;
+use vulkano::framebuffer as vkfb;
+use vulkano::framebuffer::RenderPassDesc;
+use vulkano::image as vkim;
+use vulkano::image::ImageLayout;
+use vulkano::sync::{AccessFlagBits, PipelineStages};
+
+use std::sync::Arc;
+
+use super::swapchain::Dimensions;
+
+pub mod desc;
+pub mod targets;
+
+pub use self::desc::GraphPassDesc;
+pub use self::targets::GraphTargets;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AttachmentId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PassId(usize);
+
+/// Size of an attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachmentSize {
+    /// The size the targets of the graph are created for.
+    Target,
+    /// Independent of the target, e.g. for shadow maps.
+    Fixed(u32, u32),
+}
+
+#[derive(Clone, Debug)]
+struct Attachment {
+    name: String,
+    format: vkfmt::Format,
+    size: AttachmentSize,
+    /// Provided by the caller of `record`, e.g. swapchain images.
+    imported: bool,
+    /// Cleared by the first pass writing it.
+    clear: bool,
+    /// Read after the graph, e.g. by post-processing or a blit.
+    keep: bool,
+}
+
+/// Attachments a pass uses. Passes are recorded in the order they were
+/// added.
+#[derive(Clone, Debug)]
+pub struct PassDesc {
+    pub name: String,
+    pub color: Vec<AttachmentId>,
+    pub depth: Option<AttachmentId>,
+    /// Attachments of earlier passes read as input attachments, at the
+    /// pixel being shaded.
+    pub inputs: Vec<AttachmentId>,
+    /// Attachments of earlier passes read through samplers. The pass then
+    /// starts a new render pass.
+    pub sampled: Vec<AttachmentId>,
+    /// Draws are recorded into secondary command buffers.
+    pub secondary: bool,
+}
+
+impl PassDesc {
+    pub fn new(name: &str) -> PassDesc {
+        PassDesc {
+            name: name.to_owned(),
+            color: Vec::new(),
+            depth: None,
+            inputs: Vec::new(),
+            sampled: Vec::new(),
+            secondary: false,
+        }
+    }
+
+    pub fn color(mut self, attachment: AttachmentId) -> PassDesc {
+        self.color.push(attachment);
+        self
+    }
+
+    pub fn depth(mut self, attachment: AttachmentId) -> PassDesc {
+        self.depth = Some(attachment);
+        self
+    }
+
+    pub fn input(mut self, attachment: AttachmentId) -> PassDesc {
+        self.inputs.push(attachment);
+        self
+    }
+
+    pub fn sampled(mut self, attachment: AttachmentId) -> PassDesc {
+        self.sampled.push(attachment);
+        self
+    }
+
+    pub fn secondary(mut self) -> PassDesc {
+        self.secondary = true;
+        self
+    }
+
+    fn writes(&self, attachment: AttachmentId) -> bool {
+        self.color.contains(&attachment) || self.depth == Some(attachment)
+    }
+
+    fn uses(&self, attachment: AttachmentId) -> bool {
+        self.writes(attachment) || self.inputs.contains(&attachment)
+    }
+}
+
+/// Declares the attachments and passes of a render graph.
+pub struct GraphBuilder {
+    attachments: Vec<Attachment>,
+    passes: Vec<PassDesc>,
+}
+
+impl GraphBuilder {
+    pub fn new() -> GraphBuilder {
+        GraphBuilder {
+            attachments: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Image owned by the graph. Its contents are undefined at the start of
+    /// every frame unless it is cleared.
+    pub fn attachment(&mut self,
+                      name: &str,
+                      format: vkfmt::Format,
+                      size: AttachmentSize) -> AttachmentId {
+        self.attachments.push(Attachment {
+            name: name.to_owned(),
+            format: format,
+            size: size,
+            imported: false,
+            clear: false,
+            keep: false,
+        });
+        AttachmentId(self.attachments.len() - 1)
+    }
+
+    /// Image passed to every `record`. Its contents are loaded and kept.
+    pub fn import(&mut self, name: &str, format: vkfmt::Format) -> AttachmentId {
+        let id = self.attachment(name, format, AttachmentSize::Target);
+        self.attachments[id.0].imported = true;
+        self.attachments[id.0].keep = true;
+        id
+    }
+
+    /// Clears the attachment before its first pass.
+    pub fn clear(&mut self, attachment: AttachmentId) {
+        self.attachments[attachment.0].clear = true;
+    }
+
+    /// Keeps the contents of the attachment after the graph.
+    pub fn keep(&mut self, attachment: AttachmentId) {
+        self.attachments[attachment.0].keep = true;
+    }
+
+    pub fn pass(&mut self, pass: PassDesc) -> PassId {
+        self.passes.push(pass);
+        PassId(self.passes.len() - 1)
+    }
+
+    /// Groups the passes into render passes and creates them.
+    pub fn build(self, device: Arc<vkd::Device>) -> Result<RenderGraph, ()> {
+        self.validate()?;
+
+        let groups = self.group_passes();
+        let mut render_passes = Vec::with_capacity(groups.len());
+
+        for (idx, passes) in groups.iter().enumerate() {
+            let desc = self.describe(&groups, idx);
+            let attachments = self.group_attachments(passes);
+            let render_pass = vkfb::RenderPass::new(device.clone(), desc)
+                .map_err(|e| println!("Failed to create render pass for {} ({:?})",
+                                      self.passes[passes[0].0].name, e))?;
+
+            render_passes.push(Group {
+                render_pass: Arc::new(render_pass) as Arc<vkfb::RenderPassAbstract + Send + Sync>,
+                attachments: attachments,
+                passes: passes.clone(),
+            });
+        }
+
+        Ok(RenderGraph {
+            attachments: self.attachments,
+            passes: self.passes,
+            groups: render_passes,
+        })
+    }
+
+    fn validate(&self) -> Result<(), ()> {
+        for pass in &self.passes {
+            let attachments = pass.color.iter().chain(pass.depth.iter()).collect::<Vec<_>>();
+            if attachments.is_empty() {
+                println!("Pass {} writes no attachment", pass.name);
+                return Err(());
+            }
+
+            let size = self.attachments[attachments[0].0].size;
+            if attachments.iter().any(|a| self.attachments[a.0].size != size) {
+                println!("Attachments of pass {} differ in size", pass.name);
+                return Err(());
+            }
+
+            if let Some(depth) = pass.depth {
+                match self.attachments[depth.0].format.ty() {
+                    vkfmt::FormatTy::Depth | vkfmt::FormatTy::DepthStencil |
+                    vkfmt::FormatTy::Stencil => (),
+                    _ => {
+                        println!("Depth attachment of pass {} has a color format", pass.name);
+                        return Err(());
+                    },
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Consecutive passes of the same size share a render pass, as
+    /// subpasses, unless a pass samples an attachment written in it.
+    fn group_passes(&self) -> Vec<Vec<PassId>> {
+        let mut groups: Vec<Vec<PassId>> = Vec::new();
+
+        for (idx, pass) in self.passes.iter().enumerate() {
+            let size = self.pass_size(pass);
+            let joins = match groups.last() {
+                Some(group) => {
+                    self.pass_size(&self.passes[group[0].0]) == size &&
+                        !pass.sampled.iter().any(|&a| {
+                            group.iter().any(|p| self.passes[p.0].writes(a))
+                        })
+                },
+                None => false,
+            };
+
+            if joins {
+                groups.last_mut().unwrap().push(PassId(idx));
+            } else {
+                groups.push(vec![PassId(idx)]);
+            }
+        }
+
+        groups
+    }
+
+    fn pass_size(&self, pass: &PassDesc) -> AttachmentSize {
+        let first = pass.color.first().cloned().or(pass.depth).unwrap();
+        self.attachments[first.0].size
+    }
+
+    /// Attachments used by a group of passes, in order of first use.
+    fn group_attachments(&self, passes: &[PassId]) -> Vec<AttachmentId> {
+        let mut attachments = Vec::new();
+        for pass in passes {
+            let pass = &self.passes[pass.0];
+            for &a in pass.color.iter().chain(pass.depth.iter()).chain(pass.inputs.iter()) {
+                if !attachments.contains(&a) {
+                    attachments.push(a);
+                }
+            }
+        }
+        attachments
+    }
+
+    fn used_outside(&self, groups: &[Vec<PassId>], group: usize, attachment: AttachmentId,
+                    before: bool) -> bool {
+        let range = if before { 0 .. group } else { group + 1 .. groups.len() };
+        groups[range].iter().flat_map(|g| g.iter()).any(|p| {
+            let pass = &self.passes[p.0];
+            pass.uses(attachment) || pass.sampled.contains(&attachment)
+        })
+    }
+
+    fn describe(&self, groups: &[Vec<PassId>], group: usize) -> GraphPassDesc {
+        let passes = &groups[group];
+        let attachments = self.group_attachments(passes);
+        let index = |a: AttachmentId| attachments.iter().position(|&b| b == a).unwrap();
+
+        let layouts = |pass: &PassDesc, a: AttachmentId| {
+            if pass.depth == Some(a) {
+                Some(ImageLayout::DepthStencilAttachmentOptimal)
+            } else if pass.color.contains(&a) {
+                Some(ImageLayout::ColorAttachmentOptimal)
+            } else if pass.inputs.contains(&a) {
+                Some(ImageLayout::ShaderReadOnlyOptimal)
+            } else {
+                None
+            }
+        };
+
+        let attachment_descs = attachments.iter().map(|&a| {
+            let attachment = &self.attachments[a.0];
+            let uses = passes.iter()
+                .filter_map(|p| layouts(&self.passes[p.0], a))
+                .collect::<Vec<_>>();
+
+            let loaded = attachment.imported || self.used_outside(groups, group, a, true);
+            let first_write = passes.iter()
+                .map(|p| &self.passes[p.0])
+                .find(|p| p.uses(a))
+                .map_or(false, |p| p.writes(a));
+
+            let load = if loaded {
+                vkfb::LoadOp::Load
+            } else if attachment.clear && first_write {
+                vkfb::LoadOp::Clear
+            } else {
+                vkfb::LoadOp::DontCare
+            };
+            let store = if attachment.keep || self.used_outside(groups, group, a, false) {
+                vkfb::StoreOp::Store
+            } else {
+                vkfb::StoreOp::DontCare
+            };
+
+            // Contents that are not loaded can start in any layout
+            let initial_layout = if load == vkfb::LoadOp::Load || !first_write {
+                uses[0]
+            } else {
+                ImageLayout::Undefined
+            };
+
+            vkfb::LayoutAttachmentDescription {
+                format: attachment.format,
+                samples: 1,
+                load: load,
+                store: store,
+                stencil_load: load,
+                stencil_store: store,
+                initial_layout: initial_layout,
+                final_layout: *uses.last().unwrap(),
+            }
+        }).collect::<Vec<_>>();
+
+        let subpasses = passes.iter().enumerate().map(|(sub, p)| {
+            let pass = &self.passes[p.0];
+            // Keep attachments used both before and after this subpass
+            let preserve = attachments.iter().cloned().filter(|&a| {
+                !pass.uses(a) &&
+                    passes[..sub].iter().any(|q| self.passes[q.0].uses(a)) &&
+                    passes[sub + 1..].iter().any(|q| self.passes[q.0].uses(a))
+            }).map(&index).collect();
+
+            vkfb::LayoutPassDescription {
+                color_attachments: pass.color.iter()
+                    .map(|&a| (index(a), ImageLayout::ColorAttachmentOptimal))
+                    .collect(),
+                depth_stencil: pass.depth.map(|a| (index(a), ImageLayout::DepthStencilAttachmentOptimal)),
+                input_attachments: pass.inputs.iter()
+                    .map(|&a| (index(a), ImageLayout::ShaderReadOnlyOptimal))
+                    .collect(),
+                resolve_attachments: Vec::new(),
+                preserve_attachments: preserve,
+            }
+        }).collect::<Vec<_>>();
+
+        let mut dependencies = Vec::new();
+        for dst in 0..passes.len() {
+            for src in 0..dst {
+                let source = &self.passes[passes[src].0];
+                let destination = &self.passes[passes[dst].0];
+                let shared = attachments.iter().any(|&a| {
+                    (source.writes(a) && destination.uses(a)) ||
+                        (source.uses(a) && destination.writes(a))
+                });
+                if shared {
+                    dependencies.push(subpass_dependency(src, dst));
+                }
+            }
+        }
+
+        GraphPassDesc {
+            attachments: attachment_descs,
+            subpasses: subpasses,
+            dependencies: dependencies,
+        }
+    }
+}
+
+/// Orders the attachment accesses of two subpasses, per pixel.
+fn subpass_dependency(source: usize, destination: usize) -> vkfb::LayoutPassDependencyDescription {
+    let stages = PipelineStages {
+        fragment_shader: true,
+        early_fragment_tests: true,
+        late_fragment_tests: true,
+        color_attachment_output: true,
+        .. PipelineStages::none()
+    };
+
+    vkfb::LayoutPassDependencyDescription {
+        source_subpass: source,
+        destination_subpass: destination,
+        source_stages: stages,
+        destination_stages: stages,
+        source_access: AccessFlagBits {
+            color_attachment_write: true,
+            depth_stencil_attachment_write: true,
+            .. AccessFlagBits::none()
+        },
+        destination_access: AccessFlagBits {
+            input_attachment_read: true,
+            color_attachment_read: true,
+            color_attachment_write: true,
+            depth_stencil_attachment_read: true,
+            depth_stencil_attachment_write: true,
+            .. AccessFlagBits::none()
+        },
+        by_region: true,
+    }
+}
+
+/// Render pass made of consecutive graph passes.
+struct Group {
+    render_pass: Arc<vkfb::RenderPassAbstract + Send + Sync>,
+    attachments: Vec<AttachmentId>,
+    passes: Vec<PassId>,
+}
+
+/// Passes and the attachments flowing between them.
+///
+/// The graph works out which passes can be subpasses of one render pass,
+/// the load and store operations and layouts of every attachment, and the
+/// dependencies between subpasses. Attachments only used inside one render
+/// pass are transient and may never leave tile memory. Barriers between
+/// render passes, and with the compute and transfer work around them, are
+/// inserted by vulkano.
+///
+/// The graph itself does not depend on the size of the target; images and
+/// framebuffers are created by `targets` for a given size.
+pub struct RenderGraph {
+    attachments: Vec<Attachment>,
+    passes: Vec<PassDesc>,
+    groups: Vec<Group>,
+}
+
+impl RenderGraph {
+    pub fn attachment(&self, name: &str) -> Option<AttachmentId> {
+        self.attachments.iter().position(|a| a.name == name).map(AttachmentId)
+    }
+
+    pub fn pass(&self, name: &str) -> Option<PassId> {
+        self.passes.iter().position(|p| p.name == name).map(PassId)
+    }
+
+    /// Subpass pipelines drawing in `pass` must be built for.
+    pub fn subpass(&self, pass: PassId) -> vkfb::Subpass<Arc<vkfb::RenderPassAbstract + Send + Sync>> {
+        let (group, index) = self.locate(pass);
+        vkfb::Subpass::from(self.groups[group].render_pass.clone(), index as u32).unwrap()
+    }
+
+    /// Whether the attachment is only used inside one render pass, so its
+    /// contents never need to be in memory.
+    pub fn is_transient(&self, attachment: AttachmentId) -> bool {
+        let a = &self.attachments[attachment.0];
+        if a.imported || a.keep {
+            return false;
+        }
+        let sampled = self.passes.iter().any(|p| p.sampled.contains(&attachment));
+        let groups = self.groups.iter()
+            .filter(|g| g.attachments.contains(&attachment))
+            .count();
+        !sampled && groups <= 1
+    }
+
+    /// Creates the images and framebuffers of the graph for a target of
+    /// the given size.
+    pub fn targets(&self, device: Arc<vkd::Device>, dimensions: Dimensions) -> Result<GraphTargets, ()> {
+        GraphTargets::new(self, device, dimensions)
+    }
+
+    /// Records every pass. `draw` is called once per pass, inside its
+    /// subpass, and records its draws or executes its secondary command
+    /// buffers.
+    ///
+    /// `imports` are the images of imported attachments for this frame.
+    /// Cleared attachments without a value in `clear_values` are cleared to
+    /// zero, or to the far plane for depth.
+    pub fn record<F>(&self,
+                     targets: &GraphTargets,
+                     builder: vkcb::AutoCommandBufferBuilder,
+                     imports: &[(AttachmentId, Arc<vkim::ImageViewAccess + Send + Sync>)],
+                     clear_values: &[(AttachmentId, vkfmt::ClearValue)],
+                     mut draw: F) -> Result<vkcb::AutoCommandBufferBuilder, ()>
+        where F: FnMut(PassId, vkcb::AutoCommandBufferBuilder)
+                       -> Result<vkcb::AutoCommandBufferBuilder, ()>
+    {
+        let mut builder = builder;
+
+        for (idx, group) in self.groups.iter().enumerate() {
+            let framebuffer = targets.framebuffer(self, idx, imports)?;
+
+            let clears = group.attachments.iter().enumerate().map(|(num, &a)| {
+                match group.render_pass.attachment_desc(num) {
+                    Some(ref desc) if desc.load == vkfb::LoadOp::Clear => {
+                        clear_values.iter()
+                            .find(|&&(id, _)| id == a)
+                            .map(|&(_, value)| value)
+                            .unwrap_or_else(|| default_clear(self.attachments[a.0].format))
+                    },
+                    _ => vkfmt::ClearValue::None,
+                }
+            }).collect::<Vec<_>>();
+
+            for (sub, &pass) in group.passes.iter().enumerate() {
+                let secondary = self.passes[pass.0].secondary;
+                builder = if sub == 0 {
+                    builder.begin_render_pass(framebuffer.clone(), secondary, clears.clone())
+                        .map_err(|e| println!("Failed to begin pass {} ({:?})",
+                                              self.passes[pass.0].name, e))?
+                } else {
+                    builder.next_subpass(secondary)
+                        .map_err(|e| println!("Failed to begin pass {} ({:?})",
+                                              self.passes[pass.0].name, e))?
+                };
+                builder = draw(pass, builder)?;
+            }
+
+            builder = builder.end_render_pass()
+                .map_err(|e| println!("Failed to end render pass ({:?})", e))?;
+        }
+
+        Ok(builder)
+    }
+
+    fn locate(&self, pass: PassId) -> (usize, usize) {
+        for (group, g) in self.groups.iter().enumerate() {
+            if let Some(index) = g.passes.iter().position(|&p| p == pass) {
+                return (group, index);
+            }
+        }
+        panic!("{:?} is not part of the graph", pass)
+    }
+}
+
+fn default_clear(format: vkfmt::Format) -> vkfmt::ClearValue {
+    match format.ty() {
+        vkfmt::FormatTy::Depth => vkfmt::ClearValue::Depth(1.0),
+        vkfmt::FormatTy::Stencil => vkfmt::ClearValue::Stencil(0),
+        vkfmt::FormatTy::DepthStencil => vkfmt::ClearValue::DepthStencil((1.0, 0)),
+        vkfmt::FormatTy::Uint => vkfmt::ClearValue::Uint([0; 4]),
+        vkfmt::FormatTy::Sint => vkfmt::ClearValue::Int([0; 4]),
+        _ => vkfmt::ClearValue::Float([0.0; 4]),
+    }
+}
@@ -0,0 +1,178 @@
+use vulkano::device as vkd;
+use vulkano::format as vkfmt;
+use vulkano::framebuffer as vkfb;
+use vulkano::image as vkim;
+
+use std::sync::{Arc, Mutex};
+
+use super::{AttachmentId, AttachmentSize, RenderGraph};
+use super::super::swapchain::Dimensions;
+
+/// Framebuffers kept for imported images. Enough for every swapchain image
+/// with triple buffering and a recreated swapchain.
+const IMPORTED_FRAMEBUFFERS: usize = 8;
+
+type Framebuffer = Arc<vkfb::FramebufferAbstract + Send + Sync>;
+
+struct ImportedFramebuffer {
+    group: usize,
+    /// Identity of the images the framebuffer was created with.
+    images: Vec<usize>,
+    framebuffer: Framebuffer,
+}
+
+/// Images and framebuffers of a render graph for one target size.
+pub struct GraphTargets {
+    pub dimensions: Dimensions,
+    images: Vec<Option<Arc<vkim::AttachmentImage<vkfmt::Format>>>>,
+    /// `None` for render passes using imported attachments.
+    framebuffers: Vec<Option<Framebuffer>>,
+    imported: Mutex<Vec<ImportedFramebuffer>>,
+}
+
+impl GraphTargets {
+    pub fn new(graph: &RenderGraph,
+               device: Arc<vkd::Device>,
+               dimensions: Dimensions) -> Result<GraphTargets, ()> {
+        let mut images = Vec::with_capacity(graph.attachments.len());
+
+        for (idx, attachment) in graph.attachments.iter().enumerate() {
+            if attachment.imported {
+                images.push(None);
+                continue;
+            }
+
+            let size = match attachment.size {
+                AttachmentSize::Target => [dimensions.width, dimensions.height],
+                AttachmentSize::Fixed(width, height) => [width, height],
+            };
+            let id = AttachmentId(idx);
+            let input = graph.passes.iter().any(|p| p.inputs.contains(&id));
+
+            let image = if graph.is_transient(id) {
+                let usage = vkim::ImageUsage {
+                    transient_attachment: true,
+                    input_attachment: input,
+                    .. vkim::ImageUsage::none()
+                };
+                vkim::AttachmentImage::with_usage(device.clone(), size, attachment.format, usage)
+            } else {
+                let usage = vkim::ImageUsage {
+                    transfer_source: true,
+                    sampled: true,
+                    input_attachment: input,
+                    .. vkim::ImageUsage::none()
+                };
+                vkim::AttachmentImage::with_usage(device.clone(), size, attachment.format, usage)
+            }.map_err(|e| println!("Failed to create {} attachment ({:?})", attachment.name, e))?;
+
+            images.push(Some(image));
+        }
+
+        let mut targets = GraphTargets {
+            dimensions: dimensions,
+            images: images,
+            framebuffers: Vec::new(),
+            imported: Mutex::new(Vec::new()),
+        };
+
+        for group in &graph.groups {
+            let owned = group.attachments.iter().all(|a| targets.images[a.0].is_some());
+            let framebuffer = if owned {
+                let views = group.attachments.iter()
+                    .map(|a| targets.view(*a, &[]))
+                    .collect::<Result<Vec<_>, ()>>()?;
+                Some(framebuffer(group.render_pass.clone(), views)?)
+            } else {
+                None
+            };
+            targets.framebuffers.push(framebuffer);
+        }
+
+        Ok(targets)
+    }
+
+    /// Image of an attachment owned by the graph.
+    pub fn image(&self, attachment: AttachmentId) -> Option<Arc<vkim::AttachmentImage<vkfmt::Format>>> {
+        self.images[attachment.0].clone()
+    }
+
+    pub(super) fn framebuffer(&self,
+                              graph: &RenderGraph,
+                              group: usize,
+                              imports: &[(AttachmentId, Arc<vkim::ImageViewAccess + Send + Sync>)])
+                              -> Result<Framebuffer, ()> {
+        if let Some(ref framebuffer) = self.framebuffers[group] {
+            return Ok(framebuffer.clone());
+        }
+
+        let attachments = &graph.groups[group].attachments;
+        let views = attachments.iter()
+            .map(|a| self.view(*a, imports))
+            .collect::<Result<Vec<_>, ()>>()?;
+        let identity = views.iter()
+            .map(|v| &**v as *const vkim::ImageViewAccess as *const () as usize)
+            .collect::<Vec<_>>();
+
+        let mut imported = self.imported.lock().unwrap();
+        if let Some(cached) = imported.iter().find(|c| c.group == group && c.images == identity) {
+            return Ok(cached.framebuffer.clone());
+        }
+
+        let framebuffer = framebuffer(graph.groups[group].render_pass.clone(), views)?;
+        if imported.len() >= IMPORTED_FRAMEBUFFERS {
+            imported.remove(0);
+        }
+        imported.push(ImportedFramebuffer {
+            group: group,
+            images: identity,
+            framebuffer: framebuffer.clone(),
+        });
+
+        Ok(framebuffer)
+    }
+
+    fn view(&self,
+            attachment: AttachmentId,
+            imports: &[(AttachmentId, Arc<vkim::ImageViewAccess + Send + Sync>)])
+            -> Result<Arc<vkim::ImageViewAccess + Send + Sync>, ()> {
+        if let Some(ref image) = self.images[attachment.0] {
+            return Ok(image.clone() as Arc<vkim::ImageViewAccess + Send + Sync>);
+        }
+
+        imports.iter()
+            .find(|&&(id, _)| id == attachment)
+            .map(|&(_, ref view)| view.clone())
+            .ok_or_else(|| println!("No image imported for {:?}", attachment))
+    }
+}
+
+/// Creates a framebuffer from attachments only known at runtime. The
+/// builder's type depends on the number of attachments, hence the macro.
+fn framebuffer(render_pass: Arc<vkfb::RenderPassAbstract + Send + Sync>,
+               views: Vec<Arc<vkim::ImageViewAccess + Send + Sync>>) -> Result<Framebuffer, ()> {
+    macro_rules! build {
+        ($($idx:expr),*) => {
+            Arc::new(vkfb::Framebuffer::start(render_pass)
+                     $(.add(views[$idx].clone())
+                       .map_err(|e| println!("Failed to add framebuffer attachment ({:?})", e))?)*
+                     .build()
+                     .map_err(|e| println!("Failed to create framebuffer ({:?})", e))?) as Framebuffer
+        }
+    }
+
+    Ok(match views.len() {
+        1 => build!(0),
+        2 => build!(0, 1),
+        3 => build!(0, 1, 2),
+        4 => build!(0, 1, 2, 3),
+        5 => build!(0, 1, 2, 3, 4),
+        6 => build!(0, 1, 2, 3, 4, 5),
+        7 => build!(0, 1, 2, 3, 4, 5, 6),
+        8 => build!(0, 1, 2, 3, 4, 5, 6, 7),
+        count => {
+            println!("Render passes support up to 8 attachments, not {}", count);
+            return Err(());
+        },
+    })
+}
@@ -0,0 +1,203 @@
+use vulkano::buffer::{BufferAccess, TypedBufferAccess};
+use vulkano::command_buffer as vkcb;
+use vulkano::command_buffer::pool::standard::{StandardCommandPoolAlloc, StandardCommandPoolBuilder};
+use vulkano::command_buffer::synced::{SyncCommandBuffer, SyncCommandBufferBuilder};
+use vulkano::command_buffer::sys::{Flags, Kind, KindOcclusionQuery, KindSecondaryRenderPass,
+                                   UnsafeCommandBuffer};
+use vulkano::command_buffer::validity;
+use vulkano::descriptor::descriptor_set::DescriptorSetsCollection;
+use vulkano::device::{Device, DeviceOwned, Queue};
+use vulkano::framebuffer::{EmptySinglePassRenderPassDesc, Framebuffer, RenderPass,
+                           RenderPassAbstract, Subpass};
+use vulkano::image::{ImageAccess, ImageLayout};
+use vulkano::instance::QueueFamily;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::pipeline::input_assembly::Index;
+use vulkano::query::QueryPipelineStatisticFlags;
+use vulkano::sync::{AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages};
+
+use std::iter;
+use std::mem;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Builder of a secondary command buffer of instanced, indexed draws.
+///
+/// vulkano's `AutoCommandBufferBuilder::draw_indexed` always draws a single
+/// instance, so these draws are recorded with the synchronized builder it
+/// wraps instead. Every draw goes through the same checks and binds its
+/// whole state again, there is no state cache.
+pub struct InstancedBuilder {
+    inner: SyncCommandBufferBuilder<StandardCommandPoolBuilder>,
+}
+
+impl InstancedBuilder {
+    /// Starts a command buffer executed once from `subpass`.
+    pub fn secondary_graphics<R>(device: Arc<Device>,
+                                 queue_family: QueueFamily,
+                                 subpass: Subpass<R>) -> Result<InstancedBuilder, ()>
+        where R: RenderPassAbstract + Clone + Send + Sync + 'static
+    {
+        let kind = Kind::Secondary {
+            render_pass: Some(KindSecondaryRenderPass {
+                subpass: subpass,
+                framebuffer: None::<Framebuffer<RenderPass<EmptySinglePassRenderPassDesc>, ()>>,
+            }),
+            occlusion_query: KindOcclusionQuery::Forbidden,
+            query_statistics_flags: QueryPipelineStatisticFlags::none(),
+        };
+
+        let pool = Device::standard_command_pool(&device, queue_family);
+        let inner = unsafe { SyncCommandBufferBuilder::new(&pool, kind, Flags::OneTimeSubmit) }
+            .map_err(|e| println!("Failed to create instanced command buffer ({:?})", e))?;
+
+        Ok(InstancedBuilder {
+            inner: inner,
+        })
+    }
+
+    /// Draws `instance_count` instances of the indexed geometry, with
+    /// `gl_InstanceIndex` counting from 0.
+    pub fn draw_indexed<Gp, S, Pc, Ib, I>(mut self,
+                                          pipeline: Gp,
+                                          dynamic: &vkcb::DynamicState,
+                                          vertices: Vec<Arc<BufferAccess + Send + Sync>>,
+                                          indices: Ib,
+                                          sets: S,
+                                          constants: Pc,
+                                          instance_count: u32)
+                                          -> Result<InstancedBuilder, ()>
+        where Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
+              S: DescriptorSetsCollection,
+              Ib: BufferAccess + TypedBufferAccess<Content = [I]> + Send + Sync + 'static,
+              I: Index + 'static
+    {
+        validity::check_dynamic_state_validity(&pipeline, dynamic)
+            .map_err(|e| println!("Invalid dynamic state for draw ({:?})", e))?;
+        validity::check_push_constants_validity(&pipeline, &constants)
+            .map_err(|e| println!("Invalid push constants for draw ({:?})", e))?;
+        validity::check_descriptor_sets_validity(&pipeline, &sets)
+            .map_err(|e| println!("Invalid descriptor sets for draw ({:?})", e))?;
+        let vertices = validity::check_vertex_buffers(&pipeline, vertices)
+            .map_err(|e| println!("Invalid vertex buffers for draw ({:?})", e))?;
+        let index_count = validity::check_index_buffer(self.inner.device(), &indices)
+            .map_err(|e| println!("Invalid index buffer for draw ({:?})", e))?
+            .num_indices as u32;
+
+        unsafe {
+            self.inner.bind_pipeline_graphics(pipeline.clone());
+            self.inner.bind_index_buffer(indices, I::ty())
+                .map_err(|e| println!("Failed to bind index buffer ({:?})", e))?;
+
+            for num in 0..pipeline.num_push_constants_ranges() {
+                let range = match pipeline.push_constants_range(num) {
+                    Some(range) => range,
+                    None => continue,
+                };
+                if range.offset + range.size > mem::size_of::<Pc>() {
+                    println!("Push constants are smaller than range {} of the pipeline", num);
+                    return Err(());
+                }
+                let data = slice::from_raw_parts((&constants as *const Pc as *const u8)
+                                                     .offset(range.offset as isize),
+                                                 range.size);
+                self.inner.push_constants::<_, [u8]>(pipeline.clone(),
+                                                     range.stages,
+                                                     range.offset as u32,
+                                                     range.size as u32,
+                                                     data);
+            }
+
+            if let Some(line_width) = dynamic.line_width {
+                self.inner.set_line_width(line_width);
+            }
+            if let Some(ref viewports) = dynamic.viewports {
+                self.inner.set_viewport(0, viewports.clone().into_iter());
+            }
+            if let Some(ref scissors) = dynamic.scissors {
+                self.inner.set_scissor(0, scissors.clone().into_iter());
+            }
+
+            {
+                let mut binder = self.inner.bind_descriptor_sets();
+                for set in sets.into_vec() {
+                    binder.add(set);
+                }
+                binder.submit(true, pipeline.clone(), 0, iter::empty())
+                    .map_err(|e| println!("Failed to bind descriptor sets ({:?})", e))?;
+            }
+            {
+                let mut binder = self.inner.bind_vertex_buffers();
+                for buffer in vertices.vertex_buffers {
+                    binder.add(buffer);
+                }
+                binder.submit(0)
+                    .map_err(|e| println!("Failed to bind vertex buffers ({:?})", e))?;
+            }
+
+            self.inner.draw_indexed(index_count, instance_count, 0, 0, 0);
+        }
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<InstancedCommandBuffer, ()> {
+        let inner = self.inner.build()
+            .map_err(|e| println!("Failed to build instanced command buffer ({:?})", e))?;
+
+        Ok(InstancedCommandBuffer {
+            inner: inner,
+            submitted: AtomicBool::new(false),
+        })
+    }
+}
+
+/// Command buffer recorded by an `InstancedBuilder`, to be executed from a
+/// primary command buffer once.
+pub struct InstancedCommandBuffer {
+    inner: SyncCommandBuffer<StandardCommandPoolAlloc>,
+    submitted: AtomicBool,
+}
+
+unsafe impl vkcb::CommandBuffer for InstancedCommandBuffer {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> {
+        self.inner.as_ref()
+    }
+
+    fn lock_submit(&self, future: &GpuFuture, queue: &Queue)
+                   -> Result<(), vkcb::CommandBufferExecError> {
+        if self.submitted.swap(true, Ordering::SeqCst) {
+            return Err(vkcb::CommandBufferExecError::OneTimeSubmitAlreadySubmitted);
+        }
+
+        let result = self.inner.lock_submit(future, queue);
+        if result.is_err() {
+            self.submitted.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    unsafe fn unlock(&self) {
+        self.inner.unlock();
+    }
+
+    fn check_buffer_access(&self, buffer: &BufferAccess, exclusive: bool, queue: &Queue)
+                           -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        self.inner.check_buffer_access(buffer, exclusive, queue)
+    }
+
+    fn check_image_access(&self, image: &ImageAccess, layout: ImageLayout, exclusive: bool,
+                          queue: &Queue)
+                          -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        self.inner.check_image_access(image, layout, exclusive, queue)
+    }
+}
+
+unsafe impl DeviceOwned for InstancedCommandBuffer {
+    fn device(&self) -> &Arc<Device> {
+        self.inner.device()
+    }
+}
@@ -0,0 +1,214 @@
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set::DescriptorSetsCollection;
+use vulkano::device as vkd;
+use vulkano::pipeline as vkp;
+
+use std::sync::Arc;
+
+use super::super::bounds::Bounds;
+use super::buffer::{Allocation, ArenaSlice, BufferArena, MemoryTracker, Uploader};
+use super::instanced::InstancedBuilder;
+use super::pipeline::Topology;
+use super::vertex::{VertexLayout, VertexStreams};
+
+/// Index data of a mesh in host memory.
+#[derive(Clone, Debug)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    /// Stores the indices as `u16` whenever they fit, which halves their
+    /// size. The maximum value is kept free for primitive restart.
+    pub fn from_u32(indices: Vec<u32>) -> Indices {
+        if indices.iter().all(|&i| i < u16::max_value() as u32) {
+            Indices::U16(indices.into_iter().map(|i| i as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            Indices::U16(ref indices) => indices.len(),
+            Indices::U32(ref indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Mesh in host memory, ready to be uploaded.
+pub struct MeshData {
+    pub layout: VertexLayout,
+    pub topology: Topology,
+    pub vertices: Vec<u8>,
+    pub indices: Indices,
+    /// `None` for meshes without vertices.
+    pub bounds: Option<Bounds>,
+}
+
+impl MeshData {
+    /// Interleaves the streams a loader produced into `layout`.
+    pub fn from_streams(layout: VertexLayout,
+                        topology: Topology,
+                        streams: &VertexStreams,
+                        indices: Indices) -> MeshData {
+        MeshData {
+            vertices: streams.interleave(&layout),
+            bounds: Bounds::from_points(&streams.positions),
+            layout: layout,
+            topology: topology,
+            indices: indices,
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / self.layout.stride()
+    }
+}
+
+/// Index range of a mesh in the arena of matching index width.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IndexAllocation {
+    U16(Allocation),
+    U32(Allocation),
+}
+
+/// Index buffer of a mesh, in whichever width it was stored.
+///
+/// Slices own references to their arena chunks, so they can be sent to the
+/// threads recording draws.
+#[derive(Clone)]
+pub enum IndexSlice {
+    U16(ArenaSlice<u16>),
+    U32(ArenaSlice<u32>),
+}
+
+impl IndexSlice {
+    /// Records `instance_count` instances of an indexed draw, binding the
+    /// index buffer of matching width.
+    pub fn draw<Gp, S, Pc>(self,
+                           builder: InstancedBuilder,
+                           pipeline: Gp,
+                           dynamic: &vkcb::DynamicState,
+                           vertices: Vec<Arc<vkb::BufferAccess + Send + Sync>>,
+                           sets: S,
+                           constants: Pc,
+                           instance_count: u32)
+                           -> Result<InstancedBuilder, ()>
+        where Gp: vkp::GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
+              S: DescriptorSetsCollection
+    {
+        match self {
+            IndexSlice::U16(indices) =>
+                builder.draw_indexed(pipeline, dynamic, vertices, indices, sets, constants,
+                                     instance_count),
+            IndexSlice::U32(indices) =>
+                builder.draw_indexed(pipeline, dynamic, vertices, indices, sets, constants,
+                                     instance_count),
+        }
+    }
+}
+
+/// Mesh living in the device local vertex and index arenas.
+pub struct Mesh {
+    pub layout: VertexLayout,
+    pub topology: Topology,
+    pub vertices: Allocation,
+    pub indices: IndexAllocation,
+    pub bounds: Option<Bounds>,
+}
+
+impl Mesh {
+    pub fn index_count(&self) -> usize {
+        match self.indices {
+            IndexAllocation::U16(ref allocation) => allocation.len(),
+            IndexAllocation::U32(ref allocation) => allocation.len(),
+        }
+    }
+}
+
+/// Arenas all meshes are suballocated from.
+pub struct MeshStorage {
+    pub vertices: BufferArena<u8>,
+    pub indices16: BufferArena<u16>,
+    pub indices32: BufferArena<u32>,
+}
+
+impl MeshStorage {
+    pub fn new(device: Arc<vkd::Device>,
+               queue_family: u32,
+               tracker: Arc<MemoryTracker>) -> MeshStorage {
+        MeshStorage {
+            vertices: BufferArena::new(device.clone(),
+                                       queue_family,
+                                       vkb::BufferUsage::vertex_buffer(),
+                                       1 << 22,
+                                       tracker.clone()),
+            indices16: BufferArena::indices(device.clone(), queue_family, tracker.clone()),
+            indices32: BufferArena::indices(device, queue_family, tracker),
+        }
+    }
+
+    pub fn upload(&mut self, uploader: &mut Uploader, data: &MeshData) -> Result<Mesh, ()> {
+        let indices = match data.indices {
+            Indices::U16(ref indices) =>
+                IndexAllocation::U16(uploader.upload(&mut self.indices16, indices)?),
+            Indices::U32(ref indices) =>
+                IndexAllocation::U32(uploader.upload(&mut self.indices32, indices)?),
+        };
+
+        Ok(Mesh {
+            layout: data.layout.clone(),
+            topology: data.topology,
+            vertices: uploader.upload(&mut self.vertices, &data.vertices)?,
+            indices: indices,
+            bounds: data.bounds,
+        })
+    }
+
+    pub fn free(&mut self, mesh: Mesh) {
+        self.vertices.free(mesh.vertices);
+        match mesh.indices {
+            IndexAllocation::U16(allocation) => self.indices16.free(allocation),
+            IndexAllocation::U32(allocation) => self.indices32.free(allocation),
+        }
+    }
+
+    /// Vertex buffers of `mesh`, in the form every pipeline accepts.
+    pub fn vertex_buffers(&self, mesh: &Mesh) -> Vec<Arc<vkb::BufferAccess + Send + Sync>> {
+        vec![Arc::new(self.vertices.slice(&mesh.vertices))]
+    }
+
+    pub fn index_slice(&self, mesh: &Mesh) -> IndexSlice {
+        match mesh.indices {
+            IndexAllocation::U16(ref allocation) =>
+                IndexSlice::U16(self.indices16.slice(allocation)),
+            IndexAllocation::U32(ref allocation) =>
+                IndexSlice::U32(self.indices32.slice(allocation)),
+        }
+    }
+
+    /// Records `instance_count` instances of `mesh`.
+    pub fn draw<Gp, S, Pc>(&self,
+                           builder: InstancedBuilder,
+                           pipeline: Gp,
+                           dynamic: &vkcb::DynamicState,
+                           mesh: &Mesh,
+                           sets: S,
+                           constants: Pc,
+                           instance_count: u32)
+                           -> Result<InstancedBuilder, ()>
+        where Gp: vkp::GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
+              S: DescriptorSetsCollection
+    {
+        self.index_slice(mesh)
+            .draw(builder, pipeline, dynamic, self.vertex_buffers(mesh), sets, constants,
+                  instance_count)
+    }
+}
@@ -2,8 +2,32 @@
 pub use vulkano::swapchain::{AcquireError, SwapchainAcquireFuture};
 
 pub mod core;
+pub mod queues;
 pub mod swapchain;
-//pub mod pipeline;
+pub mod scene_target;
+pub mod buffer;
+pub mod vertex;
+pub mod mesh;
+pub mod instanced;
+pub mod decimate;
+pub mod pipeline;
+pub mod compute;
+pub mod ownership;
+pub mod post;
+pub mod graph;
+pub mod environment;
 
-pub use self::swapchain::Dimensions;
-pub use self::core::Core;
+pub use self::swapchain::{AcquiredFrame, Dimensions, RefreshError};
+pub use self::scene_target::SceneTarget;
+pub use self::buffer::{Allocation, BufferArena, Uploader};
+pub use self::vertex::{Attribute, VertexLayout, VertexStreams};
+pub use self::mesh::{IndexSlice, Indices, Mesh, MeshData, MeshStorage};
+pub use self::instanced::{InstancedBuilder, InstancedCommandBuffer};
+pub use self::decimate::decimate;
+pub use self::pipeline::{BlendMode, Pipeline, PipelineBuilderExt, Topology};
+pub use self::compute::{ComputeBatch, ComputePipeline};
+pub use self::post::{Lut, PostChain, PostSettings};
+pub use self::environment::{Environment, HdrImage};
+pub use self::graph::{AttachmentId, AttachmentSize, GraphBuilder, GraphTargets, PassDesc, PassId, RenderGraph};
+pub use self::queues::Queues;
+pub use self::core::{Core, RenderPath, create_instance};
@@ -0,0 +1,150 @@
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer as vkcb;
+use vulkano::command_buffer::pool::standard::StandardCommandPoolAlloc;
+use vulkano::command_buffer::sys::{Flags, Kind, UnsafeCommandBuffer, UnsafeCommandBufferBuilder,
+                                   UnsafeCommandBufferBuilderPipelineBarrier};
+use vulkano::device::{Device, DeviceOwned, Queue};
+use vulkano::image::{ImageAccess, ImageLayout};
+use vulkano::sync::{AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages};
+
+use std::sync::Arc;
+
+/// Side of a queue family ownership transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Half {
+    /// Executed on the queue giving the buffers up, after its last use.
+    Release,
+    /// Executed on the queue taking the buffers, before its first use.
+    Acquire,
+}
+
+/// Command buffer of nothing but the barriers of one half of an ownership
+/// transfer of buffers.
+///
+/// Buffers are created for the family of the queue that uses them most,
+/// with exclusive sharing. vulkano does not know about ownership, so the
+/// barriers are recorded with its unsafe builder and the buffers are only
+/// kept alive, not locked.
+pub struct OwnershipTransfer {
+    inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+    /// Kept alive until the barriers are executed.
+    _buffers: Vec<Arc<BufferAccess + Send + Sync>>,
+}
+
+impl OwnershipTransfer {
+    /// Records `half` of moving `buffers` from family `from` to family `to`.
+    /// Releases are executed on a queue of `from`, acquires on one of `to`.
+    pub fn new(device: Arc<Device>,
+               half: Half,
+               from: u32,
+               to: u32,
+               buffers: Vec<Arc<BufferAccess + Send + Sync>>) -> Result<OwnershipTransfer, ()> {
+        let family = match half {
+            Half::Release => from,
+            Half::Acquire => to,
+        };
+        let family = device.physical_device().queue_family_by_id(family).unwrap();
+
+        // The release makes the writes of every earlier command available,
+        // the acquire makes them visible to every later one
+        let (src_stage, src_access, dst_stage, dst_access) = match half {
+            Half::Release => (PipelineStages { all_commands: true, .. PipelineStages::none() },
+                              AccessFlagBits { memory_write: true, .. AccessFlagBits::none() },
+                              PipelineStages { bottom_of_pipe: true, .. PipelineStages::none() },
+                              AccessFlagBits::none()),
+            Half::Acquire => (PipelineStages { top_of_pipe: true, .. PipelineStages::none() },
+                              AccessFlagBits::none(),
+                              PipelineStages { all_commands: true, .. PipelineStages::none() },
+                              AccessFlagBits {
+                                  memory_read: true,
+                                  memory_write: true,
+                                  .. AccessFlagBits::none()
+                              }),
+        };
+
+        let pool = Device::standard_command_pool(&device, family);
+        let inner = unsafe {
+            let mut builder = UnsafeCommandBufferBuilder::new(&pool, Kind::primary(),
+                                                              Flags::OneTimeSubmit)
+                .map_err(|e| println!("Failed to create ownership transfer ({:?})", e))?;
+
+            let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
+            for buffer in &buffers {
+                barrier.add_buffer_memory_barrier(&**buffer, src_stage, src_access,
+                                                  dst_stage, dst_access, false,
+                                                  Some((from, to)), 0, buffer.size());
+            }
+            builder.pipeline_barrier(&barrier);
+
+            builder.build()
+                .map_err(|e| println!("Failed to build ownership transfer ({:?})", e))?
+        };
+
+        Ok(OwnershipTransfer {
+            inner: inner,
+            _buffers: buffers,
+        })
+    }
+}
+
+unsafe impl vkcb::CommandBuffer for OwnershipTransfer {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> {
+        &self.inner
+    }
+
+    fn lock_submit(&self, _: &GpuFuture, _: &Queue) -> Result<(), vkcb::CommandBufferExecError> {
+        Ok(())
+    }
+
+    unsafe fn unlock(&self) {
+    }
+
+    // Accesses are checked against the commands around the transfer
+    fn check_buffer_access(&self, _: &BufferAccess, _: bool, _: &Queue)
+                           -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+
+    fn check_image_access(&self, _: &ImageAccess, _: ImageLayout, _: bool, _: &Queue)
+                          -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+}
+
+unsafe impl DeviceOwned for OwnershipTransfer {
+    fn device(&self) -> &Arc<Device> {
+        self.inner.device()
+    }
+}
+
+/// Moves `buffers` from the family of `from` to that of `to` once `after`
+/// is done, which must end on `from` or allow changing queues. The release
+/// is executed on `from` and the acquire on `to` once a semaphore says the
+/// release happened. Nothing is recorded when both queues are of the same
+/// family.
+pub fn transfer<F>(after: F,
+                   from: &Arc<Queue>,
+                   to: &Arc<Queue>,
+                   buffers: Vec<Arc<BufferAccess + Send + Sync>>) -> Result<Box<GpuFuture>, ()>
+    where F: GpuFuture + 'static
+{
+    let (src, dst) = (from.family().id(), to.family().id());
+    if src == dst || buffers.is_empty() {
+        return Ok(Box::new(after));
+    }
+
+    let device = from.device().clone();
+    let release = OwnershipTransfer::new(device.clone(), Half::Release, src, dst,
+                                         buffers.clone())?;
+    let acquire = OwnershipTransfer::new(device, Half::Acquire, src, dst, buffers)?;
+
+    let future = after.then_execute(from.clone(), release)
+        .map_err(|e| println!("Failed to release buffers ({:?})", e))?
+        .then_signal_semaphore()
+        .then_execute(to.clone(), acquire)
+        .map_err(|e| println!("Failed to acquire buffers ({:?})", e))?;
+
+    Ok(Box::new(future))
+}
@@ -1,18 +1,147 @@
+use vulkano::pipeline as vkp;
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor};
+use vulkano::pipeline::input_assembly::PrimitiveTopology;
+
 use std::sync::Arc;
 
-use vulkano::pipeline as vkp;
+use super::vertex::VertexLayout;
 
-use super::core;
+/// How the indices of a mesh are assembled into primitives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topology {
+    TriangleList,
+    TriangleStrip,
+    LineList,
+    LineStrip,
+    /// Vertex shaders drawing points must write `gl_PointSize`.
+    PointList,
+}
 
-struct Pipeline {
-    pub id: Arc<vkp::GraphicsPipelineAbstract + Send + Sync>,
+impl Topology {
+    pub fn primitive(&self) -> PrimitiveTopology {
+        match *self {
+            Topology::TriangleList => PrimitiveTopology::TriangleList,
+            Topology::TriangleStrip => PrimitiveTopology::TriangleStrip,
+            Topology::LineList => PrimitiveTopology::LineList,
+            Topology::LineStrip => PrimitiveTopology::LineStrip,
+            Topology::PointList => PrimitiveTopology::PointList,
+        }
+    }
+
+    /// Strips are cut with the maximum index value, so several strips can
+    /// share one draw.
+    pub fn is_strip(&self) -> bool {
+        match *self {
+            Topology::TriangleStrip | Topology::LineStrip => true,
+            _ => false,
+        }
+    }
 }
 
-struct PipelineBuilder
+impl Default for Topology {
+    fn default() -> Topology {
+        Topology::TriangleList
+    }
+}
 
-impl Pipeline {
-    pub fn new(core: Arc<core::Core>,
-    params: vkp::G) -> Result<Pipeline, ()> {
-    } 
+/// How fragments are combined with the color already in the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Opaque,
+    /// Blended by alpha. Must be drawn back to front.
+    Alpha,
+    /// Added to the target, scaled by alpha. Order independent.
+    Additive,
+    /// Colors already multiplied by alpha, added to the target scaled by
+    /// one minus alpha. Covers alpha and additive blending at once. Must be
+    /// drawn back to front.
+    Premultiplied,
+    /// Opaque, but fragments with an alpha below the material's cutoff are
+    /// discarded by the shader.
+    AlphaTested,
+}
+
+impl BlendMode {
+    pub fn attachment_blend(&self) -> AttachmentBlend {
+        match *self {
+            BlendMode::Opaque | BlendMode::AlphaTested => AttachmentBlend::pass_through(),
+            BlendMode::Alpha => AttachmentBlend::alpha_blending(),
+            BlendMode::Additive => AttachmentBlend {
+                color_source: BlendFactor::SrcAlpha,
+                color_destination: BlendFactor::One,
+                alpha_source: BlendFactor::Zero,
+                alpha_destination: BlendFactor::One,
+                .. AttachmentBlend::alpha_blending()
+            },
+            BlendMode::Premultiplied => AttachmentBlend {
+                color_source: BlendFactor::One,
+                color_destination: BlendFactor::OneMinusSrcAlpha,
+                alpha_source: BlendFactor::One,
+                alpha_destination: BlendFactor::OneMinusSrcAlpha,
+                .. AttachmentBlend::alpha_blending()
+            },
+        }
+    }
+
+    /// Whether draws using the mode depend on what is behind them. They do
+    /// not write depth and are drawn after opaque geometry.
+    pub fn is_transparent(&self) -> bool {
+        match *self {
+            BlendMode::Opaque | BlendMode::AlphaTested => false,
+            _ => true,
+        }
+    }
+}
+
+impl Default for BlendMode {
+    fn default() -> BlendMode {
+        BlendMode::Opaque
+    }
+}
+
+/// Sets up the fixed function state matching what a pipeline draws.
+pub trait PipelineBuilderExt: Sized {
+    fn topology(self, topology: Topology) -> Self;
+    fn blend(self, blend: BlendMode) -> Self;
+}
+
+impl<Vdef, Vs, Vss, Tcs, Tcss, Tes, Tess, Gs, Gss, Fs, Fss, Rp> PipelineBuilderExt
+    for vkp::graphics_pipeline::GraphicsPipelineBuilder<Vdef, Vs, Vss, Tcs, Tcss, Tes, Tess,
+                                                         Gs, Gss, Fs, Fss, Rp>
+{
+    fn topology(self, topology: Topology) -> Self {
+        self.primitive_topology(topology.primitive())
+            .primitive_restart(topology.is_strip())
+    }
+
+    fn blend(self, blend: BlendMode) -> Self {
+        self.blend_collective(blend.attachment_blend())
+    }
 }
 
+/// Built pipeline together with the state it was built for, so meshes can
+/// be matched against it.
+#[derive(Clone)]
+pub struct Pipeline {
+    pub id: Arc<vkp::GraphicsPipelineAbstract + Send + Sync>,
+    pub layout: VertexLayout,
+    pub topology: Topology,
+}
+
+impl Pipeline {
+    pub fn new(id: Arc<vkp::GraphicsPipelineAbstract + Send + Sync>,
+               layout: VertexLayout,
+               topology: Topology) -> Pipeline {
+        Pipeline {
+            id: id,
+            layout: layout,
+            topology: topology,
+        }
+    }
+
+    /// Whether meshes with this layout and topology can be drawn with the
+    /// pipeline.
+    pub fn accepts(&self, layout: &VertexLayout, topology: Topology) -> bool {
+        self.layout == *layout && self.topology == topology
+    }
+}
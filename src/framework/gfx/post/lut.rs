@@ -0,0 +1,164 @@
+use vulkano::device as vkd;
+use vulkano::format as vkfmt;
+use vulkano::image as vkim;
+use vulkano::sync::GpuFuture;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::super::super::parse::invalid_line;
+
+/// Color grading lookup table, mapping tone mapped colors to graded ones.
+///
+/// Entries are ordered with red changing fastest, then green, then blue,
+/// as in `.cube` files.
+#[derive(Clone, Debug)]
+pub struct Lut {
+    pub size: u32,
+    pub entries: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    /// Table that leaves colors unchanged. Lookups are filtered linearly,
+    /// so a size of 2 is already exact.
+    pub fn identity(size: u32) -> Result<Lut, ()> {
+        Lut::from_fn(size, |color| color)
+    }
+
+    /// Table sampling `f` on a grid of `size` entries per channel, which
+    /// needs at least 2 to include both 0 and 1.
+    pub fn from_fn<F>(size: u32, f: F) -> Result<Lut, ()>
+        where F: Fn([f32; 3]) -> [f32; 3]
+    {
+        if size < 2 {
+            println!("Failed to create color grading table of size {}", size);
+            return Err(());
+        }
+
+        let max = (size - 1) as f32;
+        let mut entries = Vec::with_capacity((size * size * size) as usize);
+
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    entries.push(f([r as f32 / max, g as f32 / max, b as f32 / max]));
+                }
+            }
+        }
+
+        Ok(Lut {
+            size: size,
+            entries: entries,
+        })
+    }
+
+    /// Reads a 3D table in the `.cube` format exported by most grading
+    /// tools. Domain keywords are ignored, the domain must be 0 to 1.
+    pub fn load_cube<P: AsRef<Path>>(path: P) -> io::Result<Lut> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut size = None;
+        let mut entries = Vec::new();
+
+        for (number, line) in reader.lines().enumerate() {
+            let number = number + 1;
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let first = fields.next().unwrap();
+
+            if first == "LUT_3D_SIZE" {
+                size = Some(fields.next().and_then(|s| s.parse::<u32>().ok())
+                    .and_then(|s| if s >= 2 { Some(s) } else { None })
+                    .ok_or_else(|| invalid_line("color table", number, "bad table size"))?);
+            } else if first.starts_with(|c: char| c.is_alphabetic()) {
+                // TITLE, DOMAIN_MIN, DOMAIN_MAX, LUT_1D_SIZE...
+                if first == "LUT_1D_SIZE" {
+                    return Err(invalid_line("color table", number, "1D tables are not supported"));
+                }
+            } else {
+                let mut entry = [0.0; 3];
+                let values = Some(first).into_iter().chain(fields).map(|v| v.parse::<f32>());
+                let mut count = 0;
+                for value in values {
+                    if count == 3 {
+                        return Err(invalid_line("color table", number, "too many values"));
+                    }
+                    entry[count] = value
+                        .map_err(|_| invalid_line("color table", number, "bad value"))?;
+                    count += 1;
+                }
+                if count != 3 {
+                    return Err(invalid_line("color table", number, "expected three values"));
+                }
+                entries.push(entry);
+            }
+        }
+
+        let size = size.ok_or_else(|| invalid_line("color table", 0, "missing LUT_3D_SIZE"))?;
+        if entries.len() != (size * size * size) as usize {
+            return Err(invalid_line("color table", 0, "entry count does not match LUT_3D_SIZE"));
+        }
+
+        Ok(Lut {
+            size: size,
+            entries: entries,
+        })
+    }
+
+    /// Uploads the table as a 3D image and waits for the upload to finish.
+    pub fn upload(&self, queue: Arc<vkd::Queue>)
+                  -> Result<Arc<vkim::ImmutableImage<vkfmt::R8G8B8A8Unorm>>, ()> {
+        let texels = self.entries.iter().map(|c| {
+            let byte = |v: f32| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+            [byte(c[0]), byte(c[1]), byte(c[2]), 255]
+        }).collect::<Vec<_>>();
+
+        let (image, future) = vkim::ImmutableImage::from_iter(
+            texels.into_iter(),
+            vkim::Dimensions::Dim3d {
+                width: self.size,
+                height: self.size,
+                depth: self.size,
+            },
+            vkfmt::R8G8B8A8Unorm,
+            queue
+        ).map_err(|e| println!("Failed to create color grading table ({:?})", e))?;
+
+        future.then_signal_fence_and_flush()
+            .map_err(|e| println!("Failed to upload color grading table ({:?})", e))?
+            .wait(None)
+            .map_err(|e| println!("Failed to upload color grading table ({:?})", e))?;
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_with_red_changing_fastest() {
+        let lut = Lut::from_fn(3, |c| [c[0], c[1] * 2.0, c[2] * 3.0]).unwrap();
+        assert_eq!(lut.entries.len(), 27);
+        assert_eq!(lut.entries[0], [0.0, 0.0, 0.0]);
+        assert_eq!(lut.entries[1], [0.5, 0.0, 0.0]);
+        assert_eq!(lut.entries[3], [0.0, 1.0, 0.0]);
+        assert_eq!(lut.entries[9], [0.0, 0.0, 1.5]);
+        assert_eq!(lut.entries[26], [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn rejects_tables_without_both_ends() {
+        assert!(Lut::identity(0).is_err());
+        assert!(Lut::identity(1).is_err());
+        assert_eq!(Lut::identity(2).unwrap().entries[7], [1.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,291 @@
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::device as vkd;
+use vulkano::format as vkfmt;
+use vulkano::image as vkim;
+use vulkano::sampler as vksm;
+
+use std::sync::Arc;
+
+use super::compute::{self, ComputePipeline};
+use super::core::Core;
+use super::scene_target::SceneTarget;
+use super::swapchain::Dimensions;
+
+pub mod lut;
+mod shaders;
+
+pub use self::lut::Lut;
+
+/// Effects of the post-processing chain, read every frame so they can be
+/// changed at any time.
+#[derive(Clone, Copy, Debug)]
+pub struct PostSettings {
+    /// Map HDR colors to the displayable range with a filmic curve instead
+    /// of clamping them.
+    pub tonemap: bool,
+    /// Scales the scene before tone mapping.
+    pub exposure: f32,
+    pub bloom: bool,
+    /// Brightness above which pixels bloom.
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub fxaa: bool,
+    /// Look the tone mapped colors up in the color grading table.
+    pub color_grading: bool,
+    pub vignette: bool,
+    /// Darkening of the corners, from 0 to 1.
+    pub vignette_strength: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> PostSettings {
+        PostSettings {
+            tonemap: true,
+            exposure: 1.0,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.5,
+            fxaa: true,
+            color_grading: true,
+            vignette: true,
+            vignette_strength: 0.4,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct BrightParams {
+    threshold: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct BlurParams {
+    direction: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CompositeParams {
+    exposure: f32,
+    bloom_intensity: f32,
+    vignette: f32,
+    flags: u32,
+}
+
+const TONEMAP: u32 = 1;
+const GRADING: u32 = 2;
+
+type PostImage = vkim::StorageImage<vkfmt::Format>;
+
+/// Images and descriptor sets for one size of the scene target.
+struct Targets {
+    scene: Arc<vkim::AttachmentImage<vkfmt::Format>>,
+    dimensions: Dimensions,
+    bloom_dimensions: Dimensions,
+    /// Tone mapped, graded and vignetted scene.
+    composited: Arc<PostImage>,
+    antialiased: Arc<PostImage>,
+    bright_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    blur_x_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    blur_y_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    composite_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    fxaa_set: Arc<vkds::DescriptorSet + Send + Sync>,
+}
+
+/// Turns the HDR scene target into the image shown on screen.
+///
+/// Bloom is extracted and blurred at half resolution and added to the scene
+/// before tone mapping, color grading and vignetting, which happen in one
+/// pass. FXAA runs last, on tone mapped colors. Every step is a compute
+/// dispatch recorded into the frame's command buffer, and the result is
+/// blitted to the swapchain image.
+pub struct PostChain {
+    pub settings: PostSettings,
+    device: Arc<vkd::Device>,
+    queue_family: u32,
+    bright: ComputePipeline,
+    blur: ComputePipeline,
+    composite: ComputePipeline,
+    fxaa: ComputePipeline,
+    sampler: Arc<vksm::Sampler>,
+    lut: Arc<vkim::ImmutableImage<vkfmt::R8G8B8A8Unorm>>,
+    targets: Option<Targets>,
+}
+
+impl PostChain {
+    pub fn new(core: &Core) -> Result<PostChain, ()> {
+        let device = core.device.clone();
+
+        let bright = shaders::bright_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load bloom threshold shader ({:?})", e))?;
+        let blur = shaders::blur_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load blur shader ({:?})", e))?;
+        let composite = shaders::composite_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load composite shader ({:?})", e))?;
+        let fxaa = shaders::fxaa_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load FXAA shader ({:?})", e))?;
+
+        let sampler = vksm::Sampler::new(device.clone(),
+                                         vksm::Filter::Linear,
+                                         vksm::Filter::Linear,
+                                         vksm::MipmapMode::Nearest,
+                                         vksm::SamplerAddressMode::ClampToEdge,
+                                         vksm::SamplerAddressMode::ClampToEdge,
+                                         vksm::SamplerAddressMode::ClampToEdge,
+                                         0.0, 1.0, 0.0, 0.0)
+            .map_err(|e| println!("Failed to create post-processing sampler ({:?})", e))?;
+
+        Ok(PostChain {
+            settings: PostSettings::default(),
+            queue_family: core.queue.family().id(),
+            bright: ComputePipeline::build(device.clone(), &bright.main_entry_point(), [8, 8, 1])?,
+            blur: ComputePipeline::build(device.clone(), &blur.main_entry_point(), [8, 8, 1])?,
+            composite: ComputePipeline::build(device.clone(), &composite.main_entry_point(),
+                                              [8, 8, 1])?,
+            fxaa: ComputePipeline::build(device.clone(), &fxaa.main_entry_point(), [8, 8, 1])?,
+            sampler: sampler,
+            lut: Lut::identity(2)?.upload(core.queue.clone())?,
+            targets: None,
+            device: device,
+        })
+    }
+
+    /// Replaces the color grading table, waiting for its upload.
+    pub fn set_lut(&mut self, lut: &Lut, queue: Arc<vkd::Queue>) -> Result<(), ()> {
+        self.lut = lut.upload(queue)?;
+        self.targets = None;
+        Ok(())
+    }
+
+    /// Records the chain for the scene target, after its render pass ended.
+    /// Returns the image to show, the size of the scene target.
+    pub fn record(&mut self,
+                  builder: vkcb::AutoCommandBufferBuilder,
+                  scene: &SceneTarget)
+                  -> Result<(vkcb::AutoCommandBufferBuilder, Arc<vkim::StorageImage<vkfmt::Format>>), ()> {
+        let stale = match self.targets {
+            Some(ref targets) => !Arc::ptr_eq(&targets.scene, &scene.color),
+            None => true,
+        };
+        if stale {
+            self.targets = Some(self.create_targets(scene)?);
+        }
+
+        let settings = self.settings;
+        let targets = self.targets.as_ref().unwrap();
+        let size = [targets.dimensions.width, targets.dimensions.height, 1];
+        let bloom_size = [targets.bloom_dimensions.width, targets.bloom_dimensions.height, 1];
+        let mut builder = builder;
+
+        if settings.bloom {
+            builder = dispatch(builder, &self.bright, bloom_size, targets.bright_set.clone(),
+                               BrightParams { threshold: settings.bloom_threshold })?;
+            builder = dispatch(builder, &self.blur, bloom_size, targets.blur_x_set.clone(),
+                               BlurParams { direction: [1.0, 0.0] })?;
+            builder = dispatch(builder, &self.blur, bloom_size, targets.blur_y_set.clone(),
+                               BlurParams { direction: [0.0, 1.0] })?;
+        }
+
+        let mut flags = 0;
+        if settings.tonemap {
+            flags |= TONEMAP;
+        }
+        if settings.color_grading {
+            flags |= GRADING;
+        }
+        builder = dispatch(builder, &self.composite, size, targets.composite_set.clone(),
+                           CompositeParams {
+                               exposure: settings.exposure,
+                               bloom_intensity: if settings.bloom { settings.bloom_intensity } else { 0.0 },
+                               vignette: if settings.vignette { settings.vignette_strength } else { 0.0 },
+                               flags: flags,
+                           })?;
+
+        if !settings.fxaa {
+            return Ok((builder, targets.composited.clone()));
+        }
+
+        builder = dispatch(builder, &self.fxaa, size, targets.fxaa_set.clone(), ())?;
+        Ok((builder, targets.antialiased.clone()))
+    }
+
+    fn create_targets(&self, scene: &SceneTarget) -> Result<Targets, ()> {
+        let dimensions = scene.dimensions;
+        let bloom_dimensions = Dimensions {
+            width: (dimensions.width / 2).max(1),
+            height: (dimensions.height / 2).max(1),
+        };
+
+        let image = |dims: Dimensions| {
+            compute::storage_image(self.device.clone(), self.queue_family,
+                                   vkfmt::Format::R16G16B16A16Sfloat,
+                                   dims.width, dims.height)
+        };
+        let bloom_a = image(bloom_dimensions)?;
+        let bloom_b = image(bloom_dimensions)?;
+        let composited = image(dimensions)?;
+        let antialiased = image(dimensions)?;
+
+        let bright_set = vkds::PersistentDescriptorSet::start(self.bright.id.clone(), 0)
+            .add_sampled_image(scene.color.clone(), self.sampler.clone())
+            .and_then(|s| s.add_image(bloom_a.clone()))
+            .map_err(|e| println!("Failed to bind bloom images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create bloom set ({:?})", e))?;
+
+        let blur_x_set = vkds::PersistentDescriptorSet::start(self.blur.id.clone(), 0)
+            .add_sampled_image(bloom_a.clone(), self.sampler.clone())
+            .and_then(|s| s.add_image(bloom_b.clone()))
+            .map_err(|e| println!("Failed to bind blur images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create blur set ({:?})", e))?;
+
+        let blur_y_set = vkds::PersistentDescriptorSet::start(self.blur.id.clone(), 0)
+            .add_sampled_image(bloom_b.clone(), self.sampler.clone())
+            .and_then(|s| s.add_image(bloom_a.clone()))
+            .map_err(|e| println!("Failed to bind blur images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create blur set ({:?})", e))?;
+
+        let composite_set = vkds::PersistentDescriptorSet::start(self.composite.id.clone(), 0)
+            .add_sampled_image(scene.color.clone(), self.sampler.clone())
+            .and_then(|s| s.add_sampled_image(bloom_a.clone(), self.sampler.clone()))
+            .and_then(|s| s.add_sampled_image(self.lut.clone(), self.sampler.clone()))
+            .and_then(|s| s.add_image(composited.clone()))
+            .map_err(|e| println!("Failed to bind composite images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create composite set ({:?})", e))?;
+
+        let fxaa_set = vkds::PersistentDescriptorSet::start(self.fxaa.id.clone(), 0)
+            .add_sampled_image(composited.clone(), self.sampler.clone())
+            .and_then(|s| s.add_image(antialiased.clone()))
+            .map_err(|e| println!("Failed to bind FXAA images ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create FXAA set ({:?})", e))?;
+
+        Ok(Targets {
+            scene: scene.color.clone(),
+            dimensions: dimensions,
+            bloom_dimensions: bloom_dimensions,
+            composited: composited,
+            antialiased: antialiased,
+            bright_set: Arc::new(bright_set),
+            blur_x_set: Arc::new(blur_x_set),
+            blur_y_set: Arc::new(blur_y_set),
+            composite_set: Arc::new(composite_set),
+            fxaa_set: Arc::new(fxaa_set),
+        })
+    }
+}
+
+fn dispatch<Pc>(builder: vkcb::AutoCommandBufferBuilder,
+                pipeline: &ComputePipeline,
+                size: [u32; 3],
+                set: Arc<vkds::DescriptorSet + Send + Sync>,
+                constants: Pc) -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+    builder.dispatch(pipeline.groups(size), pipeline.id.clone(), set, constants)
+        .map_err(|e| println!("Failed to record post-processing pass ({:?})", e))
+}
@@ -0,0 +1,196 @@
+//! Compute shaders of the post-processing chain. They all run in 8x8
+//! workgroups, one invocation per output pixel.
+
+pub mod bright_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D bright;
+
+layout(push_constant) uniform Bright {
+    float threshold;
+} params;
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(bright);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    // Half resolution, the linear filter averages 2x2 scene pixels
+    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+    vec3 color = texture(scene, uv).rgb;
+
+    float brightness = max(color.r, max(color.g, color.b));
+    float contribution = max(brightness - params.threshold, 0.0) / max(brightness, 0.0001);
+    imageStore(bright, pixel, vec4(color * contribution, 1.0));
+}
+"]
+    struct Dummy;
+}
+
+pub mod blur_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D target;
+
+layout(push_constant) uniform Blur {
+    vec2 direction;
+} params;
+
+// 9 tap gaussian folded into 5 linearly filtered taps
+const float OFFSETS[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(target);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    vec2 texel = 1.0 / vec2(size);
+    vec2 uv = (vec2(pixel) + 0.5) * texel;
+    vec2 offset = params.direction * texel;
+
+    vec3 color = texture(source, uv).rgb * WEIGHTS[0];
+    for (int i = 1; i < 3; i++) {
+        color += texture(source, uv + offset * OFFSETS[i]).rgb * WEIGHTS[i];
+        color += texture(source, uv - offset * OFFSETS[i]).rgb * WEIGHTS[i];
+    }
+
+    imageStore(target, pixel, vec4(color, 1.0));
+}
+"]
+    struct Dummy;
+}
+
+pub mod composite_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1) uniform sampler2D bloom;
+layout(set = 0, binding = 2) uniform sampler3D lut;
+layout(set = 0, binding = 3, rgba16f) uniform writeonly image2D target;
+
+const uint TONEMAP = 1;
+const uint GRADING = 2;
+
+layout(push_constant) uniform Composite {
+    float exposure;
+    float bloom_intensity;
+    float vignette;
+    uint flags;
+} params;
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(target);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+    vec3 color = texture(scene, uv).rgb + texture(bloom, uv).rgb * params.bloom_intensity;
+    color *= params.exposure;
+
+    if ((params.flags & TONEMAP) != 0) {
+        color = aces(color);
+    } else {
+        color = clamp(color, 0.0, 1.0);
+    }
+
+    if ((params.flags & GRADING) != 0) {
+        float lut_size = float(textureSize(lut, 0).x);
+        color = texture(lut, color * (lut_size - 1.0) / lut_size + 0.5 / lut_size).rgb;
+    }
+
+    float edge = length(uv - 0.5) * 1.41421356;
+    color *= 1.0 - params.vignette * smoothstep(0.4, 1.0, edge);
+
+    // FXAA reads perceptual luma from alpha
+    float luma = sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+    imageStore(target, pixel, vec4(color, luma));
+}
+"]
+    struct Dummy;
+}
+
+pub mod fxaa_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+layout(set = 0, binding = 1, rgba16f) uniform writeonly image2D target;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float SPAN_MAX = 8.0;
+
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(target);
+    if (pixel.x >= size.x || pixel.y >= size.y) {
+        return;
+    }
+
+    vec2 texel = 1.0 / vec2(size);
+    vec2 uv = (vec2(pixel) + 0.5) * texel;
+
+    vec4 center = texture(source, uv);
+    float nw = texture(source, uv + vec2(-1.0, -1.0) * texel).a;
+    float ne = texture(source, uv + vec2(1.0, -1.0) * texel).a;
+    float sw = texture(source, uv + vec2(-1.0, 1.0) * texel).a;
+    float se = texture(source, uv + vec2(1.0, 1.0) * texel).a;
+
+    float luma_min = min(center.a, min(min(nw, ne), min(sw, se)));
+    float luma_max = max(center.a, max(max(nw, ne), max(sw, se)));
+
+    // Blur along the edge, perpendicular to the luma gradient
+    vec2 dir = vec2(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    float reduce = max((nw + ne + sw + se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, -SPAN_MAX, SPAN_MAX) * texel;
+
+    vec3 a = 0.5 * (texture(source, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+                    texture(source, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 b = a * 0.5 + 0.25 * (texture(source, uv - dir * 0.5).rgb +
+                               texture(source, uv + dir * 0.5).rgb);
+
+    float luma_b = luma(b);
+    vec3 color = (luma_b < luma_min || luma_b > luma_max) ? a : b;
+    imageStore(target, pixel, vec4(color, 1.0));
+}
+"]
+    struct Dummy;
+}
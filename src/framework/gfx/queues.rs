@@ -0,0 +1,94 @@
+use vulkano::device as vkd;
+use vulkano::instance as vki;
+use vulkano::swapchain as vks;
+
+use std::sync::Arc;
+
+/// Queue families picked for a device.
+///
+/// Transfer and compute families are only set when the device has families
+/// dedicated to that work. Otherwise that work goes to the graphics queue.
+pub struct QueueFamilies<'a> {
+    pub graphics: vki::QueueFamily<'a>,
+    pub transfer: Option<vki::QueueFamily<'a>>,
+    pub compute: Option<vki::QueueFamily<'a>>,
+}
+
+impl<'a> QueueFamilies<'a> {
+    /// Finds a family that can draw and present to `surface`, plus the most
+    /// specialized transfer and compute families that are not the graphics
+    /// one.
+    pub fn find(physical: vki::PhysicalDevice<'a>,
+                surface: &vks::Surface) -> Option<QueueFamilies<'a>> {
+        let graphics = physical.queue_families().find(|&q| {
+            q.supports_graphics() && surface.is_supported(q).unwrap_or(false)
+        })?;
+
+        // Transfer-only families usually map to the DMA engines
+        let transfer = physical.queue_families()
+            .filter(|q| q.id() != graphics.id() && q.supports_transfers())
+            .min_by_key(|q| (q.supports_graphics(), q.supports_compute()));
+
+        let compute = physical.queue_families()
+            .filter(|q| q.id() != graphics.id() && q.supports_compute())
+            .min_by_key(|q| q.supports_graphics());
+
+        Some(QueueFamilies {
+            graphics: graphics,
+            transfer: transfer,
+            compute: compute,
+        })
+    }
+
+    /// Families and priorities to create the device with, one queue each.
+    ///
+    /// A family picked for both transfer and compute only gets one queue,
+    /// which both kinds of work then share.
+    pub fn requests(&self) -> Vec<(vki::QueueFamily<'a>, f32)> {
+        let mut requests = vec![(self.graphics, 0.5)];
+        for family in self.transfer.iter().chain(self.compute.iter()) {
+            if requests.iter().all(|&(f, _)| f.id() != family.id()) {
+                requests.push((*family, 0.5));
+            }
+        }
+        requests
+    }
+}
+
+/// Queues of the device.
+///
+/// `transfer` and `compute` are the graphics queue on devices without
+/// dedicated families, so callers never need to check.
+#[derive(Clone)]
+pub struct Queues {
+    pub graphics: Arc<vkd::Queue>,
+    pub transfer: Arc<vkd::Queue>,
+    pub compute: Arc<vkd::Queue>,
+}
+
+impl Queues {
+    /// Sorts the queues returned by `Device::new` for `families.requests()`.
+    pub fn new<I>(families: &QueueFamilies, queues: I) -> Queues
+        where I: Iterator<Item = Arc<vkd::Queue>>
+    {
+        let queues = queues.collect::<Vec<_>>();
+        let find = |family: Option<vki::QueueFamily>| {
+            family.and_then(|f| queues.iter().find(|q| q.family().id() == f.id()).cloned())
+                .unwrap_or_else(|| queues[0].clone())
+        };
+
+        Queues {
+            graphics: queues[0].clone(),
+            transfer: find(families.transfer),
+            compute: find(families.compute),
+        }
+    }
+
+    pub fn has_dedicated_transfer(&self) -> bool {
+        self.transfer.family().id() != self.graphics.family().id()
+    }
+
+    pub fn has_async_compute(&self) -> bool {
+        self.compute.family().id() != self.graphics.family().id()
+    }
+}
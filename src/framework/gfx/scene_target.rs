@@ -0,0 +1,51 @@
+use vulkano::device as vkd;
+use vulkano::image as vkim;
+use vulkano::format as vkfmt;
+
+use std::sync::Arc;
+
+use super::graph::{GraphTargets, RenderGraph};
+use super::swapchain::Dimensions;
+
+/// Offscreen images the 3D scene is rendered into, created from the scene
+/// render graph.
+///
+/// The color image holds HDR values, which the post-processing chain maps
+/// to the display range. The scene target can be smaller than the
+/// swapchain, in which case the result is upscaled when copied to the
+/// swapchain image.
+pub struct SceneTarget {
+    pub targets: GraphTargets,
+    pub color: Arc<vkim::attachment::AttachmentImage<vkfmt::Format>>,
+    pub dimensions: Dimensions,
+}
+
+impl SceneTarget {
+    pub fn new(device: Arc<vkd::Device>,
+               graph: &RenderGraph,
+               width: u32,
+               height: u32) -> Result<SceneTarget, ()> {
+        let dimensions = Dimensions {
+            width: width,
+            height: height,
+        };
+        let targets = graph.targets(device, dimensions)?;
+
+        let color = graph.attachment("color")
+            .and_then(|id| targets.image(id))
+            .ok_or_else(|| println!("The scene graph has no color attachment"))?;
+
+        Ok(SceneTarget {
+            targets: targets,
+            color: color,
+            dimensions: dimensions,
+        })
+    }
+}
+
+/// Size of the scene target for a window of the given size.
+pub fn scaled_dimensions(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    let scale = scale.max(0.1).min(1.0);
+    (((width as f32 * scale) as u32).max(1),
+     ((height as f32 * scale) as u32).max(1))
+}
@@ -1,21 +1,41 @@
 use vulkano_win;
 use vulkano::device as vkd;
 use vulkano::swapchain as vks;
-use vulkano::framebuffer as vkfb;
 use vulkano::image as vkim;
-use vulkano::format as vkfmt;
 
-use std::sync::{Arc,RwLock};
+use std::sync::Arc;
 use vulkano::swapchain::{AcquireError, SwapchainAcquireFuture};
 
+#[derive(Clone, Copy, Debug)]
 pub struct Dimensions {
     pub width: u32,
     pub height: u32,
 }
 
+/// Reasons the swapchain could not be recreated.
+#[derive(Debug)]
+pub enum RefreshError {
+    /// The window has no area, usually because it is minimized.
+    Minimized,
+    /// The surface does not accept the window size yet, which happens while
+    /// the user is resizing the window. Try again on a later frame.
+    UnsupportedDimensions,
+    /// The surface is gone and has to be recreated with its window.
+    SurfaceLost,
+    /// Anything else, already printed.
+    Other,
+}
+
+pub struct AcquiredFrame {
+    pub index: usize,
+    pub image: Arc<vkim::swapchain::SwapchainImage>,
+    pub future: SwapchainAcquireFuture,
+    /// The image can still be presented, but the swapchain no longer
+    /// matches the surface and should be recreated.
+    pub suboptimal: bool,
+}
+
 pub struct Swapchain {
-    pub framebuffers: Vec<Arc<vkfb::FramebufferAbstract + Send + Sync>>,
-    pub depth_buffer: Arc<vkim::attachment::AttachmentImage<vkfmt::D16Unorm>>,
     pub images: Vec<Arc<vkim::swapchain::SwapchainImage>>,
     pub id: Arc<vks::Swapchain>,
 }
@@ -24,12 +44,9 @@ impl Swapchain {
     pub fn new(device: Arc<vkd::Device>,
                queue: Arc<vkd::Queue>,
                window: Arc<vulkano_win::Window>,
-               render_pass: Arc<vkfb::RenderPassAbstract + Send + Sync>,
                surface_capabilities: Arc<vks::Capabilities>,
                width: u32,
-               height: u32) -> Result<Arc<RwLock<Swapchain>>, ()> {
-        // Create Swapchain
-        
+               height: u32) -> Result<Swapchain, vks::SwapchainCreationError> {
         let dims = [width, height];
 
         let (swapchain, images) = {
@@ -50,78 +67,54 @@ impl Swapchain {
                 vks::PresentMode::Fifo,
                 true,
                 None
-            ).expect("failed to create swapchain")
+            )?
         };
 
-        // Create Depth Buffer
-
-        let depth_buffer = vkim::attachment::AttachmentImage::transient(
-            device.clone(),
-            dims,
-            vkfmt::D16Unorm
-        ).unwrap();
-
-        // Create Framebuffers
-
-        let framebuffers = images.iter().map(|image| {
-            let fb = vkfb::Framebuffer::start(render_pass.clone())
-                     .add(image.clone()).unwrap()
-                     .add(depth_buffer.clone()).unwrap()
-                     .build().unwrap();
-            Arc::new(fb) as Arc<vkfb::FramebufferAbstract + Send + Sync>
-        }).collect::<Vec<_>>();
-
-        Ok(Arc::new(RwLock::new(Swapchain {
-            framebuffers: framebuffers,
-            depth_buffer: depth_buffer,
+        Ok(Swapchain {
             images: images,
             id: swapchain,
-        })))
+        })
     }
 
-    pub fn acquire_next_framebuffer(&self) -> Result<(usize, Arc<vkfb::FramebufferAbstract + Send + Sync>, SwapchainAcquireFuture), AcquireError> {
+    pub fn acquire_next_image(&self) -> Result<AcquiredFrame, AcquireError> {
         match vks::acquire_next_image(self.id.clone(), None) {
-            Ok((idx, future)) => Ok((idx, self.framebuffers[idx].clone(), future)),
+            Ok((idx, future)) => Ok(AcquiredFrame {
+                index: idx,
+                image: self.images[idx].clone(),
+                future: future,
+                suboptimal: false,
+            }),
             Err(err) => Err(err),
         }
     }
 
     pub fn refresh(&mut self,
-                   device: Arc<vkd::Device>,
-                   render_pass: Arc<vkfb::RenderPassAbstract + Send + Sync>,
                    width: u32,
-                   height: u32) -> bool {
+                   height: u32) -> Result<(), RefreshError> {
+        if width == 0 || height == 0 {
+            return Err(RefreshError::Minimized);
+        }
+
         let dims = [width, height];
 
         let (new_swapchain, new_images) = match self.id.recreate_with_dimension(dims) {
             Ok(r) => r,
             // This seems to happen when the user is manually resizing the window.
             Err(vks::SwapchainCreationError::UnsupportedDimensions) => {
-                return false;
+                return Err(RefreshError::UnsupportedDimensions);
+            },
+            Err(vks::SwapchainCreationError::SurfaceLost) => {
+                return Err(RefreshError::SurfaceLost);
+            },
+            Err(err) => {
+                println!("Failed to recreate swapchain ({:?})", err);
+                return Err(RefreshError::Other);
             },
-            Err(err) => panic!("{:?}", err)
         };
 
-        let new_depth_buffer = vkim::attachment::AttachmentImage
-            ::transient(
-                device.clone(),
-                dims,
-                vkfmt::D16Unorm
-            ).unwrap();
-
-        let new_framebuffers = new_images.iter().map(|image| {
-            let fb = vkfb::Framebuffer::start(render_pass.clone())
-                .add(image.clone()).unwrap()
-                .add(new_depth_buffer.clone()).unwrap()
-                .build().unwrap();
-            Arc::new(fb) as Arc<vkfb::FramebufferAbstract + Send + Sync>
-        }).collect::<Vec<_>>();
-
-        self.framebuffers = new_framebuffers;
-        self.depth_buffer = new_depth_buffer;
         self.images = new_images;
         self.id = new_swapchain;
 
-        true
+        Ok(())
     }
 }
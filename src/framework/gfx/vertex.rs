@@ -0,0 +1,245 @@
+use vulkano::buffer::BufferAccess;
+use vulkano::format::Format;
+use vulkano::pipeline::shader::ShaderInterfaceDef;
+use vulkano::pipeline::vertex as vkv;
+
+use std::mem;
+use std::slice;
+use std::sync::Arc;
+use std::vec::IntoIter as VecIntoIter;
+
+use super::buffer::ArenaSlice;
+
+/// Vertex attributes a mesh can provide.
+///
+/// Shaders pick attributes up by the name of their input variable, e.g.
+/// `layout(location = 0) in vec3 position;`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Attribute {
+    Position,
+    Normal,
+    /// Tangent with the bitangent sign in `w`.
+    Tangent,
+    Uv0,
+    Uv1,
+    Color,
+    Joints,
+    Weights,
+}
+
+impl Attribute {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Attribute::Position => "position",
+            Attribute::Normal => "normal",
+            Attribute::Tangent => "tangent",
+            Attribute::Uv0 => "uv0",
+            Attribute::Uv1 => "uv1",
+            Attribute::Color => "color",
+            Attribute::Joints => "joints",
+            Attribute::Weights => "weights",
+        }
+    }
+
+    /// Format of the attribute in the vertex buffer.
+    pub fn format(&self) -> Format {
+        match *self {
+            Attribute::Position | Attribute::Normal => Format::R32G32B32Sfloat,
+            Attribute::Uv0 | Attribute::Uv1 => Format::R32G32Sfloat,
+            Attribute::Tangent | Attribute::Color | Attribute::Weights =>
+                Format::R32G32B32A32Sfloat,
+            Attribute::Joints => Format::R16G16B16A16Uint,
+        }
+    }
+
+    /// Format of the matching shader input.
+    pub fn shader_format(&self) -> Format {
+        match *self {
+            Attribute::Joints => Format::R32G32B32A32Uint,
+            _ => self.format(),
+        }
+    }
+
+    fn member(&self) -> (vkv::VertexMemberTy, usize) {
+        match *self {
+            Attribute::Position | Attribute::Normal => (vkv::VertexMemberTy::F32, 3),
+            Attribute::Uv0 | Attribute::Uv1 => (vkv::VertexMemberTy::F32, 2),
+            Attribute::Tangent | Attribute::Color | Attribute::Weights =>
+                (vkv::VertexMemberTy::F32, 4),
+            Attribute::Joints => (vkv::VertexMemberTy::U16, 4),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.format().size().unwrap()
+    }
+}
+
+/// Interleaved vertex layout, built at runtime from the attributes a
+/// material needs.
+///
+/// The layout doubles as the vulkano vertex definition of pipelines that
+/// draw vertices stored in this layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexLayout {
+    attributes: Vec<(Attribute, usize)>,
+    stride: usize,
+}
+
+impl VertexLayout {
+    /// Interleaves `attributes` in the given order.
+    pub fn new(attributes: &[Attribute]) -> VertexLayout {
+        let mut offset = 0;
+        let attributes = attributes.iter().map(|&a| {
+            let entry = (a, offset);
+            offset += a.size();
+            entry
+        }).collect();
+
+        VertexLayout {
+            attributes: attributes,
+            stride: offset,
+        }
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn attributes<'a>(&'a self) -> Box<Iterator<Item = Attribute> + 'a> {
+        Box::new(self.attributes.iter().map(|&(a, _)| a))
+    }
+
+    pub fn has(&self, attribute: Attribute) -> bool {
+        self.offset(attribute).is_some()
+    }
+
+    pub fn offset(&self, attribute: Attribute) -> Option<usize> {
+        self.attributes.iter()
+            .find(|&&(a, _)| a == attribute)
+            .map(|&(_, offset)| offset)
+    }
+
+    fn find(&self, name: &str) -> Option<(Attribute, usize)> {
+        self.attributes.iter().cloned().find(|&(a, _)| a.name() == name)
+    }
+}
+
+unsafe impl<I> vkv::VertexDefinition<I> for VertexLayout
+    where I: ShaderInterfaceDef
+{
+    type BuffersIter = VecIntoIter<(u32, usize, vkv::InputRate)>;
+    type AttribsIter = VecIntoIter<(u32, u32, vkv::AttributeInfo)>;
+
+    fn definition(&self, interface: &I)
+                  -> Result<(Self::BuffersIter, Self::AttribsIter),
+                            vkv::IncompatibleVertexDefinitionError> {
+        let mut attribs = Vec::with_capacity(interface.elements().len());
+
+        for e in interface.elements() {
+            let name = e.name.as_ref().unwrap();
+
+            let (attribute, offset) = match self.find(name) {
+                Some(found) => found,
+                None => return Err(vkv::IncompatibleVertexDefinitionError::MissingAttribute {
+                    attribute: name.clone().into_owned(),
+                }),
+            };
+
+            if e.format != attribute.shader_format() || e.location.end - e.location.start != 1 {
+                return Err(vkv::IncompatibleVertexDefinitionError::FormatMismatch {
+                    attribute: name.clone().into_owned(),
+                    shader: (e.format, (e.location.end - e.location.start) as usize),
+                    definition: attribute.member(),
+                });
+            }
+
+            attribs.push((e.location.start, 0, vkv::AttributeInfo {
+                offset: offset,
+                format: attribute.format(),
+            }));
+        }
+
+        let buffers = vec![(0, self.stride, vkv::InputRate::Vertex)];
+        Ok((buffers.into_iter(), attribs.into_iter()))
+    }
+}
+
+unsafe impl vkv::VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for VertexLayout {
+    fn decode(&self, mut source: Vec<Arc<BufferAccess + Send + Sync>>)
+              -> (Vec<Box<BufferAccess + Send + Sync>>, usize, usize) {
+        assert_eq!(source.len(), 1);
+        let len = source[0].size() / self.stride;
+        (vec![Box::new(source.remove(0))], len, 1)
+    }
+}
+
+unsafe impl vkv::VertexSource<ArenaSlice<u8>> for VertexLayout {
+    fn decode(&self, source: ArenaSlice<u8>)
+              -> (Vec<Box<BufferAccess + Send + Sync>>, usize, usize) {
+        let len = source.size() / self.stride;
+        (vec![Box::new(source) as Box<_>], len, 1)
+    }
+}
+
+/// Per-attribute vertex data as produced by mesh loaders.
+///
+/// Loaders fill in what the source file provides. `interleave` then writes
+/// the attributes a layout asks for, using defaults for missing streams.
+#[derive(Clone, Debug, Default)]
+pub struct VertexStreams {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 4]>,
+    pub uv0: Vec<[f32; 2]>,
+    pub uv1: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+}
+
+impl VertexStreams {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn interleave(&self, layout: &VertexLayout) -> Vec<u8> {
+        let mut data = vec![0u8; self.len() * layout.stride()];
+
+        for (idx, vertex) in data.chunks_mut(layout.stride()).enumerate() {
+            for &(attribute, offset) in &layout.attributes {
+                let dst = &mut vertex[offset .. offset + attribute.size()];
+                match attribute {
+                    Attribute::Position => copy_bytes(dst, &self.positions[idx]),
+                    Attribute::Normal =>
+                        copy_bytes(dst, self.normals.get(idx).unwrap_or(&[0.0, 0.0, 1.0])),
+                    Attribute::Tangent =>
+                        copy_bytes(dst, self.tangents.get(idx).unwrap_or(&[1.0, 0.0, 0.0, 1.0])),
+                    Attribute::Uv0 =>
+                        copy_bytes(dst, self.uv0.get(idx).unwrap_or(&[0.0, 0.0])),
+                    Attribute::Uv1 =>
+                        copy_bytes(dst, self.uv1.get(idx).unwrap_or(&[0.0, 0.0])),
+                    Attribute::Color =>
+                        copy_bytes(dst, self.colors.get(idx).unwrap_or(&[1.0, 1.0, 1.0, 1.0])),
+                    Attribute::Joints =>
+                        copy_bytes(dst, self.joints.get(idx).unwrap_or(&[0, 0, 0, 0])),
+                    Attribute::Weights =>
+                        copy_bytes(dst, self.weights.get(idx).unwrap_or(&[1.0, 0.0, 0.0, 0.0])),
+                }
+            }
+        }
+
+        data
+    }
+}
+
+fn copy_bytes<T: Copy>(dst: &mut [u8], value: &T) {
+    let src = unsafe {
+        slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+    };
+    dst.copy_from_slice(src);
+}
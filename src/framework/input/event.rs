@@ -0,0 +1,300 @@
+use winit;
+
+use std::fmt;
+use std::str::FromStr;
+
+pub use winit::{ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+/// Window independent copy of the winit events the game cares about.
+///
+/// winit events carry window and device ids that cannot be constructed
+/// outside of winit, so they cannot be written to disk and read back.
+#[derive(Clone, Copy, Debug)]
+pub enum InputEvent {
+    Closed,
+    Resized(u32, u32),
+    Focused(bool),
+    ReceivedCharacter(char),
+    KeyboardInput {
+        scancode: u32,
+        state: ElementState,
+        keycode: Option<VirtualKeyCode>,
+        modifiers: ModifiersState,
+    },
+    MouseMoved(f64, f64),
+    MouseInput {
+        state: ElementState,
+        button: MouseButton,
+    },
+    MouseWheel(MouseScrollDelta),
+    Motion {
+        axis: u32,
+        value: f64,
+    },
+}
+
+impl InputEvent {
+    pub fn from_winit(event: &winit::Event) -> Option<InputEvent> {
+        match *event {
+            winit::Event::WindowEvent { ref event, .. } => match *event {
+                winit::WindowEvent::Closed => Some(InputEvent::Closed),
+                winit::WindowEvent::Resized(w, h) => Some(InputEvent::Resized(w, h)),
+                winit::WindowEvent::Focused(f) => Some(InputEvent::Focused(f)),
+                winit::WindowEvent::ReceivedCharacter(c) => Some(InputEvent::ReceivedCharacter(c)),
+                winit::WindowEvent::KeyboardInput { input, .. } => Some(InputEvent::KeyboardInput {
+                    scancode: input.scancode,
+                    state: input.state,
+                    keycode: input.virtual_keycode,
+                    modifiers: input.modifiers,
+                }),
+                winit::WindowEvent::MouseMoved { position: (x, y), .. } => Some(InputEvent::MouseMoved(x, y)),
+                winit::WindowEvent::MouseInput { state, button, .. } => Some(InputEvent::MouseInput {
+                    state: state,
+                    button: button,
+                }),
+                winit::WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::MouseWheel(delta)),
+                _ => None,
+            },
+            winit::Event::DeviceEvent { event: winit::DeviceEvent::Motion { axis, value }, .. } =>
+                Some(InputEvent::Motion { axis: axis, value: value }),
+            _ => None,
+        }
+    }
+
+    /// Events that describe the host window rather than player input.
+    ///
+    /// These are taken from the live window during a replay instead of
+    /// from the recording.
+    pub fn is_window_event(&self) -> bool {
+        match *self {
+            InputEvent::Closed | InputEvent::Resized(..) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for InputEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputEvent::Closed => write!(f, "closed"),
+            InputEvent::Resized(w, h) => write!(f, "resized {} {}", w, h),
+            InputEvent::Focused(focused) => write!(f, "focused {}", focused),
+            InputEvent::ReceivedCharacter(c) => write!(f, "char {}", c as u32),
+            InputEvent::KeyboardInput { scancode, state, keycode, modifiers } => {
+                write!(f, "key {} {} ", scancode, state_to_str(state))?;
+                match keycode {
+                    Some(code) => write!(f, "{} ", keycode_to_str(code))?,
+                    None => write!(f, "- ")?,
+                }
+                write!(f, "{}{}{}{}",
+                       modifiers.shift as u8,
+                       modifiers.ctrl as u8,
+                       modifiers.alt as u8,
+                       modifiers.logo as u8)
+            },
+            InputEvent::MouseMoved(x, y) => write!(f, "mousemove {} {}", x, y),
+            InputEvent::MouseInput { state, button } => {
+                write!(f, "mousebutton {} ", state_to_str(state))?;
+                match button {
+                    MouseButton::Left => write!(f, "left"),
+                    MouseButton::Right => write!(f, "right"),
+                    MouseButton::Middle => write!(f, "middle"),
+                    MouseButton::Other(n) => write!(f, "{}", n),
+                }
+            },
+            InputEvent::MouseWheel(MouseScrollDelta::LineDelta(x, y)) =>
+                write!(f, "wheel line {} {}", x, y),
+            InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(x, y)) =>
+                write!(f, "wheel pixel {} {}", x, y),
+            InputEvent::Motion { axis, value } => write!(f, "motion {} {}", axis, value),
+        }
+    }
+}
+
+impl FromStr for InputEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<InputEvent, ()> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+
+        let event = match (fields.get(0).cloned(), fields.len()) {
+            (Some("closed"), 1) => InputEvent::Closed,
+            (Some("resized"), 3) => InputEvent::Resized(parse(fields[1])?, parse(fields[2])?),
+            (Some("focused"), 2) => InputEvent::Focused(parse(fields[1])?),
+            (Some("char"), 2) => {
+                let c = ::std::char::from_u32(parse(fields[1])?).ok_or(())?;
+                InputEvent::ReceivedCharacter(c)
+            },
+            (Some("key"), 5) => {
+                let keycode = match fields[3] {
+                    "-" => None,
+                    name => Some(keycode_from_str(name)?),
+                };
+                let mods = fields[4].as_bytes();
+                if mods.len() != 4 {
+                    return Err(());
+                }
+
+                InputEvent::KeyboardInput {
+                    scancode: parse(fields[1])?,
+                    state: state_from_str(fields[2])?,
+                    keycode: keycode,
+                    modifiers: ModifiersState {
+                        shift: mods[0] == b'1',
+                        ctrl: mods[1] == b'1',
+                        alt: mods[2] == b'1',
+                        logo: mods[3] == b'1',
+                    },
+                }
+            },
+            (Some("mousemove"), 3) => InputEvent::MouseMoved(parse(fields[1])?, parse(fields[2])?),
+            (Some("mousebutton"), 3) => {
+                let button = match fields[2] {
+                    "left" => MouseButton::Left,
+                    "right" => MouseButton::Right,
+                    "middle" => MouseButton::Middle,
+                    n => MouseButton::Other(parse(n)?),
+                };
+
+                InputEvent::MouseInput {
+                    state: state_from_str(fields[1])?,
+                    button: button,
+                }
+            },
+            (Some("wheel"), 4) => {
+                let (x, y) = (parse(fields[2])?, parse(fields[3])?);
+                match fields[1] {
+                    "line" => InputEvent::MouseWheel(MouseScrollDelta::LineDelta(x, y)),
+                    "pixel" => InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(x, y)),
+                    _ => return Err(()),
+                }
+            },
+            (Some("motion"), 3) => InputEvent::Motion {
+                axis: parse(fields[1])?,
+                value: parse(fields[2])?,
+            },
+            _ => return Err(()),
+        };
+
+        Ok(event)
+    }
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, ()> {
+    s.parse::<T>().map_err(|_| ())
+}
+
+fn state_to_str(state: ElementState) -> &'static str {
+    match state {
+        ElementState::Pressed => "pressed",
+        ElementState::Released => "released",
+    }
+}
+
+fn state_from_str(s: &str) -> Result<ElementState, ()> {
+    match s {
+        "pressed" => Ok(ElementState::Pressed),
+        "released" => Ok(ElementState::Released),
+        _ => Err(()),
+    }
+}
+
+/// Defines the names keys are recorded by, which are those of their
+/// `VirtualKeyCode` variants.
+///
+/// Recordings name keys rather than store their discriminants, which
+/// change when winit adds keys. A winit with keys missing from this list
+/// fails to compile instead of replaying wrong keys.
+macro_rules! key_names {
+    ($($key:ident,)*) => {
+        fn keycode_to_str(code: VirtualKeyCode) -> &'static str {
+            match code {
+                $(VirtualKeyCode::$key => stringify!($key),)*
+            }
+        }
+
+        fn keycode_from_str(s: &str) -> Result<VirtualKeyCode, ()> {
+            match s {
+                $(stringify!($key) => Ok(VirtualKeyCode::$key),)*
+                _ => Err(()),
+            }
+        }
+    };
+}
+
+key_names! {
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15,
+    Snapshot, Scroll, Pause,
+    Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down,
+    Back, Return, Space,
+    Compose,
+    Numlock, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8,
+    Numpad9,
+    AbntC1, AbntC2, Add, Apostrophe, Apps, At, Ax, Backslash, Calculator, Capital, Colon, Comma,
+    Convert, Decimal, Divide, Equals, Grave, Kana, Kanji, LAlt, LBracket, LControl, LMenu, LShift,
+    LWin, Mail, MediaSelect, MediaStop, Minus, Multiply, Mute, MyComputer, NavigateForward,
+    NavigateBackward, NextTrack, NoConvert, NumpadComma, NumpadEnter, NumpadEquals, OEM102, Period,
+    PlayPause, Power, PrevTrack, RAlt, RBracket, RControl, RMenu, RShift, RWin, Semicolon, Slash,
+    Sleep, Stop, Subtract, Sysrq, Tab, Underline, Unlabeled, VolumeDown, VolumeUp, Wake, WebBack,
+    WebFavorites, WebForward, WebHome, WebRefresh, WebSearch, WebStop, Yen,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(line: &str) -> String {
+        line.parse::<InputEvent>().unwrap().to_string()
+    }
+
+    #[test]
+    fn events_round_trip() {
+        for &line in &["closed",
+                       "resized 1280 720",
+                       "focused false",
+                       "char 233",
+                       "key 30 pressed A 1000",
+                       "key 57 released Space 0101",
+                       "key 99 pressed - 0000",
+                       "mousemove 10.5 -3",
+                       "mousebutton pressed left",
+                       "mousebutton released 4",
+                       "wheel line 0 -1",
+                       "wheel pixel 1.5 2",
+                       "motion 1 -0.25"] {
+            assert_eq!(round_trip(line), line);
+        }
+    }
+
+    #[test]
+    fn keys_round_trip_by_name() {
+        for &code in &[VirtualKeyCode::Key1, VirtualKeyCode::Escape, VirtualKeyCode::F15,
+                       VirtualKeyCode::NumpadEnter, VirtualKeyCode::Yen] {
+            let name = keycode_to_str(code);
+            assert_eq!(keycode_to_str(keycode_from_str(name).unwrap()), name);
+        }
+        assert_eq!(keycode_to_str(VirtualKeyCode::LShift), "LShift");
+    }
+
+    #[test]
+    fn rejects_malformed_events() {
+        for &line in &["",
+                       "closed now",
+                       "resized 1280",
+                       "focused maybe",
+                       "char 55296",
+                       "key 30 pressed 30 0000",
+                       "key 30 pressed Hyper 0000",
+                       "key 30 held A 0000",
+                       "key 30 pressed A 00",
+                       "mousebutton pressed",
+                       "wheel page 0 1",
+                       "teleported 1 2"] {
+            assert!(line.parse::<InputEvent>().is_err(), "{:?} parsed", line);
+        }
+    }
+}
@@ -1,5 +1,129 @@
 use winit;
 
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub mod event;
+pub mod record;
+pub mod replay;
+
+pub use self::event::InputEvent;
+pub use self::record::Recorder;
+pub use self::replay::Replayer;
+
 pub fn make_event_loop() -> winit::EventsLoop {
     winit::EventsLoop::new()
 }
+
+/// Source of input for the game loop.
+///
+/// Input comes either from the live events loop, optionally written to a
+/// recording, or from a recording played back frame by frame. Replays do
+/// not need an events loop, so they can drive a headless session.
+pub struct Input {
+    pub events_loop: Option<winit::EventsLoop>,
+    recorder: Option<Recorder>,
+    replayer: Option<Replayer>,
+    frame: u64,
+    timestamp: Duration,
+    start: Instant,
+}
+
+impl Input {
+    pub fn new() -> Input {
+        Input {
+            events_loop: Some(make_event_loop()),
+            recorder: None,
+            replayer: None,
+            frame: 0,
+            timestamp: Duration::new(0, 0),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn headless() -> Input {
+        Input {
+            events_loop: None,
+            recorder: None,
+            replayer: None,
+            frame: 0,
+            timestamp: Duration::new(0, 0),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn record<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    pub fn replay<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.replayer = Some(Replayer::open(path)?);
+        Ok(())
+    }
+
+    /// Number of the frame the next call to `poll_events` will produce.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Time since the start of the session at the last `poll_events`.
+    ///
+    /// During a replay this is the recorded time, not the wall clock.
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    pub fn is_replay_finished(&self) -> bool {
+        self.replayer.as_ref().map_or(false, |r| r.is_finished())
+    }
+
+    /// Gathers the input for one frame and hands it to `callback`.
+    pub fn poll_events<F>(&mut self, mut callback: F)
+        where F: FnMut(InputEvent)
+    {
+        let frame = self.frame;
+        self.frame += 1;
+
+        let mut live = Vec::new();
+        if let Some(ref mut events_loop) = self.events_loop {
+            events_loop.poll_events(|ev| {
+                if let Some(event) = InputEvent::from_winit(&ev) {
+                    live.push(event);
+                }
+            });
+        }
+
+        match self.replayer {
+            Some(ref mut replayer) => {
+                let (timestamp, recorded) = replayer.next_frame(frame);
+                self.timestamp = timestamp;
+
+                for event in live.into_iter().filter(|e| e.is_window_event()) {
+                    callback(event);
+                }
+                for event in recorded.into_iter().filter(|e| !e.is_window_event()) {
+                    callback(event);
+                }
+            },
+            None => {
+                self.timestamp = self.start.elapsed();
+
+                let failed = match self.recorder {
+                    Some(ref mut recorder) =>
+                        recorder.record_frame(frame, self.timestamp, &live).err(),
+                    None => None,
+                };
+                if let Some(err) = failed {
+                    println!("Failed to record input ({}), recording stopped", err);
+                    self.recorder = None;
+                }
+
+                for event in live {
+                    callback(event);
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use super::event::InputEvent;
+
+pub const HEADER: &'static str = "# wargod input recording v2";
+
+/// Writes the input of a session to a file, one line per frame and event.
+///
+/// ```text
+/// F <frame> <nanoseconds since start>
+/// E <frame> <nanoseconds since start> <event>
+/// ```
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+
+        Ok(Recorder {
+            writer: writer,
+        })
+    }
+
+    pub fn record_frame(&mut self,
+                        frame: u64,
+                        timestamp: Duration,
+                        events: &[InputEvent]) -> io::Result<()> {
+        let nanos = duration_to_nanos(timestamp);
+
+        writeln!(self.writer, "F {} {}", frame, nanos)?;
+        for event in events {
+            writeln!(self.writer, "E {} {} {}", frame, nanos, event)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub fn duration_to_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
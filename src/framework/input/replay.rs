@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+use super::event::InputEvent;
+use super::record::HEADER;
+use super::super::parse::invalid_line;
+
+pub struct RecordedFrame {
+    pub frame: u64,
+    pub timestamp: Duration,
+    pub events: Vec<InputEvent>,
+}
+
+/// Plays back a file written by `Recorder`.
+pub struct Replayer {
+    frames: VecDeque<RecordedFrame>,
+    last_timestamp: Duration,
+}
+
+impl Replayer {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replayer> {
+        Replayer::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Replayer> {
+        let mut lines = reader.lines();
+
+        match lines.next() {
+            Some(Ok(ref line)) if line == HEADER => (),
+            Some(Err(err)) => return Err(err),
+            _ => return Err(invalid_line("input recording", 0, "missing recording header")),
+        }
+
+        let mut frames: VecDeque<RecordedFrame> = VecDeque::new();
+
+        for (number, line) in lines.enumerate() {
+            let number = number + 2;
+            let line = line?;
+            let mut fields = line.splitn(4, ' ');
+
+            let kind = fields.next();
+            let frame = fields.next().and_then(|f| f.parse::<u64>().ok())
+                .ok_or_else(|| invalid_line("input recording", number, "bad frame number"))?;
+            let nanos = fields.next().and_then(|f| f.parse::<u64>().ok())
+                .ok_or_else(|| invalid_line("input recording", number, "bad timestamp"))?;
+
+            match kind {
+                Some("F") => {
+                    if frames.back().map_or(false, |f| f.frame >= frame) {
+                        return Err(invalid_line("input recording", number, "frames out of order"));
+                    }
+
+                    frames.push_back(RecordedFrame {
+                        frame: frame,
+                        timestamp: nanos_to_duration(nanos),
+                        events: Vec::new(),
+                    });
+                },
+                Some("E") => {
+                    let event = fields.next().and_then(|e| e.parse::<InputEvent>().ok())
+                        .ok_or_else(|| invalid_line("input recording", number, "bad event"))?;
+
+                    match frames.back_mut() {
+                        Some(ref mut f) if f.frame == frame => f.events.push(event),
+                        _ => return Err(invalid_line("input recording", number,
+                                                     "event outside of its frame")),
+                    }
+                },
+                _ => return Err(invalid_line("input recording", number, "unknown record")),
+            }
+        }
+
+        Ok(Replayer {
+            frames: frames,
+            last_timestamp: Duration::new(0, 0),
+        })
+    }
+
+    /// Takes the recorded frame with the given number.
+    ///
+    /// Frames must be requested in increasing order. Frames that were not
+    /// recorded produce no events and keep the previous timestamp.
+    pub fn next_frame(&mut self, frame: u64) -> (Duration, Vec<InputEvent>) {
+        while self.frames.front().map_or(false, |f| f.frame < frame) {
+            self.frames.pop_front();
+        }
+
+        if self.frames.front().map_or(false, |f| f.frame == frame) {
+            let recorded = self.frames.pop_front().unwrap();
+            self.last_timestamp = recorded.timestamp;
+            (recorded.timestamp, recorded.events)
+        } else {
+            (self.last_timestamp, Vec::new())
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+fn nanos_to_duration(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::record::Recorder;
+    use std::env;
+    use std::fs;
+
+    fn replayer(lines: &str) -> io::Result<Replayer> {
+        Replayer::read(format!("{}\n{}", HEADER, lines).as_bytes())
+    }
+
+    fn names(events: &[InputEvent]) -> Vec<String> {
+        events.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn plays_frames_in_order() {
+        let mut replayer = replayer("F 0 0\n\
+                                     F 1 16000000\n\
+                                     E 1 16000000 focused true\n\
+                                     E 1 16000000 char 97\n\
+                                     F 3 50000000\n").unwrap();
+
+        let (timestamp, events) = replayer.next_frame(0);
+        assert_eq!(timestamp, Duration::new(0, 0));
+        assert!(events.is_empty());
+        let (timestamp, events) = replayer.next_frame(1);
+        assert_eq!(timestamp, Duration::new(0, 16_000_000));
+        assert_eq!(names(&events), ["focused true", "char 97"]);
+        // Frames that were not recorded keep the previous timestamp
+        let (timestamp, events) = replayer.next_frame(2);
+        assert_eq!(timestamp, Duration::new(0, 16_000_000));
+        assert!(events.is_empty());
+        assert!(!replayer.is_finished());
+        assert_eq!(replayer.next_frame(3).0, Duration::new(0, 50_000_000));
+        assert!(replayer.is_finished());
+    }
+
+    #[test]
+    fn drops_frames_requested_past() {
+        let mut replayer = replayer("F 0 0\nF 1 10\nE 1 10 closed\nF 2 20\n").unwrap();
+        let (timestamp, events) = replayer.next_frame(2);
+        assert_eq!(timestamp, Duration::new(0, 20));
+        assert!(events.is_empty());
+        assert!(replayer.is_finished());
+    }
+
+    #[test]
+    fn rejects_malformed_recordings() {
+        assert!(Replayer::read(&b"F 0 0\n"[..]).is_err());
+        assert!(replayer("F 1 0\nF 1 10\n").is_err());
+        assert!(replayer("F 2 0\nF 1 10\n").is_err());
+        assert!(replayer("F 0 0\nE 1 0 closed\n").is_err());
+        assert!(replayer("F 0 0\nE 0 0 teleported\n").is_err());
+        assert!(replayer("F x 0\n").is_err());
+        assert!(replayer("G 0 0\n").is_err());
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let path = env::temp_dir().join(format!("wargod-replay-{}", ::std::process::id()));
+        let events = ["resized 800 600".parse::<InputEvent>().unwrap(),
+                      "key 30 pressed A 1000".parse().unwrap()];
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            recorder.record_frame(0, Duration::new(0, 0), &[]).unwrap();
+            recorder.record_frame(1, Duration::new(1, 5), &events).unwrap();
+            recorder.flush().unwrap();
+        }
+        let replayer = Replayer::open(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut replayer = replayer.unwrap();
+        assert!(replayer.next_frame(0).1.is_empty());
+        let (timestamp, replayed) = replayer.next_frame(1);
+        assert_eq!(timestamp, Duration::new(1, 5));
+        assert_eq!(names(&replayed), ["resized 800 600", "key 30 pressed A 1000"]);
+        assert!(replayer.is_finished());
+    }
+}
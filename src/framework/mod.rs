@@ -1,2 +1,5 @@
 pub mod input;
+pub mod game_loop;
 pub mod gfx;
+pub mod bounds;
+pub mod parse;
@@ -0,0 +1,11 @@
+use std::io;
+
+/// Error for malformed input, with `context` naming what was being read.
+pub fn invalid_data(context: &str, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", context, message))
+}
+
+/// Error for malformed input on `line`, counted from 1, of a text file.
+pub fn invalid_line(context: &str, line: usize, message: &str) -> io::Error {
+    invalid_data(&format!("{} line {}", context, line), message)
+}
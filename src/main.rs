@@ -18,38 +18,384 @@ extern crate vulkano;
 extern crate vulkano_shader_derive;
 extern crate vulkano_win;
 
-use vulkano_win::VkSurfaceBuild;
+use cgmath::{EuclideanSpace, Rotation3, Transform};
+
 use vulkano::sync::GpuFuture;
 
 use std::sync::Arc;
 
+mod animation;
+mod debug_draw;
 mod framework;
+mod particles;
 mod renderer;
+mod scene;
+mod text;
+mod window;
+
+struct Options {
+    record: Option<String>,
+    replay: Option<String>,
+    headless: bool,
+    render_scale: f32,
+    renderer: renderer::RendererConfig,
+    /// Radiance `.hdr` image lighting the scene, instead of a generated sky.
+    environment: Option<String>,
+    /// glTF file of an animated unit to show next to the teapots.
+    unit: Option<String>,
+    /// TrueType or OpenType font of the HUD and labels, without which no
+    /// text is drawn.
+    font: Option<String>,
+}
+
+impl Options {
+    fn from_args() -> Options {
+        let mut options = Options {
+            record: None,
+            replay: None,
+            headless: false,
+            render_scale: 1.0,
+            renderer: renderer::RendererConfig {
+                threads: 4,
+                .. Default::default()
+            },
+            environment: None,
+            unit: None,
+            font: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => options.record = args.next(),
+                "--replay" => options.replay = args.next(),
+                "--headless" => options.headless = true,
+                "--render-scale" => match args.next().and_then(|s| s.parse().ok()) {
+                    Some(scale) => options.render_scale = scale,
+                    None => println!("--render-scale requires a number"),
+                },
+                "--render-threads" => match args.next().and_then(|s| s.parse().ok()) {
+                    Some(threads) => options.renderer.threads = threads,
+                    None => println!("--render-threads requires a number"),
+                },
+                "--render-path" => match args.next().as_ref().map(|s| s.as_str()) {
+                    Some("forward") => options.renderer.path = framework::gfx::RenderPath::Forward,
+                    Some("deferred") => options.renderer.path = framework::gfx::RenderPath::Deferred,
+                    _ => println!("--render-path requires forward or deferred"),
+                },
+                "--environment" => options.environment = args.next(),
+                "--unit" => options.unit = args.next(),
+                "--font" => options.font = args.next(),
+                _ => println!("Ignoring unknown argument {}", arg),
+            }
+        }
+
+        if options.headless && options.replay.is_none() {
+            println!("--headless requires --replay <file>");
+            print_usage();
+            std::process::exit(1);
+        }
+
+        options
+    }
+}
+
+fn print_usage() {
+    println!("Usage: {} [options]", std::env::args().next().unwrap_or_default());
+    println!("  --record <file>          record the input of the session");
+    println!("  --replay <file>          play back a recorded session");
+    println!("  --headless               with --replay, simulate without a window");
+    println!("  --render-scale <scale>   resolution of the scene relative to the window");
+    println!("  --render-threads <count> threads recording draw commands");
+    println!("  --render-path <path>     forward or deferred");
+    println!("  --environment <file>     radiance .hdr image lighting the scene");
+    println!("  --unit <file>            glTF file of an animated unit");
+    println!("  --font <file>            TrueType or OpenType font of the HUD");
+}
+
+/// Nodes of the demo scene moved by its timelines.
+struct DemoNodes {
+    teapot: scene::NodeId,
+    glass: scene::NodeId,
+    /// Where the smoke comes out of the teapot.
+    spout: scene::NodeId,
+}
+
+/// The demo scene, without renderables so a headless replay can animate
+/// it too.
+fn demo_scene() -> (scene::Scene, DemoNodes) {
+    let mut scene = scene::Scene::new();
+    let teapot = scene.add("teapot", None, scene::Transform {
+        scale: cgmath::Vector3::new(0.01, 0.01, 0.01),
+        .. scene::Transform::identity()
+    });
+    let glass = scene.add("glass teapot", None, scene::Transform {
+        translation: cgmath::Vector3::new(-0.6, 0.0, -0.4),
+        scale: cgmath::Vector3::new(0.006, 0.006, 0.006),
+        .. scene::Transform::identity()
+    });
+    let spout = scene.add("spout", Some(teapot), scene::Transform {
+        translation: cgmath::Vector3::new(90.0, 35.0, 0.0),
+        .. scene::Transform::identity()
+    });
+
+    (scene, DemoNodes {
+        teapot: teapot,
+        glass: glass,
+        spout: spout,
+    })
+}
+
+/// Timelines of the demo scene. The glow of the glass teapot is only
+/// animated given its material.
+fn demo_timelines(nodes: &DemoNodes,
+                  glass_material: Option<renderer::MaterialId>)
+                  -> Vec<animation::TimelinePlayer> {
+    // The teapot turns once every 2π seconds and smokes during the half
+    // turn its spout faces the camera
+    let quarters = (0..5).map(|i| i as f32 * std::f32::consts::FRAC_PI_2).collect::<Vec<_>>();
+    let mut spin = animation::Timeline::new("spin", vec![animation::Track {
+        times: quarters.clone(),
+        property: animation::Property::Rotation(
+            nodes.teapot,
+            quarters.iter().map(|&a| cgmath::Quaternion::from_angle_y(cgmath::Rad(a))).collect()),
+        interpolation: animation::Interpolation::Linear,
+    }]);
+    spin.add_event(quarters[2], "smoke on");
+    spin.add_event(quarters[4], "smoke off");
+
+    // The glass teapot bobs up and down, glowing at the top
+    let still = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    let mut tracks = vec![
+        animation::Track {
+            times: vec![0.0, 1.5],
+            property: animation::Property::Translation(nodes.glass, vec![
+                still, cgmath::Vector3::new(-0.6, 0.0, -0.4), still,
+                still, cgmath::Vector3::new(-0.6, 0.1, -0.4), still,
+            ]),
+            interpolation: animation::Interpolation::CubicSpline,
+        },
+        animation::Track {
+            times: vec![0.0, 1.5],
+            property: animation::Property::Tint(nodes.glass, vec![
+                cgmath::Vector4::new(0.3, 0.6, 1.0, 0.4),
+                cgmath::Vector4::new(0.3, 0.6, 1.0, 0.7),
+            ]),
+            interpolation: animation::Interpolation::Linear,
+        },
+    ];
+    if let Some(material) = glass_material {
+        tracks.push(animation::Track {
+            times: vec![0.0, 1.5],
+            property: animation::Property::Emissive(material, vec![0.0, 0.3]),
+            interpolation: animation::Interpolation::Linear,
+        });
+    }
+    let hover = animation::Timeline::new("hover", tracks);
+
+    vec![
+        animation::TimelinePlayer::new(spin, animation::Wrap::Loop),
+        animation::TimelinePlayer::new(hover, animation::Wrap::PingPong),
+    ]
+}
+
+/// Effects of the smoke coming out of the spout.
+fn smoke_configs() -> Vec<particles::EmitterConfig> {
+    match particles::config::load("assets/particles/battle.particles") {
+        Ok(configs) => configs.into_iter().filter(|c| c.name == "smoke").collect(),
+        Err(err) => {
+            println!("Failed to load particle effects ({})", err);
+            Vec::new()
+        },
+    }
+}
+
+/// Drains the timeline `events`, returning whether the last of them turned
+/// the smoke on or off.
+fn smoke_toggle(events: &mut Vec<String>) -> Option<bool> {
+    let mut active = None;
+    for event in events.drain(..) {
+        match event.as_str() {
+            "smoke on" => active = Some(true),
+            "smoke off" => active = Some(false),
+            _ => (),
+        }
+    }
+    active
+}
+
+fn load_unit<P: AsRef<std::path::Path>>(path: P) -> Option<animation::gltf::SkinnedModel> {
+    animation::gltf::load(path)
+        .map_err(|err| println!("Failed to load unit ({})", err))
+        .ok()
+}
+
+/// Plays back the recorded input without a window or a device.
+///
+/// Everything the CPU simulates runs on the same fixed steps as in a
+/// windowed session: the timelines and their events, the scene transforms,
+/// the spawning of the smoke and the animation of the unit. Nothing is
+/// drawn, so what the GPU simulates, the particles once spawned, is not.
+fn run_headless(options: &Options, input: &mut framework::input::Input) {
+    let (mut scene, nodes) = demo_scene();
+    let mut animations = demo_timelines(&nodes, None);
+    let mut events = Vec::new();
+    let mut smoke = smoke_configs().iter().map(|config| {
+        let mut spawner = particles::Spawner::new(config);
+        spawner.active = false;
+        spawner
+    }).collect::<Vec<_>>();
+    let mut unit = options.unit.as_ref().and_then(load_unit).map(|model| {
+        let walk = model.clips.iter().position(|c| c.name == "walk").unwrap_or(0);
+        (model, animation::Animator::new(walk))
+    });
+    let mut spawned = 0;
+    let mut behind = 0;
+
+    let mut game_loop = framework::game_loop::GameLoop::new(60);
+
+    while !input.is_replay_finished() {
+        let timing = game_loop.advance(input.timestamp());
+        if timing.fell_behind {
+            behind += 1;
+        }
+        for _ in 0..timing.ticks {
+            for player in &mut animations {
+                player.update(game_loop.dt(), &mut events);
+            }
+        }
+        if let Some(active) = smoke_toggle(&mut events) {
+            for spawner in &mut smoke {
+                spawner.active = active;
+            }
+        }
+
+        for player in &animations {
+            player.apply(&mut scene, None, timing.alpha * game_loop.dt());
+        }
+        scene.update();
+        for spawner in &mut smoke {
+            spawned += spawner.spawn(timing.ticks as f32 * game_loop.dt()).1;
+        }
+        if let Some((ref model, ref mut animator)) = unit {
+            animator.update(timing.ticks as f32 * game_loop.dt(), &model.clips);
+        }
+
+        let mut done = false;
+        input.poll_events(|ev| {
+            match ev {
+                framework::input::InputEvent::Closed => done = true,
+                _ => ()
+            }
+        });
+        if done { break; }
+    }
+
+    let spout = scene.node(nodes.spout).world().transform_point(cgmath::Point3::origin());
+    println!("Replayed {} frames, {} steps ({:.2} s)",
+             input.frame(), game_loop.tick(),
+             framework::game_loop::duration_to_secs(game_loop.simulation_time()));
+    if behind > 0 {
+        // The recording was made on a machine too slow for the tick rate
+        println!("{} frames were more than {} steps behind and dropped time",
+                 behind, game_loop.max_steps);
+    }
+    println!("Spawned {} smoke particles, spout at ({:.3}, {:.3}, {:.3})",
+             spawned, spout.x, spout.y, spout.z);
+    if let Some((ref model, ref animator)) = unit {
+        println!("Unit playing {} at {:.2} s",
+                 model.clips[animator.clip()].name, animator.time());
+    }
+}
 
 fn main() {
-    let mut events_loop = framework::input::make_event_loop();
-    let gfx_core = framework::gfx::Core::new(&events_loop).unwrap();
-
-    let vertex_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-        ::from_iter(
-            gfx_core.device.clone(),
-            vulkano::buffer::BufferUsage::all(),
-            VERTICES.iter().cloned()
-        ).expect("failed to create buffer");
-
-    let normals_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-        ::from_iter(
-            gfx_core.device.clone(),
-            vulkano::buffer::BufferUsage::all(),
-            NORMALS.iter().cloned()
-        ).expect("failed to create buffer");
-
-    let index_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-        ::from_iter(
-            gfx_core.device.clone(),
-            vulkano::buffer::BufferUsage::all(),
-            INDICES.iter().cloned()
-        ).expect("failed to create buffer");
+    let options = Options::from_args();
+
+    let mut input = if options.headless {
+        framework::input::Input::headless()
+    } else {
+        framework::input::Input::new()
+    };
+    if let Some(ref path) = options.record {
+        input.record(path).expect("failed to create input recording");
+    }
+    if let Some(ref path) = options.replay {
+        input.replay(path).expect("failed to load input recording");
+    }
+
+    if options.headless {
+        run_headless(&options, &mut input);
+        return;
+    }
+
+    let instance = framework::gfx::create_instance();
+    let mut window = window::Window::new(input.events_loop.as_ref().unwrap(),
+                                     instance,
+                                     window::WindowConfig::default()).unwrap();
+    let gfx_core = framework::gfx::Core::new(window.surface.clone(),
+                                             options.renderer.path).unwrap();
+    gfx_core.set_render_scale(options.render_scale).unwrap();
+
+    let mut renderer = renderer::Renderer::new(gfx_core.clone(), options.renderer).unwrap();
+    let mut uploader = framework::gfx::Uploader::new(gfx_core.device.clone(),
+                                                     gfx_core.queues.transfer.clone(),
+                                                     gfx_core.queue.clone(),
+                                                     gfx_core.memory.clone());
+
+    let layout = framework::gfx::VertexLayout::new(&[framework::gfx::Attribute::Position,
+                                                     framework::gfx::Attribute::Normal]);
+    let teapot = {
+        let streams = framework::gfx::VertexStreams {
+            positions: VERTICES.iter().map(|v| [v.position.0, v.position.1, v.position.2]).collect(),
+            normals: NORMALS.iter().map(|n| [n.normal.0, n.normal.1, n.normal.2]).collect(),
+            .. Default::default()
+        };
+        let indices = INDICES.iter().map(|&i| i as u32).collect::<Vec<_>>();
+
+        // Full detail up close, then two clustered approximations
+        let mut levels = vec![(streams.clone(), indices.clone(), 0.25)];
+        for &(resolution, screen_size) in &[(24, 0.08), (10, 0.0)] {
+            let (streams, indices) = framework::gfx::decimate(&streams, &indices, resolution)
+                .unwrap();
+            levels.push((streams, indices, screen_size));
+        }
+
+        let data = levels.into_iter().map(|(streams, indices, screen_size)| {
+            (framework::gfx::MeshData::from_streams(
+                layout.clone(),
+                framework::gfx::Topology::TriangleList,
+                &streams,
+                framework::gfx::Indices::from_u32(indices)), screen_size)
+        }).collect::<Vec<_>>();
+        let levels = data.iter().map(|&(ref data, size)| (data, size)).collect::<Vec<_>>();
+
+        renderer.add_mesh_lods(&mut uploader, &levels).unwrap()
+    };
+    let mut unit = options.unit.as_ref().and_then(load_unit).map(|model| {
+        let layout = framework::gfx::VertexLayout::new(&[framework::gfx::Attribute::Position,
+                                                         framework::gfx::Attribute::Normal,
+                                                         framework::gfx::Attribute::Joints,
+                                                         framework::gfx::Attribute::Weights]);
+        let data = framework::gfx::MeshData::from_streams(
+            layout.clone(),
+            framework::gfx::Topology::TriangleList,
+            &model.streams,
+            framework::gfx::Indices::from_u32(model.indices.clone()));
+        let mesh = renderer.add_mesh(&mut uploader, &data).unwrap();
+        let material = renderer::Material::skinned(&gfx_core, &layout,
+                                                   framework::gfx::Topology::TriangleList,
+                                                   framework::gfx::BlendMode::Opaque).unwrap();
+        let material = renderer.add_material(material);
+        let walk = model.clips.iter().position(|c| c.name == "walk").unwrap_or(0);
+        (model, mesh, material, animation::Animator::new(walk))
+    });
+    uploader.flush().unwrap();
+
+    for heap in gfx_core.memory_report() {
+        println!("Memory heap {}: {} of {} bytes used{}",
+                 heap.heap, heap.used, heap.size,
+                 if heap.device_local { " (device local)" } else { "" });
+    }
 
     // note: this teapot was meant for OpenGL where the origin is at the lower left
     //       instead the origin is at the upper left in vulkan, so we reverse the Y axis
@@ -61,127 +407,399 @@ fn main() {
     let view = cgmath::Matrix4::look_at(cgmath::Point3::new(0.3, 0.3, 1.0),
                                         cgmath::Point3::new(0.0, 0.0, 0.0),
                                         cgmath::Vector3::new(0.0, -1.0, 0.0));
-    let scale = cgmath::Matrix4::from_scale(0.01);
-
-    let uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<vs::ty::Data>
-        ::new(
-            gfx_core.device.clone(),
-            vulkano::buffer::BufferUsage::all(),
-        );
-
-    let vs = vs::Shader::load(gfx_core.device.clone())
-        .expect("failed to create shader module");
-    let fs = fs::Shader::load(gfx_core.device.clone())
-        .expect("failed to create shader module");
-
-    let pipeline = Arc::new(
-        vulkano::pipeline::GraphicsPipeline::start()
-            .vertex_input(vulkano::pipeline::vertex::TwoBuffersDefinition::new())
-            .vertex_shader(vs.main_entry_point(), ())
-            .triangle_list()
-            .viewports_dynamic_scissors_irrelevant(1)
-            .fragment_shader(fs.main_entry_point(), ())
-            .depth_stencil_simple_depth()
-            .render_pass(vulkano::framebuffer::Subpass::from(gfx_core.render_pass.clone(),
-                                                             0).unwrap())
-            .build(gfx_core.device.clone()).unwrap()
-    );
+
+    let material = {
+        let mesh = renderer.mesh(teapot);
+        renderer::Material::lit(&gfx_core, &mesh.layout, mesh.topology).unwrap()
+    };
+    let material = renderer.add_material(material);
+    let glass = {
+        let mesh = renderer.mesh(teapot);
+        renderer::Material::lit_blended(&gfx_core, &mesh.layout, mesh.topology,
+                                        framework::gfx::BlendMode::Alpha).unwrap()
+    };
+    let glass = renderer.add_material(glass);
+
+    let (mut scene, nodes) = demo_scene();
+    let (teapot_node, glass_node, spout) = (nodes.teapot, nodes.glass, nodes.spout);
+    scene.node_mut(teapot_node).renderable = Some(scene::Renderable {
+        mesh: teapot,
+        material: material,
+        tint: [1.0, 0.0, 0.0, 1.0],
+    });
+    scene.node_mut(glass_node).renderable = Some(scene::Renderable {
+        mesh: teapot,
+        material: glass,
+        tint: [0.3, 0.6, 1.0, 0.4],
+    });
+
+    let mut post = framework::gfx::PostChain::new(&gfx_core).unwrap();
+    match framework::gfx::Lut::load_cube("assets/luts/warm.cube") {
+        Ok(lut) => post.set_lut(&lut, gfx_core.queue.clone()).unwrap(),
+        Err(err) => println!("Failed to load color grading table ({})", err),
+    }
+
+    let sky = options.environment.as_ref().and_then(|path| {
+        framework::gfx::HdrImage::load(path)
+            .map_err(|err| println!("Failed to load environment ({})", err))
+            .ok()
+    }).unwrap_or_else(|| framework::gfx::HdrImage::from_fn(256, 128, |d| {
+        let horizon = [1.0, 0.9, 0.8];
+        if d[1] < 0.0 {
+            return [0.25, 0.22, 0.2];
+        }
+        let zenith = [0.25, 0.45, 1.2];
+        let t = d[1].sqrt();
+        [horizon[0] + (zenith[0] - horizon[0]) * t,
+         horizon[1] + (zenith[1] - horizon[1]) * t,
+         horizon[2] + (zenith[2] - horizon[2]) * t]
+    }));
+    let environment = framework::gfx::Environment::new(&gfx_core, &sky).unwrap();
+    renderer.set_environment(environment).unwrap();
+
+    let mut particles = particles::ParticleSystem::new(gfx_core.clone()).unwrap();
+    let mut smoke = Vec::new();
+    for config in &smoke_configs() {
+        let emitter = particles.add_emitter(config, spout).unwrap();
+        particles.set_active(emitter, false);
+        smoke.push(emitter);
+    }
+
+    let mut animations = demo_timelines(&nodes, Some(glass));
+    let mut events = Vec::new();
+
+    let mut debug = debug_draw::DebugDraw::new(gfx_core.clone()).unwrap();
+    let mut show_debug = false;
+
+    let mut text = text::TextRenderer::new(gfx_core.clone()).unwrap();
+    let font = options.font.as_ref().and_then(|path| {
+        text::Font::load(path)
+            .map_err(|err| println!("Failed to load font ({})", err))
+            .ok()
+    }).map(|font| text.add_font(font));
+    // Milliseconds per frame, smoothed so the HUD stays readable
+    let mut frame_ms = 0.0;
+
+    let scene_color = gfx_core.scene_graph.attachment("color").unwrap();
+    let ui_swapchain = gfx_core.ui_graph.attachment("swapchain").unwrap();
 
     let mut recreate_swapchain = false;
+    let mut surface_lost = false;
 
     let mut previous_frame = Box::new(vulkano::sync::now(gfx_core.device.clone()))
         as Box<GpuFuture>;
-    let rotation_start = std::time::Instant::now();
+
+    let mut game_loop = framework::game_loop::GameLoop::new(60);
 
     loop {
         previous_frame.cleanup_finished();
 
+        let timing = game_loop.advance(input.timestamp());
+        for _ in 0..timing.ticks {
+            for player in &mut animations {
+                player.update(game_loop.dt(), &mut events);
+            }
+        }
+        if let Some(active) = smoke_toggle(&mut events) {
+            for &emitter in &smoke {
+                particles.set_active(emitter, active);
+            }
+        }
+
         if recreate_swapchain {
-            gfx_core.recreate_swapchain();
-            proj = cgmath::perspective(cgmath::Rad(std::f32::consts::FRAC_PI_2),
-                                       { gfx_core.dimensions.read().unwrap().width as f32 /
-                                         gfx_core.dimensions.read().unwrap().height as f32 },
-                                       0.01,
-                                       100.0);
-            recreate_swapchain = false;
+            match gfx_core.recreate_swapchain() {
+                Ok(()) => {
+                    proj = cgmath::perspective(cgmath::Rad(std::f32::consts::FRAC_PI_2),
+                                               { gfx_core.dimensions.read().unwrap().width as f32 /
+                                                 gfx_core.dimensions.read().unwrap().height as f32 },
+                                               0.01,
+                                               100.0);
+                    recreate_swapchain = false;
+                },
+                Err(framework::gfx::RefreshError::SurfaceLost) => surface_lost = true,
+                // Minimized or mid-resize, try again after the next events
+                Err(framework::gfx::RefreshError::Minimized) |
+                Err(framework::gfx::RefreshError::UnsupportedDimensions) => (),
+                Err(framework::gfx::RefreshError::Other) => {
+                    println!("Failed to recreate the swapchain, stopping");
+                    return;
+                },
+            }
         }
 
-        let (image_num, framebuffer, acquire_future) = match
-            gfx_core.acquire_next_framebuffer() {
-                Ok(r) => r,
+        // Everything simulated advances whether or not there is an image to
+        // draw to, as in a headless replay of the same input
+        for player in &animations {
+            player.apply(&mut scene, Some(&mut renderer), timing.alpha * game_loop.dt());
+        }
+        scene.update();
+        if let Some(ref mut unit) = unit {
+            unit.3.update(timing.ticks as f32 * game_loop.dt(), &unit.0.clips);
+        }
+        let camera = renderer::Camera {
+            view: view,
+            proj: proj,
+        };
+        let particle_batch = particles.update(&scene,
+                                              &camera,
+                                              timing.ticks as f32 * game_loop.dt()).unwrap();
+
+        let acquired = if recreate_swapchain || surface_lost {
+            None
+        } else {
+            match gfx_core.acquire_next_image() {
+                Ok(r) => Some(r),
                 Err(framework::gfx::AcquireError::OutOfDate) => {
                     recreate_swapchain = true;
-                    continue;
+                    None
                 },
-                Err(err) => panic!("{:?}", err)
-            };
-
-        let uniform_buffer_subbuffer = {
-            let elapsed = rotation_start.elapsed();
-            let rotation = elapsed.as_secs() as f64 +
-                elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
-            let rotation = cgmath::Matrix3::from_angle_y(cgmath::Rad(rotation as f32));
+                Err(framework::gfx::AcquireError::SurfaceLost) => {
+                    surface_lost = true;
+                    None
+                },
+                // Skip the frame, a new swapchain either fixes it or fails
+                // for good
+                Err(err) => {
+                    println!("Failed to acquire a swapchain image ({:?})", err);
+                    recreate_swapchain = true;
+                    None
+                },
+            }
+        };
 
-            let uniform_data = vs::ty::Data {
-                world : cgmath::Matrix4::from(rotation).into(),
-                view : (view * scale).into(),
-                proj : proj.into(),
+        if let Some(acquired) = acquired {
+            if acquired.suboptimal {
+                recreate_swapchain = true;
+            }
+            let image_num = acquired.index;
+            let swapchain_image = acquired.image.clone();
+
+            scene.submit(&mut renderer);
+            if let Some(ref unit) = unit {
+                let pose = unit.3.pose(&unit.0.skeleton, &unit.0.clips);
+                renderer.submit_skinned(unit.1,
+                                        unit.2,
+                                        cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.6, 0.0, -0.4)),
+                                        [0.8, 0.8, 0.8, 1.0],
+                                        &pose.skinning_matrices(&unit.0.skeleton));
+            }
+            let light = renderer::Light {
+                position: cgmath::Point3::new(0.6, -0.4, 0.4),
+                color: [1.0, 0.7, 0.4],
+                intensity: 2.0,
+                radius: 2.0,
+            };
+            renderer.submit_light(light);
+            if show_debug {
+                debug.grid(cgmath::Point3::origin(), 10, 0.2, [0.6, 0.6, 0.6, 0.5],
+                           debug_draw::Depth::Tested);
+                for &id in &[teapot_node, glass_node, spout] {
+                    let node = scene.node(id);
+                    let world = node.world();
+                    let origin = world.transform_point(cgmath::Point3::origin());
+                    debug.axes(&world, 0.1, debug_draw::Depth::OnTop);
+                    debug.label(origin + cgmath::Vector3::new(0.0, 0.15, 0.0), &node.name, 0.03,
+                                [1.0, 1.0, 0.4, 1.0], debug_draw::Depth::OnTop);
+                }
+                debug.arrow(light.position, cgmath::Point3::origin(),
+                            [light.color[0], light.color[1], light.color[2], 1.0],
+                            debug_draw::Depth::Tested);
+            }
+            if let Some(font) = font {
+                let ms = framework::game_loop::duration_to_secs(timing.frame_time) * 1000.0;
+                frame_ms += (ms - frame_ms) * 0.1;
+                // Sized in points, so the HUD reads the same on high DPI screens
+                let hidpi = *gfx_core.hidpi_factor.read().unwrap();
+                text.screen_text(font, [12.0 * hidpi, 12.0 * hidpi], &format!("{:.1} ms", frame_ms),
+                                 18.0 * hidpi, [1.0, 1.0, 1.0, 1.0], None);
+                // World y points down the screen
+                let glass = scene.node(glass_node).world().transform_point(cgmath::Point3::origin());
+                text.world_text(font, glass + cgmath::Vector3::new(0.0, -0.12, 0.0), "Glass teapot",
+                                0.04, [1.0, 1.0, 1.0, 1.0]);
+            }
+            let scene_target = gfx_core.scene_target.read().unwrap();
+            let scene_dims = scene_target.dimensions;
+            let window_dims = *gfx_core.dimensions.read().unwrap();
+
+            let builder = vulkano::command_buffer::AutoCommandBufferBuilder
+                ::primary_one_time_submit(gfx_core.device.clone(),
+                                          gfx_core.queue.family()
+                ).unwrap();
+
+            let dynamic = vulkano::command_buffer::DynamicState {
+                line_width: None,
+                viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [scene_dims.width as f32,
+                                 scene_dims.height as f32],
+                    depth_range: 0.0 .. 1.0,
+                }]),
+                scissors: None,
+            };
+            let builder = text.prepare(builder, &camera, &dynamic).unwrap();
+
+            let builder = gfx_core.scene_graph.record(
+                &scene_target.targets,
+                builder,
+                &[],
+                &[(scene_color, renderer.clear_color.into())],
+                |pass, builder| {
+                    let mut builder = builder;
+                    if pass == gfx_core.scene_pass {
+                        builder = renderer.render(builder, &camera, &dynamic)?;
+                    }
+                    if Some(pass) == gfx_core.lighting_pass {
+                        builder = renderer.render_lighting(builder, &scene_target.targets,
+                                                           &camera, &dynamic)?;
+                    }
+                    if pass == gfx_core.transparent_pass {
+                        builder = renderer.render_transparent(builder, &camera, &dynamic)?;
+                        if let Some(command_buffer) = particles.render(&camera, &dynamic)? {
+                            builder = renderer::workers::execute(builder, command_buffer)?;
+                        }
+                        if let Some(command_buffer) = text.render_world(&camera, &dynamic)? {
+                            builder = renderer::workers::execute(builder, command_buffer)?;
+                        }
+                        if let Some(command_buffer) = debug.render(&camera, &dynamic)? {
+                            builder = renderer::workers::execute(builder, command_buffer)?;
+                        }
+                    }
+                    Ok(builder)
+                }).unwrap();
+
+            let (builder, image) = post.record(builder, &scene_target).unwrap();
+
+            let builder = builder
+                .blit_image(
+                    image,
+                    [0, 0, 0],
+                    [scene_dims.width as i32, scene_dims.height as i32, 1],
+                    0, 0,
+                    swapchain_image.clone(),
+                    [0, 0, 0],
+                    [window_dims.width as i32, window_dims.height as i32, 1],
+                    0, 0, 1,
+                    vulkano::sampler::Filter::Linear).unwrap();
+
+            // UI is drawn here, at native resolution
+            let ui_dynamic = vulkano::command_buffer::DynamicState {
+                line_width: None,
+                viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [window_dims.width as f32,
+                                 window_dims.height as f32],
+                    depth_range: 0.0 .. 1.0,
+                }]),
+                scissors: None,
+            };
+            let command_buffer = gfx_core.ui_graph.record(
+                &gfx_core.ui_targets.read().unwrap(),
+                builder,
+                &[(ui_swapchain, swapchain_image as Arc<vulkano::image::ImageViewAccess + Send + Sync>)],
+                &[],
+                |_, builder| text.render_screen(builder, &ui_dynamic)).unwrap()
+                .build().unwrap();
+            drop(scene_target);
+        
+            // Particles are simulated once the previous frame stopped drawing them
+            let before = match particle_batch {
+                Some(batch) => batch.submit(previous_frame).unwrap(),
+                None => previous_frame,
             };
 
-            uniform_buffer.next(uniform_data)
-        };
-
-        let set = Arc::new(
-            vulkano::descriptor::descriptor_set::PersistentDescriptorSet
-                ::start(pipeline.clone(), 0)
-                .add_buffer(uniform_buffer_subbuffer).ok().unwrap()
-                .build().unwrap()
-        );
-
-        let command_buffer = vulkano::command_buffer::AutoCommandBufferBuilder
-            ::primary_one_time_submit(gfx_core.device.clone(),
-                                      gfx_core.queue.family()
-            ).unwrap()
-            .begin_render_pass(
-                framebuffer.clone(),
-                false,
-                vec![
-                    [0.0, 0.0, 1.0, 1.0].into(),
-                    1f32.into()
-                ]).unwrap()
-            .draw_indexed(
-                pipeline.clone(),
-                vulkano::command_buffer::DynamicState {
-                    line_width: None,
-                    viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
-                        origin: [0.0, 0.0],
-                        dimensions: [gfx_core.dimensions.read().unwrap().width as f32,
-                                     gfx_core.dimensions.read().unwrap().height as f32],
-                        depth_range: 0.0 .. 1.0,
-                    }]),
-                    scissors: None,
+            let future = before.join(acquired.future)
+                .then_execute(gfx_core.queue.clone(), command_buffer).unwrap()
+                .then_swapchain_present(gfx_core.queue.clone(),
+                                        gfx_core.swapchain.read().unwrap().id.clone(),
+                                        image_num)
+                .then_signal_fence_and_flush();
+            previous_frame = match future {
+                Ok(future) => Box::new(future) as Box<_>,
+                Err(vulkano::sync::FlushError::OutOfDate) => {
+                    recreate_swapchain = true;
+                    Box::new(vulkano::sync::now(gfx_core.device.clone())) as Box<_>
                 },
-                (vertex_buffer.clone(), normals_buffer.clone()), 
-                index_buffer.clone(), set.clone(), ()).unwrap()
-            .end_render_pass().unwrap()
-            .build().unwrap();
-        
-        let future = previous_frame.join(acquire_future)
-            .then_execute(gfx_core.queue.clone(), command_buffer).unwrap()
-            .then_swapchain_present(gfx_core.queue.clone(),
-                                    gfx_core.swapchain.read().unwrap().id.clone(),
-                                    image_num)
-            .then_signal_fence_and_flush().unwrap();
-        previous_frame = Box::new(future) as Box<_>;
+                Err(vulkano::sync::FlushError::SurfaceLost) => {
+                    surface_lost = true;
+                    Box::new(vulkano::sync::now(gfx_core.device.clone())) as Box<_>
+                },
+                Err(err) => {
+                    println!("Failed to submit the frame ({:?})", err);
+                    recreate_swapchain = true;
+                    Box::new(vulkano::sync::now(gfx_core.device.clone())) as Box<_>
+                },
+            };
+        } else {
+            // The particles move on without being drawn
+            if let Some(batch) = particle_batch {
+                previous_frame = batch.submit(previous_frame).unwrap();
+            }
+            // Nothing to draw to, don't spin while waiting for the window
+            std::thread::sleep(std::time::Duration::from_millis(16));
+        }
 
         let mut done = false;
-        events_loop.poll_events(|ev| {
+        let mut fullscreen = None;
+        input.poll_events(|ev| {
             match ev {
-                winit::Event::WindowEvent { event: winit::WindowEvent::Closed, .. } => done = true,
+                framework::input::InputEvent::Closed => done = true,
+                framework::input::InputEvent::KeyboardInput {
+                    state: winit::ElementState::Pressed,
+                    keycode: Some(winit::VirtualKeyCode::F11), ..
+                } => fullscreen = Some(window::Fullscreen::Borderless),
+                framework::input::InputEvent::KeyboardInput {
+                    state: winit::ElementState::Pressed,
+                    keycode: Some(winit::VirtualKeyCode::Return),
+                    modifiers, ..
+                } if modifiers.alt => fullscreen = Some(window::Fullscreen::Exclusive),
+                framework::input::InputEvent::KeyboardInput {
+                    state: winit::ElementState::Pressed,
+                    keycode: Some(key), ..
+                } => {
+                    let settings = &mut post.settings;
+                    match key {
+                        winit::VirtualKeyCode::F1 => settings.tonemap = !settings.tonemap,
+                        winit::VirtualKeyCode::F2 => settings.bloom = !settings.bloom,
+                        winit::VirtualKeyCode::F3 => settings.fxaa = !settings.fxaa,
+                        winit::VirtualKeyCode::F4 => settings.color_grading = !settings.color_grading,
+                        winit::VirtualKeyCode::F5 => settings.vignette = !settings.vignette,
+                        winit::VirtualKeyCode::F6 => show_debug = !show_debug,
+                        _ => (),
+                    }
+                },
+                framework::input::InputEvent::Resized(..) => recreate_swapchain = true,
                 _ => ()
             }
         });
-        if done { return; }
+        if done || input.is_replay_finished() { return; }
+
+        if let Some(mode) = fullscreen {
+            gfx_core.queue.wait().unwrap();
+            previous_frame = Box::new(vulkano::sync::now(gfx_core.device.clone())) as Box<_>;
+
+            let switched = window.toggle_fullscreen(input.events_loop.as_ref().unwrap(), mode,
+                                                    |surface| gfx_core.set_window(surface));
+            if switched.is_err() {
+                println!("Failed to toggle {:?} fullscreen", mode);
+            }
+            recreate_swapchain = true;
+        }
+
+        if surface_lost {
+            gfx_core.queue.wait().unwrap();
+            previous_frame = Box::new(vulkano::sync::now(gfx_core.device.clone())) as Box<_>;
+
+            let recreated = window.recreate(input.events_loop.as_ref().unwrap(),
+                                            |surface| gfx_core.set_window(surface));
+            match recreated {
+                Ok(()) => {
+                    surface_lost = false;
+                    recreate_swapchain = true;
+                },
+                // Nothing is drawn until a later attempt succeeds
+                Err(()) => println!("Failed to recreate the lost window surface"),
+            }
+        }
     }
 }
 
@@ -409,8 +1027,6 @@ pub struct Vertex {
     position: (f32, f32, f32)
 }
 
-impl_vertex!(Vertex, position);
-
 pub const VERTICES: [Vertex; 531] = [
     Vertex { position: (0.0, 0.0, 0.0) },   // dummy vector because in the original model indices
                                             // start at 1
@@ -951,8 +1567,6 @@ pub struct Normal {
     normal: (f32, f32, f32)
 }
 
-impl_vertex!(Normal, normal);
-
 pub const NORMALS: [Normal; 531] = [
     Normal { normal: (0.0, 0.0, 0.0) },     // dummy vector because in the original model indices
                                             // start at 1
@@ -2514,51 +3128,3 @@ pub const INDICES: [u16; 3072] = [
     470, 469, 529,
     529, 530, 470u16,
 ];
-
-mod vs {
-    #[derive(VulkanoShader)]
-    #[ty = "vertex"]
-    #[src = "
-#version 450
-
-layout(location = 0) in vec3 position;
-layout(location = 1) in vec3 normal;
-
-layout(location = 0) out vec3 v_normal;
-
-layout(set = 0, binding = 0) uniform Data {
-    mat4 world;
-    mat4 view;
-    mat4 proj;
-} uniforms;
-
-void main() {
-    mat4 worldview = uniforms.view * uniforms.world;
-    v_normal = transpose(inverse(mat3(worldview))) * normal;
-    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
-}
-"]
-    struct Dummy;
-}
-
-mod fs {
-    #[derive(VulkanoShader)]
-    #[ty = "fragment"]
-    #[src = "
-#version 450
-
-layout(location = 0) in vec3 v_normal;
-layout(location = 0) out vec4 f_color;
-
-const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
-
-void main() {
-    float brightness = dot(normalize(v_normal), normalize(LIGHT));
-    vec3 dark_color = vec3(0.6, 0.0, 0.0);
-    vec3 regular_color = vec3(1.0, 0.0, 0.0);
-
-    f_color = vec4(mix(dark_color, regular_color, brightness), 1.0);
-}
-"]
-    struct Dummy;
-}
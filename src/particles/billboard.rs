@@ -0,0 +1,134 @@
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::framebuffer as vkfb;
+use vulkano::pipeline as vkp;
+
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+use super::super::framework::gfx::PipelineBuilderExt;
+
+/// Pipeline drawing camera facing quads without vertex buffers. Its
+/// concrete type is kept since vulkano only draws bufferless pipelines it
+/// can see the vertex definition of.
+pub type BillboardPipeline = vkp::GraphicsPipeline<vkp::vertex::BufferlessDefinition,
+                                                   Box<PipelineLayoutAbstract + Send + Sync>,
+                                                   Arc<vkfb::RenderPassAbstract + Send + Sync>>;
+
+/// Per-frame parameters of an emitter, laid out as the `Params` uniform.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    /// Sizes at birth and death in `x` and `y`.
+    pub size: [f32; 4],
+}
+
+/// Billboard pipeline for the transparent subpass.
+///
+/// Billboards are depth tested against the scene but do not write depth,
+/// so particles behind other particles are not discarded.
+pub fn pipeline(core: &gfx::Core, blend: gfx::BlendMode) -> Result<Arc<BillboardPipeline>, ()> {
+    let vs = billboard_vs::Shader::load(core.device.clone())
+        .map_err(|e| println!("Failed to load billboard vertex shader ({:?})", e))?;
+    let fs = billboard_fs::Shader::load(core.device.clone())
+        .map_err(|e| println!("Failed to load billboard fragment shader ({:?})", e))?;
+
+    let pipeline = vkp::GraphicsPipeline::start()
+        .vertex_input(vkp::vertex::BufferlessDefinition)
+        .vertex_shader(vs.main_entry_point(), ())
+        .topology(gfx::Topology::TriangleList)
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fs.main_entry_point(), ())
+        .depth_stencil_simple_depth()
+        .depth_write(false)
+        .blend(blend)
+        .render_pass(core.scene_graph.subpass(core.transparent_pass))
+        .build(core.device.clone())
+        .map_err(|e| println!("Failed to create billboard pipeline ({:?})", e))?;
+
+    Ok(Arc::new(pipeline))
+}
+
+mod billboard_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) out vec2 v_corner;
+layout(location = 1) out vec4 v_color;
+
+struct Particle {
+    vec4 position;
+    vec4 velocity;
+};
+
+struct SortKey {
+    float depth;
+    uint index;
+};
+
+layout(set = 0, binding = 0) uniform Params {
+    mat4 view;
+    mat4 proj;
+    vec4 color_start;
+    vec4 color_end;
+    vec4 size;
+} params;
+
+layout(set = 1, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+
+layout(set = 1, binding = 1) readonly buffer Keys {
+    SortKey keys[];
+};
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    Particle p = particles[keys[gl_VertexIndex / 6].index];
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+
+    if (p.position.w >= p.velocity.w) {
+        // Dead, clipped away
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        return;
+    }
+
+    float t = p.position.w / p.velocity.w;
+    vec4 eye = params.view * vec4(p.position.xyz, 1.0);
+    eye.xy += corner * 0.5 * mix(params.size.x, params.size.y, t);
+
+    v_corner = corner;
+    v_color = mix(params.color_start, params.color_end, t);
+    gl_Position = params.proj * eye;
+}
+"]
+    struct Dummy;
+}
+
+mod billboard_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec2 v_corner;
+layout(location = 1) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // Round soft edged sprite
+    float falloff = 1.0 - smoothstep(0.5, 1.0, length(v_corner));
+    f_color = vec4(v_color.rgb, v_color.a * falloff);
+}
+"]
+    struct Dummy;
+}
@@ -0,0 +1,219 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::super::framework::gfx::BlendMode;
+use super::super::framework::parse::invalid_line;
+
+/// Settings of an emitter, read from a particle file.
+///
+/// Particle files list emitters as sections of `key = value` lines:
+///
+/// ```text
+/// # Comments start with a hash
+/// [smoke]
+/// max_particles = 2048
+/// rate = 200
+/// lifetime = 2.0 3.0
+/// color_end = 0.3 0.3 0.3 0.0
+/// blend = alpha
+/// ```
+///
+/// Ranges take a minimum and a maximum, or a single value for both. Missing
+/// keys keep their default.
+#[derive(Clone, Debug)]
+pub struct EmitterConfig {
+    pub name: String,
+    /// Size of the ring of particles. Spawning more wraps around and
+    /// replaces the oldest ones.
+    pub max_particles: usize,
+    /// Particles spawned per second.
+    pub rate: f32,
+    /// Seconds a particle lives, picked at random in the range.
+    pub lifetime: (f32, f32),
+    /// Initial speed, picked at random in the range.
+    pub speed: (f32, f32),
+    /// Emission direction in the space of the node of the emitter.
+    pub direction: [f32; 3],
+    /// Angle in radians between `direction` and the particle velocities.
+    pub spread: f32,
+    /// World space acceleration.
+    pub gravity: [f32; 3],
+    /// Fraction of the velocity lost per second.
+    pub drag: f32,
+    /// Billboard size at birth and death.
+    pub size: (f32, f32),
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    pub blend: BlendMode,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> EmitterConfig {
+        EmitterConfig {
+            name: String::new(),
+            max_particles: 1024,
+            rate: 100.0,
+            lifetime: (1.0, 1.0),
+            speed: (1.0, 1.0),
+            direction: [0.0, 1.0, 0.0],
+            spread: 0.0,
+            gravity: [0.0, 0.0, 0.0],
+            drag: 0.0,
+            size: (0.1, 0.1),
+            color_start: [1.0, 1.0, 1.0, 1.0],
+            color_end: [1.0, 1.0, 1.0, 0.0],
+            blend: BlendMode::Alpha,
+        }
+    }
+}
+
+/// Reads all emitters of a particle file.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<EmitterConfig>> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    parse(&text)
+}
+
+pub fn parse(text: &str) -> io::Result<Vec<EmitterConfig>> {
+    let mut configs: Vec<EmitterConfig> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        }.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if !line.ends_with(']') || line.len() < 3 {
+                return Err(invalid_line("particle file", number, "bad emitter name"));
+            }
+            configs.push(EmitterConfig {
+                name: line[1..line.len() - 1].trim().to_string(),
+                .. EmitterConfig::default()
+            });
+            continue;
+        }
+
+        let mut fields = line.splitn(2, '=');
+        let key = fields.next().unwrap().trim();
+        let value = fields.next().map(|v| v.trim())
+            .ok_or_else(|| invalid_line("particle file", number, "expected key = value"))?;
+
+        let config = configs.last_mut()
+            .ok_or_else(|| invalid_line("particle file", number, "setting outside of an emitter"))?;
+
+        match key {
+            "max_particles" => {
+                config.max_particles = value.parse().ok()
+                    .and_then(|n| if n > 0 { Some(n) } else { None })
+                    .ok_or_else(|| invalid_line("particle file", number, "bad particle count"))?;
+            },
+            "rate" => config.rate = floats::<[f32; 1]>(number, value)?[0],
+            "lifetime" => config.lifetime = range(number, value)?,
+            "speed" => config.speed = range(number, value)?,
+            "direction" => config.direction = floats(number, value)?,
+            "spread" => config.spread = floats::<[f32; 1]>(number, value)?[0],
+            "gravity" => config.gravity = floats(number, value)?,
+            "drag" => config.drag = floats::<[f32; 1]>(number, value)?[0],
+            "size" => config.size = range(number, value)?,
+            "color_start" => config.color_start = floats(number, value)?,
+            "color_end" => config.color_end = floats(number, value)?,
+            "blend" => {
+                config.blend = match value {
+                    "alpha" => BlendMode::Alpha,
+                    "additive" => BlendMode::Additive,
+                    _ => return Err(invalid_line("particle file", number,
+                                                 "blend must be alpha or additive")),
+                };
+            },
+            _ => return Err(invalid_line("particle file", number, "unknown setting")),
+        }
+    }
+
+    Ok(configs)
+}
+
+/// Fixed size list of numbers separated by whitespace.
+fn floats<A>(number: usize, value: &str) -> io::Result<A>
+    where A: Default + AsMut<[f32]>
+{
+    let mut result = A::default();
+    let mut parts = value.split_whitespace();
+
+    for slot in result.as_mut().iter_mut() {
+        *slot = parts.next().and_then(|p| p.parse().ok())
+            .ok_or_else(|| invalid_line("particle file", number, "bad number"))?;
+    }
+    if parts.next().is_some() {
+        return Err(invalid_line("particle file", number, "too many numbers"));
+    }
+
+    Ok(result)
+}
+
+fn range(number: usize, value: &str) -> io::Result<(f32, f32)> {
+    match floats::<[f32; 1]>(number, value) {
+        Ok(single) => Ok((single[0], single[0])),
+        Err(_) => floats::<[f32; 2]>(number, value).map(|r| (r[0], r[1])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        let error = parse(text).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    #[test]
+    fn parses_emitters_in_order() {
+        let configs = parse("# sparks and smoke\n\
+                             [sparks]\n\
+                             max_particles = 64 # small\n\
+                             lifetime = 0.5 1.5\n\
+                             blend = additive\n\
+                             \n\
+                             [ smoke ]\n\
+                             speed = 2\n\
+                             gravity = 0 -9.8 0\n").unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].name, "sparks");
+        assert_eq!(configs[0].max_particles, 64);
+        assert_eq!(configs[0].lifetime, (0.5, 1.5));
+        assert_eq!(configs[0].blend, BlendMode::Additive);
+        assert_eq!(configs[1].name, "smoke");
+        assert_eq!(configs[1].max_particles, 1024);
+        assert_eq!(configs[1].speed, (2.0, 2.0));
+        assert_eq!(configs[1].gravity, [0.0, -9.8, 0.0]);
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        assert_eq!(error("[a]\nrate = 1\nrate = fast\n"),
+                   "particle file line 3: bad number");
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(error("[]").ends_with("bad emitter name"));
+        assert!(error("[a").ends_with("bad emitter name"));
+        assert!(error("rate = 1").ends_with("setting outside of an emitter"));
+        assert!(error("[a]\nrate").ends_with("expected key = value"));
+        assert!(error("[a]\nmax_particles = 0").ends_with("bad particle count"));
+        assert!(error("[a]\nmax_particles = -1").ends_with("bad particle count"));
+        assert!(error("[a]\ndirection = 0 1").ends_with("bad number"));
+        assert!(error("[a]\ndirection = 0 1 0 0").ends_with("too many numbers"));
+        assert!(error("[a]\nlifetime = 1 2 3").ends_with("too many numbers"));
+        assert!(error("[a]\nblend = multiply").ends_with("blend must be alpha or additive"));
+        assert!(error("[a]\ncolour = 1 1 1 1").ends_with("unknown setting"));
+    }
+}
@@ -0,0 +1,314 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Transform, Vector3};
+
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::pipeline as vkp;
+
+use std::cmp;
+use std::sync::Arc;
+
+use super::framework::gfx;
+use super::renderer::Camera;
+use super::scene::{NodeId, Scene};
+
+pub mod billboard;
+pub mod config;
+pub mod simulate;
+
+pub use self::billboard::BillboardPipeline;
+pub use self::config::EmitterConfig;
+pub use self::simulate::{ParticleBuffers, Simulation};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EmitterId(usize);
+
+/// How many particles an emitter spawns and in which slots of its ring.
+///
+/// This is all of an emitter the CPU simulates, so it also runs without a
+/// device, e.g. in headless replays.
+#[derive(Clone, Debug)]
+pub struct Spawner {
+    rate: f32,
+    count: usize,
+    /// Ring slot the next particle is spawned in.
+    next: usize,
+    /// Fraction of a particle left over from previous updates.
+    pending: f32,
+    pub active: bool,
+}
+
+impl Spawner {
+    pub fn new(config: &EmitterConfig) -> Spawner {
+        Spawner {
+            rate: config.rate,
+            count: config.max_particles,
+            next: 0,
+            pending: 0.0,
+            active: true,
+        }
+    }
+
+    /// Advances by `dt` seconds, returning the first ring slot and the
+    /// number of particles to spawn from it, wrapping around. Inactive
+    /// spawners spawn nothing and drop their leftover fraction.
+    pub fn spawn(&mut self, dt: f32) -> (usize, usize) {
+        let start = self.next;
+        if !self.active || self.count == 0 {
+            self.pending = 0.0;
+            return (start, 0);
+        }
+
+        self.pending += self.rate * dt;
+        let spawned = cmp::min(self.pending as usize, self.count);
+        // Anything past a whole ring would only replace its own particles
+        self.pending = (self.pending - spawned as f32).min(self.count as f32);
+        self.next = (self.next + spawned) % self.count;
+        (start, spawned)
+    }
+}
+
+struct Emitter {
+    config: EmitterConfig,
+    node: NodeId,
+    spawner: Spawner,
+    buffers: ParticleBuffers,
+    draw_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    cleared: bool,
+    /// World space position as of the last update.
+    origin: Point3<f32>,
+}
+
+/// Particle effects emitted from scene nodes.
+///
+/// Particles live in storage buffers and are simulated by compute shaders
+/// on the compute queue, so the CPU only decides how many to spawn. Each
+/// emitter keeps a fixed size ring of particles; spawning overwrites the
+/// oldest slots.
+///
+/// Alpha blended emitters have their particles sorted back to front on the
+/// GPU. Emitters are drawn farthest first, after the opaque scene, without
+/// interleaving their particles with each other.
+pub struct ParticleSystem {
+    gfx: Arc<gfx::Core>,
+    simulation: Simulation,
+    alpha_pipeline: Arc<BillboardPipeline>,
+    additive_pipeline: Arc<BillboardPipeline>,
+    params_pool: vkb::CpuBufferPool<billboard::Params>,
+    emitters: Vec<Emitter>,
+    seed: u32,
+}
+
+impl ParticleSystem {
+    pub fn new(gfx: Arc<gfx::Core>) -> Result<ParticleSystem, ()> {
+        Ok(ParticleSystem {
+            simulation: Simulation::new(gfx.device.clone())?,
+            alpha_pipeline: billboard::pipeline(&gfx, gfx::BlendMode::Alpha)?,
+            additive_pipeline: billboard::pipeline(&gfx, gfx::BlendMode::Additive)?,
+            params_pool: vkb::CpuBufferPool::uniform_buffer(gfx.device.clone()),
+            emitters: Vec::new(),
+            seed: 1,
+            gfx: gfx,
+        })
+    }
+
+    /// Attaches an emitter to `node`. Particles spawn at the origin of the
+    /// node and stay in world space once spawned.
+    pub fn add_emitter(&mut self, config: &EmitterConfig, node: NodeId) -> Result<EmitterId, ()> {
+        let buffers = self.simulation.buffers(self.gfx.device.clone(),
+                                              self.gfx.queue.family().id(),
+                                              config.max_particles)?;
+
+        let draw_set = vkds::PersistentDescriptorSet::start(self.pipeline(config.blend), 1)
+            .add_buffer(buffers.particles.clone())
+            .and_then(|s| s.add_buffer(buffers.keys.clone()))
+            .map_err(|e| println!("Failed to bind particles ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create particle draw set ({:?})", e))?;
+
+        self.emitters.push(Emitter {
+            config: config.clone(),
+            node: node,
+            spawner: Spawner::new(config),
+            buffers: buffers,
+            draw_set: Arc::new(draw_set),
+            cleared: false,
+            origin: Point3::origin(),
+        });
+        Ok(EmitterId(self.emitters.len() - 1))
+    }
+
+    /// Stops or resumes spawning. Particles already alive finish their
+    /// lifetime either way.
+    pub fn set_active(&mut self, id: EmitterId, active: bool) {
+        self.emitters[id.0].spawner.active = active;
+    }
+
+    /// Records the simulation of `dt` seconds and the sorting of the
+    /// particles for `camera`. Submit the batch before the frame drawing
+    /// them and join its future into the frame.
+    pub fn update(&mut self,
+                  scene: &Scene,
+                  camera: &Camera,
+                  dt: f32) -> Result<Option<gfx::ComputeBatch>, ()> {
+        if self.emitters.is_empty() {
+            return Ok(None);
+        }
+
+        let mut batch = gfx::ComputeBatch::new(self.gfx.device.clone(),
+                                               self.gfx.queues.compute.clone(),
+                                               self.gfx.queue.clone())?;
+        let view = camera.view.into();
+
+        for emitter in &mut self.emitters {
+            let world = scene.node(emitter.node).world();
+            emitter.origin = world.transform_point(Point3::origin());
+            let direction = world.transform_vector(Vector3::from(emitter.config.direction));
+            let direction = if direction.magnitude2() > 0.0 {
+                direction.normalize()
+            } else {
+                Vector3::unit_y()
+            };
+
+            let (start, spawned) = emitter.spawner.spawn(dt);
+
+            batch = batch.share(emitter.buffers.particles.clone())
+                .share(emitter.buffers.keys.clone());
+
+            if !emitter.cleared {
+                let particles = emitter.buffers.particles.clone();
+                batch = batch.record(|builder| {
+                    builder.fill_buffer(particles, 0)
+                        .map_err(|e| println!("Failed to clear particles ({:?})", e))
+                })?;
+                emitter.cleared = true;
+            }
+
+            self.seed = self.seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let spawn = simulate::Spawn {
+                origin: emitter.origin.into(),
+                direction: direction.into(),
+                start: start as u32,
+                count: spawned as u32,
+                seed: self.seed,
+            };
+
+            batch = self.simulation.step(batch, &emitter.buffers, &emitter.config, spawn, dt)?;
+            batch = self.simulation.sort(batch, &emitter.buffers, view,
+                                         emitter.config.blend == gfx::BlendMode::Alpha)?;
+        }
+
+        Ok(Some(batch))
+    }
+
+    /// Records the particles into a secondary command buffer for the
+    /// transparent subpass, to be executed after the opaque draws.
+    pub fn render(&self,
+                  camera: &Camera,
+                  dynamic: &vkcb::DynamicState)
+                  -> Result<Option<vkcb::AutoCommandBuffer>, ()> {
+        if self.emitters.is_empty() {
+            return Ok(None);
+        }
+
+        // View space z is negative in front of the camera, farthest first
+        let mut order = self.emitters.iter()
+            .map(|e| (camera.view.transform_point(e.origin).z, e))
+            .collect::<Vec<_>>();
+        order.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal));
+
+        let subpass = self.gfx.scene_graph.subpass(self.gfx.transparent_pass);
+        let mut builder = vkcb::AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+            self.gfx.device.clone(),
+            self.gfx.queue.family(),
+            subpass
+        ).map_err(|e| println!("Failed to create particle command buffer ({:?})", e))?;
+
+        for (_, emitter) in order {
+            let config = &emitter.config;
+            let params = self.params_pool.next(billboard::Params {
+                view: camera.view.into(),
+                proj: camera.proj.into(),
+                color_start: config.color_start,
+                color_end: config.color_end,
+                size: [config.size.0, config.size.1, 0.0, 0.0],
+            }).map_err(|e| println!("Failed to allocate particle parameters ({:?})", e))?;
+
+            let pipeline = self.pipeline(config.blend);
+            let params_set = vkds::PersistentDescriptorSet::start(pipeline.clone(), 0)
+                .add_buffer(params)
+                .map_err(|e| println!("Failed to bind particle parameters ({:?})", e))?
+                .build()
+                .map_err(|e| println!("Failed to create particle parameter set ({:?})", e))?;
+
+            builder = builder.draw(pipeline,
+                                   dynamic.clone(),
+                                   vkp::vertex::BufferlessVertices {
+                                       vertices: emitter.buffers.count * 6,
+                                       instances: 1,
+                                   },
+                                   (params_set, emitter.draw_set.clone()),
+                                   ())
+                .map_err(|e| println!("Failed to draw particles ({:?})", e))?;
+        }
+
+        builder.build()
+            .map(Some)
+            .map_err(|e| println!("Failed to build particle command buffer ({:?})", e))
+    }
+
+    fn pipeline(&self, blend: gfx::BlendMode) -> Arc<BillboardPipeline> {
+        match blend {
+            gfx::BlendMode::Additive => self.additive_pipeline.clone(),
+            _ => self.alpha_pipeline.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawner(rate: f32, max_particles: usize) -> Spawner {
+        Spawner::new(&EmitterConfig {
+            rate: rate,
+            max_particles: max_particles,
+            .. EmitterConfig::default()
+        })
+    }
+
+    #[test]
+    fn accumulates_fractions_of_particles() {
+        let mut spawner = spawner(4.0, 100);
+        assert_eq!(spawner.spawn(0.625), (0, 2));
+        assert_eq!(spawner.spawn(0.625), (2, 3));
+        assert_eq!(spawner.spawn(0.125), (5, 0));
+        assert_eq!(spawner.spawn(0.125), (5, 1));
+    }
+
+    #[test]
+    fn wraps_around_the_ring() {
+        let mut spawner = spawner(1.0, 8);
+        assert_eq!(spawner.spawn(6.0), (0, 6));
+        assert_eq!(spawner.spawn(4.0), (6, 4));
+        assert_eq!(spawner.spawn(1.0), (2, 1));
+    }
+
+    #[test]
+    fn spawns_at_most_the_ring_per_update() {
+        let mut spawner = spawner(1.0, 8);
+        assert_eq!(spawner.spawn(100.0), (0, 8));
+        assert_eq!(spawner.spawn(0.5), (0, 8));
+        assert_eq!(spawner.spawn(0.5), (0, 1));
+    }
+
+    #[test]
+    fn inactive_spawners_drop_their_leftovers() {
+        let mut spawner = spawner(1.0, 8);
+        assert_eq!(spawner.spawn(1.5), (0, 1));
+        spawner.active = false;
+        assert_eq!(spawner.spawn(10.0), (1, 0));
+        spawner.active = true;
+        assert_eq!(spawner.spawn(0.75), (1, 0));
+    }
+}
@@ -0,0 +1,389 @@
+use vulkano::buffer as vkb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::device as vkd;
+
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+
+/// Particle as stored on the GPU. Particles whose age reached their
+/// lifetime are dead and not drawn.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    /// `w` is the age in seconds.
+    pub position: [f32; 4],
+    /// `w` is the lifetime in seconds.
+    pub velocity: [f32; 4],
+}
+
+/// Draw order entry. Dead particles and padding sort after live ones.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SortKey {
+    pub depth: f32,
+    pub index: u32,
+}
+
+/// Particles spawned by a step, in world space.
+#[derive(Clone, Copy, Debug)]
+pub struct Spawn {
+    pub origin: [f32; 3],
+    pub direction: [f32; 3],
+    /// First ring slot to overwrite.
+    pub start: u32,
+    pub count: u32,
+    pub seed: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    origin: [f32; 4],
+    direction: [f32; 4],
+    gravity: [f32; 4],
+    ranges: [f32; 4],
+    spawn_start: u32,
+    spawn_count: u32,
+    count: u32,
+    seed: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DepthView {
+    view: [[f32; 4]; 4],
+    count: u32,
+    padded: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SortStep {
+    j: u32,
+    k: u32,
+}
+
+/// GPU buffers of one emitter.
+pub struct ParticleBuffers {
+    pub particles: Arc<vkb::DeviceLocalBuffer<[Particle]>>,
+    /// Padded to a power of two for the bitonic sort.
+    pub keys: Arc<vkb::DeviceLocalBuffer<[SortKey]>>,
+    pub count: usize,
+    simulate_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    depth_set: Arc<vkds::DescriptorSet + Send + Sync>,
+    sort_set: Arc<vkds::DescriptorSet + Send + Sync>,
+}
+
+impl ParticleBuffers {
+    pub fn padded_count(&self) -> usize {
+        self.count.next_power_of_two()
+    }
+}
+
+/// Compute pipelines integrating particles and sorting them for drawing.
+pub struct Simulation {
+    simulate: gfx::ComputePipeline,
+    depth_keys: gfx::ComputePipeline,
+    sort_step: gfx::ComputePipeline,
+}
+
+impl Simulation {
+    pub fn new(device: Arc<vkd::Device>) -> Result<Simulation, ()> {
+        let simulate = simulate_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load particle simulation shader ({:?})", e))?;
+        let depth_keys = depth_keys_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load particle depth shader ({:?})", e))?;
+        let sort_step = sort_step_cs::Shader::load(device.clone())
+            .map_err(|e| println!("Failed to load particle sort shader ({:?})", e))?;
+
+        Ok(Simulation {
+            simulate: gfx::ComputePipeline::build(device.clone(), &simulate.main_entry_point(),
+                                                  [64, 1, 1])?,
+            depth_keys: gfx::ComputePipeline::build(device.clone(), &depth_keys.main_entry_point(),
+                                                    [64, 1, 1])?,
+            sort_step: gfx::ComputePipeline::build(device, &sort_step.main_entry_point(),
+                                                   [64, 1, 1])?,
+        })
+    }
+
+    /// Creates the buffers of `count` particles, owned by `queue_family`.
+    ///
+    /// The particles must be cleared to zero, which kills them all, before
+    /// the first step.
+    pub fn buffers(&self,
+                   device: Arc<vkd::Device>,
+                   queue_family: u32,
+                   count: usize) -> Result<ParticleBuffers, ()> {
+        let particles = gfx::compute::storage_buffer::<Particle>(device.clone(),
+                                                                 queue_family,
+                                                                 count,
+                                                                 vkb::BufferUsage::none())?;
+        let keys = gfx::compute::storage_buffer::<SortKey>(device,
+                                                           queue_family,
+                                                           count.next_power_of_two(),
+                                                           vkb::BufferUsage::none())?;
+
+        let simulate_set = vkds::PersistentDescriptorSet::start(self.simulate.id.clone(), 0)
+            .add_buffer(particles.clone())
+            .map_err(|e| println!("Failed to bind particles ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create particle set ({:?})", e))?;
+
+        let depth_set = vkds::PersistentDescriptorSet::start(self.depth_keys.id.clone(), 0)
+            .add_buffer(particles.clone())
+            .and_then(|s| s.add_buffer(keys.clone()))
+            .map_err(|e| println!("Failed to bind particle keys ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create particle key set ({:?})", e))?;
+
+        let sort_set = vkds::PersistentDescriptorSet::start(self.sort_step.id.clone(), 0)
+            .add_buffer(keys.clone())
+            .map_err(|e| println!("Failed to bind particle keys ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create particle sort set ({:?})", e))?;
+
+        Ok(ParticleBuffers {
+            particles: particles,
+            keys: keys,
+            count: count,
+            simulate_set: Arc::new(simulate_set),
+            depth_set: Arc::new(depth_set),
+            sort_set: Arc::new(sort_set),
+        })
+    }
+
+    /// Advances the particles by `dt` seconds and spawns new ones.
+    pub fn step(&self,
+                batch: gfx::ComputeBatch,
+                buffers: &ParticleBuffers,
+                config: &super::EmitterConfig,
+                spawn: Spawn,
+                dt: f32) -> Result<gfx::ComputeBatch, ()> {
+        let step = Step {
+            origin: [spawn.origin[0], spawn.origin[1], spawn.origin[2], dt],
+            direction: [spawn.direction[0], spawn.direction[1], spawn.direction[2], config.spread],
+            gravity: [config.gravity[0], config.gravity[1], config.gravity[2], config.drag],
+            ranges: [config.lifetime.0, config.lifetime.1, config.speed.0, config.speed.1],
+            spawn_start: spawn.start,
+            spawn_count: spawn.count,
+            count: buffers.count as u32,
+            seed: spawn.seed,
+        };
+
+        batch.dispatch(&self.simulate, [buffers.count as u32, 1, 1],
+                       buffers.simulate_set.clone(), step)
+    }
+
+    /// Writes the draw order of the particles to `buffers.keys`, farthest
+    /// from the camera first if `sorted`, else in storage order.
+    pub fn sort(&self,
+                batch: gfx::ComputeBatch,
+                buffers: &ParticleBuffers,
+                view: [[f32; 4]; 4],
+                sorted: bool) -> Result<gfx::ComputeBatch, ()> {
+        let padded = buffers.padded_count() as u32;
+        let mut batch = batch.dispatch(&self.depth_keys,
+                                       [padded, 1, 1],
+                                       buffers.depth_set.clone(),
+                                       DepthView {
+                                           view: view,
+                                           count: buffers.count as u32,
+                                           padded: padded,
+                                       })?;
+
+        if !sorted {
+            return Ok(batch);
+        }
+
+        // Bitonic sort, one dispatch per merge step
+        let mut k = 2;
+        while k <= padded {
+            let mut j = k / 2;
+            while j > 0 {
+                batch = batch.dispatch(&self.sort_step, [padded, 1, 1],
+                                       buffers.sort_set.clone(), SortStep { j: j, k: k })?;
+                j /= 2;
+            }
+            k *= 2;
+        }
+
+        Ok(batch)
+    }
+}
+
+mod simulate_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 64) in;
+
+struct Particle {
+    vec4 position;
+    vec4 velocity;
+};
+
+layout(set = 0, binding = 0) buffer Particles {
+    Particle particles[];
+};
+
+layout(push_constant) uniform Step {
+    vec4 origin;     // w: dt
+    vec4 direction;  // w: spread
+    vec4 gravity;    // w: drag
+    vec4 ranges;     // lifetime min/max, speed min/max
+    uint spawn_start;
+    uint spawn_count;
+    uint count;
+    uint seed;
+} params;
+
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352dU;
+    x ^= x >> 15;
+    x *= 0x846ca68bU;
+    x ^= x >> 16;
+    return x;
+}
+
+float random(inout uint state) {
+    state = hash(state);
+    return float(state) / 4294967295.0;
+}
+
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    if (idx >= params.count) {
+        return;
+    }
+
+    Particle p = particles[idx];
+    float dt = params.origin.w;
+
+    // Spawned slots wrap around the end of the ring
+    uint offset = (idx + params.count - params.spawn_start) % params.count;
+    if (offset < params.spawn_count) {
+        uint state = hash(idx ^ params.seed);
+        float lifetime = mix(params.ranges.x, params.ranges.y, random(state));
+        float speed = mix(params.ranges.z, params.ranges.w, random(state));
+
+        vec3 axis = normalize(params.direction.xyz);
+        vec3 side = normalize(cross(axis, abs(axis.y) < 0.99 ? vec3(0.0, 1.0, 0.0)
+                                                             : vec3(1.0, 0.0, 0.0)));
+        vec3 up = cross(axis, side);
+        float angle = random(state) * 6.2831853;
+        float tilt = random(state) * params.direction.w;
+        vec3 direction = cos(tilt) * axis + sin(tilt) * (cos(angle) * side + sin(angle) * up);
+
+        p.position = vec4(params.origin.xyz, 0.0);
+        p.velocity = vec4(direction * speed, lifetime);
+    } else if (p.position.w < p.velocity.w) {
+        p.velocity.xyz += params.gravity.xyz * dt;
+        p.velocity.xyz *= max(0.0, 1.0 - params.gravity.w * dt);
+        p.position.xyz += p.velocity.xyz * dt;
+        p.position.w += dt;
+    }
+
+    particles[idx] = p;
+}
+"]
+    struct Dummy;
+}
+
+mod depth_keys_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 64) in;
+
+struct Particle {
+    vec4 position;
+    vec4 velocity;
+};
+
+struct SortKey {
+    float depth;
+    uint index;
+};
+
+layout(set = 0, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+
+layout(set = 0, binding = 1) writeonly buffer Keys {
+    SortKey keys[];
+};
+
+layout(push_constant) uniform View {
+    mat4 view;
+    uint count;
+    uint padded;
+} view;
+
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    if (idx >= view.padded) {
+        return;
+    }
+
+    float depth = -1.0e30;
+    if (idx < view.count) {
+        Particle p = particles[idx];
+        if (p.position.w < p.velocity.w) {
+            depth = -(view.view * vec4(p.position.xyz, 1.0)).z;
+        }
+    }
+
+    keys[idx] = SortKey(depth, min(idx, view.count - 1));
+}
+"]
+    struct Dummy;
+}
+
+mod sort_step_cs {
+    #[derive(VulkanoShader)]
+    #[ty = "compute"]
+    #[src = "
+#version 450
+
+layout(local_size_x = 64) in;
+
+struct SortKey {
+    float depth;
+    uint index;
+};
+
+layout(set = 0, binding = 0) buffer Keys {
+    SortKey keys[];
+};
+
+layout(push_constant) uniform SortStep {
+    uint j;
+    uint k;
+} params;
+
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    uint partner = idx ^ params.j;
+    if (partner <= idx || partner >= keys.length()) {
+        return;
+    }
+
+    SortKey a = keys[idx];
+    SortKey b = keys[partner];
+
+    // Blocks alternate direction so they merge into a descending sequence
+    bool descending = (idx & params.k) == 0;
+    if (descending ? a.depth < b.depth : a.depth > b.depth) {
+        keys[idx] = b;
+        keys[partner] = a;
+    }
+}
+"]
+    struct Dummy;
+}
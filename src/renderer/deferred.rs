@@ -0,0 +1,250 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::framebuffer as vkfb;
+use vulkano::pipeline as vkp;
+
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+use super::super::framework::gfx::PipelineBuilderExt;
+use super::light::LightData;
+
+/// Fullscreen pipeline of the lighting subpass. Its concrete type is kept
+/// since vulkano only draws bufferless pipelines it can see the vertex
+/// definition of.
+type LightingPipeline = vkp::GraphicsPipeline<vkp::vertex::BufferlessDefinition,
+                                              Box<PipelineLayoutAbstract + Send + Sync>,
+                                              Arc<vkfb::RenderPassAbstract + Send + Sync>>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Unproject {
+    inverse_proj: [[f32; 4]; 4],
+    /// Inverse view, to look the environment up in world space.
+    inverse_view: [[f32; 4]; 4],
+}
+
+/// Shades the G-buffer of the deferred path.
+///
+/// A fullscreen triangle reads albedo, normal, material parameters and
+/// depth of its pixel through input attachments, rebuilds the view space
+/// position from depth and adds up the environment and the lights. Pixels
+/// nothing was drawn to are left for the skybox.
+pub struct Lighting {
+    pipeline: Arc<LightingPipeline>,
+    gbuffer: [gfx::AttachmentId; 4],
+}
+
+impl Lighting {
+    pub fn new(core: &gfx::Core) -> Result<Lighting, ()> {
+        let pass = core.lighting_pass
+            .ok_or_else(|| println!("The scene graph has no lighting pass"))?;
+
+        let mut gbuffer = Vec::with_capacity(4);
+        for name in &["albedo", "normal", "material", "depth"] {
+            gbuffer.push(core.scene_graph.attachment(name)
+                .ok_or_else(|| println!("The scene graph has no {} attachment", name))?);
+        }
+
+        let vs = lighting_vs::Shader::load(core.device.clone())
+            .map_err(|e| println!("Failed to load lighting vertex shader ({:?})", e))?;
+        let fs = lighting_fs::Shader::load(core.device.clone())
+            .map_err(|e| println!("Failed to load lighting fragment shader ({:?})", e))?;
+
+        let pipeline = vkp::GraphicsPipeline::start()
+            .vertex_input(vkp::vertex::BufferlessDefinition)
+            .vertex_shader(vs.main_entry_point(), ())
+            .topology(gfx::Topology::TriangleList)
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .render_pass(core.scene_graph.subpass(pass))
+            .build(core.device.clone())
+            .map_err(|e| println!("Failed to create lighting pipeline ({:?})", e))?;
+
+        Ok(Lighting {
+            pipeline: Arc::new(pipeline),
+            gbuffer: [gbuffer[0], gbuffer[1], gbuffer[2], gbuffer[3]],
+        })
+    }
+
+    /// Records the lighting subpass, which must be the current one.
+    pub fn record<L>(&self,
+                     builder: vkcb::AutoCommandBufferBuilder,
+                     targets: &gfx::GraphTargets,
+                     view: &Matrix4<f32>,
+                     proj: &Matrix4<f32>,
+                     lights: L,
+                     environment: &gfx::Environment,
+                     dynamic: &vkcb::DynamicState)
+                     -> Result<vkcb::AutoCommandBufferBuilder, ()>
+        where L: vkb::TypedBufferAccess<Content = [LightData]> + Send + Sync + 'static
+    {
+        let mut images = Vec::with_capacity(4);
+        for &attachment in &self.gbuffer {
+            images.push(targets.image(attachment)
+                .ok_or_else(|| println!("The G-buffer is not owned by the scene graph"))?);
+        }
+
+        let set = vkds::PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+            .add_image(images[0].clone())
+            .and_then(|s| s.add_image(images[1].clone()))
+            .and_then(|s| s.add_image(images[2].clone()))
+            .and_then(|s| s.add_image(images[3].clone()))
+            .and_then(|s| s.add_buffer(lights))
+            .and_then(|s| s.add_sampled_image(environment.irradiance.clone(),
+                                              environment.sampler.clone()))
+            .and_then(|s| s.add_sampled_image(environment.prefiltered.clone(),
+                                              environment.sampler.clone()))
+            .and_then(|s| s.add_sampled_image(environment.brdf.clone(),
+                                              environment.lut_sampler.clone()))
+            .map_err(|e| println!("Failed to bind G-buffer ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create G-buffer set ({:?})", e))?;
+
+        let inverse_proj = proj.invert()
+            .ok_or_else(|| println!("The projection cannot be inverted"))?;
+        let inverse_view = view.invert()
+            .ok_or_else(|| println!("The view cannot be inverted"))?;
+
+        builder.draw(self.pipeline.clone(),
+                     dynamic.clone(),
+                     vkp::vertex::BufferlessVertices {
+                         vertices: 3,
+                         instances: 1,
+                     },
+                     set,
+                     Unproject {
+                         inverse_proj: inverse_proj.into(),
+                         inverse_view: inverse_view.into(),
+                     })
+            .map_err(|e| println!("Failed to draw lighting pass ({:?})", e))
+    }
+}
+
+mod lighting_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) out vec2 v_ndc;
+
+void main() {
+    // One triangle covering the screen
+    vec2 ndc = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    v_ndc = ndc;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod lighting_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec2 v_ndc;
+layout(location = 0) out vec4 f_color;
+
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput g_albedo;
+layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput g_normal;
+layout(input_attachment_index = 2, set = 0, binding = 2) uniform subpassInput g_material;
+layout(input_attachment_index = 3, set = 0, binding = 3) uniform subpassInput g_depth;
+
+struct Light {
+    vec4 position;  // view space, w: radius
+    vec4 color;
+};
+
+layout(set = 0, binding = 4) readonly buffer Lights {
+    Light lights[];
+};
+
+layout(set = 0, binding = 5) uniform sampler2D irradiance_map;
+layout(set = 0, binding = 6) uniform sampler2D prefiltered_map;
+layout(set = 0, binding = 7) uniform sampler2D brdf_lut;
+
+layout(push_constant) uniform Unproject {
+    mat4 inverse_proj;
+    mat4 inverse_view;
+} unproject;
+
+const float PI = 3.14159265359;
+// See gfx::environment
+const float LEVELS = 5.0;
+const float LEVEL_HEIGHT = 64.0;
+
+vec2 equirect(vec3 d) {
+    return vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+vec3 prefiltered(vec3 d, float roughness) {
+    vec2 uv = equirect(d);
+    // Keep the filter inside each band
+    uv.y = clamp(uv.y, 0.5 / LEVEL_HEIGHT, 1.0 - 0.5 / LEVEL_HEIGHT);
+    float level = roughness * (LEVELS - 1.0);
+    float band = min(floor(level), LEVELS - 2.0);
+    vec3 smooth_band = textureLod(prefiltered_map, vec2(uv.x, (band + uv.y) / LEVELS), 0.0).rgb;
+    vec3 rough_band = textureLod(prefiltered_map, vec2(uv.x, (band + 1.0 + uv.y) / LEVELS), 0.0).rgb;
+    return mix(smooth_band, rough_band, level - band);
+}
+
+// Diffuse and specular light of the environment, from world space vectors
+vec3 environment(vec3 n, vec3 v, vec3 albedo, float specular, float shininess) {
+    // Blinn-Phong exponent to GGX roughness
+    float roughness = pow(2.0 / (shininess + 2.0), 0.25);
+    vec2 brdf = texture(brdf_lut, vec2(max(dot(n, v), 0.0), roughness)).rg;
+    float f0 = specular * 0.08;
+    return textureLod(irradiance_map, equirect(n), 0.0).rgb * albedo +
+        prefiltered(reflect(-v, n), roughness) * (f0 * brdf.x + brdf.y);
+}
+
+void main() {
+    float z = subpassLoad(g_depth).r;
+    if (z >= 1.0) {
+        discard;
+    }
+
+    vec4 position = unproject.inverse_proj * vec4(v_ndc, z, 1.0);
+    position /= position.w;
+
+    vec3 albedo = subpassLoad(g_albedo).rgb;
+    vec3 n = normalize(subpassLoad(g_normal).xyz);
+    vec4 params = subpassLoad(g_material);
+    float specular = params.x;
+    float shininess = params.y * 128.0;
+    vec3 v = normalize(-position.xyz);
+
+    // Same lighting model as the forward lit shader
+    mat3 inverse_view = mat3(unproject.inverse_view);
+    vec3 color = environment(inverse_view * n, inverse_view * v, albedo, specular, shininess);
+    color += params.z * albedo;
+
+    for (int i = 0; i < lights.length(); i++) {
+        vec3 to_light = lights[i].position.xyz - position.xyz;
+        float dist = length(to_light);
+        float radius = lights[i].position.w;
+        if (dist >= radius) {
+            continue;
+        }
+
+        vec3 l = to_light / dist;
+        float falloff = 1.0 - dist / radius;
+        float diffuse = max(dot(n, l), 0.0);
+        float highlight = diffuse > 0.0
+            ? specular * pow(max(dot(n, normalize(l + v)), 0.0), shininess)
+            : 0.0;
+        color += lights[i].color.rgb * falloff * falloff * (diffuse * albedo + highlight);
+    }
+
+    f_color = vec4(color, 1.0);
+}
+"]
+    struct Dummy;
+}
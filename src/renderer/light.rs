@@ -0,0 +1,43 @@
+use cgmath::{Matrix4, Point3, Transform};
+
+/// Point light, submitted every frame like meshes.
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub position: Point3<f32>,
+    /// Linear color, multiplied by `intensity`.
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely.
+    pub radius: f32,
+}
+
+/// Light in view space, laid out as the `Light` struct of lit shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LightData {
+    /// `w` is the radius.
+    pub position: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl LightData {
+    pub fn new(light: &Light, view: &Matrix4<f32>) -> LightData {
+        let position = view.transform_point(light.position);
+        LightData {
+            position: [position.x, position.y, position.z, light.radius],
+            color: [light.color[0] * light.intensity,
+                    light.color[1] * light.intensity,
+                    light.color[2] * light.intensity,
+                    0.0],
+        }
+    }
+
+    /// Light without any effect. Storage buffers cannot be empty, so frames
+    /// without lights upload one of these.
+    pub fn none() -> LightData {
+        LightData {
+            position: [0.0; 4],
+            color: [0.0; 4],
+        }
+    }
+}
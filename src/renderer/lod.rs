@@ -0,0 +1,41 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Transform};
+
+use super::super::framework::bounds::{Bounds, Sphere};
+use super::super::framework::gfx;
+
+/// One level of detail of a mesh asset.
+pub struct Lod {
+    pub mesh: gfx::Mesh,
+    /// Smallest screen size this level is used at.
+    pub screen_size: f32,
+}
+
+/// Mesh with its levels of detail, most detailed first.
+pub struct MeshAsset {
+    pub lods: Vec<Lod>,
+}
+
+impl MeshAsset {
+    /// Bounds of the most detailed level, used for culling all of them.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.lods[0].mesh.bounds
+    }
+
+    /// Index of the first level whose threshold `screen_size` reaches. The
+    /// last level is used below every threshold.
+    pub fn select(&self, screen_size: f32) -> usize {
+        self.lods.iter()
+            .position(|lod| screen_size >= lod.screen_size)
+            .unwrap_or(self.lods.len() - 1)
+    }
+}
+
+/// Projected diameter of a world space sphere relative to the viewport
+/// height.
+pub fn screen_size(sphere: &Sphere, view: &Matrix4<f32>, proj: &Matrix4<f32>) -> f32 {
+    let distance = view.transform_point(sphere.center).to_vec().magnitude();
+    if distance <= sphere.radius {
+        return ::std::f32::INFINITY;
+    }
+    sphere.radius * proj.y.y.abs() / distance
+}
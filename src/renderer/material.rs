@@ -0,0 +1,394 @@
+use vulkano::pipeline as vkp;
+
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+use super::super::framework::gfx::PipelineBuilderExt;
+
+/// Builds a lit material around the vertex shader `$vs`. Pipelines with
+/// different vertex shaders have different types, hence the macro.
+macro_rules! lit_material {
+    ($core:expr, $layout:expr, $topology:expr, $blend:expr, $vs:expr, $skinned:expr) => {{
+        let (core, layout, topology, blend) = ($core, $layout, $topology, $blend);
+        let builder = vkp::GraphicsPipeline::start()
+            .vertex_input(layout.clone())
+            .vertex_shader($vs.main_entry_point(), ())
+            .topology(topology)
+            .viewports_dynamic_scissors_irrelevant(1);
+
+        // The G-buffer has no room for transparency
+        let gbuffer = core.render_path == gfx::RenderPath::Deferred && !blend.is_transparent();
+
+        let pipeline = if gbuffer {
+            let fs = gbuffer_fs::Shader::load(core.device.clone())
+                .map_err(|e| println!("Failed to load G-buffer fragment shader ({:?})", e))?;
+            let pipeline = builder
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(core.scene_graph.subpass(core.scene_pass))
+                .build(core.device.clone())
+                .map_err(|e| println!("Failed to create G-buffer pipeline ({:?})", e))?;
+            Arc::new(pipeline) as Arc<vkp::GraphicsPipelineAbstract + Send + Sync>
+        } else {
+            let pass = if blend.is_transparent() { core.transparent_pass } else { core.scene_pass };
+            let fs = lit_fs::Shader::load(core.device.clone())
+                .map_err(|e| println!("Failed to load lit fragment shader ({:?})", e))?;
+            let pipeline = builder
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .depth_write(!blend.is_transparent())
+                .blend(blend)
+                .render_pass(core.scene_graph.subpass(pass))
+                .build(core.device.clone())
+                .map_err(|e| println!("Failed to create lit pipeline ({:?})", e))?;
+            Arc::new(pipeline) as Arc<vkp::GraphicsPipelineAbstract + Send + Sync>
+        };
+
+        Ok(Material {
+            pipeline: gfx::Pipeline::new(pipeline, layout.clone(), topology),
+            params: MaterialParams::default(),
+            blend: blend,
+            lit: !gbuffer,
+            skinned: $skinned,
+        })
+    }}
+}
+
+/// Parameters of the lighting model, laid out as the `MaterialParams`
+/// uniform of lit shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialParams {
+    /// Strength of specular highlights, from 0 to 1.
+    pub specular: f32,
+    /// Blinn-Phong exponent, from 1 to 128. Higher values give smaller
+    /// highlights.
+    pub shininess: f32,
+    /// Light emitted by the surface itself, as a fraction of its color.
+    pub emissive: f32,
+    /// Alpha below which alpha-tested materials discard fragments.
+    pub alpha_cutoff: f32,
+}
+
+impl Default for MaterialParams {
+    fn default() -> MaterialParams {
+        MaterialParams {
+            specular: 0.5,
+            shininess: 32.0,
+            emissive: 0.0,
+            alpha_cutoff: 0.5,
+        }
+    }
+}
+
+/// How the meshes drawn with it are shaded.
+///
+/// Material pipelines read the camera from set 0, binding 0, the instance
+/// array from set 0, binding 1 and their parameters from set 0, binding 2.
+/// The `first_instance` push constant says where the instances of the
+/// current batch start. Lit materials drawn with the forward shader also
+/// read the lights of the frame from set 0, binding 3, and the irradiance,
+/// prefiltered and BRDF maps of the environment from bindings 4 to 6.
+/// Skinned materials read the joint matrices of the frame from set 0,
+/// binding 7, starting at the `skin.x` of their instance.
+///
+/// Transparent materials are drawn after opaque ones, back to front, and
+/// with the deferred path always use the forward shader.
+pub struct Material {
+    pub pipeline: gfx::Pipeline,
+    pub params: MaterialParams,
+    pub blend: gfx::BlendMode,
+    /// Whether the pipeline reads the lights of the frame.
+    pub lit: bool,
+    /// Whether the pipeline reads the joint matrices of the frame.
+    pub skinned: bool,
+}
+
+impl Material {
+    /// Opaque lit material, colored by the tint of each instance. Writes
+    /// the G-buffer with the deferred path.
+    pub fn lit(core: &gfx::Core,
+               layout: &gfx::VertexLayout,
+               topology: gfx::Topology) -> Result<Material, ()> {
+        Material::lit_blended(core, layout, topology, gfx::BlendMode::Opaque)
+    }
+
+    /// Lit material blended with `blend`. The alpha of the tint is the
+    /// opacity, and with `BlendMode::Premultiplied` the tint's color must
+    /// already be multiplied by it.
+    pub fn lit_blended(core: &gfx::Core,
+                       layout: &gfx::VertexLayout,
+                       topology: gfx::Topology,
+                       blend: gfx::BlendMode) -> Result<Material, ()> {
+        let vs = lit_vs::Shader::load(core.device.clone())
+            .map_err(|e| println!("Failed to load lit vertex shader ({:?})", e))?;
+        lit_material!(core, layout, topology, blend, vs, false)
+    }
+
+    /// Lit material deforming meshes with the joint matrices submitted with
+    /// each instance. The layout must have joints and weights.
+    pub fn skinned(core: &gfx::Core,
+                   layout: &gfx::VertexLayout,
+                   topology: gfx::Topology,
+                   blend: gfx::BlendMode) -> Result<Material, ()> {
+        if !layout.has(gfx::Attribute::Joints) || !layout.has(gfx::Attribute::Weights) {
+            println!("Skinned materials need joints and weights, not {:?}", layout);
+            return Err(());
+        }
+
+        let vs = skinned_vs::Shader::load(core.device.clone())
+            .map_err(|e| println!("Failed to load skinned vertex shader ({:?})", e))?;
+        lit_material!(core, layout, topology, blend, vs, true)
+    }
+
+    /// Parameters as uploaded for the shaders. Only alpha-tested materials
+    /// discard fragments.
+    pub fn shader_params(&self) -> MaterialParams {
+        MaterialParams {
+            alpha_cutoff: match self.blend {
+                gfx::BlendMode::AlphaTested => self.params.alpha_cutoff,
+                _ => 0.0,
+            },
+            .. self.params
+        }
+    }
+}
+
+mod lit_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec4 v_tint;
+layout(location = 2) out vec3 v_position;
+
+struct Instance {
+    mat4 world;
+    vec4 tint;
+    uvec4 skin;
+};
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 proj;
+} camera;
+
+layout(set = 0, binding = 1) readonly buffer Instances {
+    Instance instances[];
+};
+
+layout(push_constant) uniform Batch {
+    uint first_instance;
+} batch;
+
+void main() {
+    Instance instance = instances[batch.first_instance + gl_InstanceIndex];
+    mat4 worldview = camera.view * instance.world;
+    v_normal = transpose(inverse(mat3(worldview))) * normal;
+    v_tint = instance.tint;
+    vec4 view_position = worldview * vec4(position, 1.0);
+    v_position = view_position.xyz;
+    gl_Position = camera.proj * view_position;
+}
+"]
+    struct Dummy;
+}
+
+mod skinned_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in uvec4 joints;
+layout(location = 3) in vec4 weights;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec4 v_tint;
+layout(location = 2) out vec3 v_position;
+
+struct Instance {
+    mat4 world;
+    vec4 tint;
+    uvec4 skin;
+};
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 proj;
+} camera;
+
+layout(set = 0, binding = 1) readonly buffer Instances {
+    Instance instances[];
+};
+
+layout(set = 0, binding = 7) readonly buffer Joints {
+    mat4 joint_matrices[];
+};
+
+layout(push_constant) uniform Batch {
+    uint first_instance;
+} batch;
+
+void main() {
+    Instance instance = instances[batch.first_instance + gl_InstanceIndex];
+    uint first = instance.skin.x;
+    mat4 skinning = weights.x * joint_matrices[first + joints.x] +
+        weights.y * joint_matrices[first + joints.y] +
+        weights.z * joint_matrices[first + joints.z] +
+        weights.w * joint_matrices[first + joints.w];
+    mat4 worldview = camera.view * instance.world * skinning;
+    v_normal = transpose(inverse(mat3(worldview))) * normal;
+    v_tint = instance.tint;
+    vec4 view_position = worldview * vec4(position, 1.0);
+    v_position = view_position.xyz;
+    gl_Position = camera.proj * view_position;
+}
+"]
+    struct Dummy;
+}
+
+mod lit_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec4 v_tint;
+layout(location = 2) in vec3 v_position;
+layout(location = 0) out vec4 f_color;
+
+struct Light {
+    vec4 position;  // view space, w: radius
+    vec4 color;
+};
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 proj;
+} camera;
+
+layout(set = 0, binding = 2) uniform MaterialParams {
+    float specular;
+    float shininess;
+    float emissive;
+    float alpha_cutoff;
+} material;
+
+layout(set = 0, binding = 3) readonly buffer Lights {
+    Light lights[];
+};
+
+layout(set = 0, binding = 4) uniform sampler2D irradiance_map;
+layout(set = 0, binding = 5) uniform sampler2D prefiltered_map;
+layout(set = 0, binding = 6) uniform sampler2D brdf_lut;
+
+const float PI = 3.14159265359;
+// See gfx::environment
+const float LEVELS = 5.0;
+const float LEVEL_HEIGHT = 64.0;
+
+vec2 equirect(vec3 d) {
+    return vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+vec3 prefiltered(vec3 d, float roughness) {
+    vec2 uv = equirect(d);
+    // Keep the filter inside each band
+    uv.y = clamp(uv.y, 0.5 / LEVEL_HEIGHT, 1.0 - 0.5 / LEVEL_HEIGHT);
+    float level = roughness * (LEVELS - 1.0);
+    float band = min(floor(level), LEVELS - 2.0);
+    vec3 smooth_band = textureLod(prefiltered_map, vec2(uv.x, (band + uv.y) / LEVELS), 0.0).rgb;
+    vec3 rough_band = textureLod(prefiltered_map, vec2(uv.x, (band + 1.0 + uv.y) / LEVELS), 0.0).rgb;
+    return mix(smooth_band, rough_band, level - band);
+}
+
+// Diffuse and specular light of the environment, from world space vectors
+vec3 environment(vec3 n, vec3 v, vec3 albedo, float specular, float shininess) {
+    // Blinn-Phong exponent to GGX roughness
+    float roughness = pow(2.0 / (shininess + 2.0), 0.25);
+    vec2 brdf = texture(brdf_lut, vec2(max(dot(n, v), 0.0), roughness)).rg;
+    float f0 = specular * 0.08;
+    return textureLod(irradiance_map, equirect(n), 0.0).rgb * albedo +
+        prefiltered(reflect(-v, n), roughness) * (f0 * brdf.x + brdf.y);
+}
+
+void main() {
+    if (v_tint.a < material.alpha_cutoff) {
+        discard;
+    }
+
+    vec3 n = normalize(v_normal);
+    vec3 v = normalize(-v_position);
+    vec3 albedo = v_tint.rgb;
+
+    mat3 inverse_view = transpose(mat3(camera.view));
+    vec3 color = environment(inverse_view * n, inverse_view * v, albedo,
+                             material.specular, material.shininess);
+    color += material.emissive * albedo;
+
+    // Every light is evaluated for every fragment, which the deferred path
+    // avoids for hidden fragments
+    for (int i = 0; i < lights.length(); i++) {
+        vec3 to_light = lights[i].position.xyz - v_position;
+        float dist = length(to_light);
+        float radius = lights[i].position.w;
+        if (dist >= radius) {
+            continue;
+        }
+
+        vec3 l = to_light / dist;
+        float falloff = 1.0 - dist / radius;
+        float diffuse = max(dot(n, l), 0.0);
+        float highlight = diffuse > 0.0
+            ? material.specular * pow(max(dot(n, normalize(l + v)), 0.0), material.shininess)
+            : 0.0;
+        color += lights[i].color.rgb * falloff * falloff * (diffuse * albedo + highlight);
+    }
+
+    f_color = vec4(color, v_tint.a);
+}
+"]
+    struct Dummy;
+}
+
+mod gbuffer_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec4 v_tint;
+layout(location = 0) out vec4 f_albedo;
+layout(location = 1) out vec4 f_normal;
+layout(location = 2) out vec4 f_material;
+
+layout(set = 0, binding = 2) uniform MaterialParams {
+    float specular;
+    float shininess;
+    float emissive;
+    float alpha_cutoff;
+} material;
+
+void main() {
+    if (v_tint.a < material.alpha_cutoff) {
+        discard;
+    }
+
+    f_albedo = vec4(v_tint.rgb, 1.0);
+    f_normal = vec4(normalize(v_normal), 0.0);
+    // Normalized to fit the unorm attachment, see the lighting shader
+    f_material = vec4(material.specular, material.shininess / 128.0, material.emissive, 0.0);
+}
+"]
+    struct Dummy;
+}
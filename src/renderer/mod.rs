@@ -1,21 +1,630 @@
+use cgmath::{Matrix4, One, Point3, Transform};
+
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::memory::pool::StdMemoryPool;
+
+use std::cmp;
+use std::mem;
+use std::ops::Range;
 use std::sync::Arc;
 
+use super::framework::bounds::Frustum;
 use super::framework::gfx;
 
-//pub mod core;
+pub mod deferred;
+pub mod light;
+pub mod lod;
+pub mod material;
+pub mod skybox;
+pub mod workers;
+
+pub use self::deferred::Lighting;
+pub use self::light::{Light, LightData};
+pub use self::lod::{Lod, MeshAsset};
+pub use self::material::{Material, MaterialParams};
+pub use self::skybox::Skybox;
+pub use self::workers::{BatchJob, SceneSubpass, Workers};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(usize);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub view: Matrix4<f32>,
+    pub proj: Matrix4<f32>,
+}
+
+/// Per-instance data, laid out as the `Instance` struct of material shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Instance {
+    pub world: [[f32; 4]; 4],
+    pub tint: [f32; 4],
+    /// `x` is the first matrix of the instance in the joint buffer of the
+    /// frame. Only read by skinned materials.
+    pub skin: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CameraData {
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RendererConfig {
+    /// Threads recording draws. With 0 or 1, draws are recorded on the
+    /// calling thread.
+    pub threads: usize,
+    /// Must be the path the scene graph of `gfx::Core` was built for.
+    pub path: gfx::RenderPath,
+}
+
+impl Default for RendererConfig {
+    fn default() -> RendererConfig {
+        RendererConfig {
+            threads: 1,
+            path: gfx::RenderPath::Forward,
+        }
+    }
+}
+
+type LightBuffer = vkb::cpu_pool::CpuBufferPoolChunk<LightData, Arc<StdMemoryPool>>;
+type JointBuffer = vkb::cpu_pool::CpuBufferPoolChunk<[[f32; 4]; 4], Arc<StdMemoryPool>>;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    /// Instances submitted this frame.
+    pub submitted: usize,
+    /// Instances outside the view frustum.
+    pub culled: usize,
+    /// Instances drawn.
+    pub instances: usize,
+    /// Instances drawn blended, after opaque ones.
+    pub transparent: usize,
+    /// Instanced draws issued.
+    pub batches: usize,
+    /// Secondary command buffers recorded.
+    pub command_buffers: usize,
+    pub lights: usize,
+}
+
+struct Draw {
+    material: MaterialId,
+    mesh: MeshId,
+    lod: usize,
+    instance: Instance,
+    /// Distance from the camera plane, for sorting transparent draws.
+    distance: f32,
+}
 
+/// Draws the meshes submitted during a frame.
+///
+/// Submissions sharing a mesh and a material are batched together and read
+/// their transforms and tints from one instance storage buffer. Each batch is
+/// a single instanced draw, with the `first_instance` push constant saying
+/// where its instances start in the buffer.
+///
+/// Each instance is drawn with the level of detail matching the screen size
+/// of its bounding sphere.
+///
+/// Batches are split evenly between the worker threads, which record them
+/// into secondary command buffers executed in order from the primary one.
+///
+/// Opaque draws are sorted by material to bind as little as possible.
+/// Draws with transparent materials are held back by `render` and drawn by
+/// `render_transparent`, back to front.
+///
+/// With the deferred path, `render` fills the G-buffer and
+/// `render_lighting` shades it with the lights of the frame.
+///
+/// Lit materials are also lit by the environment, which
+/// `render_transparent` draws as the sky behind opaque geometry.
 pub struct Renderer {
     pub gfx: Arc<gfx::Core>,
+    pub meshes: gfx::MeshStorage,
+    pub stats: FrameStats,
+    /// Skip instances whose bounds are outside the view frustum.
+    pub culling: bool,
+    /// Scales screen sizes before picking levels of detail. Lower values
+    /// switch to cheaper levels sooner.
+    pub lod_bias: f32,
+    /// Linear HDR color the scene target is cleared to.
+    pub clear_color: [f32; 4],
+    mesh_list: Vec<MeshAsset>,
+    workers: Workers,
+    materials: Vec<Material>,
+    draws: Vec<Draw>,
+    /// Transparent draws of the frame, split off by `render`.
+    transparent: Vec<Draw>,
+    lights: Vec<Light>,
+    /// Lights of the frame, uploaded by `render` for `render_lighting`.
+    frame_lights: Option<LightBuffer>,
+    /// Skinning matrices of the instances submitted this frame.
+    joints: Vec<[[f32; 4]; 4]>,
+    frame_joints: Option<JointBuffer>,
+    lighting: Option<Lighting>,
+    environment: gfx::Environment,
+    skybox: Skybox,
+    camera_pool: vkb::CpuBufferPool<CameraData>,
+    instance_pool: vkb::CpuBufferPool<Instance>,
+    material_pool: vkb::CpuBufferPool<MaterialParams>,
+    light_pool: vkb::CpuBufferPool<LightData>,
+    joint_pool: vkb::CpuBufferPool<[[f32; 4]; 4]>,
 }
 
 impl Renderer {
-    pub fn new(gfx: Arc<gfx::Core>) -> Result<Self, ()> {
+    pub fn new(gfx: Arc<gfx::Core>, config: RendererConfig) -> Result<Self, ()> {
+        if config.path != gfx.render_path {
+            println!("The scene graph was built for the {:?} path, not {:?}",
+                     gfx.render_path, config.path);
+            return Err(());
+        }
+        let lighting = match config.path {
+            gfx::RenderPath::Forward => None,
+            gfx::RenderPath::Deferred => Some(Lighting::new(&gfx)?),
+        };
+        let environment = gfx::Environment::uniform(&gfx, [0.6, 0.6, 0.6])?;
+        let skybox = Skybox::new(gfx.clone(), &environment)?;
+
+        let meshes = gfx::MeshStorage::new(gfx.device.clone(),
+                                           gfx.queue.family().id(),
+                                           gfx.memory.clone());
+        let instance_usage = vkb::BufferUsage {
+            storage_buffer: true,
+            .. vkb::BufferUsage::none()
+        };
+
         Ok(Renderer {
+            meshes: meshes,
+            stats: FrameStats::default(),
+            culling: true,
+            lod_bias: 1.0,
+            clear_color: [0.02, 0.02, 0.05, 1.0],
+            mesh_list: Vec::new(),
+            workers: Workers::new(config.threads)?,
+            materials: Vec::new(),
+            draws: Vec::new(),
+            transparent: Vec::new(),
+            lights: Vec::new(),
+            frame_lights: None,
+            joints: Vec::new(),
+            frame_joints: None,
+            lighting: lighting,
+            environment: environment,
+            skybox: skybox,
+            camera_pool: vkb::CpuBufferPool::uniform_buffer(gfx.device.clone()),
+            instance_pool: vkb::CpuBufferPool::new(gfx.device.clone(), instance_usage),
+            material_pool: vkb::CpuBufferPool::uniform_buffer(gfx.device.clone()),
+            light_pool: vkb::CpuBufferPool::new(gfx.device.clone(), instance_usage),
+            joint_pool: vkb::CpuBufferPool::new(gfx.device.clone(), instance_usage),
             gfx: gfx,
         })
     }
 
-    pub fn render(&self) -> Result<(), ()> {
+    pub fn add_mesh(&mut self,
+                    uploader: &mut gfx::Uploader,
+                    data: &gfx::MeshData) -> Result<MeshId, ()> {
+        self.add_mesh_lods(uploader, &[(data, 0.0)])
+    }
+
+    /// Uploads the levels of detail of a mesh, each with the smallest screen
+    /// size it is used at.
+    pub fn add_mesh_lods(&mut self,
+                         uploader: &mut gfx::Uploader,
+                         levels: &[(&gfx::MeshData, f32)]) -> Result<MeshId, ()> {
+        if levels.is_empty() {
+            println!("Meshes need at least one level of detail");
+            return Err(());
+        }
+        if levels.iter().any(|&(_, screen_size)| screen_size.is_nan()) {
+            println!("Levels of detail need a screen size, not NaN");
+            return Err(());
+        }
+
+        let mut lods = Vec::with_capacity(levels.len());
+        for &(data, screen_size) in levels {
+            lods.push(Lod {
+                mesh: self.meshes.upload(uploader, data)?,
+                screen_size: screen_size,
+            });
+        }
+        lods.sort_by(|a, b| {
+            b.screen_size.partial_cmp(&a.screen_size).unwrap_or(cmp::Ordering::Equal)
+        });
+
+        self.mesh_list.push(MeshAsset {
+            lods: lods,
+        });
+        Ok(MeshId(self.mesh_list.len() - 1))
+    }
+
+    /// Most detailed level of `id`.
+    pub fn mesh(&self, id: MeshId) -> &gfx::Mesh {
+        &self.mesh_list[id.0].lods[0].mesh
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        MaterialId(self.materials.len() - 1)
+    }
+
+    /// Parameters of `id`, uploaded again with every `render`.
+    pub fn material_params_mut(&mut self, id: MaterialId) -> &mut MaterialParams {
+        &mut self.materials[id.0].params
+    }
+
+    /// Queues an instance of `mesh` for the next `render`.
+    pub fn submit(&mut self,
+                  mesh: MeshId,
+                  material: MaterialId,
+                  world: Matrix4<f32>,
+                  tint: [f32; 4]) {
+        self.draws.push(Draw {
+            material: material,
+            mesh: mesh,
+            lod: 0,
+            distance: 0.0,
+            instance: Instance {
+                world: world.into(),
+                tint: tint,
+                skin: [0; 4],
+            },
+        });
+    }
+
+    /// Queues an instance of `mesh` posed by `joints`, the skinning matrices
+    /// of its skeleton. `material` must be skinned.
+    pub fn submit_skinned(&mut self,
+                          mesh: MeshId,
+                          material: MaterialId,
+                          world: Matrix4<f32>,
+                          tint: [f32; 4],
+                          joints: &[Matrix4<f32>]) {
+        let first = self.joints.len() as u32;
+        self.joints.extend(joints.iter().map(|&m| -> [[f32; 4]; 4] { m.into() }));
+        self.submit(mesh, material, world, tint);
+        self.draws.last_mut().unwrap().instance.skin = [first, 0, 0, 0];
+    }
+
+    /// Replaces the environment that lights lit materials and is shown as
+    /// the sky.
+    pub fn set_environment(&mut self, environment: gfx::Environment) -> Result<(), ()> {
+        self.skybox.set_environment(&environment)?;
+        self.environment = environment;
         Ok(())
     }
+
+    /// Queues a light for the next `render`.
+    pub fn submit_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    /// Records the opaque draws submitted since the last call. Must be
+    /// called inside the scene subpass of the scene graph.
+    pub fn render(&mut self,
+                  builder: vkcb::AutoCommandBufferBuilder,
+                  camera: &Camera,
+                  dynamic: &vkcb::DynamicState)
+                  -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        self.stats = FrameStats::default();
+        self.stats.submitted = self.draws.len();
+        self.stats.lights = self.lights.len();
+        self.transparent.clear();
+
+        let lights = {
+            let view = camera.view;
+            let data = self.lights.drain(..).map(|l| LightData::new(&l, &view)).collect::<Vec<_>>();
+            let data = if data.is_empty() { vec![LightData::none()] } else { data };
+            self.light_pool.chunk(data)
+                .map_err(|e| println!("Failed to allocate light buffer ({:?})", e))?
+        };
+        self.frame_lights = Some(lights);
+
+        let joints = {
+            // Like lights, the buffer cannot be empty
+            let data = if self.joints.is_empty() {
+                vec![Matrix4::<f32>::one().into()]
+            } else {
+                mem::replace(&mut self.joints, Vec::new())
+            };
+            self.joint_pool.chunk(data)
+                .map_err(|e| println!("Failed to allocate joint buffer ({:?})", e))?
+        };
+        self.frame_joints = Some(joints);
+
+        if self.culling {
+            self.cull(&Frustum::from_matrix(&(camera.proj * camera.view)));
+        }
+        if self.draws.is_empty() {
+            return Ok(builder);
+        }
+
+        self.select_lods(camera);
+
+        let (transparent, mut opaque): (Vec<_>, Vec<_>) = {
+            let materials = &self.materials;
+            self.draws.drain(..).partition(|d| materials[d.material.0].blend.is_transparent())
+        };
+        self.transparent = transparent;
+
+        opaque.sort_by_key(|d| (d.material, d.mesh, d.lod));
+        let pass = self.gfx.scene_pass;
+        self.record_draws(builder, camera, opaque, pass, dynamic)
+    }
+
+    /// Records the sky, then the transparent draws of the last `render`,
+    /// farthest from the camera first. Must be called inside the
+    /// transparent subpass of the scene graph, after `render`.
+    pub fn render_transparent(&mut self,
+                              builder: vkcb::AutoCommandBufferBuilder,
+                              camera: &Camera,
+                              dynamic: &vkcb::DynamicState)
+                              -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let sky = self.skybox.render(camera, dynamic)?;
+        let builder = workers::execute(builder, sky)?;
+
+        let mut draws = mem::replace(&mut self.transparent, Vec::new());
+        self.stats.transparent = draws.len();
+        if draws.is_empty() {
+            return Ok(builder);
+        }
+
+        for draw in &mut draws {
+            let world = Matrix4::from(draw.instance.world);
+            let center = match self.mesh_list[draw.mesh.0].bounds() {
+                Some(bounds) => bounds.sphere.transform(&world).center,
+                None => Point3::new(world.w.x, world.w.y, world.w.z),
+            };
+            // View space z is negative in front of the camera
+            draw.distance = -camera.view.transform_point(center).z;
+        }
+        draws.sort_by(|a, b| b.distance.partial_cmp(&a.distance).unwrap_or(cmp::Ordering::Equal));
+
+        let pass = self.gfx.transparent_pass;
+        self.record_draws(builder, camera, draws, pass, dynamic)
+    }
+
+    /// Shades the G-buffer filled by the last `render`. Must be called
+    /// inside the lighting subpass of the scene graph.
+    pub fn render_lighting(&mut self,
+                           builder: vkcb::AutoCommandBufferBuilder,
+                           targets: &gfx::GraphTargets,
+                           camera: &Camera,
+                           dynamic: &vkcb::DynamicState)
+                           -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let lights = match self.frame_lights.clone() {
+            Some(lights) => lights,
+            None => self.light_pool.chunk(vec![LightData::none()])
+                .map_err(|e| println!("Failed to allocate light buffer ({:?})", e))?,
+        };
+        let lighting = match self.lighting {
+            Some(ref lighting) => lighting,
+            None => {
+                println!("The forward path has no lighting pass");
+                return Err(());
+            },
+        };
+
+        lighting.record(builder, targets, &camera.view, &camera.proj, lights,
+                        &self.environment, dynamic)
+    }
+
+    /// Batches consecutive draws sharing a material, mesh and level of
+    /// detail, and records them on the workers for `pass`.
+    fn record_draws(&mut self,
+                    builder: vkcb::AutoCommandBufferBuilder,
+                    camera: &Camera,
+                    draws: Vec<Draw>,
+                    pass: gfx::PassId,
+                    dynamic: &vkcb::DynamicState)
+                    -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let lights = self.frame_lights.clone()
+            .ok_or_else(|| println!("Draws must be recorded after render"))?;
+        let joints = self.frame_joints.clone()
+            .ok_or_else(|| println!("Draws must be recorded after render"))?;
+
+        let camera = self.camera_pool.next(CameraData {
+            view: camera.view.into(),
+            proj: camera.proj.into(),
+        }).map_err(|e| println!("Failed to allocate camera buffer ({:?})", e))?;
+
+        let instances = self.instance_pool.chunk(draws.iter().map(|d| d.instance))
+            .map_err(|e| println!("Failed to allocate instance buffer ({:?})", e))?;
+
+        let mut jobs = Vec::new();
+        let mut set = None;
+        let mut start = 0;
+
+        while start < draws.len() {
+            let material = draws[start].material;
+            let mesh = draws[start].mesh;
+            let lod = draws[start].lod;
+            let len = draws[start..].iter()
+                .take_while(|d| d.material == material && d.mesh == mesh && d.lod == lod)
+                .count();
+
+            let rebind = match set {
+                Some((id, _)) => id != material,
+                None => true,
+            };
+            if rebind {
+                let new_set = self.material_set(material, camera.clone(), instances.clone(),
+                                                lights.clone(), joints.clone())?;
+                set = Some((material, new_set));
+            }
+            let material_set = set.as_ref().unwrap().1.clone();
+
+            jobs.push(self.batch_job(material, &self.mesh_list[mesh.0].lods[lod].mesh,
+                                     start .. start + len, material_set)?);
+            start += len;
+        }
+
+        self.stats.batches += jobs.len();
+        self.stats.instances += draws.len();
+
+        let lists = split_jobs(jobs, self.workers.len(), draws.len());
+        self.stats.command_buffers += lists.len();
+
+        let subpass = self.gfx.scene_graph.subpass(pass);
+        let command_buffers = self.workers.record(&self.gfx.device,
+                                                  self.gfx.queue.family().id(),
+                                                  &subpass,
+                                                  dynamic,
+                                                  lists)?;
+
+        let mut builder = builder;
+        for command_buffer in command_buffers {
+            // Everything the draws read is either written before the frame by
+            // the uploader, which waits for completion, or allocated for it
+            builder = workers::execute(builder, command_buffer)?;
+        }
+
+        Ok(builder)
+    }
+
+    fn material_set<C, I>(&self,
+                          material: MaterialId,
+                          camera: C,
+                          instances: I,
+                          lights: LightBuffer,
+                          joints: JointBuffer)
+                          -> Result<Arc<vkds::DescriptorSet + Send + Sync>, ()>
+        where C: vkb::BufferAccess + Send + Sync + 'static,
+              I: vkb::BufferAccess + Send + Sync + 'static
+    {
+        let material = &self.materials[material.0];
+        let params = self.material_pool.next(material.shader_params())
+            .map_err(|e| println!("Failed to allocate material parameters ({:?})", e))?;
+
+        let set = vkds::PersistentDescriptorSet::start(material.pipeline.id.clone(), 0)
+            .add_buffer(camera)
+            .and_then(|s| s.add_buffer(instances))
+            .and_then(|s| s.add_buffer(params))
+            .map_err(|e| println!("Failed to bind material buffers ({:?})", e))?;
+
+        if !material.lit {
+            if material.skinned {
+                // Bindings 3 to 6 are only used by the forward shader
+                let set = set.add_empty()
+                    .and_then(|s| s.add_empty())
+                    .and_then(|s| s.add_empty())
+                    .and_then(|s| s.add_empty())
+                    .and_then(|s| s.add_buffer(joints))
+                    .map_err(|e| println!("Failed to bind joints ({:?})", e))?
+                    .build()
+                    .map_err(|e| println!("Failed to create material set ({:?})", e))?;
+                return Ok(Arc::new(set));
+            }
+
+            let set = set.build()
+                .map_err(|e| println!("Failed to create material set ({:?})", e))?;
+            return Ok(Arc::new(set));
+        }
+
+        let environment = &self.environment;
+        let set = set.add_buffer(lights)
+            .and_then(|s| s.add_sampled_image(environment.irradiance.clone(),
+                                              environment.sampler.clone()))
+            .and_then(|s| s.add_sampled_image(environment.prefiltered.clone(),
+                                              environment.sampler.clone()))
+            .and_then(|s| s.add_sampled_image(environment.brdf.clone(),
+                                              environment.lut_sampler.clone()))
+            .map_err(|e| println!("Failed to bind lights and environment ({:?})", e))?;
+
+        if material.skinned {
+            let set = set.add_buffer(joints)
+                .map_err(|e| println!("Failed to bind joints ({:?})", e))?
+                .build()
+                .map_err(|e| println!("Failed to create material set ({:?})", e))?;
+            return Ok(Arc::new(set));
+        }
+
+        let set = set.build()
+            .map_err(|e| println!("Failed to create material set ({:?})", e))?;
+        Ok(Arc::new(set))
+    }
+
+    fn cull(&mut self, frustum: &Frustum) {
+        let meshes = &self.mesh_list;
+        self.draws.retain(|d| {
+            match meshes[d.mesh.0].bounds() {
+                Some(ref bounds) => frustum.intersects(&bounds.transform(&d.instance.world.into())),
+                None => false,
+            }
+        });
+        self.stats.culled = self.stats.submitted - self.draws.len();
+    }
+
+    fn select_lods(&mut self, camera: &Camera) {
+        let meshes = &self.mesh_list;
+        let bias = self.lod_bias;
+
+        for draw in &mut self.draws {
+            let asset = &meshes[draw.mesh.0];
+            draw.lod = match asset.bounds() {
+                Some(bounds) if asset.lods.len() > 1 => {
+                    let sphere = bounds.sphere.transform(&draw.instance.world.into());
+                    asset.select(lod::screen_size(&sphere, &camera.view, &camera.proj) * bias)
+                },
+                _ => 0,
+            };
+        }
+    }
+
+    fn batch_job(&self,
+                 material: MaterialId,
+                 mesh: &gfx::Mesh,
+                 instances: Range<usize>,
+                 set: Arc<vkds::DescriptorSet + Send + Sync>) -> Result<BatchJob, ()> {
+        let pipeline = &self.materials[material.0].pipeline;
+
+        if !pipeline.accepts(&mesh.layout, mesh.topology) {
+            println!("Material {:?} cannot draw mesh with layout {:?} as {:?}",
+                     material, mesh.layout, mesh.topology);
+            return Err(());
+        }
+
+        Ok(BatchJob {
+            pipeline: pipeline.id.clone(),
+            vertices: self.meshes.vertex_buffers(mesh),
+            indices: self.meshes.index_slice(mesh),
+            set: set,
+            instances: instances,
+        })
+    }
+}
+
+/// Splits batches into `lists` lists of about the same number of instances,
+/// keeping their order. Batches are cut where a list fills up.
+fn split_jobs(jobs: Vec<BatchJob>, lists: usize, instances: usize) -> Vec<Vec<BatchJob>> {
+    let per_list = cmp::max(1, (instances + lists - 1) / lists);
+    let mut result = vec![Vec::new()];
+    let mut filled = 0;
+
+    for job in jobs {
+        let mut start = job.instances.start;
+        while start < job.instances.end {
+            if filled == per_list {
+                result.push(Vec::new());
+                filled = 0;
+            }
+
+            let end = cmp::min(job.instances.end, start + per_list - filled);
+            result.last_mut().unwrap().push(BatchJob {
+                instances: start .. end,
+                .. job.clone()
+            });
+            filled += end - start;
+            start = end;
+        }
+    }
+
+    result
 }
@@ -0,0 +1,171 @@
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix};
+
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::framebuffer as vkfb;
+use vulkano::pipeline as vkp;
+use vulkano::pipeline::depth_stencil as vkdst;
+
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+use super::super::framework::gfx::PipelineBuilderExt;
+use super::Camera;
+
+/// Fullscreen pipeline of the sky. Its concrete type is kept since vulkano
+/// only draws bufferless pipelines it can see the vertex definition of.
+type SkyboxPipeline = vkp::GraphicsPipeline<vkp::vertex::BufferlessDefinition,
+                                            Box<PipelineLayoutAbstract + Send + Sync>,
+                                            Arc<vkfb::RenderPassAbstract + Send + Sync>>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Sky {
+    /// Inverse projection followed by the inverse rotation of the view.
+    unproject: [[f32; 4]; 4],
+}
+
+/// Draws the environment behind everything else.
+///
+/// A fullscreen triangle at the far plane is drawn in the transparent
+/// subpass, after opaque geometry, so it only covers pixels nothing was
+/// drawn to. Each pixel looks the source map up in the direction of its
+/// view ray.
+pub struct Skybox {
+    gfx: Arc<gfx::Core>,
+    pipeline: Arc<SkyboxPipeline>,
+    set: Arc<vkds::DescriptorSet + Send + Sync>,
+}
+
+impl Skybox {
+    pub fn new(gfx: Arc<gfx::Core>, environment: &gfx::Environment) -> Result<Skybox, ()> {
+        let vs = skybox_vs::Shader::load(gfx.device.clone())
+            .map_err(|e| println!("Failed to load skybox vertex shader ({:?})", e))?;
+        let fs = skybox_fs::Shader::load(gfx.device.clone())
+            .map_err(|e| println!("Failed to load skybox fragment shader ({:?})", e))?;
+
+        // The depth buffer is cleared to the far plane, which the sky is at
+        let depth = vkdst::DepthStencil {
+            depth_compare: vkdst::Compare::LessOrEqual,
+            depth_write: false,
+            .. vkdst::DepthStencil::simple_depth_test()
+        };
+
+        let pipeline = vkp::GraphicsPipeline::start()
+            .vertex_input(vkp::vertex::BufferlessDefinition)
+            .vertex_shader(vs.main_entry_point(), ())
+            .topology(gfx::Topology::TriangleList)
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .depth_stencil(depth)
+            .render_pass(gfx.scene_graph.subpass(gfx.transparent_pass))
+            .build(gfx.device.clone())
+            .map_err(|e| println!("Failed to create skybox pipeline ({:?})", e))?;
+        let pipeline = Arc::new(pipeline);
+
+        Ok(Skybox {
+            set: Skybox::environment_set(&pipeline, environment)?,
+            pipeline: pipeline,
+            gfx: gfx,
+        })
+    }
+
+    /// Shows `environment` from now on.
+    pub fn set_environment(&mut self, environment: &gfx::Environment) -> Result<(), ()> {
+        self.set = Skybox::environment_set(&self.pipeline, environment)?;
+        Ok(())
+    }
+
+    /// Records the sky into a secondary command buffer for the transparent
+    /// subpass, to be executed before any blended draws.
+    pub fn render(&self,
+                  camera: &Camera,
+                  dynamic: &vkcb::DynamicState)
+                  -> Result<vkcb::AutoCommandBuffer, ()> {
+        let inverse_proj = camera.proj.invert()
+            .ok_or_else(|| println!("The projection cannot be inverted"))?;
+        // Views are rigid, so the inverse rotation is the transpose
+        let rotation = Matrix3::from_cols(camera.view.x.truncate(),
+                                          camera.view.y.truncate(),
+                                          camera.view.z.truncate()).transpose();
+
+        let subpass = self.gfx.scene_graph.subpass(self.gfx.transparent_pass);
+        vkcb::AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+            self.gfx.device.clone(),
+            self.gfx.queue.family(),
+            subpass
+        ).map_err(|e| println!("Failed to create skybox command buffer ({:?})", e))?
+            .draw(self.pipeline.clone(),
+                  dynamic.clone(),
+                  vkp::vertex::BufferlessVertices {
+                      vertices: 3,
+                      instances: 1,
+                  },
+                  self.set.clone(),
+                  Sky {
+                      unproject: (Matrix4::from(rotation) * inverse_proj).into(),
+                  })
+            .map_err(|e| println!("Failed to draw skybox ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to build skybox command buffer ({:?})", e))
+    }
+
+    fn environment_set(pipeline: &Arc<SkyboxPipeline>,
+                       environment: &gfx::Environment)
+                       -> Result<Arc<vkds::DescriptorSet + Send + Sync>, ()> {
+        let set = vkds::PersistentDescriptorSet::start(pipeline.clone(), 0)
+            .add_sampled_image(environment.source.clone(), environment.sampler.clone())
+            .map_err(|e| println!("Failed to bind skybox image ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create skybox set ({:?})", e))?;
+        Ok(Arc::new(set))
+    }
+}
+
+mod skybox_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) out vec3 v_direction;
+
+layout(push_constant) uniform Sky {
+    mat4 unproject;
+} sky;
+
+void main() {
+    // One triangle covering the screen, at the far plane
+    vec2 ndc = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    vec4 far = sky.unproject * vec4(ndc, 1.0, 1.0);
+    v_direction = far.xyz / far.w;
+    gl_Position = vec4(ndc, 1.0, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod skybox_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 v_direction;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D environment;
+
+const float PI = 3.14159265359;
+
+vec2 equirect(vec3 d) {
+    return vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+void main() {
+    f_color = vec4(textureLod(environment, equirect(normalize(v_direction)), 0.0).rgb, 1.0);
+}
+"]
+    struct Dummy;
+}
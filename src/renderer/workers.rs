@@ -0,0 +1,185 @@
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::device as vkd;
+use vulkano::framebuffer as vkfb;
+use vulkano::pipeline as vkp;
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+
+use super::super::framework::gfx;
+
+pub type SceneSubpass = vkfb::Subpass<Arc<vkfb::RenderPassAbstract + Send + Sync>>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Batch {
+    first_instance: u32,
+}
+
+/// Batch of instances with everything needed to draw it, owned so that it
+/// can be recorded on any thread.
+#[derive(Clone)]
+pub struct BatchJob {
+    pub pipeline: Arc<vkp::GraphicsPipelineAbstract + Send + Sync>,
+    pub vertices: Vec<Arc<vkb::BufferAccess + Send + Sync>>,
+    pub indices: gfx::IndexSlice,
+    pub set: Arc<vkds::DescriptorSet + Send + Sync>,
+    pub instances: Range<usize>,
+}
+
+struct Job {
+    device: Arc<vkd::Device>,
+    queue_family: u32,
+    subpass: SceneSubpass,
+    dynamic: vkcb::DynamicState,
+    batches: Vec<BatchJob>,
+    reply: mpsc::Sender<Result<gfx::InstancedCommandBuffer, ()>>,
+}
+
+struct Worker {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Threads recording secondary command buffers for the scene subpass.
+///
+/// Worker threads live as long as the pool, so vulkano's per-thread command
+/// pools are reused from frame to frame.
+pub struct Workers {
+    workers: Vec<Worker>,
+}
+
+impl Workers {
+    /// Starts `count` worker threads. With a count of 0 or 1 everything is
+    /// recorded on the calling thread.
+    pub fn new(count: usize) -> Result<Workers, ()> {
+        let mut workers = Vec::new();
+
+        if count > 1 {
+            for idx in 0..count {
+                let (sender, receiver) = mpsc::channel::<Job>();
+                let thread = thread::Builder::new()
+                    .name(format!("render worker {}", idx))
+                    .spawn(move || {
+                        for job in receiver.iter() {
+                            let result = record(job.device, job.queue_family, job.subpass,
+                                                job.dynamic, job.batches);
+                            let _ = job.reply.send(result);
+                        }
+                    })
+                    .map_err(|e| println!("Failed to start render worker ({:?})", e))?;
+
+                workers.push(Worker {
+                    jobs: Some(sender),
+                    thread: Some(thread),
+                });
+            }
+        }
+
+        Ok(Workers {
+            workers: workers,
+        })
+    }
+
+    /// Number of command buffers draws should be split into.
+    pub fn len(&self) -> usize {
+        self.workers.len().max(1)
+    }
+
+    /// Records one secondary command buffer per list of batches, in
+    /// parallel, and returns them in the same order.
+    pub fn record(&self,
+                  device: &Arc<vkd::Device>,
+                  queue_family: u32,
+                  subpass: &SceneSubpass,
+                  dynamic: &vkcb::DynamicState,
+                  lists: Vec<Vec<BatchJob>>)
+                  -> Result<Vec<gfx::InstancedCommandBuffer>, ()> {
+        if self.workers.is_empty() {
+            return lists.into_iter()
+                .map(|batches| record(device.clone(), queue_family, subpass.clone(),
+                                      dynamic.clone(), batches))
+                .collect();
+        }
+
+        let mut replies = Vec::with_capacity(lists.len());
+        for (idx, batches) in lists.into_iter().enumerate() {
+            let (reply, receiver) = mpsc::channel();
+            let worker = &self.workers[idx % self.workers.len()];
+
+            worker.jobs.as_ref().unwrap().send(Job {
+                device: device.clone(),
+                queue_family: queue_family,
+                subpass: subpass.clone(),
+                dynamic: dynamic.clone(),
+                batches: batches,
+                reply: reply,
+            }).map_err(|_| println!("Render worker {} has stopped", idx))?;
+
+            replies.push(receiver);
+        }
+
+        replies.into_iter()
+            .map(|receiver| {
+                receiver.recv().map_err(|_| println!("Render worker dropped its job"))?
+            })
+            .collect()
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        // Closing the channels ends the loops of the workers
+        for worker in &mut self.workers {
+            worker.jobs = None;
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Executes a secondary command buffer recorded for the scene subpass.
+///
+/// vulkano does not check secondary command buffers yet, so the submission
+/// of the frame must wait for everything that writes what they read.
+pub fn execute<C>(builder: vkcb::AutoCommandBufferBuilder,
+                  command_buffer: C)
+                  -> Result<vkcb::AutoCommandBufferBuilder, ()>
+    where C: vkcb::CommandBuffer + Send + Sync + 'static
+{
+    unsafe {
+        builder.execute_commands(command_buffer)
+            .map_err(|e| println!("Failed to execute secondary command buffer ({:?})", e))
+    }
+}
+
+fn record(device: Arc<vkd::Device>,
+          queue_family: u32,
+          subpass: SceneSubpass,
+          dynamic: vkcb::DynamicState,
+          batches: Vec<BatchJob>) -> Result<gfx::InstancedCommandBuffer, ()> {
+    let family = device.physical_device().queue_family_by_id(queue_family).unwrap();
+    let mut builder = gfx::InstancedBuilder::secondary_graphics(device.clone(), family, subpass)?;
+
+    // One draw per batch, the shaders offset gl_InstanceIndex by
+    // first_instance to find the instances of the batch
+    for batch in batches {
+        builder = batch.indices
+            .draw(builder,
+                  batch.pipeline,
+                  &dynamic,
+                  batch.vertices,
+                  batch.set,
+                  Batch { first_instance: batch.instances.start as u32 },
+                  batch.instances.len() as u32)?;
+    }
+
+    builder.build()
+}
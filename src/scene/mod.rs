@@ -0,0 +1,126 @@
+use cgmath::{Matrix4, One, Quaternion, Vector3};
+
+use super::renderer::{MaterialId, MeshId, Renderer};
+
+/// Local transform of a node relative to its parent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation) *
+            Matrix4::from(self.rotation) *
+            Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+/// Mesh drawn at the position of a node.
+#[derive(Clone, Copy, Debug)]
+pub struct Renderable {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub tint: [f32; 4],
+}
+
+pub struct Node {
+    pub name: String,
+    pub parent: Option<NodeId>,
+    pub transform: Transform,
+    pub renderable: Option<Renderable>,
+    world: Matrix4<f32>,
+}
+
+impl Node {
+    /// World matrix as of the last `Scene::update`.
+    pub fn world(&self) -> Matrix4<f32> {
+        self.world
+    }
+}
+
+/// Hierarchy of transformed nodes.
+///
+/// Nodes can only be parented to nodes added before them, so world matrices
+/// are updated in a single pass in insertion order.
+pub struct Scene {
+    nodes: Vec<Node>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            nodes: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, parent: Option<NodeId>, transform: Transform) -> NodeId {
+        self.nodes.push(Node {
+            name: name.to_owned(),
+            parent: parent,
+            transform: transform,
+            renderable: None,
+            world: Matrix4::one(),
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name).map(NodeId)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Recomputes the world matrices from the local transforms.
+    pub fn update(&mut self) {
+        for idx in 0..self.nodes.len() {
+            let local = self.nodes[idx].transform.matrix();
+            self.nodes[idx].world = match self.nodes[idx].parent {
+                Some(parent) => self.nodes[parent.0].world * local,
+                None => local,
+            };
+        }
+    }
+
+    /// Submits every renderable node to `renderer`.
+    pub fn submit(&self, renderer: &mut Renderer) {
+        for node in &self.nodes {
+            if let Some(ref r) = node.renderable {
+                renderer.submit(r.mesh, r.material, node.world, r.tint);
+            }
+        }
+    }
+}
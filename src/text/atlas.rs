@@ -0,0 +1,238 @@
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::device as vkd;
+use vulkano::device::DeviceOwned;
+use vulkano::format as vkfmt;
+use vulkano::image as vkim;
+
+use std::collections::HashMap;
+use std::iter;
+use std::mem;
+use std::sync::Arc;
+
+use super::FontId;
+use super::raster::Bitmap;
+
+/// A glyph of a font rasterized at a size in pixels per em.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    pub font: FontId,
+    pub glyph: u16,
+    pub size: u32,
+}
+
+/// Place of a glyph in the atlas.
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasGlyph {
+    /// Corner and size of its texels.
+    pub texels: [u32; 4],
+    /// Pixels from the pen position to the left edge.
+    pub left: i32,
+    /// Pixels from the baseline up to the top edge.
+    pub top: i32,
+}
+
+/// Row of glyphs packed left to right.
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+/// Single channel texture of the coverage of the glyphs drawn recently.
+///
+/// Glyphs are packed in shelves on first use and kept until the atlas is
+/// cleared, which the text renderer does when it fills up. The texture is
+/// created once and only the texels of new glyphs are copied to it.
+pub struct Atlas {
+    size: u32,
+    pixels: Vec<u8>,
+    shelves: Vec<Shelf>,
+    /// Blank glyphs are cached as `None`.
+    glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+    overflowed: bool,
+    image: Option<Arc<vkim::StorageImage<vkfmt::R8Unorm>>>,
+    /// Corners and sizes of the texels changed since the last upload.
+    dirty: Vec<[u32; 4]>,
+}
+
+impl Atlas {
+    pub fn new(size: u32) -> Atlas {
+        Atlas {
+            size: size,
+            pixels: vec![0; (size * size) as usize],
+            shelves: Vec::new(),
+            glyphs: HashMap::new(),
+            overflowed: false,
+            image: None,
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Glyph `key`, rasterized by `rasterize` on first use. Returns `None`
+    /// for blank glyphs and, marking the atlas overflowed, for glyphs that
+    /// do not fit.
+    pub fn glyph<F>(&mut self, key: GlyphKey, rasterize: F) -> Option<AtlasGlyph>
+        where F: FnOnce() -> Option<Bitmap>
+    {
+        if let Some(&glyph) = self.glyphs.get(&key) {
+            return glyph;
+        }
+
+        let bitmap = match rasterize() {
+            Some(bitmap) => bitmap,
+            None => {
+                self.glyphs.insert(key, None);
+                return None;
+            },
+        };
+        let corner = match self.allocate(bitmap.width, bitmap.height) {
+            Some(corner) => corner,
+            None => {
+                self.overflowed = true;
+                return None;
+            },
+        };
+
+        for row in 0..bitmap.height {
+            let src = (row * bitmap.width) as usize;
+            let dst = ((corner[1] + row) * self.size + corner[0]) as usize;
+            self.pixels[dst..dst + bitmap.width as usize]
+                .copy_from_slice(&bitmap.pixels[src..src + bitmap.width as usize]);
+        }
+        let texels = [corner[0], corner[1], bitmap.width, bitmap.height];
+        if bitmap.width > 0 && bitmap.height > 0 {
+            self.dirty.push(texels);
+        }
+
+        let glyph = AtlasGlyph {
+            texels: texels,
+            left: bitmap.left,
+            top: bitmap.top,
+        };
+        self.glyphs.insert(key, Some(glyph));
+        Some(glyph)
+    }
+
+    /// Whether a glyph did not fit since the atlas was last cleared.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// Forgets every glyph.
+    pub fn clear(&mut self) {
+        for pixel in &mut self.pixels {
+            *pixel = 0;
+        }
+        self.shelves.clear();
+        self.glyphs.clear();
+        self.overflowed = false;
+        // Linear filtering reads the texels around glyphs too
+        self.dirty = vec![[0, 0, self.size, self.size]];
+    }
+
+    /// Records the copies of the texels changed since the last upload into
+    /// `builder`, outside of a render pass and before any draw sampling the
+    /// atlas. The first upload creates the image, for `queue` only.
+    pub fn upload(&mut self,
+                  builder: vkcb::AutoCommandBufferBuilder,
+                  queue: Arc<vkd::Queue>)
+                  -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let image = match self.image {
+            Some(ref image) => image.clone(),
+            None => {
+                let usage = vkim::ImageUsage {
+                    transfer_destination: true,
+                    sampled: true,
+                    .. vkim::ImageUsage::none()
+                };
+                let image = vkim::StorageImage::with_usage(queue.device().clone(),
+                                                           vkim::Dimensions::Dim2d {
+                                                               width: self.size,
+                                                               height: self.size,
+                                                           },
+                                                           vkfmt::R8Unorm,
+                                                           usage,
+                                                           iter::once(queue.family()))
+                    .map_err(|e| println!("Failed to create glyph atlas ({:?})", e))?;
+                self.dirty = vec![[0, 0, self.size, self.size]];
+                self.image = Some(image.clone());
+                image
+            },
+        };
+        if self.dirty.is_empty() {
+            return Ok(builder);
+        }
+
+        // One staging buffer holds the rows of every region in turn
+        let dirty = mem::replace(&mut self.dirty, Vec::new());
+        let mut texels = Vec::new();
+        for region in &dirty {
+            for row in region[1]..region[1] + region[3] {
+                let start = (row * self.size + region[0]) as usize;
+                texels.extend_from_slice(&self.pixels[start..start + region[2] as usize]);
+            }
+        }
+        let staging = vkb::CpuAccessibleBuffer::from_iter(queue.device().clone(),
+                                                          vkb::BufferUsage::transfer_source(),
+                                                          texels.into_iter())
+            .map_err(|e| println!("Failed to stage glyphs ({:?})", e))?;
+
+        let mut builder = builder;
+        let mut offset = 0;
+        for region in dirty {
+            let len = (region[2] * region[3]) as usize;
+            let source = vkb::BufferSlice::from_typed_buffer_access(staging.clone())
+                .slice(offset..offset + len)
+                .unwrap();
+            builder = builder.copy_buffer_to_image_dimensions(source,
+                                                              image.clone(),
+                                                              [region[0], region[1], 0],
+                                                              [region[2], region[3], 1],
+                                                              0, 1, 0)
+                .map_err(|e| println!("Failed to record glyph upload ({:?})", e))?;
+            offset += len;
+        }
+
+        Ok(builder)
+    }
+
+    /// Image of the atlas, `None` before the first upload.
+    pub fn image(&self) -> Option<Arc<vkim::StorageImage<vkfmt::R8Unorm>>> {
+        self.image.clone()
+    }
+
+    /// Corner of a free `width` by `height` rectangle, on the shortest shelf
+    /// that fits it or on a new one.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        if width > self.size {
+            return None;
+        }
+
+        {
+            let size = self.size;
+            let best = self.shelves.iter_mut()
+                .filter(|s| s.height >= height && s.x + width <= size)
+                .min_by_key(|s| s.height);
+            if let Some(shelf) = best {
+                shelf.x += width;
+                return Some([shelf.x - width, shelf.y]);
+            }
+        }
+
+        let y = self.shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+        if y + height > self.size {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y: y,
+            height: height,
+            x: width,
+        });
+        Some([0, y])
+    }
+}
@@ -0,0 +1,54 @@
+/// Big endian reads of font data.
+///
+/// Reads past the end give zeros, so a malformed font draws wrong glyphs
+/// instead of panicking. Sizes read from headers are checked where the
+/// font is loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl<'a> Bytes<'a> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn u8(&self, offset: usize) -> u8 {
+        self.0.get(offset).cloned().unwrap_or(0)
+    }
+
+    pub fn i8(&self, offset: usize) -> i8 {
+        self.u8(offset) as i8
+    }
+
+    pub fn u16(&self, offset: usize) -> u16 {
+        (self.u8(offset) as u16) << 8 | self.u8(offset + 1) as u16
+    }
+
+    pub fn i16(&self, offset: usize) -> i16 {
+        self.u16(offset) as i16
+    }
+
+    pub fn u32(&self, offset: usize) -> u32 {
+        (self.u16(offset) as u32) << 16 | self.u16(offset + 2) as u32
+    }
+
+    /// Unsigned integer of `size` bytes, as used by CFF offsets.
+    pub fn uint(&self, offset: usize, size: usize) -> u32 {
+        (0..size).fold(0, |value, idx| value << 8 | self.u8(offset + idx) as u32)
+    }
+
+    pub fn tag(&self, offset: usize) -> [u8; 4] {
+        [self.u8(offset), self.u8(offset + 1), self.u8(offset + 2), self.u8(offset + 3)]
+    }
+
+    /// `len` bytes from `offset`, cut at the end of the data.
+    pub fn slice(&self, offset: usize, len: usize) -> Bytes<'a> {
+        let start = offset.min(self.0.len());
+        let end = offset.saturating_add(len).min(self.0.len());
+        Bytes(&self.0[start..end])
+    }
+
+    /// Everything from `offset` on.
+    pub fn from(&self, offset: usize) -> Bytes<'a> {
+        Bytes(&self.0[offset.min(self.0.len())..])
+    }
+}
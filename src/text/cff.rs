@@ -0,0 +1,622 @@
+//! Outlines of OpenType fonts in the Compact Font Format, drawn by Type 2
+//! charstrings.
+
+use std::io;
+
+use super::bytes::Bytes;
+use super::outline::{Outline, PathBuilder};
+use super::super::framework::parse::invalid_data;
+
+// Operators of DICTs, escaped ones offset by 1200
+const CHAR_STRINGS: u16 = 17;
+const PRIVATE: u16 = 18;
+const SUBRS: u16 = 19;
+const CHARSTRING_TYPE: u16 = 1206;
+const FD_ARRAY: u16 = 1236;
+const FD_SELECT: u16 = 1237;
+
+/// Deepest nesting of subroutine calls followed.
+const MAX_CALL_DEPTH: u32 = 10;
+
+/// Objects of an INDEX, by absolute offsets into the font data.
+#[derive(Clone, Copy, Debug)]
+struct Index {
+    count: usize,
+    offset_size: usize,
+    offsets: usize,
+    /// Offsets count from the byte before this one.
+    data: usize,
+    /// First byte after the INDEX.
+    end: usize,
+}
+
+impl Index {
+    fn parse(data: Bytes, offset: usize) -> io::Result<Index> {
+        let count = data.u16(offset) as usize;
+        if count == 0 {
+            return Ok(Index {
+                count: 0,
+                offset_size: 1,
+                offsets: offset + 2,
+                data: offset + 1,
+                end: offset + 2,
+            });
+        }
+
+        let offset_size = data.u8(offset + 2) as usize;
+        if offset_size < 1 || offset_size > 4 {
+            return Err(invalid_data("CFF table", "invalid INDEX offset size"));
+        }
+        let offsets = offset + 3;
+        let start = offsets + (count + 1) * offset_size - 1;
+        let end = start + data.uint(offsets + count * offset_size, offset_size) as usize;
+        if end > data.len() {
+            return Err(invalid_data("CFF table", "INDEX out of bounds"));
+        }
+
+        Ok(Index {
+            count: count,
+            offset_size: offset_size,
+            offsets: offsets,
+            data: start,
+            end: end,
+        })
+    }
+
+    /// Byte range of object `idx`.
+    fn get(&self, data: Bytes, idx: usize) -> Option<(usize, usize)> {
+        if idx >= self.count {
+            return None;
+        }
+        let offset = |idx: usize| {
+            self.data + data.uint(self.offsets + idx * self.offset_size, self.offset_size) as usize
+        };
+        let (start, end) = (offset(idx), offset(idx + 1));
+        if start <= end && end <= self.end { Some((start, end)) } else { None }
+    }
+
+    /// Added to the operands of subroutine calls, which are signed.
+    fn bias(&self) -> i32 {
+        if self.count < 1240 {
+            107
+        } else if self.count < 33900 {
+            1131
+        } else {
+            32768
+        }
+    }
+}
+
+/// `CFF ` table of an OpenType font.
+pub struct Cff {
+    char_strings: Index,
+    global_subrs: Index,
+    /// Local subroutines of each font DICT. Fonts that are not CID-keyed
+    /// have one.
+    local_subrs: Vec<Index>,
+    /// Which font DICT each glyph of a CID-keyed font uses.
+    fd_select: Option<usize>,
+}
+
+impl Cff {
+    /// Parses the table at `offset` of `data`, `len` bytes long.
+    pub fn parse(data: Bytes, offset: usize, len: usize) -> io::Result<Cff> {
+        let data = data.slice(0, offset + len);
+        let header_size = data.u8(offset + 2) as usize;
+        let names = Index::parse(data, offset + header_size)?;
+        let top_dicts = Index::parse(data, names.end)?;
+        let strings = Index::parse(data, top_dicts.end)?;
+        let global_subrs = Index::parse(data, strings.end)?;
+
+        let (start, end) = top_dicts.get(data, 0)
+            .ok_or_else(|| invalid_data("CFF table", "no top DICT"))?;
+        let top = dict(data, start, end);
+        if operand(&top, CHARSTRING_TYPE, 0).unwrap_or(2.0) != 2.0 {
+            return Err(invalid_data("CFF table", "only Type 2 charstrings are supported"));
+        }
+        let char_strings = operand(&top, CHAR_STRINGS, 0)
+            .ok_or_else(|| invalid_data("CFF table", "no charstrings"))?;
+        let char_strings = Index::parse(data, offset + char_strings as usize)?;
+
+        // CID-keyed fonts have a private DICT per font DICT
+        let local_subrs = match operand(&top, FD_ARRAY, 0) {
+            Some(fd_array) => {
+                let fd_array = Index::parse(data, offset + fd_array as usize)?;
+                let mut local_subrs = Vec::with_capacity(fd_array.count);
+                for idx in 0..fd_array.count {
+                    let (start, end) = fd_array.get(data, idx)
+                        .ok_or_else(|| invalid_data("CFF table", "font DICT out of bounds"))?;
+                    local_subrs.push(private_subrs(data, offset, &dict(data, start, end))?);
+                }
+                local_subrs
+            },
+            None => vec![private_subrs(data, offset, &top)?],
+        };
+
+        Ok(Cff {
+            char_strings: char_strings,
+            global_subrs: global_subrs,
+            local_subrs: local_subrs,
+            fd_select: operand(&top, FD_SELECT, 0).map(|fd_select| offset + fd_select as usize),
+        })
+    }
+
+    pub fn outline(&self, data: Bytes, glyph: u16) -> Outline {
+        let (start, end) = match self.char_strings.get(data, glyph as usize) {
+            Some(range) => range,
+            None => return Outline::default(),
+        };
+        let local_subrs = match self.local_subrs.get(self.font_dict(data, glyph)) {
+            Some(&subrs) => subrs,
+            None => return Outline::default(),
+        };
+
+        let mut charstring = Charstring {
+            data: data,
+            global_subrs: self.global_subrs,
+            local_subrs: local_subrs,
+            stack: Vec::new(),
+            stems: 0,
+            width_parsed: false,
+            done: false,
+            x: 0.0,
+            y: 0.0,
+            path: PathBuilder::new(),
+        };
+        charstring.run(start, end, 0);
+        charstring.path.build()
+    }
+
+    fn font_dict(&self, data: Bytes, glyph: u16) -> usize {
+        let fd_select = match self.fd_select {
+            Some(fd_select) => fd_select,
+            None => return 0,
+        };
+        match data.u8(fd_select) {
+            0 => data.u8(fd_select + 1 + glyph as usize) as usize,
+            3 => {
+                // Ranges of glyphs sorted by their first, with a sentinel
+                let ranges = data.u16(fd_select + 1) as usize;
+                let mut font_dict = 0;
+                for idx in 0..ranges {
+                    let range = fd_select + 3 + idx * 3;
+                    if data.u16(range) > glyph {
+                        break;
+                    }
+                    font_dict = data.u8(range + 2) as usize;
+                }
+                font_dict
+            },
+            _ => 0,
+        }
+    }
+}
+
+/// Local subroutines of the private DICT referenced by `dict`.
+fn private_subrs(data: Bytes, cff: usize, dict_entries: &[(u16, Vec<f64>)]) -> io::Result<Index> {
+    let size = operand(dict_entries, PRIVATE, 0).unwrap_or(0.0) as usize;
+    let private = cff + operand(dict_entries, PRIVATE, 1).unwrap_or(0.0) as usize;
+    match operand(&dict(data, private, private + size), SUBRS, 0) {
+        Some(subrs) if size > 0 => Index::parse(data, private + subrs as usize),
+        _ => Index::parse(data, data.len()),
+    }
+}
+
+/// Operators of a DICT with their operands.
+fn dict(data: Bytes, start: usize, end: usize) -> Vec<(u16, Vec<f64>)> {
+    let mut entries = Vec::new();
+    let mut operands = Vec::new();
+    let mut pos = start;
+    while pos < end {
+        let b0 = data.u8(pos);
+        match b0 {
+            0...21 => {
+                let operator = if b0 == 12 {
+                    pos += 1;
+                    1200 + data.u8(pos) as u16
+                } else {
+                    b0 as u16
+                };
+                pos += 1;
+                entries.push((operator, operands));
+                operands = Vec::new();
+            },
+            28 => {
+                operands.push(data.i16(pos + 1) as f64);
+                pos += 3;
+            },
+            29 => {
+                operands.push(data.u32(pos + 1) as i32 as f64);
+                pos += 5;
+            },
+            30 => {
+                let (value, len) = real(data, pos + 1);
+                operands.push(value);
+                pos += 1 + len;
+            },
+            32...246 => {
+                operands.push(b0 as f64 - 139.0);
+                pos += 1;
+            },
+            247...250 => {
+                operands.push(((b0 as f64 - 247.0) * 256.0) + data.u8(pos + 1) as f64 + 108.0);
+                pos += 2;
+            },
+            251...254 => {
+                operands.push(-((b0 as f64 - 251.0) * 256.0) - data.u8(pos + 1) as f64 - 108.0);
+                pos += 2;
+            },
+            _ => pos += 1,
+        }
+    }
+    entries
+}
+
+/// Real number of nibbles packed in bytes from `pos`, and how many bytes
+/// it takes.
+fn real(data: Bytes, pos: usize) -> (f64, usize) {
+    let mut text = String::new();
+    let mut len = 0;
+    loop {
+        let byte = data.u8(pos + len);
+        len += 1;
+        for &nibble in &[byte >> 4, byte & 0xf] {
+            match nibble {
+                0...9 => text.push((b'0' + nibble) as char),
+                0xa => text.push('.'),
+                0xb => text.push('E'),
+                0xc => text.push_str("E-"),
+                0xe => text.push('-'),
+                0xf => return (text.parse().unwrap_or(0.0), len),
+                _ => (),
+            }
+        }
+        if pos + len >= data.len() {
+            return (0.0, len);
+        }
+    }
+}
+
+fn operand(dict: &[(u16, Vec<f64>)], operator: u16, idx: usize) -> Option<f64> {
+    dict.iter().find(|e| e.0 == operator).and_then(|e| e.1.get(idx).cloned())
+}
+
+/// State of drawing one glyph.
+struct Charstring<'a> {
+    data: Bytes<'a>,
+    global_subrs: Index,
+    local_subrs: Index,
+    stack: Vec<f32>,
+    /// Stem hints declared so far, which size hint masks.
+    stems: usize,
+    /// The first stack clearing operator may be preceded by the advance
+    /// width, which is read from `hmtx` instead.
+    width_parsed: bool,
+    done: bool,
+    x: f32,
+    y: f32,
+    path: PathBuilder,
+}
+
+impl<'a> Charstring<'a> {
+    fn run(&mut self, start: usize, end: usize, depth: u32) {
+        let data = self.data;
+        let mut pos = start;
+        while pos < end && !self.done {
+            let b0 = data.u8(pos);
+            pos += 1;
+            let operand = match b0 {
+                28 => {
+                    pos += 2;
+                    Some(data.i16(pos - 2) as f32)
+                },
+                32...246 => Some(b0 as f32 - 139.0),
+                247...250 => {
+                    pos += 1;
+                    Some((b0 as f32 - 247.0) * 256.0 + data.u8(pos - 1) as f32 + 108.0)
+                },
+                251...254 => {
+                    pos += 1;
+                    Some(-(b0 as f32 - 251.0) * 256.0 - data.u8(pos - 1) as f32 - 108.0)
+                },
+                // 16.16 fixed point
+                255 => {
+                    pos += 4;
+                    Some(data.u32(pos - 4) as i32 as f32 / 65536.0)
+                },
+                _ => None,
+            };
+            if let Some(operand) = operand {
+                self.stack.push(operand);
+                continue;
+            }
+
+            match b0 {
+                // hstem, vstem, hstemhm, vstemhm
+                1 | 3 | 18 | 23 => self.stem_hints(),
+                // hintmask, cntrmask
+                19 | 20 => {
+                    // Stems may be declared right before the mask
+                    self.stem_hints();
+                    pos += (self.stems + 7) / 8;
+                },
+                // rmoveto
+                21 => {
+                    let extra = self.stack.len() > 2;
+                    self.parse_width(extra);
+                    let (dx, dy) = (self.arg(0), self.arg(1));
+                    self.move_by(dx, dy);
+                },
+                // hmoveto
+                22 => {
+                    let extra = self.stack.len() > 1;
+                    self.parse_width(extra);
+                    let dx = self.arg(0);
+                    self.move_by(dx, 0.0);
+                },
+                // vmoveto
+                4 => {
+                    let extra = self.stack.len() > 1;
+                    self.parse_width(extra);
+                    let dy = self.arg(0);
+                    self.move_by(0.0, dy);
+                },
+                // rlineto
+                5 => {
+                    let mut idx = 0;
+                    while idx + 2 <= self.stack.len() {
+                        let (dx, dy) = (self.arg(idx), self.arg(idx + 1));
+                        self.line_by(dx, dy);
+                        idx += 2;
+                    }
+                },
+                // hlineto, vlineto
+                6 | 7 => {
+                    let mut horizontal = b0 == 6;
+                    for idx in 0..self.stack.len() {
+                        let d = self.arg(idx);
+                        if horizontal { self.line_by(d, 0.0) } else { self.line_by(0.0, d) }
+                        horizontal = !horizontal;
+                    }
+                },
+                // rrcurveto
+                8 => {
+                    let mut idx = 0;
+                    while idx + 6 <= self.stack.len() {
+                        self.curve_at(idx);
+                        idx += 6;
+                    }
+                },
+                // rcurveline
+                24 => {
+                    let mut idx = 0;
+                    while idx + 8 <= self.stack.len() {
+                        self.curve_at(idx);
+                        idx += 6;
+                    }
+                    let (dx, dy) = (self.arg(idx), self.arg(idx + 1));
+                    self.line_by(dx, dy);
+                },
+                // rlinecurve
+                25 => {
+                    let mut idx = 0;
+                    while idx + 8 <= self.stack.len() {
+                        let (dx, dy) = (self.arg(idx), self.arg(idx + 1));
+                        self.line_by(dx, dy);
+                        idx += 2;
+                    }
+                    self.curve_at(idx);
+                },
+                // vvcurveto
+                26 => {
+                    let mut idx = self.stack.len() % 2;
+                    let mut dx1 = if idx == 1 { self.arg(0) } else { 0.0 };
+                    while idx + 4 <= self.stack.len() {
+                        let (dya, dxb, dyb, dyc) = (self.arg(idx), self.arg(idx + 1),
+                                                    self.arg(idx + 2), self.arg(idx + 3));
+                        self.curve_by(dx1, dya, dxb, dyb, 0.0, dyc);
+                        dx1 = 0.0;
+                        idx += 4;
+                    }
+                },
+                // hhcurveto
+                27 => {
+                    let mut idx = self.stack.len() % 2;
+                    let mut dy1 = if idx == 1 { self.arg(0) } else { 0.0 };
+                    while idx + 4 <= self.stack.len() {
+                        let (dxa, dxb, dyb, dxc) = (self.arg(idx), self.arg(idx + 1),
+                                                    self.arg(idx + 2), self.arg(idx + 3));
+                        self.curve_by(dxa, dy1, dxb, dyb, dxc, 0.0);
+                        dy1 = 0.0;
+                        idx += 4;
+                    }
+                },
+                // vhcurveto, hvcurveto
+                30 | 31 => {
+                    // Curves alternate between starting vertically and
+                    // horizontally, the last one may end diagonally
+                    let mut horizontal = b0 == 31;
+                    let mut idx = 0;
+                    while idx + 4 <= self.stack.len() {
+                        let last = if self.stack.len() - idx == 5 { self.arg(idx + 4) } else { 0.0 };
+                        let (a, b, c, d) = (self.arg(idx), self.arg(idx + 1),
+                                            self.arg(idx + 2), self.arg(idx + 3));
+                        if horizontal {
+                            self.curve_by(a, 0.0, b, c, last, d);
+                        } else {
+                            self.curve_by(0.0, a, b, c, d, last);
+                        }
+                        horizontal = !horizontal;
+                        idx += 4;
+                    }
+                },
+                // callsubr, callgsubr
+                10 | 29 => {
+                    let subrs = if b0 == 10 { self.local_subrs } else { self.global_subrs };
+                    let idx = self.stack.pop().unwrap_or(0.0) as i32 + subrs.bias();
+                    if depth < MAX_CALL_DEPTH && idx >= 0 {
+                        if let Some((start, end)) = subrs.get(data, idx as usize) {
+                            self.run(start, end, depth + 1);
+                        }
+                    }
+                    // Subroutines leave their operands for the caller
+                    continue;
+                },
+                // return
+                11 => return,
+                // endchar
+                14 => {
+                    let extra = self.stack.len() == 1 || self.stack.len() == 5;
+                    self.parse_width(extra);
+                    self.path.close();
+                    self.done = true;
+                },
+                12 => {
+                    let b1 = data.u8(pos);
+                    pos += 1;
+                    self.flex(b1);
+                },
+                _ => (),
+            }
+            // Every operator but the subroutine calls clears the stack
+            self.stack.clear();
+        }
+    }
+
+    /// Counts stem hints, which only matter to skip hint masks.
+    fn stem_hints(&mut self) {
+        let extra = self.stack.len() % 2 == 1;
+        self.parse_width(extra);
+        self.stems += self.stack.len() / 2;
+    }
+
+    fn parse_width(&mut self, extra: bool) {
+        if !self.width_parsed && extra {
+            self.stack.remove(0);
+        }
+        self.width_parsed = true;
+    }
+
+    /// Escaped operators, of which only the flex curves draw.
+    fn flex(&mut self, operator: u8) {
+        let curves = {
+            let a = |idx: usize| self.arg(idx);
+            match operator {
+                // flex
+                35 => [[a(0), a(1), a(2), a(3), a(4), a(5)],
+                       [a(6), a(7), a(8), a(9), a(10), a(11)]],
+                // hflex
+                34 => [[a(0), 0.0, a(1), a(2), a(3), 0.0],
+                       [a(4), 0.0, a(5), -a(2), a(6), 0.0]],
+                // hflex1
+                36 => [[a(0), a(1), a(2), a(3), a(4), 0.0],
+                       [a(5), 0.0, a(6), a(7), a(8), -(a(1) + a(3) + a(7))]],
+                // flex1
+                37 => {
+                    let dx = a(0) + a(2) + a(4) + a(6) + a(8);
+                    let dy = a(1) + a(3) + a(5) + a(7) + a(9);
+                    // The last operand moves along the axis the curves moved
+                    // most, the other returns to the start
+                    let (last_x, last_y) = if dx.abs() > dy.abs() { (a(10), -dy) } else { (-dx, a(10)) };
+                    [[a(0), a(1), a(2), a(3), a(4), a(5)],
+                     [a(6), a(7), a(8), a(9), last_x, last_y]]
+                },
+                _ => return,
+            }
+        };
+        for c in &curves {
+            self.curve_by(c[0], c[1], c[2], c[3], c[4], c[5]);
+        }
+    }
+
+    fn arg(&self, idx: usize) -> f32 {
+        self.stack.get(idx).cloned().unwrap_or(0.0)
+    }
+
+    fn move_by(&mut self, dx: f32, dy: f32) {
+        self.x += dx;
+        self.y += dy;
+        self.path.move_to([self.x, self.y]);
+    }
+
+    fn line_by(&mut self, dx: f32, dy: f32) {
+        self.x += dx;
+        self.y += dy;
+        self.path.line_to([self.x, self.y]);
+    }
+
+    /// Curve of the six operands from `idx`.
+    fn curve_at(&mut self, idx: usize) {
+        let (dx1, dy1, dx2, dy2, dx3, dy3) = (self.arg(idx), self.arg(idx + 1), self.arg(idx + 2),
+                                              self.arg(idx + 3), self.arg(idx + 4), self.arg(idx + 5));
+        self.curve_by(dx1, dy1, dx2, dy2, dx3, dy3);
+    }
+
+    /// Curve with each point relative to the one before.
+    fn curve_by(&mut self, dx1: f32, dy1: f32, dx2: f32, dy2: f32, dx3: f32, dy3: f32) {
+        let c0 = [self.x + dx1, self.y + dy1];
+        let c1 = [c0[0] + dx2, c0[1] + dy2];
+        self.x = c1[0] + dx3;
+        self.y = c1[1] + dy3;
+        self.path.cubic_to(c0, c1, [self.x, self.y]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::outline::Segment;
+
+    /// `CFF ` table of two glyphs, an empty one and a square drawn partly by
+    /// a global subroutine.
+    fn table() -> Vec<u8> {
+        vec![1, 0, 4, 1,
+             // Name INDEX
+             0, 1, 1, 1, 2, b'A',
+             // Top DICT INDEX, with the charstrings at 30
+             0, 1, 1, 1, 5, 28, 0, 30, 17,
+             // String INDEX
+             0, 0,
+             // Global subroutine INDEX, of 0 100 rlineto return
+             0, 1, 1, 1, 5, 139, 239, 5, 11,
+             // CharStrings INDEX, of endchar and of a width of 500 before
+             // 10 20 rmoveto 100 0 rlineto -107 callgsubr -100 hlineto endchar
+             0, 2, 1, 1, 2, 15,
+             14,
+             248, 136, 149, 159, 21, 239, 139, 5, 32, 29, 39, 6, 14]
+    }
+
+    #[test]
+    fn draws_charstrings_with_subroutines() {
+        let data = table();
+        let cff = Cff::parse(Bytes(&data), 0, data.len()).unwrap();
+
+        assert!(cff.outline(Bytes(&data), 0).is_empty());
+        assert!(cff.outline(Bytes(&data), 2).is_empty());
+        assert_eq!(cff.outline(Bytes(&data), 1).segments,
+                   [Segment::Line([10.0, 20.0], [110.0, 20.0]),
+                    Segment::Line([110.0, 20.0], [110.0, 120.0]),
+                    Segment::Line([110.0, 120.0], [10.0, 120.0]),
+                    Segment::Line([10.0, 120.0], [10.0, 20.0])]);
+    }
+
+    #[test]
+    fn rejects_tables_without_charstrings() {
+        let mut data = table();
+        // CharStrings becomes Private
+        data[18] = PRIVATE as u8;
+        assert!(Cff::parse(Bytes(&data), 0, data.len()).is_err());
+        // The last charstring ends past the table
+        let data = table();
+        assert!(Cff::parse(Bytes(&data), 0, data.len() - 1).is_err());
+    }
+
+    #[test]
+    fn reads_dict_operands() {
+        // -2.25 as a real and 256 as a short, before the escaped operator 6,
+        // and 1000 as two bytes before operator 17
+        let data = [30, 0xe2, 0xa2, 0x5f, 28, 1, 0, 12, 6, 250, 124, 17];
+        assert_eq!(dict(Bytes(&data), 0, data.len()),
+                   [(CHARSTRING_TYPE, vec![-2.25, 256.0]), (CHAR_STRINGS, vec![1000.0])]);
+    }
+}
@@ -0,0 +1,608 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::bytes::Bytes;
+use super::cff::Cff;
+use super::kerning::Kerning;
+use super::outline::{Outline, PathBuilder};
+use super::super::framework::parse::invalid_data;
+
+// Flags of the points of simple TrueType glyphs
+const ON_CURVE: u8 = 0x01;
+const X_SHORT: u8 = 0x02;
+const Y_SHORT: u8 = 0x04;
+const REPEAT: u8 = 0x08;
+const X_SAME_OR_POSITIVE: u8 = 0x10;
+const Y_SAME_OR_POSITIVE: u8 = 0x20;
+
+// Flags of the components of composite TrueType glyphs
+const ARGS_ARE_WORDS: u16 = 0x0001;
+const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+const HAVE_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const HAVE_X_AND_Y_SCALE: u16 = 0x0040;
+const HAVE_TWO_BY_TWO: u16 = 0x0080;
+
+/// Deepest nesting of composite glyphs followed.
+const MAX_COMPONENT_DEPTH: u32 = 8;
+
+/// Vertical metrics of a font, in font units.
+#[derive(Clone, Copy, Debug)]
+pub struct Metrics {
+    pub units_per_em: f32,
+    /// Height of the tallest letters above the baseline.
+    pub ascender: f32,
+    /// Depth below the baseline, negative.
+    pub descender: f32,
+    /// Space between the descender of one line and the ascender of the next.
+    pub line_gap: f32,
+}
+
+impl Metrics {
+    /// Distance between two baselines.
+    pub fn line_height(&self) -> f32 {
+        self.ascender - self.descender + self.line_gap
+    }
+}
+
+enum Outlines {
+    TrueType {
+        loca: usize,
+        glyf: usize,
+        glyf_len: usize,
+        long_offsets: bool,
+    },
+    Cff(Cff),
+}
+
+/// TrueType or OpenType font.
+///
+/// Glyph outlines are read from the `glyf` table of TrueType fonts or the
+/// `CFF ` table of OpenType ones, and kerning from the `kern` feature of
+/// `GPOS` or else from the `kern` table. Of font collections the first font
+/// is used.
+pub struct Font {
+    data: Vec<u8>,
+    metrics: Metrics,
+    glyph_count: u16,
+    hmtx: usize,
+    h_metric_count: u16,
+    /// Unicode subtable of `cmap` and its format.
+    cmap: Option<(usize, u16)>,
+    outlines: Outlines,
+    kerning: Kerning,
+}
+
+impl Font {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Font> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Font::from_data(data)
+    }
+
+    pub fn from_data(data: Vec<u8>) -> io::Result<Font> {
+        let (metrics, glyph_count, hmtx, h_metric_count, cmap, outlines, kerning) = {
+            let bytes = Bytes(&data);
+            let tables = directory(bytes)?;
+            let table = |tag: &[u8; 4]| tables.iter().find(|t| &t.0 == tag).map(|t| (t.1, t.2));
+            let required = |tag: &[u8; 4]| table(tag).ok_or_else(|| {
+                invalid_data("font", &format!("no {} table", String::from_utf8_lossy(tag)))
+            });
+
+            let (head, _) = required(b"head")?;
+            let (hhea, _) = required(b"hhea")?;
+            let (maxp, _) = required(b"maxp")?;
+            let (hmtx, hmtx_len) = required(b"hmtx")?;
+
+            let units_per_em = bytes.u16(head + 18);
+            if units_per_em == 0 {
+                return Err(invalid_data("font", "no units per em"));
+            }
+            let metrics = Metrics {
+                units_per_em: units_per_em as f32,
+                ascender: bytes.i16(hhea + 4) as f32,
+                descender: bytes.i16(hhea + 6) as f32,
+                line_gap: bytes.i16(hhea + 8) as f32,
+            };
+            let h_metric_count = bytes.u16(hhea + 34);
+            if h_metric_count == 0 || h_metric_count as usize * 4 > hmtx_len {
+                return Err(invalid_data("font", "horizontal metrics out of bounds"));
+            }
+            let glyph_count = bytes.u16(maxp + 4);
+
+            let outlines = match (table(b"glyf"), table(b"loca"), table(b"CFF ")) {
+                (Some((glyf, glyf_len)), Some((loca, loca_len)), _) => {
+                    let long_offsets = bytes.i16(head + 50) != 0;
+                    let size = if long_offsets { 4 } else { 2 };
+                    if (glyph_count as usize + 1) * size > loca_len {
+                        return Err(invalid_data("font", "glyph locations out of bounds"));
+                    }
+                    Outlines::TrueType {
+                        loca: loca,
+                        glyf: glyf,
+                        glyf_len: glyf_len,
+                        long_offsets: long_offsets,
+                    }
+                },
+                (_, _, Some((cff, cff_len))) => Outlines::Cff(Cff::parse(bytes, cff, cff_len)?),
+                _ => return Err(invalid_data("font", "no glyph outlines")),
+            };
+
+            (metrics,
+             glyph_count,
+             hmtx,
+             h_metric_count,
+             table(b"cmap").and_then(|(cmap, _)| unicode_subtable(bytes, cmap)),
+             outlines,
+             Kerning::parse(bytes, table(b"GPOS"), table(b"kern")))
+        };
+
+        Ok(Font {
+            data: data,
+            metrics: metrics,
+            glyph_count: glyph_count,
+            hmtx: hmtx,
+            h_metric_count: h_metric_count,
+            cmap: cmap,
+            outlines: outlines,
+            kerning: kerning,
+        })
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    pub fn glyph_count(&self) -> u16 {
+        self.glyph_count
+    }
+
+    /// Pixels per font unit for text `size` pixels per em.
+    pub fn scale(&self, size: f32) -> f32 {
+        size / self.metrics.units_per_em
+    }
+
+    /// Glyph of `c`, or the missing glyph 0 if the font has none.
+    pub fn glyph(&self, c: char) -> u16 {
+        let data = Bytes(&self.data);
+        let c = c as u32;
+        match self.cmap {
+            Some((subtable, 4)) => segment_glyph(data.from(subtable), c),
+            Some((subtable, 12)) => group_glyph(data.from(subtable), c),
+            _ => 0,
+        }
+    }
+
+    /// Horizontal distance to the next glyph, in font units.
+    pub fn advance(&self, glyph: u16) -> f32 {
+        let metric = glyph.min(self.h_metric_count - 1) as usize;
+        Bytes(&self.data).u16(self.hmtx + metric * 4) as f32
+    }
+
+    /// Adjustment of the advance of `left` when followed by `right`, in
+    /// font units. Usually negative.
+    pub fn kerning(&self, left: u16, right: u16) -> f32 {
+        self.kerning.get(Bytes(&self.data), left, right) as f32
+    }
+
+    /// Outline of `glyph` in font units, empty for blank glyphs like spaces.
+    pub fn outline(&self, glyph: u16) -> Outline {
+        if glyph >= self.glyph_count {
+            return Outline::default();
+        }
+        match self.outlines {
+            Outlines::TrueType { .. } => self.glyf_outline(glyph, 0),
+            Outlines::Cff(ref cff) => cff.outline(Bytes(&self.data), glyph),
+        }
+    }
+
+    fn glyf_outline(&self, glyph: u16, depth: u32) -> Outline {
+        let (loca, glyf, glyf_len, long_offsets) = match self.outlines {
+            Outlines::TrueType { loca, glyf, glyf_len, long_offsets } =>
+                (loca, glyf, glyf_len, long_offsets),
+            Outlines::Cff(_) => return Outline::default(),
+        };
+        let data = Bytes(&self.data);
+
+        let glyph = glyph as usize;
+        let (start, end) = if long_offsets {
+            (data.u32(loca + glyph * 4) as usize, data.u32(loca + glyph * 4 + 4) as usize)
+        } else {
+            (data.u16(loca + glyph * 2) as usize * 2, data.u16(loca + glyph * 2 + 2) as usize * 2)
+        };
+        if end <= start || end > glyf_len {
+            return Outline::default();
+        }
+
+        let glyph = data.slice(glyf + start, end - start);
+        let contours = glyph.i16(0);
+        if contours >= 0 {
+            simple_glyph(glyph, contours as usize)
+        } else if depth < MAX_COMPONENT_DEPTH {
+            self.composite_glyph(glyph, depth)
+        } else {
+            Outline::default()
+        }
+    }
+
+    fn composite_glyph(&self, glyph: Bytes, depth: u32) -> Outline {
+        let mut outline = Outline::default();
+        let mut offset = 10;
+        loop {
+            let flags = glyph.u16(offset);
+            let component = glyph.u16(offset + 2);
+            offset += 4;
+
+            let (dx, dy) = if flags & ARGS_ARE_WORDS != 0 {
+                offset += 4;
+                (glyph.i16(offset - 4) as f32, glyph.i16(offset - 2) as f32)
+            } else {
+                offset += 2;
+                (glyph.i8(offset - 2) as f32, glyph.i8(offset - 1) as f32)
+            };
+            // Components placed by matching points are left unmoved
+            let translation = if flags & ARGS_ARE_XY_VALUES != 0 { [dx, dy] } else { [0.0, 0.0] };
+
+            let f2dot14 = |offset: usize| glyph.i16(offset) as f32 / 16384.0;
+            let m = if flags & HAVE_SCALE != 0 {
+                offset += 2;
+                let s = f2dot14(offset - 2);
+                [[s, 0.0], [0.0, s]]
+            } else if flags & HAVE_X_AND_Y_SCALE != 0 {
+                offset += 4;
+                [[f2dot14(offset - 4), 0.0], [0.0, f2dot14(offset - 2)]]
+            } else if flags & HAVE_TWO_BY_TWO != 0 {
+                offset += 8;
+                [[f2dot14(offset - 8), f2dot14(offset - 6)],
+                 [f2dot14(offset - 4), f2dot14(offset - 2)]]
+            } else {
+                [[1.0, 0.0], [0.0, 1.0]]
+            };
+
+            outline.append(&self.glyf_outline(component, depth + 1), m, translation);
+            if flags & MORE_COMPONENTS == 0 {
+                break;
+            }
+        }
+        outline
+    }
+}
+
+/// Tag, offset and length of every table.
+fn directory(data: Bytes) -> io::Result<Vec<([u8; 4], usize, usize)>> {
+    let mut start = 0;
+    if &data.tag(0) == b"ttcf" {
+        start = data.u32(12) as usize;
+    }
+    match &data.tag(start) {
+        b"\0\x01\0\0" | b"true" | b"OTTO" => (),
+        _ => return Err(invalid_data("font", "not a TrueType or OpenType font")),
+    }
+
+    let count = data.u16(start + 4) as usize;
+    if start + 12 + count * 16 > data.len() {
+        return Err(invalid_data("font", "table directory out of bounds"));
+    }
+
+    (0..count).map(|idx| {
+        let record = start + 12 + idx * 16;
+        let offset = data.u32(record + 8) as usize;
+        let len = data.u32(record + 12) as usize;
+        if offset.saturating_add(len) > data.len() {
+            return Err(invalid_data("font", "table out of bounds"));
+        }
+        Ok((data.tag(record), offset, len))
+    }).collect()
+}
+
+/// Best supported subtable of `cmap` mapping Unicode characters.
+fn unicode_subtable(data: Bytes, cmap: usize) -> Option<(usize, u16)> {
+    let count = data.u16(cmap + 2) as usize;
+    (0..count).filter_map(|idx| {
+        let record = cmap + 4 + idx * 8;
+        let subtable = cmap + data.u32(record + 4) as usize;
+        let format = data.u16(subtable);
+        // Full Unicode first, then the basic plane, then symbol fonts
+        let rank = match (data.u16(record), data.u16(record + 2), format) {
+            (0, _, 12) | (3, 10, 12) => 3,
+            (0, _, 4) | (3, 1, 4) => 2,
+            (3, 0, 4) => 1,
+            _ => return None,
+        };
+        Some((rank, subtable, format))
+    }).max_by_key(|&(rank, _, _)| rank).map(|(_, subtable, format)| (subtable, format))
+}
+
+/// Glyph of `c` in a format 4 `cmap` subtable, of segments of the basic
+/// multilingual plane.
+fn segment_glyph(subtable: Bytes, c: u32) -> u16 {
+    if c > 0xffff {
+        return 0;
+    }
+    let segments = subtable.u16(6) as usize / 2;
+    let ends = 14;
+    let starts = ends + segments * 2 + 2;
+    let deltas = starts + segments * 2;
+    let range_offsets = deltas + segments * 2;
+
+    // Segments are sorted by their end, the last one ends at 0xffff
+    let (mut low, mut high) = (0, segments);
+    while low < high {
+        let mid = (low + high) / 2;
+        if (subtable.u16(ends + mid * 2) as u32) < c {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == segments {
+        return 0;
+    }
+
+    let start = subtable.u16(starts + low * 2) as u32;
+    if c < start {
+        return 0;
+    }
+    let delta = subtable.u16(deltas + low * 2);
+    let range_offset = subtable.u16(range_offsets + low * 2) as usize;
+    if range_offset == 0 {
+        return (c as u16).wrapping_add(delta);
+    }
+
+    // The offset is relative to where it is stored
+    let glyph = subtable.u16(range_offsets + low * 2 + range_offset + (c - start) as usize * 2);
+    if glyph == 0 { 0 } else { glyph.wrapping_add(delta) }
+}
+
+/// Glyph of `c` in a format 12 `cmap` subtable, of groups of characters
+/// with consecutive glyphs.
+fn group_glyph(subtable: Bytes, c: u32) -> u16 {
+    let groups = subtable.u32(12) as usize;
+    let (mut low, mut high) = (0, groups);
+    while low < high {
+        let mid = (low + high) / 2;
+        let group = 16 + mid * 12;
+        if c < subtable.u32(group) {
+            high = mid;
+        } else if c > subtable.u32(group + 4) {
+            low = mid + 1;
+        } else {
+            return (subtable.u32(group + 8) + c - subtable.u32(group)) as u16;
+        }
+    }
+    0
+}
+
+fn simple_glyph(glyph: Bytes, contours: usize) -> Outline {
+    let ends = (0..contours).map(|idx| glyph.u16(10 + idx * 2) as usize).collect::<Vec<_>>();
+    let count = ends.last().map(|&end| end + 1).unwrap_or(0);
+    let instructions = glyph.u16(10 + contours * 2) as usize;
+    let mut offset = 12 + contours * 2 + instructions;
+
+    let mut flags = Vec::with_capacity(count);
+    while flags.len() < count && offset < glyph.len() {
+        let flag = glyph.u8(offset);
+        offset += 1;
+        flags.push(flag);
+        if flag & REPEAT != 0 {
+            for _ in 0..glyph.u8(offset) {
+                flags.push(flag);
+            }
+            offset += 1;
+        }
+    }
+    flags.truncate(count);
+
+    // Coordinates are deltas from the previous point, one or two bytes each
+    let mut coordinates = |short: u8, same_or_positive: u8| {
+        let mut value = 0i32;
+        flags.iter().map(|&flag| {
+            if flag & short != 0 {
+                let delta = glyph.u8(offset) as i32;
+                offset += 1;
+                value += if flag & same_or_positive != 0 { delta } else { -delta };
+            } else if flag & same_or_positive == 0 {
+                value += glyph.i16(offset) as i32;
+                offset += 2;
+            }
+            value as f32
+        }).collect::<Vec<_>>()
+    };
+    let xs = coordinates(X_SHORT, X_SAME_OR_POSITIVE);
+    let ys = coordinates(Y_SHORT, Y_SAME_OR_POSITIVE);
+
+    let mut path = PathBuilder::new();
+    let mut first = 0;
+    for &end in &ends {
+        if end < first || end >= flags.len() {
+            break;
+        }
+        let points = (first..end + 1)
+            .map(|idx| ([xs[idx], ys[idx]], flags[idx] & ON_CURVE != 0))
+            .collect::<Vec<_>>();
+        quadratic_contour(&mut path, &points);
+        first = end + 1;
+    }
+    path.build()
+}
+
+/// Adds a TrueType contour, where two off-curve points in a row imply an
+/// on-curve point between them.
+fn quadratic_contour(path: &mut PathBuilder, points: &[([f32; 2], bool)]) {
+    let count = points.len();
+    if count == 0 {
+        return;
+    }
+    let midpoint = |a: [f32; 2], b: [f32; 2]| [(a[0] + b[0]) * 0.5, (a[1] + b[1]) * 0.5];
+
+    // Start on an on-curve point, visiting it again last to close the
+    // contour, or between the last and first point if all are off-curve
+    let (start, first) = match points.iter().position(|p| p.1) {
+        Some(idx) => (points[idx].0, idx + 1),
+        None => (midpoint(points[count - 1].0, points[0].0), 0),
+    };
+    path.move_to(start);
+
+    let mut control = None;
+    for step in 0..count {
+        let (point, on_curve) = points[(first + step) % count];
+        if on_curve {
+            match control.take() {
+                Some(control) => path.quad_to(control, point),
+                None => path.line_to(point),
+            }
+        } else {
+            if let Some(control) = control {
+                path.quad_to(control, midpoint(control, point));
+            }
+            control = Some(point);
+        }
+    }
+    if let Some(control) = control {
+        path.quad_to(control, start);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Big endian bytes of `words`.
+    pub fn words(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|&w| vec![(w >> 8) as u8, w as u8]).collect()
+    }
+
+    /// Font file of `tables`, each starting four byte aligned.
+    pub fn sfnt(tables: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut data = words(&[1, 0, tables.len() as u16, 0, 0, 0]);
+        let mut offset = 12 + tables.len() * 16;
+        for &(tag, ref table) in tables {
+            data.extend_from_slice(tag);
+            data.extend(words(&[0, 0, (offset >> 16) as u16, offset as u16,
+                                0, table.len() as u16]));
+            offset += (table.len() + 3) / 4 * 4;
+        }
+        for &(_, ref table) in tables {
+            data.extend_from_slice(table);
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+        }
+        data
+    }
+
+    /// TrueType font of blank glyphs with 100 units per em, so text of size
+    /// 100 is laid out in font units.
+    ///
+    /// Glyphs are .notdef, space, A, V and B, and one more without a
+    /// character. The space advances 25 units and the letters 60, A is
+    /// kerned by -10 before V and V by -8 before A.
+    pub fn fixture() -> Font {
+        let mut head = vec![0; 54];
+        head[19] = 100;
+        let mut hhea = words(&[0, 0, 80, -20i16 as u16, 0]);
+        hhea.resize(34, 0);
+        hhea.extend(words(&[3]));
+        // Glyphs after the third share its advance
+        let hmtx = words(&[50, 0, 25, 0, 60, 0, 0, 0, 0]);
+        let cmap = [
+            words(&[0, 1, 3, 1, 0, 12]),
+            words(&[4, 54, 0, 8, 0, 0, 0,
+                    0x20, 0x43, 0x56, 0xffff,
+                    0,
+                    0x20, 0x41, 0x56, 0xffff,
+                    1u16.wrapping_sub(0x20), 0, 3u16.wrapping_sub(0x56), 1,
+                    0, 6, 0, 0,
+                    // A to C, of which C has no glyph
+                    2, 4, 0]),
+        ].concat();
+        let kern = words(&[0, 1,
+                           0, 26, 0x0001, 2, 12, 1, 0,
+                           2, 3, -10i16 as u16,
+                           3, 2, -8i16 as u16]);
+
+        Font::from_data(sfnt(&[(b"cmap", cmap),
+                               (b"glyf", Vec::new()),
+                               (b"head", head),
+                               (b"hhea", hhea),
+                               (b"hmtx", hmtx),
+                               (b"kern", kern),
+                               (b"loca", words(&[0; 7])),
+                               (b"maxp", words(&[0, 0x5000, 6]))])).unwrap()
+    }
+
+    #[test]
+    fn reads_metrics_and_advances() {
+        let font = fixture();
+        let metrics = font.metrics();
+        assert_eq!(metrics.units_per_em, 100.0);
+        assert_eq!(metrics.line_height(), 100.0);
+        assert_eq!(font.glyph_count(), 6);
+        assert_eq!(font.scale(50.0), 0.5);
+
+        assert_eq!(font.advance(0), 50.0);
+        assert_eq!(font.advance(1), 25.0);
+        assert_eq!(font.advance(2), 60.0);
+        assert_eq!(font.advance(5), 60.0);
+        assert!(font.outline(2).is_empty());
+    }
+
+    #[test]
+    fn maps_characters_by_segments() {
+        let font = fixture();
+        assert_eq!(font.glyph(' '), 1);
+        assert_eq!(font.glyph('A'), 2);
+        assert_eq!(font.glyph('B'), 4);
+        assert_eq!(font.glyph('V'), 3);
+        // In a segment but without a glyph, between segments and past the
+        // basic plane
+        assert_eq!(font.glyph('C'), 0);
+        assert_eq!(font.glyph('D'), 0);
+        assert_eq!(font.glyph('a'), 0);
+        assert_eq!(font.glyph('\u{1f600}'), 0);
+    }
+
+    #[test]
+    fn maps_characters_by_groups() {
+        let subtable = words(&[12, 0, 0, 0, 0, 0, 0, 2,
+                               0, 0x41, 0, 0x5a, 0, 10,
+                               1, 0xf600, 1, 0xf602, 0, 50]);
+        let glyph = |c: char| group_glyph(Bytes(&subtable), c as u32);
+        assert_eq!(glyph('A'), 10);
+        assert_eq!(glyph('Z'), 35);
+        assert_eq!(glyph('\u{1f601}'), 51);
+        assert_eq!(glyph('a'), 0);
+        assert_eq!(glyph('\u{1f603}'), 0);
+    }
+
+    #[test]
+    fn prefers_full_unicode_subtables() {
+        // Records of Windows Unicode subtables of formats 4 and 12, and of a
+        // Macintosh one
+        let cmap = words(&[0, 3, 3, 1, 0, 28, 3, 10, 0, 30, 1, 0, 0, 32, 4, 12, 0]);
+        assert_eq!(unicode_subtable(Bytes(&cmap), 0), Some((30, 12)));
+        let cmap = words(&[0, 2, 1, 0, 0, 20, 3, 1, 0, 22, 0, 4]);
+        assert_eq!(unicode_subtable(Bytes(&cmap), 0), Some((22, 4)));
+        let cmap = words(&[0, 1, 1, 0, 0, 12, 0]);
+        assert_eq!(unicode_subtable(Bytes(&cmap), 0), None);
+    }
+
+    #[test]
+    fn kerns_from_the_kern_table() {
+        let font = fixture();
+        assert_eq!(font.kerning(2, 3), -10.0);
+        assert_eq!(font.kerning(3, 2), -8.0);
+        assert_eq!(font.kerning(2, 4), 0.0);
+    }
+
+    #[test]
+    fn rejects_incomplete_fonts() {
+        assert!(Font::from_data(vec![0; 12]).is_err());
+        assert!(Font::from_data(sfnt(&[(b"head", vec![0; 54])])).is_err());
+        // A table past the end of the file
+        let mut data = sfnt(&[(b"head", vec![0; 54])]);
+        data.truncate(40);
+        assert!(Font::from_data(data).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+//! Pair kerning from the `kern` feature of `GPOS`, or else from the older
+//! `kern` table.
+
+use super::bytes::Bytes;
+
+const PAIR_POS: u16 = 2;
+const EXTENSION_POS: u16 = 9;
+
+// Bits of value formats
+const X_PLACEMENT_Y_PLACEMENT: u16 = 0x0003;
+const X_ADVANCE: u16 = 0x0004;
+
+// Bits of the coverage of `kern` subtables
+const HORIZONTAL: u16 = 0x0001;
+const MINIMUM: u16 = 0x0002;
+const CROSS_STREAM: u16 = 0x0004;
+
+/// Kerning subtables, by absolute offsets into the font data.
+#[derive(Debug, Default)]
+pub struct Kerning {
+    /// Pair adjustment subtables of each `GPOS` lookup of the `kern`
+    /// feature.
+    lookups: Vec<Vec<usize>>,
+    /// Offset and count of the sorted pairs of `kern` subtables.
+    pairs: Vec<(usize, usize)>,
+}
+
+impl Kerning {
+    /// Finds the subtables of the tables at the given offsets and lengths.
+    pub fn parse(data: Bytes, gpos: Option<(usize, usize)>, kern: Option<(usize, usize)>) -> Kerning {
+        let lookups = gpos.map(|(gpos, _)| pair_lookups(data, gpos)).unwrap_or_default();
+        // The feature replaces the table where fonts have both
+        let pairs = match kern {
+            Some((kern, _)) if lookups.is_empty() => kern_pairs(data, kern),
+            _ => Vec::new(),
+        };
+
+        Kerning {
+            lookups: lookups,
+            pairs: pairs,
+        }
+    }
+
+    /// Adjustment of the advance of `left` when followed by `right`, in
+    /// font units.
+    pub fn get(&self, data: Bytes, left: u16, right: u16) -> i16 {
+        let mut value = 0i16;
+        for subtables in &self.lookups {
+            // The first subtable of a lookup matching the pair applies
+            if let Some(v) = subtables.iter().filter_map(|&s| pair_pos(data, s, left, right)).next() {
+                value = value.wrapping_add(v);
+            }
+        }
+        for &(pairs, count) in &self.pairs {
+            value = value.wrapping_add(kern_pair(data, pairs, count, left, right));
+        }
+        value
+    }
+}
+
+/// Pair adjustment subtables of the lookups of the `kern` feature.
+fn pair_lookups(data: Bytes, gpos: usize) -> Vec<Vec<usize>> {
+    let features = gpos + data.u16(gpos + 6) as usize;
+    let lookups = gpos + data.u16(gpos + 8) as usize;
+
+    let mut indices = Vec::new();
+    for idx in 0..data.u16(features) as usize {
+        let record = features + 2 + idx * 6;
+        if &data.tag(record) != b"kern" {
+            continue;
+        }
+        let feature = features + data.u16(record + 4) as usize;
+        for lookup in 0..data.u16(feature + 2) as usize {
+            indices.push(data.u16(feature + 4 + lookup * 2));
+        }
+    }
+    // Features for different scripts often share lookups
+    indices.sort();
+    indices.dedup();
+
+    indices.into_iter().filter_map(|idx| {
+        if idx >= data.u16(lookups) {
+            return None;
+        }
+        let lookup = lookups + data.u16(lookups + 2 + idx as usize * 2) as usize;
+        let lookup_type = data.u16(lookup);
+        let subtables = (0..data.u16(lookup + 4) as usize).filter_map(|s| {
+            let subtable = lookup + data.u16(lookup + 6 + s * 2) as usize;
+            match lookup_type {
+                PAIR_POS => Some(subtable),
+                // Extensions point further than 16 bits reach
+                EXTENSION_POS if data.u16(subtable + 2) == PAIR_POS =>
+                    Some(subtable + data.u32(subtable + 4) as usize),
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+        if subtables.is_empty() { None } else { Some(subtables) }
+    }).collect()
+}
+
+/// Advance adjustment of a pair adjustment subtable, or `None` if it does
+/// not apply to the pair.
+fn pair_pos(data: Bytes, subtable: usize, left: u16, right: u16) -> Option<i16> {
+    let covered = coverage(data, subtable + data.u16(subtable + 2) as usize, left)?;
+    let first_format = data.u16(subtable + 4);
+    let second_format = data.u16(subtable + 6);
+    let first_size = value_size(first_format);
+    let record_size = first_size + value_size(second_format);
+    // Only the advance of the first glyph kerns horizontal text
+    let x_advance = |record: usize| if first_format & X_ADVANCE != 0 {
+        data.i16(record + value_size(first_format & X_PLACEMENT_Y_PLACEMENT))
+    } else {
+        0
+    };
+
+    match data.u16(subtable) {
+        1 => {
+            // Sets of second glyphs sorted by glyph, for each first one
+            let set = subtable + data.u16(subtable + 10 + covered * 2) as usize;
+            let count = data.u16(set) as usize;
+            let stride = 2 + record_size;
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let mid = (low + high) / 2;
+                let record = set + 2 + mid * stride;
+                let glyph = data.u16(record);
+                if glyph < right {
+                    low = mid + 1;
+                } else if glyph > right {
+                    high = mid;
+                } else {
+                    return Some(x_advance(record + 2));
+                }
+            }
+            None
+        },
+        2 => {
+            let first_class = class(data, subtable + data.u16(subtable + 8) as usize, left);
+            let second_class = class(data, subtable + data.u16(subtable + 10) as usize, right);
+            let first_count = data.u16(subtable + 12) as usize;
+            let second_count = data.u16(subtable + 14) as usize;
+            if first_class >= first_count || second_class >= second_count {
+                return None;
+            }
+            let record = subtable + 16 + (first_class * second_count + second_class) * record_size;
+            Some(x_advance(record))
+        },
+        _ => None,
+    }
+}
+
+/// Bytes taken by a value record of `format`, two for each field.
+fn value_size(format: u16) -> usize {
+    format.count_ones() as usize * 2
+}
+
+/// Index of `glyph` in a coverage table, if covered.
+fn coverage(data: Bytes, table: usize, glyph: u16) -> Option<usize> {
+    let count = data.u16(table + 2) as usize;
+    match data.u16(table) {
+        1 => {
+            // Sorted glyphs
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let mid = (low + high) / 2;
+                let g = data.u16(table + 4 + mid * 2);
+                if g < glyph {
+                    low = mid + 1;
+                } else if g > glyph {
+                    high = mid;
+                } else {
+                    return Some(mid);
+                }
+            }
+            None
+        },
+        2 => {
+            // Sorted ranges with the index of their first glyph
+            range(data, table + 4, count, glyph).map(|record| {
+                data.u16(record + 4) as usize + (glyph - data.u16(record)) as usize
+            })
+        },
+        _ => None,
+    }
+}
+
+/// Class of `glyph` in a class definition table, 0 for glyphs not listed.
+fn class(data: Bytes, table: usize, glyph: u16) -> usize {
+    match data.u16(table) {
+        1 => {
+            let start = data.u16(table + 2);
+            let count = data.u16(table + 4);
+            if glyph >= start && glyph - start < count {
+                data.u16(table + 6 + (glyph - start) as usize * 2) as usize
+            } else {
+                0
+            }
+        },
+        2 => {
+            let count = data.u16(table + 2) as usize;
+            range(data, table + 4, count, glyph)
+                .map(|record| data.u16(record + 4) as usize)
+                .unwrap_or(0)
+        },
+        _ => 0,
+    }
+}
+
+/// Record containing `glyph` of `count` sorted six byte records of first
+/// glyph, last glyph and a value.
+fn range(data: Bytes, records: usize, count: usize, glyph: u16) -> Option<usize> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        let record = records + mid * 6;
+        if glyph < data.u16(record) {
+            high = mid;
+        } else if glyph > data.u16(record + 2) {
+            low = mid + 1;
+        } else {
+            return Some(record);
+        }
+    }
+    None
+}
+
+/// Pairs of the horizontal format 0 subtables of a `kern` table.
+fn kern_pairs(data: Bytes, kern: usize) -> Vec<(usize, usize)> {
+    // Apple's version 1 tables have a different header
+    if data.u16(kern) != 0 {
+        return Vec::new();
+    }
+
+    let mut pairs = Vec::new();
+    let mut subtable = kern + 4;
+    for _ in 0..data.u16(kern + 2) {
+        let len = data.u16(subtable + 2) as usize;
+        let coverage = data.u16(subtable + 4);
+        let format = coverage >> 8;
+        if format == 0 && coverage & (HORIZONTAL | MINIMUM | CROSS_STREAM) == HORIZONTAL {
+            pairs.push((subtable + 14, data.u16(subtable + 6) as usize));
+        }
+        if len == 0 {
+            break;
+        }
+        subtable += len;
+    }
+    pairs
+}
+
+/// Value of a pair of `count` sorted by left and then right glyph.
+fn kern_pair(data: Bytes, pairs: usize, count: usize, left: u16, right: u16) -> i16 {
+    let key = (left as u32) << 16 | right as u32;
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        let pair = pairs + mid * 6;
+        let k = data.u32(pair);
+        if k < key {
+            low = mid + 1;
+        } else if k > key {
+            high = mid;
+        } else {
+            return data.i16(pair + 4);
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::font::tests::words;
+
+    /// `GPOS` table of a `kern` feature with one lookup of two pair
+    /// adjustment subtables.
+    ///
+    /// The first lists the pairs of glyph 2, kerning it by -100 before 3
+    /// and by -50 before 4. The second covers glyphs 2 to 4 and kerns the
+    /// class of glyph 4 by -30 before the class of glyph 3, after an x
+    /// placement that must be skipped.
+    fn gpos() -> Vec<u8> {
+        words(&[1, 0, 0, 10, 24,
+                // Feature list and the feature
+                1, 0x6b65, 0x726e, 8,
+                0, 1, 0,
+                // Lookup list and the lookup
+                1, 4,
+                PAIR_POS, 0, 2, 10, 38,
+                // Format 1, its pair set and coverage
+                1, 22, X_ADVANCE, 0, 1, 12,
+                2, 3, -100i16 as u16, 4, -50i16 as u16,
+                1, 1, 2,
+                // Format 2, its coverage and classes
+                2, 32, X_ADVANCE | 0x0001, 0, 42, 50, 2, 2,
+                0, 0, 0, 0, 0, 0, 7, -30i16 as u16,
+                2, 1, 2, 4, 0,
+                1, 4, 1, 1,
+                2, 1, 3, 3, 1])
+    }
+
+    /// `kern` table of a horizontal subtable kerning glyph 2 by -80 before
+    /// 3 and 4 by -20 before 2, and a subtable of minimums to ignore.
+    fn kern() -> Vec<u8> {
+        words(&[0, 2,
+                0, 26, HORIZONTAL, 2, 12, 1, 0,
+                2, 3, -80i16 as u16,
+                4, 2, -20i16 as u16,
+                0, 20, HORIZONTAL | MINIMUM, 1, 6, 0, 0,
+                2, 3, -999i16 as u16])
+    }
+
+    #[test]
+    fn kerns_pairs_of_the_kern_table() {
+        let data = kern();
+        let kerning = Kerning::parse(Bytes(&data), None, Some((0, data.len())));
+        assert_eq!(kerning.get(Bytes(&data), 2, 3), -80);
+        assert_eq!(kerning.get(Bytes(&data), 4, 2), -20);
+        assert_eq!(kerning.get(Bytes(&data), 3, 2), 0);
+        assert_eq!(kerning.get(Bytes(&data), 2, 4), 0);
+    }
+
+    #[test]
+    fn kerns_pairs_of_gpos() {
+        let data = gpos();
+        let kerning = Kerning::parse(Bytes(&data), Some((0, data.len())), None);
+        let get = |left, right| kerning.get(Bytes(&data), left, right);
+        // Glyph 2 is covered by both subtables, but the first applies
+        assert_eq!(get(2, 3), -100);
+        assert_eq!(get(2, 4), -50);
+        assert_eq!(get(4, 3), -30);
+        assert_eq!(get(3, 3), 0);
+        assert_eq!(get(4, 4), 0);
+        assert_eq!(get(5, 3), 0);
+    }
+
+    #[test]
+    fn gpos_replaces_the_kern_table() {
+        let mut data = gpos();
+        let gpos_len = data.len();
+        data.extend(kern());
+        let kerning = Kerning::parse(Bytes(&data),
+                                     Some((0, gpos_len)),
+                                     Some((gpos_len, data.len() - gpos_len)));
+        assert_eq!(kerning.get(Bytes(&data), 2, 3), -100);
+        assert_eq!(kerning.get(Bytes(&data), 4, 2), 0);
+    }
+}
@@ -0,0 +1,174 @@
+use super::font::Font;
+
+/// Glyph of a layout with the pen position on its baseline, in pixels
+/// from the top left corner of the text with y down.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionedGlyph {
+    pub glyph: u16,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Glyphs of a text placed in lines.
+#[derive(Clone, Debug, Default)]
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph>,
+    /// Width of the widest line, without trailing spaces.
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Lays out `text` with `font` at `size` pixels per em, kerning pairs of
+/// glyphs.
+///
+/// Lines break at newlines and, given `max_width`, between words to keep
+/// lines narrower. Words wider than `max_width` get a line of their own.
+pub fn layout(font: &Font, text: &str, size: f32, max_width: Option<f32>) -> TextLayout {
+    let scale = font.scale(size);
+    let metrics = font.metrics();
+    let line_height = metrics.line_height() * scale;
+
+    let mut layout = TextLayout::default();
+    let mut baseline = metrics.ascender * scale;
+    for paragraph in text.split('\n') {
+        let paragraph = paragraph.trim_right_matches('\r');
+        let mut pen = 0.0;
+        let mut previous = None;
+
+        for word in words(paragraph) {
+            let mut placed = place(font, word, scale, pen, previous);
+            let wraps = match max_width {
+                Some(max_width) => pen > 0.0 && placed.ink > max_width,
+                None => false,
+            };
+            if wraps {
+                baseline += line_height;
+                placed = place(font, word, scale, 0.0, None);
+            }
+
+            layout.glyphs.extend(placed.glyphs.iter().map(|&(glyph, x)| PositionedGlyph {
+                glyph: glyph,
+                x: x,
+                y: baseline,
+            }));
+            layout.width = layout.width.max(placed.ink);
+            pen = placed.pen;
+            previous = placed.last;
+        }
+        baseline += line_height;
+    }
+    layout.height = baseline - metrics.ascender * scale;
+    layout
+}
+
+/// Glyphs of a word placed from `pen`.
+struct Placed {
+    glyphs: Vec<(u16, f32)>,
+    /// Pen position after the word.
+    pen: f32,
+    /// Right edge of the last character that is not whitespace.
+    ink: f32,
+    last: Option<u16>,
+}
+
+fn place(font: &Font, word: &str, scale: f32, mut pen: f32, mut previous: Option<u16>) -> Placed {
+    let mut glyphs = Vec::with_capacity(word.len());
+    let mut ink = 0.0;
+    for c in word.chars() {
+        let glyph = font.glyph(c);
+        if let Some(previous) = previous {
+            pen += font.kerning(previous, glyph) * scale;
+        }
+        glyphs.push((glyph, pen));
+        pen += font.advance(glyph) * scale;
+        if !c.is_whitespace() {
+            ink = pen;
+        }
+        previous = Some(glyph);
+    }
+
+    Placed {
+        glyphs: glyphs,
+        pen: pen,
+        ink: ink,
+        last: previous,
+    }
+}
+
+/// Splits `text` into words, each with the whitespace after it.
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut after_space = false;
+    for (idx, c) in text.char_indices() {
+        if after_space && !c.is_whitespace() {
+            words.push(&text[start..idx]);
+            start = idx;
+        }
+        after_space = c.is_whitespace();
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::font::tests::fixture;
+
+    /// Glyphs and pen positions of a layout at size 100, in units of the
+    /// fixture font.
+    fn lay_out(text: &str, max_width: Option<f32>) -> (Vec<(u16, f32, f32)>, TextLayout) {
+        let layout = layout(&fixture(), text, 100.0, max_width);
+        (layout.glyphs.iter().map(|g| (g.glyph, g.x, g.y)).collect(), layout)
+    }
+
+    #[test]
+    fn kerns_pairs_of_glyphs() {
+        let (glyphs, layout) = lay_out("AVA", None);
+        assert_eq!(glyphs, [(2, 0.0, 80.0), (3, 50.0, 80.0), (2, 102.0, 80.0)]);
+        assert_eq!(layout.width, 162.0);
+        assert_eq!(layout.height, 100.0);
+    }
+
+    #[test]
+    fn breaks_lines_at_newlines() {
+        let (glyphs, layout) = lay_out("A\nV\r\nB", None);
+        // Pairs are not kerned across lines
+        assert_eq!(glyphs, [(2, 0.0, 80.0), (3, 0.0, 180.0), (4, 0.0, 280.0)]);
+        assert_eq!(layout.width, 60.0);
+        assert_eq!(layout.height, 300.0);
+
+        let (glyphs, layout) = lay_out("A\n\n", None);
+        assert_eq!(glyphs, [(2, 0.0, 80.0)]);
+        assert_eq!(layout.height, 300.0);
+    }
+
+    #[test]
+    fn wraps_words_wider_than_the_line() {
+        let (glyphs, layout) = lay_out("AB AB AB", None);
+        assert_eq!(glyphs.len(), 8);
+        assert!(glyphs.iter().all(|g| g.2 == 80.0));
+        assert_eq!(layout.width, 410.0);
+
+        // Spaces stay at the end of the line they follow
+        let (glyphs, layout) = lay_out("AB AB AB", Some(300.0));
+        let lines = glyphs.iter().map(|g| (g.1, g.2)).collect::<Vec<_>>();
+        assert_eq!(lines, [(0.0, 80.0), (60.0, 80.0), (120.0, 80.0),
+                           (145.0, 80.0), (205.0, 80.0), (265.0, 80.0),
+                           (0.0, 180.0), (60.0, 180.0)]);
+        assert_eq!(layout.width, 265.0);
+        assert_eq!(layout.height, 200.0);
+    }
+
+    #[test]
+    fn gives_long_words_their_own_line() {
+        let (glyphs, layout) = lay_out("AB A", Some(50.0));
+        assert_eq!(glyphs, [(2, 0.0, 80.0), (4, 60.0, 80.0), (1, 120.0, 80.0),
+                            (2, 0.0, 180.0)]);
+        assert_eq!(layout.width, 120.0);
+        assert_eq!(layout.height, 200.0);
+    }
+}
@@ -0,0 +1,394 @@
+//! Text drawn with TrueType and OpenType fonts.
+//!
+//! Glyph outlines are rasterized on the CPU the first time they are drawn
+//! at a size and packed into an atlas texture. Text is laid out with the
+//! kerning of its font and wrapped at word boundaries.
+//!
+//! Like debug drawing, text is queued while updating a frame. `prepare`
+//! lays it out and records the upload of new glyphs before the passes, and
+//! the next renders draw it: screen text in pixels over the UI pass, world
+//! text as labels facing the camera in the transparent subpass. World text
+//! is rasterized at about the size it covers on screen.
+
+use cgmath::{Matrix4, Point3, Transform, Vector3};
+
+use vulkano::buffer as vkb;
+use vulkano::command_buffer as vkcb;
+use vulkano::descriptor::descriptor_set as vkds;
+use vulkano::pipeline as vkp;
+use vulkano::sampler as vksm;
+
+use std::mem;
+use std::sync::Arc;
+
+use super::framework::gfx;
+use super::renderer::Camera;
+
+pub mod atlas;
+mod bytes;
+mod cff;
+pub mod font;
+mod kerning;
+pub mod layout;
+pub mod outline;
+mod pipeline;
+pub mod raster;
+
+pub use self::font::{Font, Metrics};
+pub use self::layout::{layout, TextLayout};
+
+use self::atlas::{Atlas, GlyphKey};
+use self::pipeline::{GlyphQuad, TextPipeline};
+
+/// Width and height of the glyph atlas in texels.
+const ATLAS_SIZE: u32 = 1024;
+
+/// Smallest and largest sizes in pixels per em world text is rasterized
+/// at. Sizes in between are rounded to a multiple of the smallest so text
+/// moving in depth does not fill the atlas with every size.
+const MIN_WORLD_PIXELS: f32 = 8.0;
+const MAX_WORLD_PIXELS: f32 = 128.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FontId(usize);
+
+struct ScreenText {
+    font: FontId,
+    text: String,
+    position: [f32; 2],
+    size: f32,
+    color: [f32; 4],
+    max_width: Option<f32>,
+}
+
+struct WorldText {
+    font: FontId,
+    position: Point3<f32>,
+    text: String,
+    size: f32,
+    color: [f32; 4],
+}
+
+/// Fonts, their glyph atlas and the text of the current frame.
+pub struct TextRenderer {
+    gfx: Arc<gfx::Core>,
+    screen_pipeline: Arc<TextPipeline>,
+    world_pipeline: Arc<TextPipeline>,
+    glyph_pool: vkb::CpuBufferPool<GlyphQuad>,
+    sampler: Arc<vksm::Sampler>,
+    fonts: Vec<Font>,
+    atlas: Atlas,
+    screen: Vec<ScreenText>,
+    world: Vec<WorldText>,
+    /// Quads laid out by `prepare` for the next renders.
+    screen_quads: Vec<GlyphQuad>,
+    world_quads: Vec<GlyphQuad>,
+}
+
+impl TextRenderer {
+    pub fn new(gfx: Arc<gfx::Core>) -> Result<TextRenderer, ()> {
+        let usage = vkb::BufferUsage {
+            storage_buffer: true,
+            .. vkb::BufferUsage::none()
+        };
+        let sampler = vksm::Sampler::new(gfx.device.clone(),
+                                         vksm::Filter::Linear,
+                                         vksm::Filter::Linear,
+                                         vksm::MipmapMode::Nearest,
+                                         vksm::SamplerAddressMode::ClampToEdge,
+                                         vksm::SamplerAddressMode::ClampToEdge,
+                                         vksm::SamplerAddressMode::ClampToEdge,
+                                         0.0, 1.0, 0.0, 0.0)
+            .map_err(|e| println!("Failed to create glyph sampler ({:?})", e))?;
+
+        Ok(TextRenderer {
+            screen_pipeline: pipeline::screen_pipeline(&gfx)?,
+            world_pipeline: pipeline::world_pipeline(&gfx)?,
+            glyph_pool: vkb::CpuBufferPool::new(gfx.device.clone(), usage),
+            sampler: sampler,
+            fonts: Vec::new(),
+            atlas: Atlas::new(ATLAS_SIZE),
+            screen: Vec::new(),
+            world: Vec::new(),
+            screen_quads: Vec::new(),
+            world_quads: Vec::new(),
+            gfx: gfx,
+        })
+    }
+
+    pub fn add_font(&mut self, font: Font) -> FontId {
+        self.fonts.push(font);
+        FontId(self.fonts.len() - 1)
+    }
+
+    pub fn font(&self, id: FontId) -> &Font {
+        &self.fonts[id.0]
+    }
+
+    /// Layout `screen_text` gives `text`, to measure it.
+    pub fn layout(&self, font: FontId, text: &str, size: f32, max_width: Option<f32>) -> TextLayout {
+        layout(&self.fonts[font.0], text, screen_pixels(size) as f32, max_width)
+    }
+
+    /// Text with its top left corner at `position` in window pixels, `size`
+    /// pixels per em and wrapped to `max_width` pixels if given.
+    pub fn screen_text(&mut self,
+                       font: FontId,
+                       position: [f32; 2],
+                       text: &str,
+                       size: f32,
+                       color: [f32; 4],
+                       max_width: Option<f32>) {
+        self.screen.push(ScreenText {
+            font: font,
+            text: text.to_owned(),
+            position: position,
+            size: size,
+            color: color,
+            max_width: max_width,
+        });
+    }
+
+    /// Text facing the camera, centered on `position`, `size` world units
+    /// per em.
+    pub fn world_text(&mut self,
+                      font: FontId,
+                      position: Point3<f32>,
+                      text: &str,
+                      size: f32,
+                      color: [f32; 4]) {
+        self.world.push(WorldText {
+            font: font,
+            position: position,
+            text: text.to_owned(),
+            size: size,
+            color: color,
+        });
+    }
+
+    /// Lays out the text queued since the last call and records the upload
+    /// of its new glyphs into `builder`, which must be outside of a render
+    /// pass. World text is sized for `camera` and `dynamic`, the state it is
+    /// drawn with.
+    ///
+    /// If the glyphs do not all fit in the atlas, it is cleared and the text
+    /// laid out again, so glyphs of earlier frames make room for those of
+    /// this one.
+    pub fn prepare(&mut self,
+                   builder: vkcb::AutoCommandBufferBuilder,
+                   camera: &Camera,
+                   dynamic: &vkcb::DynamicState)
+                   -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let screen = mem::replace(&mut self.screen, Vec::new());
+        let world = mem::replace(&mut self.world, Vec::new());
+        let viewport_height = dynamic.viewports.as_ref()
+            .and_then(|v| v.first())
+            .map(|v| v.dimensions[1])
+            .unwrap_or(1.0);
+        // Camera distance at which a world unit covers a pixel
+        let pixel_distance = camera.proj.y.y.abs() * viewport_height * 0.5;
+
+        {
+            let fonts = &self.fonts;
+            let build = |atlas: &mut Atlas| {
+                (screen_quads(fonts, atlas, &screen),
+                 world_quads(fonts, atlas, &world, camera, pixel_distance))
+            };
+            let (mut screen_quads, mut world_quads) = build(&mut self.atlas);
+            if self.atlas.overflowed() {
+                self.atlas.clear();
+                let quads = build(&mut self.atlas);
+                screen_quads = quads.0;
+                world_quads = quads.1;
+                if self.atlas.overflowed() {
+                    println!("Glyph atlas is too small for the text of a frame");
+                }
+            }
+            self.screen_quads = screen_quads;
+            self.world_quads = world_quads;
+        }
+
+        self.atlas.upload(builder, self.gfx.queue.clone())
+    }
+
+    /// Records the world text prepared last into a secondary command buffer
+    /// for the transparent subpass. Returns `None` if there was none.
+    pub fn render_world(&mut self,
+                        camera: &Camera,
+                        dynamic: &vkcb::DynamicState)
+                        -> Result<Option<vkcb::AutoCommandBuffer>, ()> {
+        let quads = mem::replace(&mut self.world_quads, Vec::new());
+        if quads.is_empty() {
+            return Ok(None);
+        }
+
+        // The rows of the view rotation are the camera axes in world space.
+        // Projections flipping y put view space up at the bottom of the
+        // screen, and the y axis of text goes down
+        let view = camera.view;
+        let flip = if camera.proj.y.y > 0.0 { 1.0 } else { -1.0 };
+        let transform = pipeline::Transform {
+            view_proj: (camera.proj * view).into(),
+            axis_x: [view.x.x, view.y.x, view.z.x, 0.0],
+            axis_y: [view.x.y * flip, view.y.y * flip, view.z.y * flip, 0.0],
+        };
+
+        let subpass = self.gfx.scene_graph.subpass(self.gfx.transparent_pass);
+        let builder = vkcb::AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+            self.gfx.device.clone(),
+            self.gfx.queue.family(),
+            subpass
+        ).map_err(|e| println!("Failed to create text command buffer ({:?})", e))?;
+
+        let pipeline = self.world_pipeline.clone();
+        self.draw(builder, pipeline, dynamic, quads, transform)?
+            .build()
+            .map(Some)
+            .map_err(|e| println!("Failed to build text command buffer ({:?})", e))
+    }
+
+    /// Draws the screen text prepared last, inline in the UI pass.
+    pub fn render_screen(&mut self,
+                         builder: vkcb::AutoCommandBufferBuilder,
+                         dynamic: &vkcb::DynamicState)
+                         -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let quads = mem::replace(&mut self.screen_quads, Vec::new());
+        if quads.is_empty() {
+            return Ok(builder);
+        }
+
+        // Window pixels with y down to clip space
+        let dims = *self.gfx.dimensions.read().unwrap();
+        let view_proj = Matrix4::from_translation(Vector3::new(-1.0, -1.0, 0.0)) *
+            Matrix4::from_nonuniform_scale(2.0 / dims.width as f32, 2.0 / dims.height as f32, 1.0);
+        let transform = pipeline::Transform {
+            view_proj: view_proj.into(),
+            axis_x: [1.0, 0.0, 0.0, 0.0],
+            axis_y: [0.0, 1.0, 0.0, 0.0],
+        };
+
+        let pipeline = self.screen_pipeline.clone();
+        self.draw(builder, pipeline, dynamic, quads, transform)
+    }
+
+    fn draw(&mut self,
+            builder: vkcb::AutoCommandBufferBuilder,
+            pipeline: Arc<TextPipeline>,
+            dynamic: &vkcb::DynamicState,
+            quads: Vec<GlyphQuad>,
+            transform: pipeline::Transform)
+            -> Result<vkcb::AutoCommandBufferBuilder, ()> {
+        let atlas = self.atlas.image()
+            .ok_or_else(|| println!("Text was drawn before it was prepared"))?;
+        let count = quads.len();
+        let buffer = self.glyph_pool.chunk(quads.into_iter())
+            .map_err(|e| println!("Failed to allocate glyphs ({:?})", e))?;
+        let set = vkds::PersistentDescriptorSet::start(pipeline.clone(), 0)
+            .add_buffer(buffer)
+            .and_then(|s| s.add_sampled_image(atlas, self.sampler.clone()))
+            .map_err(|e| println!("Failed to bind glyphs ({:?})", e))?
+            .build()
+            .map_err(|e| println!("Failed to create glyph set ({:?})", e))?;
+
+        builder.draw(pipeline,
+                     dynamic.clone(),
+                     vkp::vertex::BufferlessVertices {
+                         vertices: count * 6,
+                         instances: 1,
+                     },
+                     set,
+                     transform)
+            .map_err(|e| println!("Failed to draw text ({:?})", e))
+    }
+}
+
+/// Whole pixels per em screen text of `size` is laid out and rasterized at.
+fn screen_pixels(size: f32) -> u32 {
+    size.round().max(1.0) as u32
+}
+
+/// Quads of screen text, rasterizing its glyphs into `atlas` as needed.
+fn screen_quads(fonts: &[Font], atlas: &mut Atlas, texts: &[ScreenText]) -> Vec<GlyphQuad> {
+    let mut quads = Vec::new();
+    for text in texts {
+        let font = &fonts[text.font.0];
+        let pixels = screen_pixels(text.size);
+        let layout = layout(font, &text.text, pixels as f32, text.max_width);
+        // Whole pixels keep glyphs as sharp as they were rasterized
+        let anchor = [text.position[0].round(), text.position[1].round(), 0.0, 1.0];
+        for (rect, uv) in glyph_rects(font, text.font, atlas, &layout, pixels, true) {
+            quads.push(GlyphQuad {
+                anchor: anchor,
+                rect: rect,
+                uv: uv,
+                color: text.color,
+            });
+        }
+    }
+    quads
+}
+
+/// Quads of world text seen from `camera`, which covers a pixel with a
+/// world unit at `pixel_distance`.
+fn world_quads(fonts: &[Font],
+               atlas: &mut Atlas,
+               texts: &[WorldText],
+               camera: &Camera,
+               pixel_distance: f32) -> Vec<GlyphQuad> {
+    let mut quads = Vec::new();
+    for text in texts {
+        // View space z is negative in front of the camera
+        let distance = -camera.view.transform_point(text.position).z;
+        if distance <= 0.0 {
+            continue;
+        }
+        let pixels = text.size * pixel_distance / distance;
+        let pixels = (pixels / MIN_WORLD_PIXELS).round() * MIN_WORLD_PIXELS;
+        let pixels = pixels.max(MIN_WORLD_PIXELS).min(MAX_WORLD_PIXELS) as u32;
+
+        let font = &fonts[text.font.0];
+        let layout = layout(font, &text.text, pixels as f32, None);
+        let scale = text.size / pixels as f32;
+        let center = [layout.width * 0.5, layout.height * 0.5];
+        for (rect, uv) in glyph_rects(font, text.font, atlas, &layout, pixels, false) {
+            quads.push(GlyphQuad {
+                anchor: [text.position.x, text.position.y, text.position.z, 1.0],
+                rect: [(rect[0] - center[0]) * scale, (rect[1] - center[1]) * scale,
+                       (rect[2] - center[0]) * scale, (rect[3] - center[1]) * scale],
+                uv: uv,
+                color: text.color,
+            });
+        }
+    }
+    quads
+}
+
+/// Rectangles in pixels from the origin of `layout` and texture coordinates
+/// of its glyphs that are not blank, rasterizing them into `atlas` as
+/// needed. `snap` moves glyphs to whole pixels.
+fn glyph_rects(font: &Font,
+               id: FontId,
+               atlas: &mut Atlas,
+               layout: &TextLayout,
+               pixels: u32,
+               snap: bool)
+               -> Vec<([f32; 4], [f32; 4])> {
+    let atlas_size = atlas.size() as f32;
+    let scale = font.scale(pixels as f32);
+    layout.glyphs.iter().filter_map(|g| {
+        let key = GlyphKey {
+            font: id,
+            glyph: g.glyph,
+            size: pixels,
+        };
+        let placed = atlas.glyph(key, || raster::rasterize(&font.outline(g.glyph), scale))?;
+
+        let (x, y) = if snap { (g.x.round(), g.y.round()) } else { (g.x, g.y) };
+        let left = x + placed.left as f32;
+        let top = y - placed.top as f32;
+        let t = placed.texels;
+        Some(([left, top, left + t[2] as f32, top + t[3] as f32],
+              [t[0] as f32 / atlas_size, t[1] as f32 / atlas_size,
+               (t[0] + t[2]) as f32 / atlas_size, (t[1] + t[3]) as f32 / atlas_size]))
+    }).collect()
+}
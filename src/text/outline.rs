@@ -0,0 +1,100 @@
+/// Piece of a glyph contour, in font units with y up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Line([f32; 2], [f32; 2]),
+    /// Quadratic Bézier curve, as in TrueType outlines.
+    Quadratic([f32; 2], [f32; 2], [f32; 2]),
+    /// Cubic Bézier curve, as in CFF outlines.
+    Cubic([f32; 2], [f32; 2], [f32; 2], [f32; 2]),
+}
+
+/// Closed contours of a glyph. Filled by the non-zero rule.
+#[derive(Clone, Debug, Default)]
+pub struct Outline {
+    pub segments: Vec<Segment>,
+}
+
+impl Outline {
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Smallest and largest coordinates of the control points, which
+    /// contain the curves.
+    pub fn bounds(&self) -> Option<([f32; 2], [f32; 2])> {
+        let mut points = self.segments.iter().flat_map(|s| {
+            let points = match *s {
+                Segment::Line(a, b) => vec![a, b],
+                Segment::Quadratic(a, b, c) => vec![a, b, c],
+                Segment::Cubic(a, b, c, d) => vec![a, b, c, d],
+            };
+            points.into_iter()
+        });
+        let first = points.next()?;
+
+        Some(points.fold((first, first), |(min, max), p| {
+            ([min[0].min(p[0]), min[1].min(p[1])],
+             [max[0].max(p[0]), max[1].max(p[1])])
+        }))
+    }
+
+    /// Adds the segments of `other` transformed by the 2x2 matrix `m`,
+    /// given by columns, followed by `offset`.
+    pub fn append(&mut self, other: &Outline, m: [[f32; 2]; 2], offset: [f32; 2]) {
+        let t = |p: [f32; 2]| [m[0][0] * p[0] + m[1][0] * p[1] + offset[0],
+                               m[0][1] * p[0] + m[1][1] * p[1] + offset[1]];
+        self.segments.extend(other.segments.iter().map(|s| match *s {
+            Segment::Line(a, b) => Segment::Line(t(a), t(b)),
+            Segment::Quadratic(a, b, c) => Segment::Quadratic(t(a), t(b), t(c)),
+            Segment::Cubic(a, b, c, d) => Segment::Cubic(t(a), t(b), t(c), t(d)),
+        }));
+    }
+}
+
+/// Builds an outline from pen movements, closing each contour with a line
+/// back to its start.
+#[derive(Debug, Default)]
+pub struct PathBuilder {
+    outline: Outline,
+    start: [f32; 2],
+    current: [f32; 2],
+}
+
+impl PathBuilder {
+    pub fn new() -> PathBuilder {
+        PathBuilder::default()
+    }
+
+    pub fn move_to(&mut self, p: [f32; 2]) {
+        self.close();
+        self.start = p;
+        self.current = p;
+    }
+
+    pub fn line_to(&mut self, p: [f32; 2]) {
+        if p != self.current {
+            self.outline.segments.push(Segment::Line(self.current, p));
+        }
+        self.current = p;
+    }
+
+    pub fn quad_to(&mut self, control: [f32; 2], p: [f32; 2]) {
+        self.outline.segments.push(Segment::Quadratic(self.current, control, p));
+        self.current = p;
+    }
+
+    pub fn cubic_to(&mut self, c0: [f32; 2], c1: [f32; 2], p: [f32; 2]) {
+        self.outline.segments.push(Segment::Cubic(self.current, c0, c1, p));
+        self.current = p;
+    }
+
+    pub fn close(&mut self) {
+        let start = self.start;
+        self.line_to(start);
+    }
+
+    pub fn build(mut self) -> Outline {
+        self.close();
+        self.outline
+    }
+}
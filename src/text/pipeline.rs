@@ -0,0 +1,150 @@
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::framebuffer as vkfb;
+use vulkano::pipeline as vkp;
+use vulkano::pipeline::depth_stencil as vkdst;
+
+use std::sync::Arc;
+
+use super::super::framework::gfx;
+use super::super::framework::gfx::PipelineBuilderExt;
+
+/// Pipeline drawing glyph quads read from a storage buffer. Its concrete
+/// type is kept since vulkano only draws bufferless pipelines it can see
+/// the vertex definition of.
+pub type TextPipeline = vkp::GraphicsPipeline<vkp::vertex::BufferlessDefinition,
+                                              Box<PipelineLayoutAbstract + Send + Sync>,
+                                              Arc<vkfb::RenderPassAbstract + Send + Sync>>;
+
+/// Glyph to draw, laid out as the `Glyph` struct of the text shader.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GlyphQuad {
+    /// Origin of the text the glyph belongs to.
+    pub anchor: [f32; 4],
+    /// Left, top, right and bottom edges, along the axes of the text.
+    pub rect: [f32; 4],
+    /// Left, top, right and bottom texture coordinates in the atlas.
+    pub uv: [f32; 4],
+    pub color: [f32; 4],
+}
+
+/// Where the glyphs of a draw end up, laid out as the `Transform` push
+/// constant.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub view_proj: [[f32; 4]; 4],
+    /// Directions of the x axis of the text, to the right, and of its y
+    /// axis, downwards, in the space `view_proj` transforms.
+    pub axis_x: [f32; 4],
+    pub axis_y: [f32; 4],
+}
+
+/// Pipeline for screen space text, drawn inline in the UI pass over
+/// everything.
+pub fn screen_pipeline(core: &gfx::Core) -> Result<Arc<TextPipeline>, ()> {
+    pipeline(core, core.ui_graph.subpass(core.ui_pass), vkdst::DepthStencil::disabled())
+}
+
+/// Pipeline for text in the scene, drawn in the transparent subpass.
+///
+/// Text is depth tested against the scene but does not write depth, so
+/// overlapping glyphs all blend.
+pub fn world_pipeline(core: &gfx::Core) -> Result<Arc<TextPipeline>, ()> {
+    let depth = vkdst::DepthStencil {
+        depth_compare: vkdst::Compare::LessOrEqual,
+        depth_write: false,
+        .. vkdst::DepthStencil::simple_depth_test()
+    };
+    pipeline(core, core.scene_graph.subpass(core.transparent_pass), depth)
+}
+
+fn pipeline(core: &gfx::Core,
+            subpass: vkfb::Subpass<Arc<vkfb::RenderPassAbstract + Send + Sync>>,
+            depth: vkdst::DepthStencil)
+            -> Result<Arc<TextPipeline>, ()> {
+    let vs = text_vs::Shader::load(core.device.clone())
+        .map_err(|e| println!("Failed to load text vertex shader ({:?})", e))?;
+    let fs = text_fs::Shader::load(core.device.clone())
+        .map_err(|e| println!("Failed to load text fragment shader ({:?})", e))?;
+
+    let pipeline = vkp::GraphicsPipeline::start()
+        .vertex_input(vkp::vertex::BufferlessDefinition)
+        .vertex_shader(vs.main_entry_point(), ())
+        .topology(gfx::Topology::TriangleList)
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fs.main_entry_point(), ())
+        .depth_stencil(depth)
+        .blend(gfx::BlendMode::Alpha)
+        .render_pass(subpass)
+        .build(core.device.clone())
+        .map_err(|e| println!("Failed to create text pipeline ({:?})", e))?;
+
+    Ok(Arc::new(pipeline))
+}
+
+mod text_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_color;
+
+struct Glyph {
+    vec4 anchor;
+    vec4 rect;
+    vec4 uv;
+    vec4 color;
+};
+
+layout(set = 0, binding = 0) readonly buffer Glyphs {
+    Glyph glyphs[];
+};
+
+layout(push_constant) uniform Transform {
+    mat4 view_proj;
+    vec4 axis_x;
+    vec4 axis_y;
+} transform;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    Glyph g = glyphs[gl_VertexIndex / 6];
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+
+    vec2 offset = mix(g.rect.xy, g.rect.zw, corner);
+    vec3 position = g.anchor.xyz + transform.axis_x.xyz * offset.x + transform.axis_y.xyz * offset.y;
+
+    v_uv = mix(g.uv.xy, g.uv.zw, corner);
+    v_color = g.color;
+    gl_Position = transform.view_proj * vec4(position, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod text_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 1) uniform sampler2D atlas;
+
+void main() {
+    // The atlas holds the coverage of each texel
+    f_color = vec4(v_color.rgb, v_color.a * texture(atlas, v_uv).r);
+}
+"]
+    struct Dummy;
+}
@@ -0,0 +1,171 @@
+//! Coverage rasterization of glyph outlines.
+//!
+//! Curves are flattened to lines, each line adds the signed area it covers
+//! to the cells it crosses and to the difference with the cell after, and
+//! summing each row from left to right gives the coverage of every pixel.
+
+use super::outline::{Outline, Segment};
+
+/// Most lines a curve is flattened to.
+const MAX_CURVE_LINES: u32 = 64;
+
+/// Antialiased coverage of a glyph, rows from the top.
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels from the pen position to the left edge.
+    pub left: i32,
+    /// Pixels from the baseline up to the top edge.
+    pub top: i32,
+    pub pixels: Vec<u8>,
+}
+
+/// Rasterizes `outline` at `scale` pixels per font unit, with a blank
+/// pixel around it. Returns `None` for empty outlines.
+pub fn rasterize(outline: &Outline, scale: f32) -> Option<Bitmap> {
+    let (min, max) = outline.bounds()?;
+    let left = (min[0] * scale).floor() as i32 - 1;
+    let bottom = (min[1] * scale).floor() as i32 - 1;
+    let right = (max[0] * scale).ceil() as i32 + 1;
+    let top = (max[1] * scale).ceil() as i32 + 1;
+    let width = (right - left) as usize;
+    let height = (top - bottom) as usize;
+
+    // Flip y so rows go down, in pixels from the corner of the bitmap
+    let point = |p: [f32; 2]| [p[0] * scale - left as f32, top as f32 - p[1] * scale];
+    let mut accumulator = Accumulator {
+        width: width,
+        height: height,
+        cells: vec![0.0; width * height + 1],
+    };
+    for segment in &outline.segments {
+        match *segment {
+            Segment::Line(a, b) => accumulator.line(point(a), point(b)),
+            Segment::Quadratic(a, b, c) => {
+                let (a, b, c) = (point(a), point(b), point(c));
+                let lines = curve_lines(deviation(a, b, c));
+                let mut from = a;
+                for idx in 1..lines + 1 {
+                    let t = idx as f32 / lines as f32;
+                    let to = lerp(lerp(a, b, t), lerp(b, c, t), t);
+                    accumulator.line(from, to);
+                    from = to;
+                }
+            },
+            Segment::Cubic(a, b, c, d) => {
+                let (a, b, c, d) = (point(a), point(b), point(c), point(d));
+                let lines = curve_lines(deviation(a, b, c).max(deviation(b, c, d)) * 1.5);
+                let mut from = a;
+                for idx in 1..lines + 1 {
+                    let t = idx as f32 / lines as f32;
+                    let (ab, bc, cd) = (lerp(a, b, t), lerp(b, c, t), lerp(c, d, t));
+                    let to = lerp(lerp(ab, bc, t), lerp(bc, cd, t), t);
+                    accumulator.line(from, to);
+                    from = to;
+                }
+            },
+        }
+    }
+
+    // Contours wound either way fill, overlapping ones do not add up
+    let mut coverage = 0.0;
+    let pixels = accumulator.cells[..width * height].iter().map(|&cell| {
+        coverage += cell;
+        (coverage.abs().min(1.0) * 255.0 + 0.5) as u8
+    }).collect();
+
+    Some(Bitmap {
+        width: width as u32,
+        height: height as u32,
+        left: left,
+        top: top,
+        pixels: pixels,
+    })
+}
+
+fn lerp(a: [f32; 2], b: [f32; 2], t: f32) -> [f32; 2] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+}
+
+/// How far the middle control point bends the curve from the line between
+/// the other two.
+fn deviation(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+    let x = a[0] - 2.0 * b[0] + c[0];
+    let y = a[1] - 2.0 * b[1] + c[1];
+    (x * x + y * y).sqrt()
+}
+
+/// Lines keeping a curve within a quarter of a pixel of them.
+fn curve_lines(deviation: f32) -> u32 {
+    // Flattening to n lines strays up to deviation / (8 n^2)
+    ((deviation * 0.5).sqrt().ceil() as u32).max(1).min(MAX_CURVE_LINES)
+}
+
+struct Accumulator {
+    width: usize,
+    height: usize,
+    /// Change of coverage from the cell before, row after row, with a
+    /// spare cell for lines touching the last column.
+    cells: Vec<f32>,
+}
+
+impl Accumulator {
+    fn line(&mut self, from: [f32; 2], to: [f32; 2]) {
+        if from[1] == to[1] {
+            return;
+        }
+        // Lines going down add coverage, lines going up remove it
+        let (direction, from, to) = if from[1] < to[1] { (1.0, from, to) } else { (-1.0, to, from) };
+        let dxdy = (to[0] - from[0]) / (to[1] - from[1]);
+        let max_x = (self.width - 1) as f32;
+
+        let first = from[1].max(0.0) as usize;
+        let last = (to[1].ceil().max(0.0) as usize).min(self.height);
+        let mut x = from[0] + ((first as f32).max(from[1]) - from[1]) * dxdy;
+        for y in first..last {
+            let row = y * self.width;
+            let dy = ((y + 1) as f32).min(to[1]) - (y as f32).max(from[1]);
+            let next_x = x + dxdy * dy;
+            let area = dy * direction;
+
+            let (x0, x1) = if x < next_x { (x, next_x) } else { (next_x, x) };
+            let (x0, x1) = (x0.max(0.0).min(max_x), x1.max(0.0).min(max_x));
+            let x0_floor = x0.floor();
+            let x0_cell = x0_floor as usize;
+            let x1_ceil = x1.ceil();
+            let x1_cell = x1_ceil as usize;
+
+            if x1_cell <= x0_cell + 1 {
+                // Within one cell, split between it and the next by the
+                // average distance into it
+                let fraction = 0.5 * (x0 + x1) - x0_floor;
+                self.cells[row + x0_cell] += area * (1.0 - fraction);
+                self.cells[row + x0_cell + 1] += area * fraction;
+            } else {
+                // Across cells, the covered area grows linearly between the
+                // partially covered first and last ones
+                let slope = 1.0 / (x1 - x0);
+                let x0_fraction = x0 - x0_floor;
+                let first_area = 0.5 * slope * (1.0 - x0_fraction) * (1.0 - x0_fraction);
+                let x1_fraction = x1 - x1_ceil + 1.0;
+                let last_area = 0.5 * slope * x1_fraction * x1_fraction;
+
+                self.cells[row + x0_cell] += area * first_area;
+                if x1_cell == x0_cell + 2 {
+                    self.cells[row + x0_cell + 1] += area * (1.0 - first_area - last_area);
+                } else {
+                    let second_area = slope * (1.5 - x0_fraction);
+                    self.cells[row + x0_cell + 1] += area * (second_area - first_area);
+                    for cell in x0_cell + 2..x1_cell - 1 {
+                        self.cells[row + cell] += area * slope;
+                    }
+                    let before_last = second_area + (x1_cell - x0_cell - 3) as f32 * slope;
+                    self.cells[row + x1_cell - 1] += area * (1.0 - before_last - last_area);
+                }
+                self.cells[row + x1_cell] += area * last_area;
+            }
+            x = next_x;
+        }
+    }
+}
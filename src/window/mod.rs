@@ -1,37 +1,295 @@
-extern crate dacite;
-extern crate winit;
+//! Window creation and switching between windowed, borderless and exclusive
+//! fullscreen on a chosen monitor.
+//!
+//! Display modes cannot be switched: winit 0.7 can neither list the video
+//! modes of a monitor nor change them, so exclusive fullscreen always uses
+//! the monitor's current mode.
+
+use winit;
+use vulkano_win;
+use vulkano_win::VkSurfaceBuild;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use vulkano::instance as vki;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fullscreen {
+    Windowed,
+    /// Undecorated window covering the whole monitor.
+    Borderless,
+    /// Fullscreen window owned by the windowing system.
+    Exclusive,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MonitorChoice {
+    Primary,
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub resizable: bool,
+    pub fullscreen: Fullscreen,
+    /// Monitor of fullscreen windows.
+    pub monitor: MonitorChoice,
+    /// winit cannot set window icons yet, so creating a window with one
+    /// fails.
+    pub icon: Option<PathBuf>,
+    pub grab_cursor: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> WindowConfig {
+        WindowConfig {
+            title: "Wargod".to_string(),
+            width: 1024,
+            height: 768,
+            resizable: true,
+            fullscreen: Fullscreen::Windowed,
+            monitor: MonitorChoice::Primary,
+            icon: None,
+            grab_cursor: false,
+        }
+    }
+}
+
+/// A monitor as reported by the windowing system.
+#[derive(Clone, Debug)]
+pub struct MonitorInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    /// Current video mode of the monitor. winit does not enumerate other
+    /// modes, so this is the only mode offered for fullscreen.
+    pub dimensions: (u32, u32),
+}
+
+pub fn monitors() -> Vec<MonitorInfo> {
+    winit::get_available_monitors().enumerate().map(|(idx, m)| {
+        MonitorInfo {
+            index: idx,
+            name: m.get_name(),
+            dimensions: m.get_dimensions(),
+        }
+    }).collect()
+}
+
+/// Position and size of the window before it went fullscreen.
+#[derive(Clone, Copy, Debug)]
+struct WindowedGeometry {
+    position: Option<(i32, i32)>,
+    size: (u32, u32),
+}
 
 pub struct Window {
-    pub events_loop: winit::EventsLoop,
-    pub window:      winit::Window,
-    pub extent:      dacite::core::Extent2D,
+    pub surface: Arc<vulkano_win::Window>,
+    pub config: WindowConfig,
+    windowed: Option<WindowedGeometry>,
 }
 
 impl Window {
-    pub fn new(extent: dacite::core::Extent2D) -> Result<Self, ()> {
-        let events_loop = winit::EventsLoop::new();
-
-        let window = winit::WindowBuilder::new()
-            .with_title("dacite triangle example")
-            .with_dimensions(extent.width, extent.height)
-            .with_min_dimensions(extent.width, extent.height)
-            .with_max_dimensions(extent.width, extent.height)
-            .with_visibility(false)
-            .build(&events_loop);
-
-        let window = window.map_err(|e| {
+    pub fn new(events_loop: &winit::EventsLoop,
+               instance: Arc<vki::Instance>,
+               config: WindowConfig) -> Result<Self, ()> {
+        if let Some(ref icon) = config.icon {
+            println!("Failed to create window (icon {} requested, window icons are not supported)",
+                     icon.display());
+            return Err(());
+        }
+
+        let monitor = find_monitor(&config.monitor);
+        // winit does not report monitor positions, so borderless windows can
+        // only be placed on the primary monitor, at the origin of the desktop
+        if config.fullscreen == Fullscreen::Borderless &&
+            monitor.get_native_identifier() != winit::get_primary_monitor().get_native_identifier() {
+            println!("Failed to create window (borderless fullscreen is only supported on the primary monitor)");
+            return Err(());
+        }
+
+        let mut builder = winit::WindowBuilder::new()
+            .with_title(config.title.clone());
+
+        builder = match config.fullscreen {
+            Fullscreen::Windowed => builder
+                .with_dimensions(config.width, config.height),
+            Fullscreen::Borderless => {
+                let (width, height) = monitor.get_dimensions();
+                builder
+                    .with_dimensions(width, height)
+                    .with_decorations(false)
+            },
+            Fullscreen::Exclusive => {
+                let (width, height) = monitor.get_dimensions();
+                builder
+                    .with_dimensions(width, height)
+                    .with_fullscreen(monitor)
+            },
+        };
+
+        if !config.resizable && config.fullscreen == Fullscreen::Windowed {
+            builder = builder
+                .with_min_dimensions(config.width, config.height)
+                .with_max_dimensions(config.width, config.height);
+        }
+
+        let surface = builder.build_vk_surface(events_loop, instance).map_err(|e| {
             match e {
-                winit::CreationError::OsError(e) =>
+                vulkano_win::CreationError::WindowCreationError(
+                    winit::CreationError::OsError(e)) =>
                     println!("Failed to create window ({})", e),
-                winit::CreationError::NotSupported =>
+                vulkano_win::CreationError::WindowCreationError(
+                    winit::CreationError::NotSupported) =>
                     println!("Failed to create window (not supported)"),
+                vulkano_win::CreationError::SurfaceCreationError(e) =>
+                    println!("Failed to create surface ({:?})", e),
             }
         })?;
 
+        if config.fullscreen == Fullscreen::Borderless {
+            surface.window().set_position(0, 0);
+        }
+
+        if config.grab_cursor {
+            if let Err(e) = surface.window().set_cursor_state(winit::CursorState::Grab) {
+                println!("Failed to grab cursor ({})", e);
+            }
+        }
+
         Ok(Window {
-            events_loop: events_loop,
-            window:      window,
-            extent:      extent,
+            surface: Arc::new(surface),
+            config: config,
+            windowed: None,
         })
     }
+
+    pub fn window(&self) -> &winit::Window {
+        self.surface.window()
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.config.fullscreen != Fullscreen::Windowed
+    }
+
+    /// Switches between windowed, borderless and exclusive fullscreen.
+    ///
+    /// winit cannot change these at runtime, so the window and its surface
+    /// are rebuilt. The caller must wait for the GPU to stop using the old
+    /// surface beforehand. `present` moves rendering to the new surface,
+    /// usually with `Core::set_window`; the old window is only replaced once
+    /// it succeeds and stays as it was otherwise.
+    pub fn set_fullscreen<F>(&mut self,
+                             events_loop: &winit::EventsLoop,
+                             fullscreen: Fullscreen,
+                             monitor: MonitorChoice,
+                             present: F) -> Result<(), ()>
+        where F: FnOnce(Arc<vulkano_win::Window>) -> Result<(), ()>
+    {
+        if fullscreen == self.config.fullscreen && monitor == self.config.monitor {
+            return Ok(());
+        }
+
+        let windowed = if self.config.fullscreen == Fullscreen::Windowed {
+            Some(WindowedGeometry {
+                position: self.window().get_position(),
+                size: self.window().get_inner_size()
+                    .unwrap_or((self.config.width, self.config.height)),
+            })
+        } else {
+            self.windowed
+        };
+
+        let mut config = self.config.clone();
+        config.fullscreen = fullscreen;
+        config.monitor = monitor;
+        if fullscreen == Fullscreen::Windowed {
+            if let Some(geometry) = windowed {
+                config.width = geometry.size.0;
+                config.height = geometry.size.1;
+            }
+        }
+
+        let instance = self.surface.surface().instance().clone();
+        let new_window = Window::new(events_loop, instance, config)?;
+
+        if fullscreen == Fullscreen::Windowed {
+            if let Some(WindowedGeometry { position: Some((x, y)), .. }) = windowed {
+                new_window.window().set_position(x, y);
+            }
+        }
+
+        present(new_window.surface.clone())?;
+
+        self.surface = new_window.surface;
+        self.config = new_window.config;
+        self.windowed = if fullscreen == Fullscreen::Windowed { None } else { windowed };
+
+        Ok(())
+    }
+
+    /// Rebuilds the window and its surface with the current configuration,
+    /// e.g. after the surface was lost. Like `set_fullscreen`, the old window
+    /// is kept unless `present` succeeds.
+    pub fn recreate<F>(&mut self, events_loop: &winit::EventsLoop, present: F) -> Result<(), ()>
+        where F: FnOnce(Arc<vulkano_win::Window>) -> Result<(), ()>
+    {
+        let geometry = (self.window().get_position(), self.window().get_inner_size());
+
+        let mut config = self.config.clone();
+        if let (_, Some((width, height))) = geometry {
+            config.width = width;
+            config.height = height;
+        }
+
+        let instance = self.surface.surface().instance().clone();
+        let new_window = Window::new(events_loop, instance, config)?;
+
+        if let (Some((x, y)), _) = geometry {
+            if self.config.fullscreen == Fullscreen::Windowed {
+                new_window.window().set_position(x, y);
+            }
+        }
+
+        present(new_window.surface.clone())?;
+        self.surface = new_window.surface;
+
+        Ok(())
+    }
+
+    pub fn toggle_fullscreen<F>(&mut self,
+                                events_loop: &winit::EventsLoop,
+                                fullscreen: Fullscreen,
+                                present: F) -> Result<(), ()>
+        where F: FnOnce(Arc<vulkano_win::Window>) -> Result<(), ()>
+    {
+        let target = if self.config.fullscreen == fullscreen {
+            Fullscreen::Windowed
+        } else {
+            fullscreen
+        };
+        let monitor = self.config.monitor.clone();
+
+        self.set_fullscreen(events_loop, target, monitor, present)
+    }
+}
+
+fn find_monitor(choice: &MonitorChoice) -> winit::MonitorId {
+    let found = match *choice {
+        MonitorChoice::Primary => None,
+        MonitorChoice::Index(idx) => winit::get_available_monitors().nth(idx),
+        MonitorChoice::Name(ref name) => winit::get_available_monitors()
+            .find(|m| m.get_name().as_ref() == Some(name)),
+    };
+
+    found.unwrap_or_else(|| {
+        if *choice != MonitorChoice::Primary {
+            println!("Monitor {:?} not found, using the primary monitor", choice);
+        }
+        winit::get_primary_monitor()
+    })
 }